@@ -0,0 +1,11 @@
+CREATE TABLE key_quotas (
+    api_key TEXT PRIMARY KEY NOT NULL,
+    monthly_limit INTEGER NOT NULL
+);
+
+CREATE TABLE usage_counters (
+    api_key TEXT NOT NULL,
+    period TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (api_key, period)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE orders ADD COLUMN updated_at TEXT;
+
+UPDATE orders SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
@@ -0,0 +1,14 @@
+CREATE TABLE import_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    status TEXT NOT NULL DEFAULT 'queued',
+    body TEXT NOT NULL,
+    total_rows INTEGER NOT NULL,
+    -- rows committed so far, a restarted worker carries on from here
+    processed_rows INTEGER NOT NULL DEFAULT 0,
+    imported_rows INTEGER NOT NULL DEFAULT 0,
+    failed_rows INTEGER NOT NULL DEFAULT 0,
+    -- json array of the rows that couldn't be imported and why
+    row_errors TEXT NOT NULL DEFAULT '[]',
+    failure TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
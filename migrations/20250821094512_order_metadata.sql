@@ -0,0 +1,6 @@
+ALTER TABLE orders ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
+
+-- filters on these keys are the common ones, `Order::find` writes the same
+-- expressions so sqlite can use the indexes
+CREATE INDEX orders_metadata_source ON orders (json_extract(metadata, '$.source'));
+CREATE INDEX orders_metadata_external_id ON orders (json_extract(metadata, '$.external_id'));
@@ -0,0 +1,4 @@
+ALTER TABLE orders ADD COLUMN reminder_sent_at TEXT;
+
+CREATE INDEX orders_pending_reminder ON orders (updated_at)
+WHERE status = 'pending' AND reminder_sent_at IS NULL;
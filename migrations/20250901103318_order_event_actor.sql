@@ -0,0 +1 @@
+ALTER TABLE order_events ADD COLUMN actor TEXT;
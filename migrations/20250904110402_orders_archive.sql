@@ -0,0 +1,35 @@
+-- Completed orders moved out of the live tables by the `archive` command, with
+-- the same columns as the tables they came from.
+CREATE TABLE orders_archive (
+    id INTEGER PRIMARY KEY,
+    status TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    updated_at TEXT,
+    metadata TEXT NOT NULL DEFAULT '{}',
+    reminder_sent_at TEXT
+);
+
+CREATE TABLE order_items_archive (
+    id INTEGER PRIMARY KEY,
+    order_id INTEGER NOT NULL REFERENCES orders_archive (id) ON DELETE CASCADE,
+    sku TEXT,
+    description TEXT NOT NULL,
+    quantity INTEGER NOT NULL,
+    unit_price INTEGER NOT NULL
+);
+
+CREATE INDEX order_items_archive_order_id ON order_items_archive (order_id);
+
+CREATE TABLE order_events_archive (
+    id INTEGER PRIMARY KEY,
+    order_id INTEGER NOT NULL REFERENCES orders_archive (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    detail TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    actor TEXT
+);
+
+CREATE INDEX order_events_archive_order_id ON order_events_archive (order_id);
+
+-- finds the next batch to archive without scanning the live table
+CREATE INDEX orders_completed ON orders (updated_at) WHERE status = 'complete';
@@ -0,0 +1 @@
+CREATE INDEX orders_drafts ON orders (updated_at) WHERE status = 'draft';
@@ -0,0 +1,13 @@
+-- Who an order is for and which api key created it, both optional, so open
+-- orders can be limited per customer, or per key for orders without one.
+ALTER TABLE orders ADD COLUMN customer_id TEXT;
+ALTER TABLE orders ADD COLUMN api_key TEXT;
+
+ALTER TABLE orders_archive ADD COLUMN customer_id TEXT;
+ALTER TABLE orders_archive ADD COLUMN api_key TEXT;
+
+-- counts a customer's open orders on every create without scanning
+CREATE INDEX orders_open_by_customer ON orders (customer_id)
+    WHERE status NOT IN ('complete', 'canceled');
+CREATE INDEX orders_open_by_key ON orders (api_key)
+    WHERE customer_id IS NULL AND status NOT IN ('complete', 'canceled');
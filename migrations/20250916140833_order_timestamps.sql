@@ -0,0 +1,16 @@
+-- When orders were created and completed, for the daily report. Existing
+-- orders only have updated_at, which is the best guess for both.
+ALTER TABLE orders ADD COLUMN created_at TEXT;
+ALTER TABLE orders ADD COLUMN completed_at TEXT;
+
+ALTER TABLE orders_archive ADD COLUMN created_at TEXT;
+ALTER TABLE orders_archive ADD COLUMN completed_at TEXT;
+
+UPDATE orders SET created_at = updated_at;
+UPDATE orders SET completed_at = updated_at WHERE status = 'complete';
+
+UPDATE orders_archive SET created_at = updated_at;
+UPDATE orders_archive SET completed_at = updated_at WHERE status = 'complete';
+
+CREATE INDEX orders_created_at ON orders (created_at);
+CREATE INDEX orders_completed_at ON orders (completed_at) WHERE completed_at IS NOT NULL;
@@ -0,0 +1,7 @@
+-- Orders and their items each have a currency. Everything before this was
+-- treated as one two decimal currency, recorded as USD.
+ALTER TABLE orders ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+ALTER TABLE orders_archive ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+
+ALTER TABLE order_items ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
+ALTER TABLE order_items_archive ADD COLUMN currency TEXT NOT NULL DEFAULT 'USD';
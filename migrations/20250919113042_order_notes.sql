@@ -0,0 +1,39 @@
+-- Notes staff leave on an order, and the @usernames mentioned in them.
+CREATE TABLE order_notes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id INTEGER NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+    author TEXT,
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX order_notes_order_id ON order_notes (order_id);
+
+CREATE TABLE note_mentions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    note_id INTEGER NOT NULL REFERENCES order_notes (id) ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    -- 0 when MENTIONABLE_USERS is set and doesn't have the username
+    known INTEGER NOT NULL
+);
+
+CREATE INDEX note_mentions_note_id ON note_mentions (note_id);
+
+CREATE TABLE order_notes_archive (
+    id INTEGER PRIMARY KEY,
+    order_id INTEGER NOT NULL REFERENCES orders_archive (id) ON DELETE CASCADE,
+    author TEXT,
+    body TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX order_notes_archive_order_id ON order_notes_archive (order_id);
+
+CREATE TABLE note_mentions_archive (
+    id INTEGER PRIMARY KEY,
+    note_id INTEGER NOT NULL REFERENCES order_notes_archive (id) ON DELETE CASCADE,
+    username TEXT NOT NULL,
+    known INTEGER NOT NULL
+);
+
+CREATE INDEX note_mentions_archive_note_id ON note_mentions_archive (note_id);
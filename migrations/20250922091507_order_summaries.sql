@@ -0,0 +1,16 @@
+-- What the order list shows, kept up to date with every write to an order or
+-- its items, see `projections.rs`. Rebuilt with `rebuild-projections`.
+CREATE TABLE order_summaries (
+    order_id INTEGER PRIMARY KEY REFERENCES orders (id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    item_count INTEGER NOT NULL,
+    customer_id TEXT,
+    updated_at TEXT
+);
+
+INSERT INTO order_summaries (order_id, status, amount, item_count, customer_id, updated_at)
+SELECT o.id, o.status, o.amount,
+    (SELECT count(*) FROM order_items i WHERE i.order_id = o.id),
+    o.customer_id, o.updated_at
+FROM orders o;
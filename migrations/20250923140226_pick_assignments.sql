@@ -0,0 +1,8 @@
+-- The warehouse worker an order was handed to by `GET /orders/pick-list`, and
+-- when. The sweeper clears both once the pick lease runs out.
+ALTER TABLE orders ADD COLUMN assigned_to TEXT;
+ALTER TABLE orders ADD COLUMN assigned_at TEXT;
+ALTER TABLE orders_archive ADD COLUMN assigned_to TEXT;
+ALTER TABLE orders_archive ADD COLUMN assigned_at TEXT;
+
+CREATE INDEX orders_assigned_at ON orders (assigned_at) WHERE assigned_to IS NOT NULL;
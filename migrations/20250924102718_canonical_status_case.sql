@@ -0,0 +1,13 @@
+-- Statuses that only differ from a stored one in case or surrounding spaces,
+-- e.g. 'Complete' or ' pending'. Anything else is left for `fix-statuses`.
+UPDATE orders SET status = lower(trim(status))
+WHERE status <> lower(trim(status))
+    AND lower(trim(status)) IN ('pending', 'in-progress', 'complete', 'canceled', 'draft');
+
+UPDATE orders_archive SET status = lower(trim(status))
+WHERE status <> lower(trim(status))
+    AND lower(trim(status)) IN ('pending', 'in-progress', 'complete', 'canceled', 'draft');
+
+UPDATE order_summaries SET status = lower(trim(status))
+WHERE status <> lower(trim(status))
+    AND lower(trim(status)) IN ('pending', 'in-progress', 'complete', 'canceled', 'draft');
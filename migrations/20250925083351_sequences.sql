@@ -0,0 +1,12 @@
+-- Named counters handed out by `db::next_value`. `value` is the last one
+-- handed out, a name gets a row the first time it's used.
+CREATE TABLE sequences (
+    name TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+
+-- The monthly usage counters are sequences named `usage:<YYYY-MM>:<api key>`.
+INSERT INTO sequences (name, value)
+SELECT 'usage:' || period || ':' || api_key, count FROM usage_counters;
+
+DROP TABLE usage_counters;
@@ -0,0 +1,32 @@
+-- Things that happened to the service rather than to an order, e.g. an error
+-- rate alert. They go to the webhooks like order events do.
+CREATE TABLE system_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    detail TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- A delivery is of either an order event or a system event. sqlite can't drop
+-- the NOT NULL from event_id in place, so the table is rebuilt.
+CREATE TABLE webhook_deliveries_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event_id INTEGER REFERENCES order_events (id) ON DELETE CASCADE,
+    system_event_id INTEGER REFERENCES system_events (id) ON DELETE CASCADE,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+    CHECK ((event_id IS NULL) <> (system_event_id IS NULL))
+);
+
+INSERT INTO webhook_deliveries_new (id, webhook_id, event_id, status, attempts, last_error, created_at)
+SELECT id, webhook_id, event_id, status, attempts, last_error, created_at FROM webhook_deliveries;
+
+DROP TABLE webhook_deliveries;
+ALTER TABLE webhook_deliveries_new RENAME TO webhook_deliveries;
+
+CREATE INDEX webhook_deliveries_pending ON webhook_deliveries (id) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_event_id ON webhook_deliveries (event_id);
+CREATE INDEX webhook_deliveries_system_event_id ON webhook_deliveries (system_event_id);
@@ -0,0 +1,19 @@
+-- Old and new values of an order's tracked fields, as json, for reading an
+-- order as it was at an earlier time. There's no foreign key, a deleted
+-- order's changes stay along with a `deleted` row holding its last state.
+CREATE TABLE order_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    order_id INTEGER NOT NULL,
+    field TEXT NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    changed_at TEXT NOT NULL
+);
+
+CREATE INDEX order_changes_order_id ON order_changes (order_id);
+
+-- Nothing is known about existing orders' changes before now.
+INSERT INTO order_changes (order_id, field, changed_at)
+SELECT id, 'tracking_started', strftime('%Y-%m-%dT%H:%M:%fZ', 'now') FROM orders
+UNION ALL
+SELECT id, 'tracking_started', strftime('%Y-%m-%dT%H:%M:%fZ', 'now') FROM orders_archive;
@@ -0,0 +1,17 @@
+-- Re-deliveries of past order events to one webhook. Their progress is read
+-- from the deliveries they queue.
+CREATE TABLE event_replays (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    after_id INTEGER,
+    before_id INTEGER,
+    -- A json array of event types, NULL for every type.
+    event_types TEXT,
+    total INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+-- Set on the deliveries a replay queued, they're sent with `X-Replay: true`.
+ALTER TABLE webhook_deliveries ADD COLUMN replay_id INTEGER REFERENCES event_replays (id) ON DELETE CASCADE;
+
+CREATE INDEX webhook_deliveries_replay_id ON webhook_deliveries (replay_id) WHERE replay_id IS NOT NULL;
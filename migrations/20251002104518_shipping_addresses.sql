@@ -0,0 +1,18 @@
+-- An order's shipping address, set on create and by PATCH until the order is
+-- complete. Columns rather than json so `GET /orders?country=` has an index.
+ALTER TABLE orders ADD COLUMN shipping_name TEXT;
+ALTER TABLE orders ADD COLUMN shipping_line1 TEXT;
+ALTER TABLE orders ADD COLUMN shipping_line2 TEXT;
+ALTER TABLE orders ADD COLUMN shipping_city TEXT;
+ALTER TABLE orders ADD COLUMN shipping_region TEXT;
+ALTER TABLE orders ADD COLUMN shipping_postal_code TEXT;
+ALTER TABLE orders ADD COLUMN shipping_country TEXT;
+ALTER TABLE orders_archive ADD COLUMN shipping_name TEXT;
+ALTER TABLE orders_archive ADD COLUMN shipping_line1 TEXT;
+ALTER TABLE orders_archive ADD COLUMN shipping_line2 TEXT;
+ALTER TABLE orders_archive ADD COLUMN shipping_city TEXT;
+ALTER TABLE orders_archive ADD COLUMN shipping_region TEXT;
+ALTER TABLE orders_archive ADD COLUMN shipping_postal_code TEXT;
+ALTER TABLE orders_archive ADD COLUMN shipping_country TEXT;
+
+CREATE INDEX orders_shipping_country ON orders (shipping_country) WHERE shipping_country IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Each change row chains onto the one before it, see `audit.rs`. Rows written
+-- before this have no hashes and aren't part of the chain.
+ALTER TABLE order_changes ADD COLUMN prev_hash TEXT;
+ALTER TABLE order_changes ADD COLUMN row_hash TEXT;
@@ -0,0 +1,3 @@
+-- Lets a webhook skip checking its receiver's certificate, for receivers on
+-- self signed certificates nobody can hand over a ca for. On unless turned off.
+ALTER TABLE webhooks ADD COLUMN verify_tls BOOLEAN NOT NULL DEFAULT 1;
@@ -0,0 +1,5 @@
+-- The work queue, open orders oldest first, is most of the reads. Ordering by
+-- orders_created_at walks past every finished order to find the open ones,
+-- this only has the open ones in it. See `Order::get_open`.
+CREATE INDEX orders_open ON orders (created_at, id)
+    WHERE status IN ('pending', 'in-progress');
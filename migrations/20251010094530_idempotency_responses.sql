@@ -0,0 +1,17 @@
+-- The response a create with an Idempotency-Key got, replayed as it was sent
+-- to retries with the same key until the sweeper deletes it. `body` is NULL
+-- when it was over the size cap, the order is looked up by the key instead.
+-- See `idempotency.rs`.
+CREATE TABLE idempotency_responses (
+    key TEXT PRIMARY KEY NOT NULL,
+    -- sha256 of the request body, a retry with a different body is a 409.
+    request_hash TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    -- A json object of the replayed headers.
+    headers TEXT NOT NULL,
+    body BLOB,
+    body_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX idempotency_responses_created_at ON idempotency_responses (created_at);
@@ -0,0 +1,17 @@
+-- Every request with its status and latency, for support to look up what a
+-- key did and when. Written in batches off the request path, and rows older
+-- than the retention are deleted by the sweeper. See `request_log.rs`.
+CREATE TABLE request_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    method TEXT NOT NULL,
+    -- The route's template, like /orders/{id}, not the path as sent.
+    path TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    api_key TEXT,
+    request_id TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX request_log_created_at ON request_log (created_at);
+CREATE INDEX request_log_api_key ON request_log (api_key, created_at);
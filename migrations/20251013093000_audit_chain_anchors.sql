@@ -0,0 +1,10 @@
+-- Where the audit log's hash chain starts once its oldest rows have been
+-- pruned: the row_hash of the last pruned row, which the first remaining row
+-- chains onto. The row with the highest last_id is the anchor, older ones are
+-- dropped as it moves on, see `audit.rs` and `retention/tables.rs`.
+CREATE TABLE audit_chain_anchors (
+    -- The last order_changes row pruned.
+    last_id INTEGER PRIMARY KEY,
+    last_hash TEXT NOT NULL,
+    pruned_at TEXT NOT NULL
+);
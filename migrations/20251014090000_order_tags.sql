@@ -0,0 +1,4 @@
+-- Labels put on orders in bulk by `POST /admin/orders/retag`, a json array of
+-- distinct strings.
+ALTER TABLE orders ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE orders_archive ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
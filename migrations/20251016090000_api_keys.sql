@@ -0,0 +1,22 @@
+-- Keys callers send in `X-Api-Key`, checked when `REQUIRE_API_KEY` is on, see
+-- `api_keys.rs`. Only a sha256 of each secret is kept, the secret itself is
+-- shown once, when the key is created or rotated.
+CREATE TABLE api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    -- The start of the secret, so a key can be told apart in lists and logs.
+    prefix TEXT NOT NULL,
+    secret_hash TEXT NOT NULL UNIQUE,
+    -- A json array of what the key can do: read, write and admin.
+    scopes TEXT NOT NULL DEFAULT '[]',
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    revoked_at TEXT,
+    -- The secret a rotation replaced, still accepted until
+    -- previous_expires_at so callers have time to move over.
+    previous_secret_hash TEXT,
+    previous_expires_at TEXT,
+    rotated_at TEXT
+);
+
+CREATE INDEX api_keys_previous_secret_hash ON api_keys (previous_secret_hash);
@@ -0,0 +1,3 @@
+-- The locale an upload's `amount_decimal` column is written in, like `de-DE`
+-- for `1.234,56`, `NULL` for plain decimals.
+ALTER TABLE import_jobs ADD COLUMN locale TEXT;
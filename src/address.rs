@@ -0,0 +1,326 @@
+//! An order's shipping address, kept in its `shipping_*` columns so lists can
+//! filter on the country. Requests send it as an object and patch it like
+//! metadata: a field set to `null` is removed, and the address as a whole
+//! `null` removes it. Every field is trimmed and the country uppercased before
+//! it's checked.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    constraints::{ADDRESS_FIELD_MAX_LEN, check_max_len},
+    error::{CustomError, Result},
+};
+
+pub const FIELDS: &[&str] = &[
+    "name",
+    "line1",
+    "line2",
+    "city",
+    "region",
+    "postal_code",
+    "country",
+];
+
+/// The fields an address can be without.
+const OPTIONAL: &[&str] = &["line2", "region"];
+
+/// Postal code lengths, spaces and dashes included, for the countries most
+/// orders ship to. Any other country's only has to fit the field.
+const POSTAL_CODE_LENGTHS: &[(&str, &[usize])] = &[
+    ("AU", &[4]),
+    ("CA", &[6, 7]),
+    ("DE", &[5]),
+    ("ES", &[5]),
+    ("FR", &[5]),
+    ("GB", &[5, 6, 7, 8]),
+    ("IT", &[5]),
+    ("JP", &[7, 8]),
+    ("NL", &[6, 7]),
+    ("US", &[5, 10]),
+];
+
+/// ISO 3166-1 alpha-2, the officially assigned codes.
+const COUNTRIES: &[&str] = &[
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS",
+    "BT", "BV", "BW", "BY", "BZ", "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN",
+    "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ", "DE", "DJ", "DK", "DM", "DO", "DZ", "EC", "EE",
+    "EG", "EH", "ER", "ES", "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA", "GB", "GD", "GE", "GF",
+    "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK", "HM",
+    "HN", "HR", "HT", "HU", "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM",
+    "JO", "JP", "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC",
+    "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY", "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK",
+    "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ", "NA",
+    "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ", "OM", "PA", "PE", "PF", "PG",
+    "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA", "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS",
+    "ST", "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO",
+    "TR", "TT", "TV", "TW", "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE", "VG", "VI",
+    "VN", "VU", "WF", "WS", "YE", "YT", "ZA", "ZM", "ZW",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShippingAddress {
+    pub name: String,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    #[serde(alias = "postalCode")]
+    pub postal_code: String,
+    pub country: String,
+}
+
+impl ShippingAddress {
+    /// Applies a patch from a request to `current`, giving the checked and
+    /// normalized address or none if the patch removed it.
+    pub fn patch(current: Option<Self>, patch: &Value) -> Result<Option<Self>> {
+        let Value::Object(patch) = patch else {
+            return match patch {
+                Value::Null => Ok(None),
+                _ => Err(CustomError::Validation(
+                    "shipping_address should be an object".to_string(),
+                )),
+            };
+        };
+
+        let mut fields = match current {
+            Some(current) => match serde_json::to_value(current) {
+                Ok(Value::Object(fields)) => fields,
+                _ => unreachable!("an address is a json object"),
+            },
+            None => Map::new(),
+        };
+
+        for (field, value) in patch {
+            let name = if field == "postalCode" {
+                "postal_code"
+            } else {
+                field.as_str()
+            };
+
+            if !FIELDS.contains(&name) {
+                return Err(CustomError::Validation(format!(
+                    "shipping_address.{field} is not a field, use {}",
+                    FIELDS.join(", ")
+                )));
+            }
+
+            match value {
+                Value::Null => fields.remove(name),
+                Value::String(value) => fields.insert(name.to_string(), value.trim().into()),
+                _ => {
+                    return Err(CustomError::Validation(format!(
+                        "shipping_address.{name} should be a string"
+                    )));
+                }
+            };
+        }
+
+        Self::from_fields(fields).map(Some)
+    }
+
+    fn from_fields(mut fields: Map<String, Value>) -> Result<Self> {
+        // an emptied optional field is the same as a removed one
+        fields.retain(|_, value| value.as_str().is_some_and(|value| !value.is_empty()));
+
+        for field in FIELDS {
+            match fields.get(*field).and_then(Value::as_str) {
+                Some(value) => check_max_len(
+                    &format!("shipping_address.{field}"),
+                    value,
+                    ADDRESS_FIELD_MAX_LEN,
+                )?,
+                None if OPTIONAL.contains(field) => {}
+                None => {
+                    return Err(CustomError::Validation(format!(
+                        "shipping_address.{field} is required"
+                    )));
+                }
+            }
+        }
+
+        let mut address: Self =
+            serde_json::from_value(Value::Object(fields)).map_err(anyhow::Error::from)?;
+
+        address.country = country(&address.country)?;
+        check_postal_code(&address.postal_code, &address.country)?;
+
+        Ok(address)
+    }
+
+    /// The columns the address is stored in, in `FIELDS` order.
+    pub fn columns(address: Option<&Self>) -> [Option<String>; 7] {
+        match address {
+            Some(address) => [
+                Some(address.name.clone()),
+                Some(address.line1.clone()),
+                address.line2.clone(),
+                Some(address.city.clone()),
+                address.region.clone(),
+                Some(address.postal_code.clone()),
+                Some(address.country.clone()),
+            ],
+            None => Default::default(),
+        }
+    }
+
+    /// The address stored in `columns`, none unless the required ones are set.
+    pub fn from_columns(columns: [Option<String>; 7]) -> Option<Self> {
+        let [name, line1, line2, city, region, postal_code, country] = columns;
+
+        Some(Self {
+            name: name?,
+            line1: line1?,
+            line2,
+            city: city?,
+            region,
+            postal_code: postal_code?,
+            country: country?,
+        })
+    }
+}
+
+/// A country code from a request, uppercased and checked to be assigned.
+pub fn country(code: &str) -> Result<String> {
+    let code = code.trim().to_ascii_uppercase();
+
+    if !COUNTRIES.contains(&code.as_str()) {
+        return Err(CustomError::Validation(format!(
+            "{code:?} is not an ISO 3166-1 alpha-2 country code"
+        )));
+    }
+
+    Ok(code)
+}
+
+fn check_postal_code(postal_code: &str, country: &str) -> Result<()> {
+    let Some((_, lengths)) = POSTAL_CODE_LENGTHS
+        .iter()
+        .find(|(code, _)| *code == country)
+    else {
+        return Ok(());
+    };
+
+    let length = postal_code.chars().count();
+
+    if !lengths.contains(&length) {
+        let lengths: Vec<String> = lengths.iter().map(usize::to_string).collect();
+
+        return Err(CustomError::Validation(format!(
+            "shipping_address.postal_code {postal_code:?} should be {} characters long in {country}",
+            lengths.join(" or ")
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn message(current: Option<ShippingAddress>, patch: Value) -> String {
+        match ShippingAddress::patch(current, &patch) {
+            Err(CustomError::Validation(message)) => message,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    fn berlin() -> ShippingAddress {
+        let patch = json!({
+            "name": " Erika Mustermann ",
+            "line1": "Heidestraße 17",
+            "city": "Berlin",
+            "postal_code": "51147",
+            "country": "de",
+        });
+
+        ShippingAddress::patch(None, &patch).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_normalizes() {
+        let address = berlin();
+
+        assert_eq!(address.name, "Erika Mustermann");
+        assert_eq!(address.country, "DE");
+        assert_eq!(address.line2, None);
+    }
+
+    #[test]
+    fn test_patches_fields() {
+        let patched = ShippingAddress::patch(
+            Some(berlin()),
+            &json!({ "line2": "Hinterhaus", "city": " Köln " }),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(patched.line2.as_deref(), Some("Hinterhaus"));
+        assert_eq!(patched.city, "Köln");
+        assert_eq!(patched.name, "Erika Mustermann");
+
+        let cleared = ShippingAddress::patch(Some(patched), &json!({ "line2": null }))
+            .unwrap()
+            .unwrap();
+        assert_eq!(cleared.line2, None);
+
+        assert_eq!(
+            ShippingAddress::patch(Some(cleared), &Value::Null).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rejects() {
+        assert_eq!(
+            message(Some(berlin()), json!({ "country": "XX" })),
+            "\"XX\" is not an ISO 3166-1 alpha-2 country code"
+        );
+        assert_eq!(
+            message(Some(berlin()), json!({ "postal_code": "5114" })),
+            "shipping_address.postal_code \"5114\" should be 5 characters long in DE"
+        );
+        assert_eq!(
+            message(Some(berlin()), json!({ "city": " " })),
+            "shipping_address.city is required"
+        );
+        assert_eq!(
+            message(None, json!({ "name": "Erika" })),
+            "shipping_address.line1 is required"
+        );
+        assert_eq!(
+            message(Some(berlin()), json!({ "city": 1 })),
+            "shipping_address.city should be a string"
+        );
+        assert!(
+            message(Some(berlin()), json!({ "street": "x" }))
+                .starts_with("shipping_address.street is not a field")
+        );
+        assert_eq!(
+            message(None, json!("Berlin")),
+            "shipping_address should be an object"
+        );
+
+        // countries without a known length only need a postal code
+        let patch = json!({ "country": "BE", "postal_code": "1000" });
+        assert!(ShippingAddress::patch(Some(berlin()), &patch).is_ok());
+    }
+
+    #[test]
+    fn test_columns_round_trip() {
+        let address = berlin();
+
+        assert_eq!(
+            ShippingAddress::from_columns(ShippingAddress::columns(Some(&address))),
+            Some(address)
+        );
+        assert_eq!(
+            ShippingAddress::from_columns(ShippingAddress::columns(None)),
+            None
+        );
+    }
+}
@@ -0,0 +1,548 @@
+//! Server rendered admin pages for quick triage, enabled with the `admin-ui`
+//! feature. Plain html and forms, the forms go through small adapters that
+//! call the same handlers as the json api.
+//!
+//! Deployments that authenticate the admin with a cookie would let any site
+//! post these forms, so they're protected with signed double submit tokens:
+//! each browser gets a random nonce cookie, every form carries the nonce's
+//! signature, and posts without a matching pair are refused.
+//!
+//! Amounts are written the way the browser's `Accept-Language` asks, or
+//! `DEFAULT_LOCALE` when it names no locale `money_format` knows.
+
+use std::sync::LazyLock;
+
+use axum::{
+    Extension, Form, Router,
+    body::{self, Body},
+    extract::{FromRequest, Request, State},
+    http::{
+        HeaderMap, HeaderValue, Method, StatusCode,
+        header::{ACCEPT_LANGUAGE, COOKIE, REFERER, SET_COOKIE},
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use hmac::{Hmac, Mac};
+use maud::{DOCTYPE, Markup, html};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{
+    AppState,
+    config::AppConfig,
+    dto::{Case, UpdateOrderStatusRequest},
+    error::{CustomError, Result},
+    events::OrderEvent,
+    extract::{LenientJson, PathId},
+    items::Item,
+    money_format::{self, Locale},
+    negotiate::{HTML, MediaTypes},
+    orders::{Order, OrderStatus},
+    routes::{self, RouterExt},
+};
+
+/// Orders listed on the front page.
+const RECENT_ORDERS: i64 = 50;
+/// Holds the browser's nonce, only sent back to the admin pages.
+const CSRF_COOKIE: &str = "admin_csrf";
+/// The hidden form field carrying the nonce's signature.
+const CSRF_FIELD: &str = "csrf_token";
+/// Largest form body read while checking its token.
+const FORM_MAX_BYTES: usize = 64 * 1024;
+
+/// Signs tokens when `ADMIN_CSRF_KEY` isn't set.
+static PROCESS_KEY: LazyLock<[u8; 32]> = LazyLock::new(rand::random);
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2rem; }
+table { border-collapse: collapse; margin-bottom: 1.5rem; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.4rem 0.8rem; text-align: left; }
+.badge { border-radius: 0.6rem; padding: 0.1rem 0.6rem; font-size: 0.85rem; }
+.pending { background: #fff3cd; }
+.in-progress { background: #cfe2ff; }
+.complete { background: #d1e7dd; }
+.canceled { background: #e2e3e5; }
+.draft { border: 1px dashed #adb5bd; }
+.error { color: #842029; background: #f8d7da; padding: 0.6rem 1rem; }
+form { display: inline; }
+";
+
+pub fn routes(state: &AppState) -> Router<AppState> {
+    Router::new()
+        .at(routes::ADMIN_UI, get(order_list))
+        .at(routes::ADMIN_UI_ORDER, get(order_detail))
+        .at(routes::ADMIN_UI_ORDER_STATUS, post(update_status))
+        .route_layer(middleware::from_fn_with_state(state.clone(), csrf))
+}
+
+/// Registers the pages as html for strict `Accept` checks.
+pub fn media_types(media_types: MediaTypes) -> MediaTypes {
+    media_types
+        .route(routes::ADMIN_UI, &[HTML])
+        .route(routes::ADMIN_UI_ORDER, &[HTML])
+        .route(routes::ADMIN_UI_ORDER_STATUS, &[HTML])
+}
+
+/// The signed token a page embeds in its forms.
+#[derive(Debug, Clone)]
+struct CsrfToken(String);
+
+fn csrf_key(config: &AppConfig) -> &[u8] {
+    match &config.admin_csrf_key {
+        Some(key) => key.as_bytes(),
+        None => PROCESS_KEY.as_slice(),
+    }
+}
+
+fn mac(key: &[u8], nonce: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+fn sign(key: &[u8], nonce: &str) -> String {
+    hex::encode(mac(key, nonce).finalize().into_bytes())
+}
+
+fn verify(key: &[u8], nonce: &str, token: &str) -> bool {
+    hex::decode(token).is_ok_and(|token| mac(key, nonce).verify_slice(&token).is_ok())
+}
+
+fn cookie_nonce(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|cookies| cookies.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| cookie.trim().strip_prefix(CSRF_COOKIE)?.strip_prefix('='))
+        .filter(|nonce| !nonce.is_empty())
+        .map(str::to_string)
+}
+
+#[derive(Debug, Deserialize)]
+struct CsrfForm {
+    csrf_token: String,
+}
+
+/// Hands the page a token for its forms, setting the nonce cookie on the first
+/// visit, and refuses form posts whose token doesn't match the cookie with a
+/// 403 page.
+async fn csrf(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let config = state.config();
+    let key = csrf_key(&config);
+    let nonce = cookie_nonce(&request);
+
+    if request.method() == Method::POST {
+        let (parts, body) = request.into_parts();
+
+        let Ok(bytes) = body::to_bytes(body, FORM_MAX_BYTES).await else {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        };
+
+        let form = Request::from_parts(parts.clone(), Body::from(bytes.clone()));
+        let token = Form::<CsrfForm>::from_request(form, &state).await;
+
+        let valid = match (&nonce, &token) {
+            (Some(nonce), Ok(Form(form))) => verify(key, nonce, &form.csrf_token),
+            _ => false,
+        };
+
+        if !valid {
+            let back = parts
+                .headers
+                .get(REFERER)
+                .and_then(|referer| referer.to_str().ok())
+                .and_then(|referer| referer.find(routes::ADMIN_UI).map(|at| &referer[at..]))
+                .unwrap_or(routes::ADMIN_UI);
+
+            return (StatusCode::FORBIDDEN, refused(back)).into_response();
+        }
+
+        return next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await;
+    }
+
+    let (nonce, fresh) = match nonce {
+        Some(nonce) => (nonce, false),
+        None => (hex::encode(rand::random::<[u8; 16]>()), true),
+    };
+
+    request
+        .extensions_mut()
+        .insert(CsrfToken(sign(key, &nonce)));
+
+    let mut response = next.run(request).await;
+
+    if fresh {
+        let cookie = format!("{CSRF_COOKIE}={nonce}; Path=/admin/ui; HttpOnly; SameSite=Strict");
+
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, cookie);
+        }
+    }
+
+    response
+}
+
+fn refused(back: &str) -> Markup {
+    page(
+        "Request refused",
+        html! {
+            p class="error" {
+                "This form was missing its security token or the token has expired. "
+                "Go back, reload the page and try again."
+            }
+            p { a href=(back) { "Back" } }
+        },
+    )
+}
+
+fn page(title: &str, content: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                title { (title) " · orders admin" }
+                style { (STYLE) }
+            }
+            body {
+                p { a href=(routes::ADMIN_UI) { "Recent orders" } }
+                h1 { (title) }
+                (content)
+            }
+        }
+    }
+}
+
+fn badge(status: &OrderStatus) -> Markup {
+    let status = status.to_string();
+
+    html! { span class={ "badge " (status) } { (status) } }
+}
+
+/// The locale the page writes amounts in.
+fn locale(headers: &HeaderMap, config: &AppConfig) -> Locale {
+    headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|languages| languages.to_str().ok())
+        .and_then(Locale::negotiate)
+        .unwrap_or(config.default_locale)
+}
+
+async fn order_list(State(state): State<AppState>, headers: HeaderMap) -> Result<Markup> {
+    let locale = locale(&headers, &state.config());
+    let orders = Order::recent(&state.db, RECENT_ORDERS).await?;
+
+    Ok(page(
+        "Recent orders",
+        html! {
+            table {
+                tr { th { "Order" } th { "Amount" } th { "Status" } th { "Updated" } }
+                @for order in &orders {
+                    @let id = order.id.unwrap_or_default();
+                    tr {
+                        td { a href=(routes::admin_ui_order(id)) { "#" (id) } }
+                        td { (money_format::format_money(order.amount, &order.currency, locale)) }
+                        td { (badge(&order.status)) }
+                        td { (order.updated_at.as_deref().unwrap_or_default()) }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+async fn order_detail(
+    State(state): State<AppState>,
+    PathId(id): PathId,
+    headers: HeaderMap,
+    Extension(CsrfToken(token)): Extension<CsrfToken>,
+) -> Result<Markup> {
+    let db = &state.db;
+    let locale = locale(&headers, &state.config());
+
+    let Some(order) = Order::get_by_id(db, id).await? else {
+        return Err(CustomError::RecordNotFound);
+    };
+
+    let items = Item::get_by_order_id(db, id).await?;
+    let events = OrderEvent::get_by_order_id(db, id).await?;
+
+    Ok(page(
+        &format!("Order #{id}"),
+        html! {
+            p {
+                "Amount " strong { (money_format::format_money(order.amount, &order.currency, locale)) }
+                " " (badge(&order.status))
+            }
+            p { "Metadata " code { (order.metadata) } }
+
+            // a button for each status the order can move on to
+            @for (status, value, label) in [
+                (OrderStatus::InProgress, "inprogress", "Start"),
+                (OrderStatus::Complete, "complete", "Complete"),
+                (OrderStatus::Canceled, "canceled", "Cancel"),
+            ] {
+                @if order.status.next().contains(&status) {
+                    form method="post" action=(routes::admin_ui_order_status(id)) {
+                        input type="hidden" name=(CSRF_FIELD) value=(token);
+                        input type="hidden" name="status" value=(value);
+                        button type="submit" { (label) }
+                    }
+                    " "
+                }
+            }
+
+            h2 { "Items" }
+            table {
+                tr { th { "Description" } th { "Sku" } th { "Quantity" } th { "Unit price" } }
+                @for item in &items {
+                    tr {
+                        td { (item.description) }
+                        td { (item.sku.as_deref().unwrap_or_default()) }
+                        td { (item.quantity) }
+                        td { (money_format::format_money(item.unit_price, &order.currency, locale)) }
+                    }
+                }
+            }
+
+            h2 { "History" }
+            table {
+                tr { th { "When" } th { "Event" } th { "Detail" } th { "By" } }
+                @for event in &events {
+                    tr {
+                        td { (event.created_at) }
+                        td { (event.kind) }
+                        td { (event.detail) }
+                        td { (event.actor.as_deref().unwrap_or_default()) }
+                    }
+                }
+            }
+        },
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusForm {
+    status: OrderStatus,
+}
+
+/// Turns the status form into the json api's `PATCH /orders/{id}` and sends
+/// the browser back to the order.
+async fn update_status(
+    State(state): State<AppState>,
+    PathId(id): PathId,
+    Form(form): Form<StatusForm>,
+) -> Result<Redirect> {
+    let body = UpdateOrderStatusRequest {
+        status: Some(form.status),
+        ..Default::default()
+    };
+
+    // no preference, the response is only checked for an error
+    let (case, headers) = (Case::default(), HeaderMap::new());
+    crate::update_order_status(State(state), case, headers, PathId(id), LenientJson(body)).await?;
+
+    Ok(Redirect::to(&routes::admin_ui_order(id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header::LOCATION};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::{app, db::test_db, events::EventKind};
+
+    use super::*;
+
+    async fn seed(db: &crate::db::Db) -> i64 {
+        let mut order = Order::new(1234);
+        order.save(db.writer()).await.unwrap();
+
+        let mut item = Item {
+            order_id: order.id.unwrap(),
+            description: "Blue widget".to_string(),
+            quantity: 2,
+            unit_price: 617,
+            ..Default::default()
+        };
+        item.save(db).await.unwrap();
+
+        order.id.unwrap()
+    }
+
+    async fn get_page(app: Router, uri: &str) -> String {
+        get_page_with_cookie(app, uri).await.0
+    }
+
+    async fn get_page_in(app: Router, uri: &str, accept_language: &str) -> String {
+        let request = Request::builder()
+            .uri(uri)
+            .header(ACCEPT_LANGUAGE, accept_language)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    /// The page and the csrf cookie it set.
+    async fn get_page_with_cookie(app: Router, uri: &str) -> (String, String) {
+        let response = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        let cookie = cookie.split(';').next().unwrap().to_string();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (String::from_utf8(body.to_vec()).unwrap(), cookie)
+    }
+
+    fn form_token(page: &str) -> &str {
+        let field = format!(r#"name="{CSRF_FIELD}" value=""#);
+        let start = page.find(&field).unwrap() + field.len();
+
+        &page[start..start + page[start..].find('"').unwrap()]
+    }
+
+    async fn post_status(app: Router, id: i64, cookie: Option<&str>, body: String) -> Response {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(routes::admin_ui_order_status(id))
+            .header("Content-Type", "application/x-www-form-urlencoded");
+
+        if let Some(cookie) = cookie {
+            request = request.header(COOKIE, cookie);
+        }
+
+        app.oneshot(request.body(Body::from(body)).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pages_show_orders() {
+        let db = test_db().await;
+        let id = seed(&db).await;
+
+        let list = get_page(app(db.clone()), routes::ADMIN_UI).await;
+
+        assert!(list.contains(&routes::admin_ui_order(id)));
+        assert!(list.contains("$12.34"));
+        assert!(list.contains(r#"class="badge pending""#));
+
+        let detail = get_page(app(db), &routes::admin_ui_order(id)).await;
+
+        assert!(detail.contains("Blue widget"));
+        assert!(detail.contains("$6.17"));
+        assert!(detail.contains(&routes::admin_ui_order_status(id)));
+    }
+
+    #[tokio::test]
+    async fn test_pages_write_amounts_in_the_browsers_locale() {
+        let db = test_db().await;
+        let id = seed(&db).await;
+
+        let detail = get_page_in(app(db.clone()), &routes::admin_ui_order(id), "de-DE,de;q=0.9,en;q=0.5").await;
+
+        assert!(detail.contains("12,34\u{a0}$"));
+        assert!(detail.contains("6,17\u{a0}$"));
+
+        // nothing known asked for, the default from the config
+        let config = AppConfig {
+            default_locale: Locale::FrFr,
+            ..Default::default()
+        };
+        let list = get_page_in(crate::app_with_config(db, config), routes::ADMIN_UI, "pt-BR").await;
+
+        assert!(list.contains("12,34\u{a0}$"));
+    }
+
+    #[tokio::test]
+    async fn test_status_form_cancels_order() {
+        let db = test_db().await;
+        let id = seed(&db).await;
+
+        let (detail, cookie) =
+            get_page_with_cookie(app(db.clone()), &routes::admin_ui_order(id)).await;
+        let token = form_token(&detail);
+
+        let response = post_status(
+            app(db.clone()),
+            id,
+            Some(&cookie),
+            format!("{CSRF_FIELD}={token}&status=canceled"),
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            &routes::admin_ui_order(id)
+        );
+
+        let order = Order::get_by_id(&db, id).await.unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Canceled);
+
+        let events = OrderEvent::get_by_order_id(&db, id).await.unwrap();
+        assert_eq!(events[0].kind, EventKind::Canceled);
+
+        let detail = get_page(app(db), &routes::admin_ui_order(id)).await;
+
+        assert!(detail.contains("canceled from pending"));
+        assert!(!detail.contains(r#"name="status""#));
+    }
+
+    #[tokio::test]
+    async fn test_status_form_refused_without_token() {
+        let db = test_db().await;
+        let id = seed(&db).await;
+
+        let (detail, cookie) =
+            get_page_with_cookie(app(db.clone()), &routes::admin_ui_order(id)).await;
+        let token = form_token(&detail);
+
+        // another browser's nonce, as a forged form would have
+        let (_, other) = get_page_with_cookie(app(db.clone()), routes::ADMIN_UI).await;
+
+        for (cookie, body) in [
+            (None, format!("{CSRF_FIELD}={token}&status=canceled")),
+            (Some(cookie.as_str()), "status=canceled".to_string()),
+            (
+                Some(cookie.as_str()),
+                format!("{CSRF_FIELD}=00{token}&status=canceled"),
+            ),
+            (
+                Some(other.as_str()),
+                format!("{CSRF_FIELD}={token}&status=canceled"),
+            ),
+        ] {
+            let response = post_status(app(db.clone()), id, cookie, body).await;
+
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let page = String::from_utf8(body.to_vec()).unwrap();
+
+            assert!(page.contains("security token"));
+        }
+
+        let order = Order::get_by_id(&db, id).await.unwrap().unwrap();
+        assert_eq!(order.status, OrderStatus::Pending);
+    }
+}
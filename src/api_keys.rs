@@ -0,0 +1,612 @@
+//! The keys callers send in `X-Api-Key`. They're only checked when
+//! `REQUIRE_API_KEY` is on, see `require`, otherwise the header is just the
+//! name quotas and the request log go by. Each key has the scopes it can use:
+//! `read` for `GET` and the searches, `write` for the other changes and
+//! `admin` for everything under `/admin`.
+//!
+//! Only a sha256 of each secret is stored. The secret is in the response that
+//! creates or rotates the key and nowhere else. A rotation gives the key a new
+//! secret and keeps the old one working for an overlap, so callers can move
+//! over without downtime. Keys are looked up through `ApiKeys`, a small cache
+//! emptied by every change made through the api.
+//!
+//! The header isn't kept anywhere either. `identify` turns it into a `Caller`,
+//! which is what the request log, quotas, orders, the journal and the logs go
+//! by instead.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{Extensions, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    API_KEY_HEADER, AppState,
+    caches::Lookups,
+    clock,
+    db::{self, Db},
+    error::CustomError,
+    routes,
+    timing::Timed,
+};
+
+/// The longest overlap a rotation can ask for, in hours.
+pub const MAX_ROTATION_OVERLAP_HOURS: u64 = 30 * 24;
+
+/// Every secret starts with this, so one pasted somewhere it shouldn't be is
+/// easy to spot.
+const SECRET_PREFIX: &str = "spk_";
+
+/// How much of the secret is kept in the clear as the key's `prefix`.
+const PREFIX_LEN: usize = SECRET_PREFIX.len() + 8;
+
+/// How long a lookup is cached. Changes made through the api empty the cache
+/// straight away, this only bounds how long another process's change takes.
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Secrets cached at most, the cache is emptied when it's full so a caller
+/// trying random keys can't grow it.
+const CACHE_MAX: usize = 10_000;
+
+/// The paths anyone can call with `REQUIRE_API_KEY` on: probes, scrapes and
+/// the api's own description.
+const OPEN: &[&str] = &[
+    routes::HEALTHZ,
+    routes::READYZ,
+    routes::METRICS,
+    routes::OPENAPI,
+    routes::ERROR_CODES,
+    routes::SCHEMA,
+];
+
+/// The posts that only read, and need no more than `read`.
+const READ_POSTS: &[&str] = &[routes::ORDER_SEARCH, routes::ORDER_PREVIEW_TOTALS];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Scope {
+    /// The scope a request needs, by its method and the route's template.
+    pub fn needed(method: &Method, path: &str) -> Self {
+        if path == "/admin" || path.starts_with("/admin/") {
+            Scope::Admin
+        } else if method == Method::GET || method == Method::HEAD || READ_POSTS.contains(&path) {
+            Scope::Read
+        } else {
+            Scope::Write
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        })
+    }
+}
+
+/// A stored key, without its secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    /// The first characters of the current secret.
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: String,
+    /// When the key stops working, `None` for never.
+    pub expires_at: Option<String>,
+    pub revoked_at: Option<String>,
+    /// Until when the secret the last rotation replaced still works.
+    pub previous_expires_at: Option<String>,
+    pub rotated_at: Option<String>,
+}
+
+/// `api_keys` as it's stored, scopes and all.
+struct Row {
+    id: i64,
+    name: String,
+    prefix: String,
+    scopes: String,
+    created_at: String,
+    expires_at: Option<String>,
+    revoked_at: Option<String>,
+    previous_expires_at: Option<String>,
+    rotated_at: Option<String>,
+}
+
+impl From<Row> for ApiKey {
+    fn from(row: Row) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            prefix: row.prefix,
+            // a scope this version doesn't know grants nothing
+            scopes: serde_json::from_str::<Vec<serde_json::Value>>(&row.scopes)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|scope| serde_json::from_value(scope).ok())
+                .collect(),
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            revoked_at: row.revoked_at,
+            previous_expires_at: row.previous_expires_at,
+            rotated_at: row.rotated_at,
+        }
+    }
+}
+
+/// A new random secret.
+pub fn generate_secret() -> String {
+    format!("{SECRET_PREFIX}{}", hex::encode(rand::random::<[u8; 24]>()))
+}
+
+/// What's stored for `secret`.
+fn hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn prefix(secret: &str) -> String {
+    secret.chars().take(PREFIX_LEN).collect()
+}
+
+impl ApiKey {
+    /// Stores a key whose secret is `secret`, generated by the caller so it
+    /// can be shown once.
+    pub async fn create(
+        db: &Db,
+        name: &str,
+        scopes: &[Scope],
+        expires_at: Option<&str>,
+        secret: &str,
+    ) -> Result<Self> {
+        let (prefix, hash) = (prefix(secret), hash(secret));
+        let scopes = serde_json::to_string(scopes)?;
+        let now = clock::now();
+
+        let id = sqlx::query!(
+            "insert into api_keys (name, prefix, secret_hash, scopes, created_at, expires_at)
+            values (?, ?, ?, ?, ?, ?)",
+            name,
+            prefix,
+            hash,
+            scopes,
+            now,
+            expires_at
+        )
+        .execute(db.writer())
+        .timed()
+        .await?
+        .last_insert_rowid();
+
+        Ok(Self::get_by_id(db, id)
+            .await?
+            .expect("the key was just inserted"))
+    }
+
+    pub async fn get_by_id(db: &Db, id: i64) -> Result<Option<Self>> {
+        let row = sqlx::query_as!(
+            Row,
+            r#"select id as "id!", name, prefix, scopes, created_at, expires_at, revoked_at,
+                previous_expires_at, rotated_at
+            from api_keys where id = ?"#,
+            id
+        )
+        .fetch_optional(db.reader())
+        .timed()
+        .await?;
+
+        Ok(row.map(Self::from))
+    }
+
+    pub async fn get_all(db: &Db) -> Result<Vec<Self>> {
+        let rows = db::read(|| {
+            sqlx::query_as!(
+                Row,
+                r#"select id as "id!", name, prefix, scopes, created_at, expires_at, revoked_at,
+                    previous_expires_at, rotated_at
+                from api_keys order by id"#
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?;
+
+        Ok(rows.into_iter().map(Self::from).collect())
+    }
+
+    /// Stops the key working, both its secrets. Revoking it again keeps the
+    /// first `revoked_at`.
+    pub async fn revoke(db: &Db, id: i64) -> Result<Option<Self>> {
+        let now = clock::now();
+
+        sqlx::query!(
+            "update api_keys set revoked_at = coalesce(revoked_at, ?) where id = ?",
+            now,
+            id
+        )
+        .execute(db.writer())
+        .timed()
+        .await?;
+
+        Self::get_by_id(db, id).await
+    }
+
+    /// Gives the key `secret` as its secret, the one it replaces working
+    /// until `overlap_until`. A secret an earlier rotation replaced stops
+    /// working now, only the last one overlaps.
+    pub async fn rotate(&mut self, db: &Db, secret: &str, overlap_until: &str) -> Result<()> {
+        let (prefix, hash) = (prefix(secret), hash(secret));
+        let now = clock::now();
+
+        sqlx::query!(
+            "update api_keys set previous_secret_hash = secret_hash, previous_expires_at = ?,
+                secret_hash = ?, prefix = ?, rotated_at = ?
+            where id = ?",
+            overlap_until,
+            hash,
+            prefix,
+            now,
+            self.id
+        )
+        .execute(db.writer())
+        .timed()
+        .await?;
+
+        self.prefix = prefix;
+        self.previous_expires_at = Some(overlap_until.to_string());
+        self.rotated_at = Some(now);
+
+        Ok(())
+    }
+
+    /// Whether a key is past its `expires_at` at `now`.
+    pub fn expired(&self, now: &str) -> bool {
+        self.expires_at.as_deref().is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Creates a key with every scope from `BOOTSTRAP_API_KEY`, when there are no
+/// keys at all, so a new deployment with `REQUIRE_API_KEY` on has a way in.
+/// Returns whether it did.
+pub async fn bootstrap(db: &Db, secret: &str) -> Result<bool> {
+    let (prefix, hash) = (prefix(secret), hash(secret));
+    let scopes = serde_json::to_string(&[Scope::Read, Scope::Write, Scope::Admin])?;
+    let now = clock::now();
+
+    let result = sqlx::query!(
+        "insert into api_keys (name, prefix, secret_hash, scopes, created_at)
+        select 'bootstrap', ?, ?, ?, ? where not exists (select 1 from api_keys)",
+        prefix,
+        hash,
+        scopes,
+        now
+    )
+    .execute(db.writer())
+    .timed()
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// The key a secret belongs to, and whether it's the secret a rotation
+/// replaced.
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub key: ApiKey,
+    pub previous: bool,
+}
+
+impl Found {
+    /// Why the secret doesn't work at `now`, `None` when it does.
+    pub fn refused(&self, now: &str) -> Option<&'static str> {
+        if self.key.revoked_at.is_some() {
+            Some("the api key was revoked")
+        } else if self.key.expired(now) {
+            Some("the api key has expired")
+        } else if self.previous
+            && self.key.previous_expires_at.as_deref().is_none_or(|until| until <= now)
+        {
+            Some("the api key was rotated, this secret no longer works")
+        } else {
+            None
+        }
+    }
+}
+
+/// Lookups of secrets by their hash, unknown ones included.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    cache: Mutex<HashMap<String, (Instant, Option<Found>)>>,
+    pub(crate) lookups: Lookups,
+}
+
+impl ApiKeys {
+    /// The key `secret` belongs to, if any.
+    pub async fn find(&self, db: &Db, secret: &str) -> Result<Option<Found>> {
+        let hash = hash(secret);
+
+        if let Some((at, found)) = self.cache.lock().unwrap().get(&hash)
+            && at.elapsed() < CACHE_TTL
+        {
+            self.lookups.record(true);
+            return Ok(found.clone());
+        }
+
+        self.lookups.record(false);
+
+        let row = db::read(|| {
+            sqlx::query!(
+                r#"select id as "id!", name, prefix, scopes, created_at, expires_at, revoked_at,
+                    previous_expires_at, rotated_at, secret_hash != ?1 as "previous!: bool"
+                from api_keys where secret_hash = ?1 or previous_secret_hash = ?1
+                order by secret_hash = ?1 desc limit 1"#,
+                hash
+            )
+            .fetch_optional(db.reader())
+            .timed()
+        })
+        .await?;
+
+        let found = row.map(|row| Found {
+            previous: row.previous,
+            key: ApiKey::from(Row {
+                id: row.id,
+                name: row.name,
+                prefix: row.prefix,
+                scopes: row.scopes,
+                created_at: row.created_at,
+                expires_at: row.expires_at,
+                revoked_at: row.revoked_at,
+                previous_expires_at: row.previous_expires_at,
+                rotated_at: row.rotated_at,
+            }),
+        });
+
+        let mut cache = self.cache.lock().unwrap();
+
+        if cache.len() >= CACHE_MAX {
+            cache.clear();
+        }
+
+        cache.insert(hash, (Instant::now(), found.clone()));
+
+        Ok(found)
+    }
+
+    /// How many lookups are kept.
+    pub fn held(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+
+    /// Forgets every lookup, after a key changed.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+/// Who a request is from, for everything that records or counts requests by
+/// caller. With `REQUIRE_API_KEY` on a known key is `key_<id>`, which also
+/// lasts through a rotation, and anything else is `unknown_` and the start of
+/// its hash. With it off the header is a plain name for quotas to go by and
+/// is kept as it is, unless it looks like a secret.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller(pub String);
+
+impl Caller {
+    fn of(header: &str, found: Option<&Found>) -> Self {
+        match found {
+            Some(found) => Caller(format!("key_{}", found.key.id)),
+            None => Caller(format!("unknown_{}", &hash(header)[..12])),
+        }
+    }
+
+    /// `bootstrap` being `BOOTSTRAP_API_KEY`, a secret without the prefix.
+    fn named(header: &str, bootstrap: Option<&str>) -> Self {
+        match header.starts_with(SECRET_PREFIX) || bootstrap == Some(header) {
+            true => Self::of(header, None),
+            false => Caller(header.to_string()),
+        }
+    }
+
+    /// The caller `identify` found for a request, by its extensions.
+    pub fn name(extensions: &Extensions) -> Option<String> {
+        extensions.get::<Caller>().map(|Caller(name)| name.clone())
+    }
+}
+
+/// The lookup `identify` made, so `require` doesn't make it again.
+#[derive(Clone)]
+struct Lookup(Option<Found>);
+
+/// Puts the request's `Caller` in its extensions when it has an `X-Api-Key`,
+/// for every layer and handler inside. A caller that's already there, put by
+/// a journal replay, is kept.
+pub(crate) async fn identify(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    if request.extensions().get::<Caller>().is_some() {
+        return next.run(request).await;
+    }
+
+    let Some(header) = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+    else {
+        return next.run(request).await;
+    };
+
+    let config = state.config();
+
+    if !config.require_api_key {
+        let caller = Caller::named(&header, config.bootstrap_api_key.as_deref());
+        request.extensions_mut().insert(caller);
+
+        return next.run(request).await;
+    }
+
+    let found = state.api_keys.find(&state.db, &header).await;
+
+    request
+        .extensions_mut()
+        .insert(Caller::of(&header, found.as_ref().ok().and_then(Option::as_ref)));
+
+    // a failed lookup is made again, and answered, by `require`
+    if let Ok(found) = found {
+        request.extensions_mut().insert(Lookup(found));
+    }
+
+    next.run(request).await
+}
+
+/// Refuses requests without a working key with the scope they need, when
+/// `REQUIRE_API_KEY` is on: a 401 for a missing, unknown, expired or revoked
+/// key and a 403 for one without the scope. The `OPEN` paths are let through.
+pub(crate) async fn require(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.config().require_api_key {
+        return next.run(request).await;
+    }
+
+    // a path that isn't routed still needs a key, so it can't be told apart
+    // from one that is
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(request.uri().path(), MatchedPath::as_str);
+
+    if OPEN.contains(&path) {
+        return next.run(request).await;
+    }
+
+    let needed = Scope::needed(request.method(), path);
+
+    let Some(secret) = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return CustomError::Unauthorized("an X-Api-Key header is required".to_string()).into_response();
+    };
+
+    let found = match request.extensions().get::<Lookup>() {
+        Some(Lookup(found)) => found.clone(),
+        None => match state.api_keys.find(&state.db, secret).await {
+            Ok(found) => found,
+            Err(err) => return CustomError::from(err).into_response(),
+        },
+    };
+
+    let Some(found) = found else {
+        return CustomError::Unauthorized("the api key isn't known".to_string()).into_response();
+    };
+
+    if let Some(reason) = found.refused(&clock::timestamp(state.clock.now())) {
+        return CustomError::Unauthorized(reason.to_string()).into_response();
+    }
+
+    if !found.key.scopes.contains(&needed) {
+        return CustomError::Forbidden(format!("the api key doesn't have the {needed} scope"))
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::test_db;
+
+    use super::*;
+
+    #[test]
+    fn test_scope_needed() {
+        assert_eq!(Scope::needed(&Method::GET, routes::ORDER), Scope::Read);
+        assert_eq!(Scope::needed(&Method::POST, routes::ORDER_SEARCH), Scope::Read);
+        assert_eq!(Scope::needed(&Method::POST, routes::ORDERS), Scope::Write);
+        assert_eq!(Scope::needed(&Method::DELETE, routes::ORDER), Scope::Write);
+        assert_eq!(Scope::needed(&Method::GET, routes::USAGE), Scope::Admin);
+        assert_eq!(Scope::needed(&Method::GET, routes::ADMIN_UI), Scope::Admin);
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_only_fills_an_empty_table() {
+        let db = test_db().await;
+        let keys = ApiKeys::default();
+        let secret = generate_secret();
+
+        assert!(bootstrap(&db, &secret).await.unwrap());
+        assert!(!bootstrap(&db, &generate_secret()).await.unwrap());
+
+        let found = keys.find(&db, &secret).await.unwrap().unwrap();
+        assert_eq!(found.key.name, "bootstrap");
+        assert_eq!(found.key.scopes, [Scope::Read, Scope::Write, Scope::Admin]);
+        assert_eq!(ApiKey::get_all(&db).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_only_a_hash_of_the_secret_is_stored() {
+        let db = test_db().await;
+        let secret = generate_secret();
+
+        let key = ApiKey::create(&db, "ci", &[Scope::Read], None, &secret).await.unwrap();
+        assert!(secret.starts_with(&key.prefix));
+
+        let stored: String = sqlx::query_scalar("select secret_hash from api_keys where id = ?")
+            .bind(key.id)
+            .fetch_one(db.reader())
+            .await
+            .unwrap();
+        assert_eq!(stored, hash(&secret));
+        assert!(!stored.contains(&secret[SECRET_PREFIX.len()..]));
+    }
+
+    #[test]
+    fn test_refused_after_expiry() {
+        let found = Found {
+            key: ApiKey {
+                id: 1,
+                name: "ci".to_string(),
+                prefix: "spk_12345678".to_string(),
+                scopes: vec![Scope::Read],
+                created_at: "2025-10-01T00:00:00.000Z".to_string(),
+                expires_at: Some("2025-10-02T00:00:00.000Z".to_string()),
+                revoked_at: None,
+                previous_expires_at: Some("2025-10-01T12:00:00.000Z".to_string()),
+                rotated_at: Some("2025-10-01T11:00:00.000Z".to_string()),
+            },
+            previous: true,
+        };
+
+        assert_eq!(found.refused("2025-10-01T11:59:59.999Z"), None);
+        assert!(found.refused("2025-10-01T12:00:00.000Z").is_some());
+
+        let current = Found {
+            previous: false,
+            ..found
+        };
+        assert_eq!(current.refused("2025-10-01T23:59:59.999Z"), None);
+        assert_eq!(
+            current.refused("2025-10-02T00:00:00.000Z"),
+            Some("the api key has expired")
+        );
+    }
+}
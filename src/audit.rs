@@ -0,0 +1,314 @@
+//! Tamper evidence for `order_changes`, the audit log of every change made to
+//! an order. Each row keeps the `row_hash` of the row before it in `prev_hash`,
+//! and its own `row_hash` is the sha-256 of that and the row's columns. Editing,
+//! removing or slipping in a row with raw sql breaks the chain there, unless
+//! every hash after it is rewritten too, and `verify` finds the first break.
+//!
+//! Removing rows from the end leaves a shorter chain that still verifies, so
+//! the `last_hash` a verification reports is worth keeping somewhere the
+//! database's users can't write to.
+//!
+//! The retention policy can prune the oldest rows, see `retention/tables.rs`.
+//! It keeps the `row_hash` of the last row it pruned in `audit_chain_anchors`,
+//! and the chain starts from that instead of `GENESIS`.
+
+use anyhow::Result;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, SqliteExecutor, Transaction, prelude::FromRow};
+
+use crate::{
+    db::{self, Db},
+    timing::Timed,
+};
+
+/// The sequence taken before a row is chained, see `link`.
+pub const SEQUENCE: &str = "audit_chain";
+/// The `prev_hash` of the first row in the chain.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Rows read per query by `verify`.
+const VERIFY_BATCH_SIZE: i64 = 1000;
+
+#[derive(Debug, Clone, FromRow)]
+struct Row {
+    id: i64,
+    order_id: i64,
+    field: String,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    changed_at: String,
+    prev_hash: Option<String>,
+    row_hash: Option<String>,
+}
+
+impl Row {
+    /// The hash of `prev_hash` followed by the row's columns as a json array.
+    fn hash(&self, prev_hash: &str) -> String {
+        let columns = json!([
+            self.id,
+            self.order_id,
+            self.field,
+            self.old_value,
+            self.new_value,
+            self.changed_at,
+        ]);
+
+        let mut hasher = Sha256::new();
+        hasher.update(prev_hash);
+        hasher.update(columns.to_string());
+
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Chains the `order_changes` row `id`, just inserted in `tx`, onto the last
+/// chained row. The sequence is taken first, which holds sqlite's write lock
+/// for the rest of the transaction, so rows are chained one at a time and two
+/// can't both follow the same one.
+pub async fn link(tx: &mut Transaction<'_, Sqlite>, id: i64) -> Result<()> {
+    db::next_value(tx, SEQUENCE).await?;
+
+    let row = sqlx::query_as!(Row, "select * from order_changes where id = ?", id)
+        .fetch_one(&mut **tx)
+        .timed()
+        .await?;
+
+    let prev_hash = sqlx::query_scalar!(
+        r#"select row_hash as "row_hash!" from order_changes
+        where id < ? and row_hash is not null order by id desc limit 1"#,
+        id
+    )
+    .fetch_optional(&mut **tx)
+    .timed()
+    .await?;
+
+    let prev_hash = match prev_hash {
+        Some(prev_hash) => prev_hash,
+        None => anchor(&mut **tx).await?.unwrap_or_else(|| GENESIS.to_string()),
+    };
+
+    let row_hash = row.hash(&prev_hash);
+
+    sqlx::query!(
+        "update order_changes set prev_hash = ?, row_hash = ? where id = ?;",
+        prev_hash,
+        row_hash,
+        id
+    )
+    .execute(&mut **tx)
+    .timed()
+    .await?;
+
+    Ok(())
+}
+
+/// The `row_hash` of the last pruned row, which the chain starts from when
+/// there is one.
+pub async fn anchor(executor: impl SqliteExecutor<'_>) -> sqlx::Result<Option<String>> {
+    sqlx::query_scalar!("select last_hash from audit_chain_anchors order by last_id desc limit 1")
+        .fetch_optional(executor)
+        .timed()
+        .await
+}
+
+/// What `verify` found.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// Chained rows checked, up to and including the first break.
+    pub checked: i64,
+    /// Rows from before the chain started, which have no hashes.
+    pub unchained: i64,
+    /// The `row_hash` of the last row, when the chain is intact.
+    pub last_hash: Option<String>,
+    pub first_break: Option<Break>,
+}
+
+impl Verification {
+    pub fn is_intact(&self) -> bool {
+        self.first_break.is_none()
+    }
+}
+
+/// The first row the chain doesn't hold for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Break {
+    pub id: i64,
+    pub reason: String,
+}
+
+/// Walks the chain from its first row, stopping at the first break.
+pub async fn verify(db: &Db) -> Result<Verification> {
+    let mut verification = Verification::default();
+    let mut prev_hash = db::read(|| anchor(db.reader())).await?;
+    let mut after = 0;
+
+    loop {
+        let rows = db::read(|| {
+            sqlx::query_as!(
+                Row,
+                "select * from order_changes where id > ? order by id limit ?",
+                after,
+                VERIFY_BATCH_SIZE
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?;
+
+        let Some(last) = rows.last() else {
+            verification.last_hash = prev_hash;
+            return Ok(verification);
+        };
+        after = last.id;
+
+        for row in rows {
+            let Some(row_hash) = &row.row_hash else {
+                if prev_hash.is_none() {
+                    verification.unchained += 1;
+                    continue;
+                }
+
+                verification.first_break = Some(Break {
+                    id: row.id,
+                    reason: "row_hash is missing after the chain started".to_string(),
+                });
+
+                return Ok(verification);
+            };
+
+            let expected = prev_hash.as_deref().unwrap_or(GENESIS);
+            verification.checked += 1;
+
+            let reason = if row.prev_hash.as_deref() != Some(expected) {
+                "prev_hash isn't the row_hash of the row before it, a row was removed or inserted"
+            } else if *row_hash != row.hash(expected) {
+                "row_hash doesn't match the row, it was changed"
+            } else {
+                prev_hash = Some(row_hash.clone());
+                continue;
+            };
+
+            verification.first_break = Some(Break {
+                id: row.id,
+                reason: reason.to_string(),
+            });
+
+            return Ok(verification);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, orders::Order};
+
+    use super::*;
+
+    async fn orders_with_changes(db: &Db) -> Vec<i64> {
+        let mut order = Order::new(500);
+        order.save(db.writer()).await.unwrap();
+
+        for amount in [600, 700, 800] {
+            order.amount = amount;
+            order.save(db.writer()).await.unwrap();
+        }
+
+        sqlx::query_scalar("select id from order_changes order by id")
+            .fetch_all(db.reader())
+            .await
+            .unwrap()
+    }
+
+    fn reason(verification: &Verification) -> (i64, &str) {
+        let found = verification.first_break.as_ref().expect("the chain should be broken");
+
+        (found.id, found.reason.as_str())
+    }
+
+    #[tokio::test]
+    async fn test_intact_chain() {
+        let db = test_db().await;
+        let ids = orders_with_changes(&db).await;
+
+        let verification = verify(&db).await.unwrap();
+
+        assert!(verification.is_intact());
+        assert_eq!(verification.checked, 3);
+        assert_eq!(verification.unchained, 0);
+
+        let last: String = sqlx::query_scalar("select row_hash from order_changes where id = ?")
+            .bind(ids[2])
+            .fetch_one(db.reader())
+            .await
+            .unwrap();
+        assert_eq!(verification.last_hash, Some(last));
+
+        let first: String = sqlx::query_scalar("select prev_hash from order_changes where id = ?")
+            .bind(ids[0])
+            .fetch_one(db.reader())
+            .await
+            .unwrap();
+        assert_eq!(first, GENESIS);
+    }
+
+    #[tokio::test]
+    async fn test_finds_a_changed_row() {
+        let db = test_db().await;
+        let ids = orders_with_changes(&db).await;
+
+        sqlx::query("update order_changes set new_value = '10000' where id = ?")
+            .bind(ids[1])
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        let verification = verify(&db).await.unwrap();
+
+        assert_eq!(reason(&verification), (ids[1], "row_hash doesn't match the row, it was changed"));
+        assert_eq!(verification.checked, 2);
+        assert_eq!(verification.last_hash, None);
+    }
+
+    #[tokio::test]
+    async fn test_finds_a_removed_row() {
+        let db = test_db().await;
+        let ids = orders_with_changes(&db).await;
+
+        sqlx::query("delete from order_changes where id = ?")
+            .bind(ids[1])
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        let verification = verify(&db).await.unwrap();
+
+        assert_eq!(reason(&verification).0, ids[2]);
+        assert!(reason(&verification).1.starts_with("prev_hash isn't the row_hash of the row before it"));
+    }
+
+    #[tokio::test]
+    async fn test_rows_from_before_the_chain() {
+        let db = test_db().await;
+
+        sqlx::query("insert into order_changes (order_id, field, changed_at) values (1, 'tracking_started', '2025-09-29')")
+            .execute(db.writer())
+            .await
+            .unwrap();
+        let ids = orders_with_changes(&db).await;
+
+        let verification = verify(&db).await.unwrap();
+        assert!(verification.is_intact());
+        assert_eq!((verification.checked, verification.unchained), (3, 1));
+
+        // a chained row can't drop out of the chain
+        sqlx::query("update order_changes set row_hash = null where id = ?")
+            .bind(ids[2])
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        let verification = verify(&db).await.unwrap();
+        assert_eq!(reason(&verification), (ids[2], "row_hash is missing after the chain started"));
+    }
+}
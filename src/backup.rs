@@ -0,0 +1,269 @@
+//! Consistent copies of the database and restoring from them. Backups use
+//! `VACUUM INTO`, which reads inside one transaction, so they're safe to take
+//! while the api is writing. Restores swap the file underneath the api, so they
+//! refuse to run while a server holds the database's lock file.
+
+use std::{
+    fs::{self, File, TryLockError},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use sqlx::{
+    ConnectOptions,
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+};
+
+use crate::{
+    db::{self, Db},
+    timing::Timed,
+};
+
+/// A backup that was written, and how big it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+}
+
+/// Held by a running server for as long as it serves, so a restore can tell
+/// the database is in use. The OS drops the lock if the process dies.
+#[derive(Debug)]
+pub struct ServerLock {
+    _file: File,
+}
+
+impl ServerLock {
+    /// Locks `<database file>.lock`, failing if another process has it.
+    pub fn acquire(database: &Path) -> Result<Self> {
+        let path = with_suffix(database, ".lock");
+        let file = File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => bail!(
+                "{} is in use by a running server, stop it first",
+                database.display()
+            ),
+            Err(TryLockError::Error(err)) => {
+                Err(err).with_context(|| format!("locking {}", path.display()))
+            }
+        }
+    }
+}
+
+/// The file behind a database url, `None` for an in-memory database.
+pub fn database_file(database_url: &str) -> Result<Option<PathBuf>> {
+    if database_url.contains(":memory:") || database_url.contains("mode=memory") {
+        return Ok(None);
+    }
+
+    let options = SqliteConnectOptions::from_str(database_url)
+        .with_context(|| format!("reading database url {database_url}"))?;
+
+    Ok(Some(options.get_filename().to_path_buf()))
+}
+
+/// Writes a consistent copy of the database to `to`, which mustn't exist yet.
+pub async fn backup(db: &Db, to: &Path) -> Result<Backup> {
+    if to.exists() {
+        bail!("{} already exists", to.display());
+    }
+
+    let path = to.to_str().context("backup paths have to be utf-8")?;
+
+    sqlx::query("VACUUM INTO ?")
+        .bind(path)
+        .execute(db.writer())
+        .timed()
+        .await
+        .with_context(|| format!("backing up to {path}"))?;
+
+    let size_bytes = fs::metadata(to)?.len();
+
+    Ok(Backup {
+        path: to.to_path_buf(),
+        size_bytes,
+    })
+}
+
+/// Backs up into `dir`, named by the time it was taken.
+pub async fn backup_to_dir(db: &Db, dir: &Path) -> Result<Backup> {
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+
+    backup(db, &dir.join(format!("backup-{millis}.sqlite"))).await
+}
+
+/// Checks `path` is a database this api made, migrated no further than the
+/// migrations it knows, so it can be started against after a restore.
+pub async fn check_backup(path: &Path) -> Result<()> {
+    let mut connection: SqliteConnection = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .connect()
+        .await
+        .with_context(|| format!("opening {}", path.display()))?;
+
+    let versions: Vec<i64> =
+        sqlx::query_scalar("select version from _sqlx_migrations where success order by version")
+            .fetch_all(&mut connection)
+            .await
+            .with_context(|| format!("{} has no migration history", path.display()))?;
+
+    let known = db::migration_versions();
+
+    if let Some(unknown) = versions.iter().find(|version| !known.contains(version)) {
+        bail!(
+            "{} has migration {unknown}, which this build doesn't know, it was made by a newer version",
+            path.display()
+        );
+    }
+
+    if versions.is_empty() {
+        bail!("{} has no migrations applied", path.display());
+    }
+
+    Ok(())
+}
+
+/// Replaces the database at `database` with the backup at `from`, keeping the
+/// replaced file as `<database>.before-restore`. Refuses while a server holds
+/// the database's lock, and holds it itself so none starts part way.
+pub async fn restore(database: &Path, from: &Path) -> Result<()> {
+    let _lock = ServerLock::acquire(database)?;
+
+    check_backup(from).await?;
+
+    // copied next to the database first so the swap is a rename
+    let incoming = with_suffix(database, ".restoring");
+    fs::copy(from, &incoming).with_context(|| format!("copying {}", from.display()))?;
+
+    let previous = with_suffix(database, ".before-restore");
+
+    for suffix in ["", "-wal", "-shm"] {
+        let file = with_suffix(database, suffix);
+
+        if file.exists() {
+            fs::rename(&file, with_suffix(&previous, suffix))
+                .with_context(|| format!("moving {} aside", file.display()))?;
+        }
+    }
+
+    fs::rename(&incoming, database).with_context(|| format!("replacing {}", database.display()))?;
+
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::orders::Order;
+
+    use super::*;
+
+    /// An empty scratch directory under the system temp dir.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    async fn order_count(path: &Path) -> i64 {
+        let mut connection: SqliteConnection = SqliteConnectOptions::new()
+            .filename(path)
+            .read_only(true)
+            .connect()
+            .await
+            .unwrap();
+
+        sqlx::query_scalar("select count(*) from orders")
+            .fetch_one(&mut connection)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backup_is_a_readable_copy() {
+        // not `test_db`, `VACUUM INTO` from an in-memory database writes to memory too
+        let dir = scratch("backup");
+        let db = db::setup_db(&format!("sqlite:{}", dir.join("db.sqlite").display()), None)
+            .await
+            .unwrap();
+
+        for amount in [500, 700, 900] {
+            Order::new(amount).save(db.writer()).await.unwrap();
+        }
+
+        let backup = backup_to_dir(&db, &dir.join("backups")).await.unwrap();
+
+        assert!(backup.size_bytes > 0);
+        assert_eq!(order_count(&backup.path).await, 3);
+        check_backup(&backup.path).await.unwrap();
+
+        assert!(super::backup(&db, &backup.path).await.is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let dir = scratch("restore");
+        let database = dir.join("db.sqlite");
+        let url = format!("sqlite:{}", database.display());
+
+        let db = db::setup_db(&url, None).await.unwrap();
+        Order::new(500).save(db.writer()).await.unwrap();
+        let backup = super::backup(&db, &dir.join("backup.sqlite"))
+            .await
+            .unwrap();
+        Order::new(700).save(db.writer()).await.unwrap();
+        db.close().await;
+
+        // refused while a server has the database
+        let lock = ServerLock::acquire(&database).unwrap();
+        let err = restore(&database, &backup.path).await.unwrap_err();
+        assert!(err.to_string().contains("in use by a running server"));
+        drop(lock);
+
+        // refused when the file isn't one of ours
+        let stranger = dir.join("stranger.sqlite");
+        db::setup_db(&format!("sqlite:{}", stranger.display()), None)
+            .await
+            .unwrap()
+            .close()
+            .await;
+        let mut connection: SqliteConnection = SqliteConnectOptions::new()
+            .filename(&stranger)
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query("drop table _sqlx_migrations")
+            .execute(&mut connection)
+            .await
+            .unwrap();
+        drop(connection);
+        assert!(restore(&database, &stranger).await.is_err());
+
+        restore(&database, &backup.path).await.unwrap();
+
+        assert_eq!(order_count(&database).await, 1);
+        assert_eq!(
+            order_count(&with_suffix(&database, ".before-restore")).await,
+            2
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! Live fan-out of order events to subscribers, which is what backs
+//! `/events/stream`. `feed` publishes events from the table once they're
+//! committed. The channel is bounded: a subscriber more than `capacity` events
+//! behind misses the oldest ones, and that's counted and reported to it as a
+//! gap rather than dropped silently.
+//!
+//! Each event goes out with its order as it was when the event was published,
+//! loaded once for every subscriber.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    AppState,
+    db::{self, Db},
+    events::OrderEvent,
+    orders::Order,
+    timing::Timed,
+};
+
+/// Events a subscriber can fall behind by when `EVENT_BUS_CAPACITY` isn't set.
+pub const DEFAULT_CAPACITY: usize = 1024;
+/// Events read from the table per pass of `feed`.
+const FEED_BATCH_SIZE: i64 = 500;
+/// How long `feed` sleeps when it's caught up.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<Published>>,
+    capacity: usize,
+    next_subscriber: AtomicU64,
+    /// Events missed by each current subscriber, by subscriber id.
+    missed: Mutex<BTreeMap<u64, u64>>,
+    /// Events missed by every subscriber since startup, gone ones included.
+    missed_total: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BusStats {
+    pub capacity: usize,
+    pub subscribers: usize,
+    pub missed_total: u64,
+    /// Events missed by each current subscriber, by subscriber id.
+    pub missed: BTreeMap<u64, u64>,
+}
+
+/// An event and its order, `None` when the order is gone.
+#[derive(Debug)]
+pub struct Published {
+    pub event: OrderEvent,
+    pub order: Option<Order>,
+}
+
+/// What a subscriber gets next.
+#[derive(Debug)]
+pub enum Received {
+    Event(Arc<Published>),
+    /// This many events were dropped before the subscriber got to them.
+    Gap(u64),
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+
+        Self {
+            sender,
+            capacity,
+            next_subscriber: AtomicU64::new(1),
+            missed: Mutex::default(),
+            missed_total: AtomicU64::new(0),
+        }
+    }
+
+    /// Sends `event` and its order to every current subscriber, returning how
+    /// many there were.
+    pub fn publish(&self, event: OrderEvent, order: Option<Order>) -> usize {
+        self.sender.send(Arc::new(Published { event, order })).unwrap_or(0)
+    }
+
+    /// Receives events published from now on.
+    pub fn subscribe(self: &Arc<Self>) -> Subscription {
+        let id = self.next_subscriber.fetch_add(1, Ordering::Relaxed);
+
+        self.missed.lock().unwrap().insert(id, 0);
+
+        Subscription {
+            id,
+            receiver: self.sender.subscribe(),
+            bus: self.clone(),
+        }
+    }
+
+    pub fn stats(&self) -> BusStats {
+        BusStats {
+            capacity: self.capacity,
+            subscribers: self.sender.receiver_count(),
+            missed_total: self.missed_total.load(Ordering::Relaxed),
+            missed: self.missed.lock().unwrap().clone(),
+        }
+    }
+}
+
+pub struct Subscription {
+    id: u64,
+    receiver: broadcast::Receiver<Arc<Published>>,
+    bus: Arc<EventBus>,
+}
+
+impl Subscription {
+    /// The next event, or the gap left by the ones dropped since the last
+    /// call. `None` once the bus is gone.
+    pub async fn recv(&mut self) -> Option<Received> {
+        match self.receiver.recv().await {
+            Ok(event) => Some(Received::Event(event)),
+            Err(RecvError::Lagged(missed)) => {
+                *self.bus.missed.lock().unwrap().entry(self.id).or_default() += missed;
+                self.bus.missed_total.fetch_add(missed, Ordering::Relaxed);
+
+                Some(Received::Gap(missed))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.bus.missed.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Publishes events recorded after `after`, oldest first, and returns the id
+/// of the last one published. An order with several events in the batch is
+/// loaded once.
+pub async fn publish_recorded(db: &Db, bus: &EventBus, after: i64) -> Result<i64> {
+    let events = OrderEvent::get_after(db, after, FEED_BATCH_SIZE).await?;
+    let mut orders: HashMap<i64, Option<Order>> = HashMap::new();
+    let mut last = after;
+
+    for event in events {
+        let order = match orders.get(&event.order_id) {
+            Some(order) => order.clone(),
+            None => {
+                let order = Order::get_by_id(db, event.order_id).await?;
+                orders.insert(event.order_id, order.clone());
+                order
+            }
+        };
+
+        last = event.id.unwrap_or(last);
+        bus.publish(event, order);
+    }
+
+    Ok(last)
+}
+
+/// Publishes events as they're recorded, starting with the ones recorded
+/// after startup, until the process exits.
+pub async fn feed(state: AppState) {
+    let mut last = loop {
+        match db::read(|| {
+            sqlx::query_scalar!(r#"select max(id) as "max_id: i64" from order_events"#)
+                .fetch_one(state.db.reader())
+                .timed()
+        })
+        .await
+        {
+            Ok(max_id) => break max_id.unwrap_or(0),
+            Err(err) => tracing::error!("failed to find the latest order event: {err:#}"),
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+
+    loop {
+        match publish_recorded(&state.db, &state.events, last).await {
+            Ok(published) if published != last => {
+                last = published;
+                continue;
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("failed to publish order events: {err:#}"),
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::test_db,
+        events::EventKind,
+        orders::{Order, OrderStatus},
+    };
+
+    use super::*;
+
+    fn event(id: i64) -> OrderEvent {
+        OrderEvent {
+            id: Some(id),
+            order_id: 1,
+            kind: EventKind::Canceled,
+            detail: "canceled from pending".to_string(),
+            actor: None,
+            created_at: "2025-09-12T10:00:00.000Z".to_string(),
+        }
+    }
+
+    fn event_id(received: Option<Received>) -> i64 {
+        match received {
+            Some(Received::Event(published)) => published.event.id.unwrap(),
+            other => panic!("expected an event, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_gets_a_gap() {
+        let bus = Arc::new(EventBus::new(2));
+        let mut slow = bus.subscribe();
+
+        for id in 1..=5 {
+            bus.publish(event(id), None);
+        }
+
+        assert!(matches!(slow.recv().await, Some(Received::Gap(3))));
+        assert_eq!(event_id(slow.recv().await), 4);
+        assert_eq!(event_id(slow.recv().await), 5);
+
+        let stats = bus.stats();
+        assert_eq!(stats.subscribers, 1);
+        assert_eq!(stats.missed_total, 3);
+        assert_eq!(stats.missed.values().copied().collect::<Vec<_>>(), vec![3]);
+
+        drop(slow);
+
+        let stats = bus.stats();
+        assert_eq!(stats.subscribers, 0);
+        assert_eq!(stats.missed_total, 3);
+        assert!(stats.missed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_publishes_recorded_events() {
+        let db = test_db().await;
+        let bus = Arc::new(EventBus::new(DEFAULT_CAPACITY));
+        let mut subscription = bus.subscribe();
+
+        let mut order = Order::new(500);
+        order.save(db.writer()).await.unwrap();
+        order.status = OrderStatus::Canceled;
+        order
+            .save_with_history(&db, OrderStatus::Pending)
+            .await
+            .unwrap();
+
+        let last = publish_recorded(&db, &bus, 0).await.unwrap();
+        let Some(Received::Event(published)) = subscription.recv().await else {
+            panic!("expected an event");
+        };
+        assert_eq!(published.event.id, Some(last));
+        assert_eq!(published.order.as_ref().unwrap().status, OrderStatus::Canceled);
+
+        // nothing is published twice
+        assert_eq!(publish_recorded(&db, &bus, last).await.unwrap(), last);
+        assert_eq!(bus.stats().missed_total, 0);
+    }
+}
@@ -0,0 +1,334 @@
+//! The in-memory caches and how writes reach them. Code that changes orders,
+//! their tags or the api keys publishes an `Invalidation` on the
+//! `InvalidationBus` in `AppState`, and each cache registered on it handles
+//! the events it cares about in its `Cache::invalidate` below, so no write
+//! path has to know which caches there are.
+//!
+//! Writes that only touch bookkeeping no cache holds, pick assignments,
+//! reminders and the retention scrub, don't publish. Neither do the cli
+//! commands, which run in another process, the counts' reconcile and the
+//! stats' refresh interval catch up with those.
+//!
+//! `GET /admin/caches` has what `InvalidationBus::report` says about each.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::{
+    api_keys::ApiKeys,
+    clock,
+    counts::StatusCounts,
+    orders::{Order, OrderStatus},
+    stats::Precomputed,
+};
+
+/// A change a cache may be holding an older copy of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidation {
+    /// One order was created, changed or deleted. `from` is its status
+    /// before, `None` for a create, and `to` after, `None` for a delete.
+    Order {
+        id: i64,
+        from: Option<OrderStatus>,
+        to: Option<OrderStatus>,
+    },
+    /// Any number of orders changed, by an import or a background task.
+    Orders,
+    /// Tags were added to or removed from orders.
+    Tags,
+    /// An api key was created, revoked or rotated.
+    Keys,
+}
+
+impl Invalidation {
+    pub fn created(order: &Order) -> Self {
+        Self::Order {
+            id: order.id.unwrap_or_default(),
+            from: None,
+            to: Some(order.status),
+        }
+    }
+
+    /// `order` was saved, in `from` before.
+    pub fn moved(order: &Order, from: OrderStatus) -> Self {
+        Self::Order {
+            id: order.id.unwrap_or_default(),
+            from: Some(from),
+            to: Some(order.status),
+        }
+    }
+
+    /// `order` was saved without changing its status.
+    pub fn changed(order: &Order) -> Self {
+        Self::moved(order, order.status)
+    }
+
+    pub fn deleted(order: &Order) -> Self {
+        Self::Order {
+            id: order.id.unwrap_or_default(),
+            from: Some(order.status),
+            to: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Order { .. } => "order_changed",
+            Self::Orders => "orders_changed",
+            Self::Tags => "tags_changed",
+            Self::Keys => "keys_changed",
+        }
+    }
+}
+
+/// A cache the `InvalidationBus` keeps up to date.
+pub trait Cache: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// How many entries it holds right now.
+    fn entries(&self) -> usize;
+
+    fn lookups(&self) -> &Lookups;
+
+    /// Drops or adjusts whatever `event` made stale, returning whether it
+    /// concerned this cache at all.
+    fn invalidate(&self, event: &Invalidation) -> bool;
+}
+
+/// How often a cache had what was asked for.
+#[derive(Debug, Default)]
+pub struct Lookups {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Lookups {
+    pub fn record(&self, hit: bool) {
+        let counter = match hit {
+            true => &self.hits,
+            false => &self.misses,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// The last event a cache handled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalidated {
+    pub event: &'static str,
+    /// The way the timestamp columns hold it.
+    pub at: String,
+}
+
+/// What `GET /admin/caches` says about a cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheReport {
+    pub name: &'static str,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// How many published events concerned it.
+    pub invalidations: u64,
+    pub last_invalidation: Option<Invalidated>,
+}
+
+/// Hands every published `Invalidation` to each registered cache, in the
+/// publishing task, so the cache has dropped its copy before the write's
+/// response goes out.
+pub struct InvalidationBus {
+    caches: Vec<Arc<dyn Cache>>,
+    handled: Mutex<HashMap<&'static str, (u64, Invalidated)>>,
+}
+
+impl InvalidationBus {
+    pub fn new(caches: Vec<Arc<dyn Cache>>) -> Self {
+        Self {
+            caches,
+            handled: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn publish(&self, event: Invalidation) {
+        for cache in &self.caches {
+            if !cache.invalidate(&event) {
+                continue;
+            }
+
+            let invalidated = Invalidated {
+                event: event.name(),
+                at: clock::now(),
+            };
+
+            let mut handled = self.handled.lock().unwrap();
+            let entry = handled.entry(cache.name()).or_insert((0, invalidated.clone()));
+            *entry = (entry.0 + 1, invalidated);
+        }
+    }
+
+    /// Every registered cache, in the order they were registered.
+    pub fn report(&self) -> Vec<CacheReport> {
+        let handled = self.handled.lock().unwrap();
+
+        self.caches
+            .iter()
+            .map(|cache| {
+                let handled = handled.get(cache.name());
+
+                CacheReport {
+                    name: cache.name(),
+                    entries: cache.entries(),
+                    hits: cache.lookups().hits(),
+                    misses: cache.lookups().misses(),
+                    invalidations: handled.map_or(0, |(count, _)| *count),
+                    last_invalidation: handled.map(|(_, last)| last.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Adjusted in place for a single order, and recounted after anything bigger.
+impl Cache for StatusCounts {
+    fn name(&self) -> &'static str {
+        "order_counts"
+    }
+
+    fn entries(&self) -> usize {
+        self.snapshot().map_or(0, |counts| counts.len())
+    }
+
+    fn lookups(&self) -> &Lookups {
+        &self.lookups
+    }
+
+    fn invalidate(&self, event: &Invalidation) -> bool {
+        match *event {
+            Invalidation::Order { from, to, .. } if from != to => {
+                match (from, to) {
+                    (Some(from), Some(to)) => self.moved(from, to),
+                    (None, Some(to)) => self.created(to),
+                    (Some(from), None) => self.removed(from, 1),
+                    (None, None) => {}
+                }
+
+                true
+            }
+            Invalidation::Orders => {
+                self.mark_stale();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Any order change can move a total, the refresh task debounces them.
+impl Cache for Precomputed {
+    fn name(&self) -> &'static str {
+        "order_stats"
+    }
+
+    fn entries(&self) -> usize {
+        usize::from(self.get().is_some())
+    }
+
+    fn lookups(&self) -> &Lookups {
+        &self.lookups
+    }
+
+    fn invalidate(&self, event: &Invalidation) -> bool {
+        match event {
+            Invalidation::Order { .. } | Invalidation::Orders => {
+                self.request_refresh();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A key's row is looked up again after any key changed, so a revoke works on
+/// the next request.
+impl Cache for ApiKeys {
+    fn name(&self) -> &'static str {
+        "api_keys"
+    }
+
+    fn entries(&self) -> usize {
+        self.held()
+    }
+
+    fn lookups(&self) -> &Lookups {
+        &self.lookups
+    }
+
+    fn invalidate(&self, event: &Invalidation) -> bool {
+        match event {
+            Invalidation::Keys => {
+                self.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_each_cache_handles_only_its_events() {
+        let counts = Arc::new(StatusCounts::default());
+        let stats = Arc::new(Precomputed::default());
+        let keys = Arc::new(ApiKeys::default());
+        let bus = InvalidationBus::new(vec![counts.clone(), stats.clone(), keys.clone()]);
+
+        let handled = |bus: &InvalidationBus| -> Vec<u64> {
+            bus.report().iter().map(|cache| cache.invalidations).collect()
+        };
+
+        bus.publish(Invalidation::Order {
+            id: 1,
+            from: Some(OrderStatus::Pending),
+            to: Some(OrderStatus::Pending),
+        });
+        assert_eq!(handled(&bus), [0, 1, 0]);
+
+        bus.publish(Invalidation::Order {
+            id: 1,
+            from: Some(OrderStatus::Pending),
+            to: Some(OrderStatus::Canceled),
+        });
+        assert_eq!(handled(&bus), [1, 2, 0]);
+
+        bus.publish(Invalidation::Tags);
+        assert_eq!(handled(&bus), [1, 2, 0]);
+
+        bus.publish(Invalidation::Keys);
+        bus.publish(Invalidation::Orders);
+        assert_eq!(handled(&bus), [2, 3, 1]);
+
+        let report = bus.report();
+        assert_eq!(
+            report.iter().map(|cache| cache.name).collect::<Vec<_>>(),
+            ["order_counts", "order_stats", "api_keys"]
+        );
+        assert_eq!(report[0].last_invalidation.as_ref().unwrap().event, "orders_changed");
+        assert_eq!(report[2].last_invalidation.as_ref().unwrap().event, "keys_changed");
+    }
+}
@@ -0,0 +1,81 @@
+//! Named points in handlers, between reading an order and writing it back,
+//! where `tests/concurrency.rs` holds a request while it runs others in the
+//! gap, so races that depend on timing happen the same way every run. Without
+//! the `chaos` feature the hooks are empty and `pause` does nothing.
+
+#[cfg(feature = "chaos")]
+use std::{collections::HashMap, sync::Mutex};
+
+#[cfg(feature = "chaos")]
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Point {
+    /// `PATCH /orders/{id}`, after the order is read and checked.
+    UpdateOrder,
+    /// `POST /orders/{id}/merge`, after both orders are read and checked.
+    MergeOrder,
+}
+
+/// What `pause` tells the test when it gets to a point, and waits on.
+#[cfg(feature = "chaos")]
+type Waiting = (oneshot::Sender<()>, oneshot::Receiver<()>);
+
+#[derive(Default)]
+pub struct ChaosHooks {
+    #[cfg(feature = "chaos")]
+    held: Mutex<HashMap<Point, Waiting>>,
+}
+
+impl ChaosHooks {
+    #[cfg(not(feature = "chaos"))]
+    #[inline(always)]
+    pub async fn pause(&self, _point: Point) {}
+
+    /// Waits here if a test holds `point`, until it lets go. Only the first
+    /// request to get here is held, a retry passes straight through.
+    #[cfg(feature = "chaos")]
+    pub async fn pause(&self, point: Point) {
+        let held = self.held.lock().unwrap().remove(&point);
+
+        if let Some((reached, release)) = held {
+            let _ = reached.send(());
+            let _ = release.await;
+        }
+    }
+
+    /// Holds the next request to get to `point` there, until the `Hold` is
+    /// released or dropped.
+    #[cfg(feature = "chaos")]
+    pub fn hold(&self, point: Point) -> Hold {
+        let (reached_tx, reached) = oneshot::channel();
+        let (release, release_rx) = oneshot::channel();
+
+        self.held
+            .lock()
+            .unwrap()
+            .insert(point, (reached_tx, release_rx));
+
+        Hold { reached, release }
+    }
+}
+
+#[cfg(feature = "chaos")]
+pub struct Hold {
+    reached: oneshot::Receiver<()>,
+    release: oneshot::Sender<()>,
+}
+
+#[cfg(feature = "chaos")]
+impl Hold {
+    /// Waits for a request to get to the point.
+    pub async fn reached(&mut self) {
+        (&mut self.reached)
+            .await
+            .expect("the hooks outlive their holds");
+    }
+
+    pub fn release(self) {
+        let _ = self.release.send(());
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, bail};
+
+/// What to do, from the command line arguments after the binary name.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// No arguments, run the api.
+    Serve,
+    /// `replay-journal [--since <timestamp>]`
+    ReplayJournal { since: String },
+    /// `archive --completed-before <date>`
+    Archive { completed_before: String },
+    /// `backup --to <path>`
+    Backup { to: String },
+    /// `restore --from <path>`
+    Restore { from: String },
+    /// `export-openapi --out <path>`
+    ExportOpenapi { out: String },
+    /// `export-error-codes --out <path>`
+    ExportErrorCodes { out: String },
+    /// `rebuild-projections`
+    RebuildProjections,
+    /// `verify-audit`
+    VerifyAudit,
+    /// `fix-statuses --dry-run`, or `fix-statuses --apply --map <from>=<to>...
+    /// [--confirm-environment <env>]`
+    FixStatuses {
+        apply: bool,
+        map: Vec<String>,
+        confirm_environment: Option<String>,
+    },
+    /// `enforce-retention [--dry-run]`
+    EnforceRetention { dry_run: bool },
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let Some((command, rest)) = args.split_first() else {
+            return Ok(Command::Serve);
+        };
+
+        match command.as_str() {
+            "replay-journal" => {
+                let mut flags = Flags::parse(rest, &["--since"])?;
+
+                Ok(Command::ReplayJournal {
+                    since: flags.take("--since").unwrap_or_default(),
+                })
+            }
+            "archive" => {
+                let mut flags = Flags::parse(rest, &["--completed-before"])?;
+
+                let Some(completed_before) = flags.take("--completed-before") else {
+                    bail!("archive requires --completed-before <date>");
+                };
+
+                Ok(Command::Archive { completed_before })
+            }
+            "backup" => {
+                let mut flags = Flags::parse(rest, &["--to"])?;
+
+                let Some(to) = flags.take("--to") else {
+                    bail!("backup requires --to <path>");
+                };
+
+                Ok(Command::Backup { to })
+            }
+            "restore" => {
+                let mut flags = Flags::parse(rest, &["--from"])?;
+
+                let Some(from) = flags.take("--from") else {
+                    bail!("restore requires --from <path>");
+                };
+
+                Ok(Command::Restore { from })
+            }
+            "export-openapi" => {
+                let mut flags = Flags::parse(rest, &["--out"])?;
+
+                let Some(out) = flags.take("--out") else {
+                    bail!("export-openapi requires --out <path>");
+                };
+
+                Ok(Command::ExportOpenapi { out })
+            }
+            "export-error-codes" => {
+                let mut flags = Flags::parse(rest, &["--out"])?;
+
+                let Some(out) = flags.take("--out") else {
+                    bail!("export-error-codes requires --out <path>");
+                };
+
+                Ok(Command::ExportErrorCodes { out })
+            }
+            "fix-statuses" => {
+                let mut flags = Flags::parse_with_switches(
+                    rest,
+                    &["--map", "--confirm-environment"],
+                    &["--dry-run", "--apply"],
+                )?;
+
+                let map = flags.take_all("--map");
+                let confirm_environment = flags.take("--confirm-environment");
+
+                match (flags.has("--dry-run"), flags.has("--apply")) {
+                    (true, false) if map.is_empty() && confirm_environment.is_none() => {
+                        Ok(Command::FixStatuses {
+                            apply: false,
+                            map,
+                            confirm_environment,
+                        })
+                    }
+                    (true, false) => {
+                        bail!("fix-statuses --dry-run doesn't take --map or --confirm-environment")
+                    }
+                    (false, true) if map.is_empty() => {
+                        bail!("fix-statuses --apply requires at least one --map <from>=<to>")
+                    }
+                    (false, true) => Ok(Command::FixStatuses {
+                        apply: true,
+                        map,
+                        confirm_environment,
+                    }),
+                    _ => bail!("fix-statuses requires one of --dry-run or --apply"),
+                }
+            }
+            "enforce-retention" => {
+                let flags = Flags::parse_with_switches(rest, &[], &["--dry-run"])?;
+
+                Ok(Command::EnforceRetention {
+                    dry_run: flags.has("--dry-run"),
+                })
+            }
+            "rebuild-projections" => {
+                Flags::parse(rest, &[])?;
+
+                Ok(Command::RebuildProjections)
+            }
+            "verify-audit" => {
+                Flags::parse(rest, &[])?;
+
+                Ok(Command::VerifyAudit)
+            }
+            _ => bail!("unknown command {command:?}"),
+        }
+    }
+}
+
+/// `--name value` pairs and bare `--name` switches, restricted to the flags a
+/// command accepts.
+struct Flags {
+    values: Vec<(String, String)>,
+    switches: HashSet<String>,
+}
+
+impl Flags {
+    fn parse(args: &[String], allowed: &[&str]) -> Result<Self> {
+        Self::parse_with_switches(args, allowed, &[])
+    }
+
+    fn parse_with_switches(args: &[String], allowed: &[&str], switches: &[&str]) -> Result<Self> {
+        let mut flags = Self {
+            values: Vec::new(),
+            switches: HashSet::new(),
+        };
+        let mut args = args.iter();
+
+        while let Some(name) = args.next() {
+            if switches.contains(&name.as_str()) {
+                flags.switches.insert(name.clone());
+                continue;
+            }
+
+            if !allowed.contains(&name.as_str()) {
+                bail!("unexpected argument {name:?}");
+            }
+
+            let Some(value) = args.next() else {
+                bail!("{name} requires a value");
+            };
+
+            flags.values.push((name.clone(), value.clone()));
+        }
+
+        Ok(flags)
+    }
+
+    /// The flag's value, the last one when it's given more than once.
+    fn take(&mut self, name: &str) -> Option<String> {
+        self.take_all(name).pop()
+    }
+
+    /// Every value the flag was given, in order.
+    fn take_all(&mut self, name: &str) -> Vec<String> {
+        let (taken, rest) = std::mem::take(&mut self.values)
+            .into_iter()
+            .partition(|(flag, _)| flag == name);
+        self.values = rest;
+
+        taken.into_iter().map(|(_, value)| value).collect()
+    }
+
+    fn has(&self, switch: &str) -> bool {
+        self.switches.contains(switch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse(&[]).unwrap(), Command::Serve);
+        assert_eq!(
+            Command::parse(&args(&["replay-journal", "--since", "2025-08-01"])).unwrap(),
+            Command::ReplayJournal {
+                since: "2025-08-01".to_string()
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&["replay-journal"])).unwrap(),
+            Command::ReplayJournal {
+                since: String::new()
+            }
+        );
+        assert!(Command::parse(&args(&["replay-journal", "--since"])).is_err());
+        assert!(Command::parse(&args(&["replay-journal", "--until", "x"])).is_err());
+        assert_eq!(
+            Command::parse(&args(&["archive", "--completed-before", "2023-01-01"])).unwrap(),
+            Command::Archive {
+                completed_before: "2023-01-01".to_string()
+            }
+        );
+        assert!(Command::parse(&args(&["archive"])).is_err());
+        assert_eq!(
+            Command::parse(&args(&["backup", "--to", "db/backup.sqlite"])).unwrap(),
+            Command::Backup {
+                to: "db/backup.sqlite".to_string()
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&["restore", "--from", "db/backup.sqlite"])).unwrap(),
+            Command::Restore {
+                from: "db/backup.sqlite".to_string()
+            }
+        );
+        assert!(Command::parse(&args(&["backup"])).is_err());
+        assert_eq!(
+            Command::parse(&args(&["export-openapi", "--out", "openapi.json"])).unwrap(),
+            Command::ExportOpenapi {
+                out: "openapi.json".to_string()
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&["export-error-codes", "--out", "codes.json"])).unwrap(),
+            Command::ExportErrorCodes {
+                out: "codes.json".to_string()
+            }
+        );
+        assert!(Command::parse(&args(&["export-openapi"])).is_err());
+        assert_eq!(
+            Command::parse(&args(&["rebuild-projections"])).unwrap(),
+            Command::RebuildProjections
+        );
+        assert!(Command::parse(&args(&["rebuild-projections", "--to", "x"])).is_err());
+        assert_eq!(
+            Command::parse(&args(&["verify-audit"])).unwrap(),
+            Command::VerifyAudit
+        );
+        assert!(Command::parse(&args(&["verify-audit", "--from", "1"])).is_err());
+        assert_eq!(
+            Command::parse(&args(&["fix-statuses", "--dry-run"])).unwrap(),
+            Command::FixStatuses {
+                apply: false,
+                map: vec![],
+                confirm_environment: None
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&[
+                "fix-statuses",
+                "--apply",
+                "--map",
+                "In Progress=in-progress",
+                "--map",
+                "done=complete"
+            ]))
+            .unwrap(),
+            Command::FixStatuses {
+                apply: true,
+                map: vec![
+                    "In Progress=in-progress".to_string(),
+                    "done=complete".to_string()
+                ],
+                confirm_environment: None
+            }
+        );
+        assert_eq!(
+            Command::parse(&args(&[
+                "fix-statuses",
+                "--apply",
+                "--map",
+                "done=complete",
+                "--confirm-environment",
+                "prod"
+            ]))
+            .unwrap(),
+            Command::FixStatuses {
+                apply: true,
+                map: vec!["done=complete".to_string()],
+                confirm_environment: Some("prod".to_string())
+            }
+        );
+        for invalid in [
+            &["fix-statuses"][..],
+            &["fix-statuses", "--apply"],
+            &["fix-statuses", "--dry-run", "--apply", "--map", "a=pending"],
+            &["fix-statuses", "--dry-run", "--map", "a=pending"],
+            &["fix-statuses", "--map", "a=pending"],
+            &["fix-statuses", "--dry-run", "--confirm-environment", "prod"],
+        ] {
+            assert!(Command::parse(&args(invalid)).is_err(), "{invalid:?}");
+        }
+        assert_eq!(
+            Command::parse(&args(&["enforce-retention", "--dry-run"])).unwrap(),
+            Command::EnforceRetention { dry_run: true }
+        );
+        assert_eq!(
+            Command::parse(&args(&["enforce-retention"])).unwrap(),
+            Command::EnforceRetention { dry_run: false }
+        );
+        assert!(Command::parse(&args(&["enforce-retention", "--apply"])).is_err());
+        assert!(Command::parse(&args(&["restore", "--to", "x"])).is_err());
+        assert!(Command::parse(&args(&["nope"])).is_err());
+    }
+}
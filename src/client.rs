@@ -0,0 +1,580 @@
+//! Typed client for services calling this api from rust, enabled with the
+//! `client` feature. It sends and receives the same `dto` types the handlers
+//! use so the two sides can't disagree on field names or status values.
+//!
+//! Requests that are safe to send twice, reads and creates with an
+//! idempotency key, are tried again on a connection error, a 429 or a 503 as
+//! `RetryPolicy` says. Anything else goes once: a create without a key that
+//! timed out may well have created the order.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    IDEMPOTENCY_KEY_HEADER, ORDERS_PER_PAGE_MAX,
+    dto::{CreateOrderRequest, OrderFilter, OrderView, PageParams, PaginatedOrders, UpdateOrderStatusRequest},
+    orders::OrderStatus,
+    routes,
+};
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("order not found")]
+    NotFound,
+    #[error("conflict: {0}")]
+    Conflict(String),
+    /// The request was refused as invalid, with the server's explanation.
+    #[error("validation failed: {0}")]
+    Validation(String),
+    #[error("server error: {0}")]
+    Server(String),
+    /// Any other refusal, e.g. a 429 once an api key is over its quota.
+    #[error("request refused with {status}: {message}")]
+    Rejected { status: StatusCode, message: String },
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    /// A request that can be retried failed every attempt, `last` is how the
+    /// final one did.
+    #[error("gave up after {attempts} attempts in {elapsed:?}: {last}")]
+    RetriesExhausted {
+        attempts: u32,
+        elapsed: Duration,
+        last: Box<ClientError>,
+    },
+}
+
+impl ClientError {
+    /// Same codes as `CustomError::code`, for callers that log or count them.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ClientError::NotFound => "record_not_found",
+            ClientError::Conflict(_) => "conflict",
+            ClientError::Validation(_) => "validation_failed",
+            ClientError::Server(_) => "internal_error",
+            ClientError::Rejected { .. } | ClientError::Http(_) => "client_error",
+            ClientError::RetriesExhausted { last, .. } => last.code(),
+        }
+    }
+
+    async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(err) => return ClientError::Http(err),
+        };
+
+        // the envelope's message, or the body as it is from something in
+        // front of the api, like a proxy
+        let message = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|body| body["error"]["message"].as_str().map(str::to_string))
+            .unwrap_or(body);
+
+        match status {
+            StatusCode::NOT_FOUND => ClientError::NotFound,
+            StatusCode::CONFLICT => ClientError::Conflict(message),
+            StatusCode::UNPROCESSABLE_ENTITY | StatusCode::BAD_REQUEST => {
+                ClientError::Validation(message)
+            }
+            status if status.is_server_error() => ClientError::Server(message),
+            status => ClientError::Rejected { status, message },
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// How often and how far apart a retriable request is tried. The wait doubles
+/// after each attempt from `initial_backoff` up to `max_backoff`, unless a 429
+/// or 503 says how long in `Retry-After`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in all, the first one included.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Every request is sent once.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// The wait after the failed attempt `attempt`, counting from 1.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let doubled = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+
+        doubled.min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Whether a request may be sent again after a failure that might have
+/// reached the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Retry {
+    Safe,
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrdersClient {
+    http: reqwest::Client,
+    base_url: String,
+    retry: RetryPolicy,
+}
+
+impl OrdersClient {
+    /// `base_url` is where the api is served, e.g. `http://orders.internal:3000`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http(reqwest::Client::new(), base_url)
+    }
+
+    /// Uses an existing `reqwest::Client`, to share its connection pool or
+    /// default headers such as `X-Api-Key`.
+    pub fn with_http(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// A copy of this client retrying by `policy`, for every call or just one
+    /// like `client.with_retry(RetryPolicy::none()).get_order(id)`. It shares
+    /// the connection pool.
+    pub fn with_retry(&self, policy: RetryPolicy) -> Self {
+        Self {
+            retry: policy,
+            ..self.clone()
+        }
+    }
+
+    /// Sent once, see `create_order_with_key` for a create that's retried.
+    /// The order starts as pending, see `update_status` to move it on.
+    pub async fn create_order(&self, amount: i64) -> Result<OrderView> {
+        let request = self.request(Method::POST, routes::ORDERS).json(&creation(amount));
+
+        self.json(request, Retry::Never).await
+    }
+
+    /// Creates the order under an `Idempotency-Key`, so a retry gets the
+    /// order the first attempt made rather than a second one.
+    pub async fn create_order_with_key(
+        &self,
+        amount: i64,
+        key: &str,
+    ) -> Result<OrderView> {
+        let request = self
+            .request(Method::POST, routes::ORDERS)
+            .header(IDEMPOTENCY_KEY_HEADER, key)
+            .json(&creation(amount));
+
+        self.json(request, Retry::Safe).await
+    }
+
+    pub async fn get_order(&self, id: i64) -> Result<OrderView> {
+        self.json(self.request(Method::GET, &routes::order(id)), Retry::Safe)
+            .await
+    }
+
+    /// Every matching order, read a page at a time.
+    pub async fn list_orders(&self, filter: &OrderFilter) -> Result<Vec<OrderView>> {
+        let mut orders = Vec::new();
+
+        for page in 1.. {
+            let params = PageParams {
+                page: Some(page),
+                per_page: Some(ORDERS_PER_PAGE_MAX),
+            };
+            let PaginatedOrders { items, total, .. } = self.list_orders_page(filter, &params).await?;
+            let last = items.is_empty();
+
+            orders.extend(items);
+
+            if last || orders.len() as i64 >= total {
+                break;
+            }
+        }
+
+        Ok(orders)
+    }
+
+    /// One page of `list_orders`, with how many there are in all.
+    pub async fn list_orders_page(&self, filter: &OrderFilter, page: &PageParams) -> Result<PaginatedOrders> {
+        self.json(self.request(Method::GET, &routes::orders_page(filter, page)), Retry::Safe)
+            .await
+    }
+
+    pub async fn update_status(&self, id: i64, status: OrderStatus) -> Result<()> {
+        let body = UpdateOrderStatusRequest {
+            status: Some(status),
+            ..Default::default()
+        };
+
+        self.send(self.request(Method::PATCH, &routes::order(id)).json(&body), Retry::Never)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Sent once: the api doesn't take `If-Match` on a delete, so a retry
+    /// after one that went through would be a 404.
+    pub async fn delete_order(&self, id: i64) -> Result<()> {
+        self.send(self.request(Method::DELETE, &routes::order(id)), Retry::Never)
+            .await?;
+
+        Ok(())
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, format!("{}{path}", self.base_url))
+    }
+
+    async fn send(&self, request: RequestBuilder, retry: Retry) -> Result<Response> {
+        let attempts = match retry {
+            Retry::Safe => self.retry.max_attempts.max(1),
+            Retry::Never => 1,
+        };
+        let started = Instant::now();
+        let mut attempt = 1;
+
+        loop {
+            // a body that can't be cloned is a stream, which only goes once
+            let Some(this) = request.try_clone().filter(|_| attempt < attempts) else {
+                return self.send_last(request, attempt, started).await;
+            };
+
+            let (err, wait) = match this.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if retriable(response.status()) => {
+                    let wait = retry_after(&response);
+                    (ClientError::from_response(response).await, wait)
+                }
+                Ok(response) => return Err(ClientError::from_response(response).await),
+                Err(err) if err.is_connect() => (ClientError::Http(err), None),
+                Err(err) => return Err(err.into()),
+            };
+
+            tracing::debug!("attempt {attempt} of {attempts} failed, retrying: {err}");
+
+            tokio::time::sleep(wait.unwrap_or_else(|| self.retry.backoff(attempt))).await;
+            attempt += 1;
+        }
+    }
+
+    /// The final attempt, its failure wrapped with the count if it wasn't the
+    /// only one.
+    async fn send_last(&self, request: RequestBuilder, attempt: u32, started: Instant) -> Result<Response> {
+        let (err, exhausted) = match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) => {
+                let exhausted = retriable(response.status());
+                (ClientError::from_response(response).await, exhausted)
+            }
+            Err(err) => {
+                let exhausted = err.is_connect();
+                (err.into(), exhausted)
+            }
+        };
+
+        match attempt > 1 && exhausted {
+            true => Err(ClientError::RetriesExhausted {
+                attempts: attempt,
+                elapsed: started.elapsed(),
+                last: Box::new(err),
+            }),
+            false => Err(err),
+        }
+    }
+
+    async fn json<T: DeserializeOwned>(&self, request: RequestBuilder, retry: Retry) -> Result<T> {
+        Ok(self.send(request, retry).await?.json().await?)
+    }
+}
+
+fn creation(amount: i64) -> CreateOrderRequest {
+    CreateOrderRequest {
+        amount,
+        status: None,
+        draft: false,
+        customer_id: None,
+        currency: None,
+        metadata: Default::default(),
+        shipping_address: None,
+    }
+}
+
+/// Statuses that say to come back later: over a quota or rate limit, or the
+/// server busy or draining.
+fn retriable(status: StatusCode) -> bool {
+    matches!(status, StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE)
+}
+
+/// The wait a response asks for, in seconds or as an http date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let at = httpdate::parse_http_date(value).ok()?;
+
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use axum::{Router, extract::Request, middleware::Next, response::IntoResponse};
+
+    use crate::{app, db::test_db};
+
+    use super::*;
+
+    async fn serve() -> OrdersClient {
+        serve_app(app(test_db().await)).await
+    }
+
+    async fn serve_app(app: Router) -> OrdersClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        OrdersClient::new(format!("http://{addr}"))
+    }
+
+    /// Requests that reached the scripted server, failed ones included.
+    #[derive(Clone, Default)]
+    struct Script {
+        requests: Arc<AtomicU32>,
+    }
+
+    impl Script {
+        fn requests(&self) -> u32 {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    /// The app behind a layer answering its first `failures` requests with
+    /// `status` and `Retry-After: 0`.
+    async fn serve_failing(failures: u32, status: StatusCode) -> (OrdersClient, Script) {
+        let script = Script::default();
+        let requests = script.requests.clone();
+
+        let app = app(test_db().await).layer(axum::middleware::from_fn(move |request: Request, next: Next| {
+            let seen = requests.fetch_add(1, Ordering::SeqCst);
+
+            async move {
+                match seen < failures {
+                    true => (status, [(RETRY_AFTER, "0")], "try again").into_response(),
+                    false => next.run(request).await,
+                }
+            }
+        }));
+
+        let client = serve_app(app).await.with_retry(fast_retries(3));
+
+        (client, script)
+    }
+
+    fn fast_retries(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_order_lifecycle() {
+        let client = serve().await;
+
+        let created = client.create_order(500).await.unwrap();
+        assert_eq!(created.amount, 500);
+
+        client
+            .update_status(created.id, OrderStatus::InProgress)
+            .await
+            .unwrap();
+
+        let fetched = client.get_order(created.id).await.unwrap();
+        assert_eq!(fetched.status, OrderStatus::InProgress);
+
+        client.delete_order(created.id).await.unwrap();
+
+        assert!(matches!(
+            client.get_order(created.id).await,
+            Err(ClientError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_orders_filter() {
+        let client = serve().await;
+
+        client.create_order(500).await.unwrap();
+        let second = client.create_order(700).await.unwrap();
+        client.update_status(second.id, OrderStatus::InProgress).await.unwrap();
+        client.update_status(second.id, OrderStatus::Complete).await.unwrap();
+
+        let all = client.list_orders(&OrderFilter::default()).await.unwrap();
+        assert_eq!(all.len(), 2);
+
+        let filter = OrderFilter {
+            status: Some(OrderStatus::Complete),
+            ..Default::default()
+        };
+        let complete = client.list_orders(&filter).await.unwrap();
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].amount, 700);
+
+        let page = PageParams {
+            page: Some(2),
+            per_page: Some(1),
+        };
+        let second = client.list_orders_page(&OrderFilter::default(), &page).await.unwrap();
+
+        assert_eq!(second.total, 2);
+        assert_eq!(second.items[0].amount, 700);
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_typed() {
+        let client = serve().await;
+
+        let err = client
+            .create_order(0)
+            .await
+            .expect_err("amount below the minimum should be refused");
+
+        match err {
+            ClientError::Validation(message) => assert_eq!(message, "amount must be at least 1"),
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+
+        assert!(matches!(
+            client.delete_order(999).await,
+            Err(ClientError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reads_retry_until_they_succeed() {
+        let (client, script) = serve_failing(2, StatusCode::SERVICE_UNAVAILABLE).await;
+
+        let orders = client.list_orders(&OrderFilter::default()).await.unwrap();
+
+        assert!(orders.is_empty());
+        assert_eq!(script.requests(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retries_honor_retry_after() {
+        let (client, script) = serve_failing(1, StatusCode::TOO_MANY_REQUESTS).await;
+
+        // the server's Retry-After of 0 goes before a minute of backoff
+        let client = client.with_retry(RetryPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::from_secs(60),
+            max_backoff: Duration::from_secs(60),
+        });
+
+        let filter = OrderFilter::default();
+        tokio::time::timeout(Duration::from_secs(5), client.list_orders(&filter))
+            .await
+            .expect("waited for the backoff instead")
+            .unwrap();
+
+        assert_eq!(script.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_retries_are_reported() {
+        let (client, script) = serve_failing(5, StatusCode::SERVICE_UNAVAILABLE).await;
+
+        let err = client.get_order(1).await.unwrap_err();
+
+        match &err {
+            ClientError::RetriesExhausted { attempts, last, .. } => {
+                assert_eq!(*attempts, 3);
+                assert!(matches!(**last, ClientError::Server(_)));
+            }
+            other => panic!("expected exhausted retries, got {other:?}"),
+        }
+        assert_eq!(err.code(), "internal_error");
+        assert_eq!(script.requests(), 3);
+
+        // overridden for the one call
+        let err = client.with_retry(RetryPolicy::none()).get_order(1).await.unwrap_err();
+        assert!(matches!(err, ClientError::Server(_)));
+        assert_eq!(script.requests(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_only_keyed_creates_retry() {
+        let (client, script) = serve_failing(1, StatusCode::SERVICE_UNAVAILABLE).await;
+
+        let err = client.create_order(500).await.unwrap_err();
+        assert!(matches!(err, ClientError::Server(_)));
+        assert_eq!(script.requests(), 1);
+
+        let (client, script) = serve_failing(1, StatusCode::SERVICE_UNAVAILABLE).await;
+
+        let created = client
+            .create_order_with_key(500, "create-500")
+            .await
+            .unwrap();
+        assert_eq!(created.amount, 500);
+        assert_eq!(script.requests(), 2);
+
+        // other refusals aren't retried either
+        let err = client.get_order(999).await.unwrap_err();
+        assert!(matches!(err, ClientError::NotFound));
+        assert_eq!(script.requests(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_connect_errors_retry() {
+        // a port nothing listens on once its listener is gone
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let client = OrdersClient::new(format!("http://{addr}")).with_retry(fast_retries(2));
+
+        match client.get_order(1).await.unwrap_err() {
+            ClientError::RetriesExhausted { attempts, last, .. } => {
+                assert_eq!(attempts, 2);
+                assert!(matches!(*last, ClientError::Http(_)));
+            }
+            other => panic!("expected exhausted retries, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! The time written into `created_at`, `updated_at` and `completed_at`. It
+//! comes from the clock in `AppState`, which `scope` makes the current one for
+//! the rest of the request, so tests can freeze it with a `FixedClock`.
+//! Outside a request, e.g. in the sweeper or an import job, it's the system's.
+
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::AppState;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Always the same time, so responses don't change between runs.
+#[cfg(any(test, feature = "deterministic"))]
+pub struct FixedClock(pub SystemTime);
+
+#[cfg(any(test, feature = "deterministic"))]
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+tokio::task_local! {
+    static CLOCK: Arc<dyn Clock>;
+}
+
+/// Makes the app's clock the current one while the request is handled.
+pub(crate) async fn scope(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    CLOCK.scope(state.clock.clone(), next.run(request)).await
+}
+
+/// Keeps the current clock for `future`, for work a request spawns onto a
+/// task of its own.
+pub fn keep<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let clock = CLOCK.try_with(Arc::clone).ok();
+
+    async move {
+        match clock {
+            Some(clock) => CLOCK.scope(clock, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// The current time in the format sqlite's `strftime('%Y-%m-%dT%H:%M:%fZ')`
+/// gives, which is what the timestamp columns hold.
+pub fn now() -> String {
+    timestamp(current())
+}
+
+/// The current clock's time, for what's worked out from it rather than stored.
+pub fn current() -> SystemTime {
+    CLOCK
+        .try_with(|clock| clock.now())
+        .unwrap_or_else(|_| SystemTime::now())
+}
+
+/// An RFC 3339 time in any offset, like `2025-09-12T12:00:00+02:00`, as the
+/// timestamp columns would hold it, so it compares with them as text.
+pub fn parse(value: &str) -> Option<String> {
+    crate::deadline::parse_rfc3339(value).map(timestamp)
+}
+
+/// `time` the way the timestamp columns hold it.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() as i64;
+    let (days, seconds) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    // days since the epoch to a civil date, from Howard Hinnant's algorithms
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::db::test_db;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timestamp_matches_sqlite() {
+        let db = test_db().await;
+
+        for seconds in [0, 951_782_400, 1_709_251_199, 4_102_444_800] {
+            let time = UNIX_EPOCH + Duration::from_millis(seconds * 1000 + 42);
+            let expected: String = sqlx::query_scalar(
+                "select strftime('%Y-%m-%dT%H:%M:%fZ', ? / 1000.0, 'unixepoch')",
+            )
+            .bind(seconds as i64 * 1000 + 42)
+            .fetch_one(db.reader())
+            .await
+            .unwrap();
+
+            assert_eq!(timestamp(time), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_now_uses_the_scoped_clock() {
+        let fixed: Arc<dyn Clock> = Arc::new(FixedClock(UNIX_EPOCH));
+
+        assert_eq!(
+            CLOCK.scope(fixed, async { now() }).await,
+            "1970-01-01T00:00:00.000Z"
+        );
+        assert_ne!(now(), "1970-01-01T00:00:00.000Z");
+    }
+}
@@ -0,0 +1,1186 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api_keys, bus,
+    dto::Case,
+    error_rate, growth, integrity,
+    listeners::{self, ListenerSpec},
+    maintenance, metadata,
+    money_format::Locale,
+    redact::RedactionRules,
+    reports, stats, webhooks,
+};
+
+const DEFAULT_DATABASE_URL: &str = "sqlite:db/db.sqlite";
+const DEFAULT_BIND_ADDR: &str = "0.0.0.0:3000";
+const DEFAULT_SLOW_REQUEST_MS: u64 = 1000;
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_GROWTH_ALERT_PER_HOUR: u64 = 10_000;
+const DEFAULT_DB_OPTIMIZE_HOURS: u64 = 24;
+const DEFAULT_PENDING_REMINDER_HOURS: u64 = 24;
+const DEFAULT_PENDING_CANCEL_HOURS: u64 = 72;
+const DEFAULT_REOPEN_WINDOW_HOURS: u64 = 24;
+const DEFAULT_DRAFT_TTL_HOURS: u64 = 24;
+const DEFAULT_IDEMPOTENCY_TTL_HOURS: u64 = 24;
+const DEFAULT_REQUEST_LOG_RETENTION_HOURS: u64 = 7 * 24;
+const DEFAULT_JOURNAL_RETENTION_HOURS: u64 = 30 * 24;
+const DEFAULT_WEBHOOK_DELIVERY_RETENTION_HOURS: u64 = 30 * 24;
+/// The default row cap of the journal, the request log and finished webhook
+/// deliveries.
+const DEFAULT_MAX_ROWS: i64 = 1_000_000;
+const DEFAULT_PICK_LEASE_MINUTES: u64 = 30;
+const DEFAULT_API_KEY_ROTATION_OVERLAP_HOURS: u64 = 24;
+/// Short enough to guess isn't worth accepting for a key with every scope.
+const MIN_BOOTSTRAP_API_KEY_LEN: usize = 32;
+const DEFAULT_OPEN_ORDERS_PER_CUSTOMER: i64 = 20;
+const DEFAULT_WRITE_BACKLOG_MAX: usize = 64;
+
+/// Which deployment this instance is, sent back on every response so a
+/// request against the wrong url is easy to spot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl Environment {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "dev" | "development" => Some(Environment::Dev),
+            "staging" => Some(Environment::Staging),
+            "prod" | "production" => Some(Environment::Prod),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Environment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let environment = match self {
+            Environment::Dev => "dev",
+            Environment::Staging => "staging",
+            Environment::Prod => "prod",
+        };
+
+        write!(f, "{environment}")
+    }
+}
+
+/// What startup does when the database schema doesn't match the one the
+/// queries were built against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DriftPolicy {
+    /// Exit without serving anything.
+    #[default]
+    Refuse,
+    /// Serve reads and refuse writes until the schema is fixed.
+    Maintenance,
+}
+
+impl DriftPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "refuse" => Some(DriftPolicy::Refuse),
+            "maintenance" => Some(DriftPolicy::Maintenance),
+            _ => None,
+        }
+    }
+}
+
+/// How thoroughly startup checks the database file for corruption, see
+/// `integrity.rs`. The periodic check is always `Quick`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IntegrityCheck {
+    /// `PRAGMA quick_check`, which reads every page but skips matching the
+    /// indexes against their tables.
+    #[default]
+    Quick,
+    /// `PRAGMA integrity_check`, much slower on a large file.
+    Full,
+}
+
+impl IntegrityCheck {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "quick" => Some(IntegrityCheck::Quick),
+            "full" => Some(IntegrityCheck::Full),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IntegrityCheck::Quick => "quick",
+            IntegrityCheck::Full => "full",
+        }
+    }
+}
+
+/// Optional parts of the api a deployment can leave out, all on by default.
+/// Routes of a disabled feature answer 404 as if they didn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Features {
+    /// `DELETE` on orders, products and webhooks.
+    pub delete_enabled: bool,
+    /// Csv order imports and their job status.
+    pub imports_enabled: bool,
+    /// Webhook registrations. Webhooks already registered keep receiving
+    /// deliveries.
+    pub webhooks_enabled: bool,
+    /// The html admin pages, when built with the `admin-ui` feature.
+    pub admin_ui_enabled: bool,
+}
+
+impl Features {
+    pub const NAMES: &[&str] = &["delete", "imports", "webhooks", "admin_ui"];
+
+    /// Turns off each feature in a comma separated list of names like
+    /// `delete,webhooks`.
+    pub fn parse_disabled(disabled: &str) -> Result<Self> {
+        let mut features = Self::default();
+
+        for name in disabled.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let Some(enabled) = features.flag(name) else {
+                bail!(
+                    "DISABLED_FEATURES has an unknown feature {name:?}, the features are {}",
+                    Self::NAMES.join(", ")
+                );
+            };
+
+            *enabled = false;
+        }
+
+        Ok(features)
+    }
+
+    /// The names of the features that are on.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let flags = [
+            ("delete", self.delete_enabled),
+            ("imports", self.imports_enabled),
+            ("webhooks", self.webhooks_enabled),
+            ("admin_ui", self.admin_ui_enabled && cfg!(feature = "admin-ui")),
+        ];
+
+        flags
+            .into_iter()
+            .filter_map(|(name, enabled)| enabled.then_some(name))
+            .collect()
+    }
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "delete" => Some(&mut self.delete_enabled),
+            "imports" => Some(&mut self.imports_enabled),
+            "webhooks" => Some(&mut self.webhooks_enabled),
+            "admin_ui" => Some(&mut self.admin_ui_enabled),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            delete_enabled: true,
+            imports_enabled: true,
+            webhooks_enabled: true,
+            admin_ui_enabled: true,
+        }
+    }
+}
+
+/// How many open orders, ones that aren't complete or canceled, can be
+/// created before new ones are refused. `None` doesn't limit them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenOrderLimits {
+    /// Per `customer_id`.
+    pub per_customer: Option<i64>,
+    /// Per api key, for orders created without a `customer_id`. Orders with
+    /// neither are never limited.
+    pub per_anonymous_key: Option<i64>,
+}
+
+/// How long the rows of a table that only grows are kept, and how many, see
+/// `retention/tables.rs`. `None` doesn't limit them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableLimits {
+    pub max_age: Option<Duration>,
+    pub max_rows: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub environment: Environment,
+    pub database_url: String,
+    /// Read only connections selects get to themselves, next to a single one
+    /// for writes, see `db::setup_db`. `None` has one pool do both.
+    pub db_read_connections: Option<u32>,
+    /// Requests that write are refused with a 503 while this many are already
+    /// waiting for the writer, see `db::Writer`. `None` lets them all wait.
+    pub write_backlog_max: Option<usize>,
+    /// A file of `NAME=value` lines read over the environment, at startup and
+    /// again on every reload.
+    pub config_file: Option<PathBuf>,
+    /// Every address the router is served on, see `listeners.rs`.
+    pub listeners: Vec<ListenerSpec>,
+    /// Permissions of the socket files of `unix://` listeners.
+    pub unix_socket_mode: u32,
+    /// Write mutating requests to the request journal before handling them.
+    pub journal_enabled: bool,
+    /// Refuse requests whose `Accept` header rules out what the route produces.
+    pub strict_accept: bool,
+    /// Casing of response keys when the request doesn't send `X-Response-Case`.
+    pub response_case: Case,
+    /// Responses slower than this are marked with `X-Slow-Request: true`.
+    pub slow_request_threshold: Duration,
+    /// Requests still running after this get a 504. A shorter
+    /// `X-Request-Deadline` on the request applies instead.
+    pub request_timeout: Duration,
+    /// Order creation rate that triggers a growth alert.
+    pub growth_alert_per_hour: u64,
+    /// How often the growth watchdog samples the orders table.
+    pub growth_sample_interval: Duration,
+    /// Percent of a minute's requests ending in a 5xx that counts as a bad
+    /// minute for the error rate alert.
+    pub error_rate_alert_percent: u64,
+    /// Bad minutes in a row before the error rate alert fires.
+    pub error_rate_alert_windows: usize,
+    /// How long after an error rate alert another one can fire.
+    pub error_rate_alert_cooldown: Duration,
+    /// How often the wal is checkpointed, see `maintenance`. `None` turns it
+    /// off.
+    pub wal_checkpoint_interval: Option<Duration>,
+    /// How often the freelist is looked at for a vacuum. `None` turns it off.
+    pub vacuum_interval: Option<Duration>,
+    /// Percent of the database's pages on the freelist above which it's
+    /// vacuumed.
+    pub vacuum_freelist_percent: u64,
+    /// Maintenance is put off while more requests than this are in flight.
+    pub maintenance_max_in_flight: usize,
+    /// How often the query planner's statistics are refreshed, starting at
+    /// startup. `None` turns it off.
+    pub db_optimize_interval: Option<Duration>,
+    /// How long an order sits in pending before the sweeper sends a reminder.
+    pub pending_reminder_after: Duration,
+    /// Pending orders older than this are past the cancel window and don't get
+    /// a reminder. Always longer than `pending_reminder_after`.
+    pub pending_cancel_after: Duration,
+    /// How long after being canceled an order can still be reopened.
+    pub reopen_window: Duration,
+    /// Drafts not updated for this long are deleted by the sweeper.
+    pub draft_ttl: Duration,
+    /// How long the response to an `Idempotency-Key` is replayed before it's
+    /// pruned.
+    pub idempotency_ttl: Duration,
+    /// How many stored responses are kept, the oldest are pruned past it.
+    pub idempotency_max_rows: Option<i64>,
+    /// Years after an order is completed or canceled that its customer's data
+    /// is scrubbed, see `retention.rs`. `None` turns the policy off.
+    pub retention_years: Option<u32>,
+    /// The metadata keys scrubbed along with the rest of the customer's data.
+    pub pii_metadata_keys: Vec<String>,
+    /// How long a worker has to finish the orders a pick list assigned them
+    /// before the sweeper hands them to someone else.
+    pub pick_lease: Duration,
+    /// Caps on open orders checked by `POST /orders`.
+    pub open_order_limits: OpenOrderLimits,
+    /// Minutes east of utc where the daily report's days start at midnight.
+    pub report_utc_offset: i32,
+    /// How many events a `/events/stream` subscriber can fall behind by before
+    /// it misses some.
+    pub event_bus_capacity: usize,
+    /// How many deliveries to one webhook can be on their way at once. They're
+    /// started in the order their events were recorded, 1 also keeps them
+    /// arriving in that order.
+    pub webhook_concurrency: usize,
+    /// How many deliveries can be on their way at once across every webhook.
+    pub webhook_max_in_flight: usize,
+    /// How many deliveries of replays are started per second, across every
+    /// replay.
+    pub replay_rate_per_second: usize,
+    /// The proxy, extra cas and timeouts of webhook deliveries.
+    pub webhook_http: webhooks::HttpSettings,
+    /// Where `POST /admin/backup` writes backups. `None` turns it off.
+    pub backup_dir: Option<PathBuf>,
+    /// Lets destructive admin operations run in prod, when the request also
+    /// confirms the environment, see `interlock.rs`. Ignored elsewhere.
+    pub allow_destructive_ops: bool,
+    /// Lets requests inject database failures with an `X-Chaos` header, for
+    /// partners testing their retries, see `faults.rs`. Ignored in prod.
+    pub chaos_header: bool,
+    /// Count orders with a query on every `/orders/count` instead of keeping
+    /// cached counts.
+    pub always_count: bool,
+    /// How often the `/orders/stats` document is precomputed. `None` turns
+    /// the precomputing off, every request works the stats out.
+    pub stats_refresh_interval: Option<Duration>,
+    /// How long the event bus has to be quiet after a burst of order events
+    /// before the stats are precomputed again.
+    pub stats_debounce: Duration,
+    /// How long a precompute of the stats may take before it's given up.
+    pub stats_budget: Duration,
+    /// Log every request to `request_log`, see `request_log.rs`.
+    pub request_log_enabled: bool,
+    /// How long logged requests are kept before they're pruned.
+    pub request_log_retention: Duration,
+    /// How many logged requests are kept, the oldest are pruned past it.
+    pub request_log_max_rows: Option<i64>,
+    /// How long and how many `request_journal` rows are kept.
+    pub journal_limits: TableLimits,
+    /// How long and how many delivered and failed webhook deliveries are
+    /// kept, pending ones never go.
+    pub webhook_delivery_limits: TableLimits,
+    /// How long and how many `order_changes` rows are kept. Unlimited by
+    /// default, a pruned change is gone from the order's history too.
+    pub audit_log_limits: TableLimits,
+    /// Json paths whose values are redacted from the request journal, debug
+    /// logs and `order_changes`.
+    pub redaction: RedactionRules,
+    /// The usernames notes can mention, any `@username` is taken as known when
+    /// it's empty.
+    pub mentionable_users: Vec<String>,
+    /// How the admin pages write amounts when the browser's `Accept-Language`
+    /// names no locale `money_format` knows.
+    pub default_locale: Locale,
+    /// Signs the admin pages' csrf tokens. `None` uses a key generated at
+    /// startup, so forms rendered before a restart stop working.
+    pub admin_csrf_key: Option<String>,
+    /// Refuse requests without a working `X-Api-Key` that has the scope they
+    /// need, see `api_keys.rs`.
+    pub require_api_key: bool,
+    /// How long a rotated key's old secret keeps working, unless the rotation
+    /// asks for another overlap.
+    pub api_key_rotation_overlap: Duration,
+    /// The secret of a key with every scope, created at startup when there are
+    /// no keys yet.
+    pub bootstrap_api_key: Option<String>,
+    /// Which optional endpoints are served.
+    pub features: Features,
+    /// What startup does about a drifted schema.
+    pub schema_drift: DriftPolicy,
+    /// The check startup runs on the database file.
+    pub integrity_check: IntegrityCheck,
+    /// How often a quick check runs after that. `None` turns it off.
+    pub integrity_check_interval: Option<Duration>,
+    /// Refuse everything but reads with a 503. Set at startup when the schema
+    /// has drifted and `schema_drift` is `Maintenance`, or the integrity check
+    /// failed.
+    pub read_only: bool,
+}
+
+impl AppConfig {
+    /// Reads `ENVIRONMENT`, `DATABASE_URL`, `DB_READ_CONNECTIONS`, `WRITE_BACKLOG_MAX`, `CONFIG_FILE`,
+    /// `LISTEN` or `BIND_ADDR`,
+    /// `UNIX_SOCKET_MODE`, `JOURNAL_ENABLED`,
+    /// `STRICT_ACCEPT`, `RESPONSE_CASE`, `SLOW_REQUEST_MS`, `REQUEST_TIMEOUT_MS`,
+    /// `GROWTH_ALERT_PER_HOUR`, `GROWTH_SAMPLE_MINUTES`, `ERROR_RATE_ALERT_PERCENT`,
+    /// `ERROR_RATE_ALERT_WINDOWS`, `ERROR_RATE_ALERT_COOLDOWN_MINUTES`,
+    /// `WAL_CHECKPOINT_MINUTES`, `VACUUM_HOURS`, `VACUUM_FREELIST_PERCENT`,
+    /// `MAINTENANCE_MAX_IN_FLIGHT`, `DB_OPTIMIZE_HOURS`,
+    /// `PENDING_REMINDER_HOURS`, `PENDING_CANCEL_HOURS`, `REOPEN_WINDOW_HOURS`,
+    /// `DRAFT_TTL_HOURS`, `IDEMPOTENCY_TTL_HOURS`, `RETENTION_YEARS`,
+    /// `PII_METADATA_KEYS`, `PICK_LEASE_MINUTES`, `OPEN_ORDERS_PER_CUSTOMER`,
+    /// `ANONYMOUS_OPEN_ORDERS_PER_KEY`, `REPORT_TIMEZONE`, `EVENT_BUS_CAPACITY`,
+    /// `WEBHOOK_CONCURRENCY`, `WEBHOOK_MAX_IN_FLIGHT`, `REPLAY_RATE_PER_SECOND`,
+    /// `WEBHOOK_PROXY_URL`, `WEBHOOK_CA_FILES`, `WEBHOOK_CONNECT_TIMEOUT_MS`,
+    /// `WEBHOOK_TIMEOUT_MS`, `BACKUP_DIR`, `ALLOW_DESTRUCTIVE_OPS`, `CHAOS_HEADER`, `ALWAYS_COUNT`,
+    /// `STATS_REFRESH_SECONDS`, `STATS_DEBOUNCE_MS`, `STATS_BUDGET_MS`,
+    /// `REQUEST_LOG_ENABLED`, `REQUEST_LOG_RETENTION_HOURS`, `REQUEST_LOG_MAX_ROWS`, `IDEMPOTENCY_MAX_ROWS`,
+    /// `JOURNAL_RETENTION_HOURS`, `JOURNAL_MAX_ROWS`, `WEBHOOK_DELIVERY_RETENTION_HOURS`,
+    /// `WEBHOOK_DELIVERY_MAX_ROWS`, `AUDIT_LOG_RETENTION_HOURS`, `AUDIT_LOG_MAX_ROWS`,
+    /// `REDACTED_FIELDS`, `MENTIONABLE_USERS`, `DEFAULT_LOCALE`, `ADMIN_CSRF_KEY`, `REQUIRE_API_KEY`,
+    /// `API_KEY_ROTATION_OVERLAP_HOURS`, `BOOTSTRAP_API_KEY`,
+    /// `DISABLED_FEATURES`, `SCHEMA_DRIFT`, `INTEGRITY_CHECK` and
+    /// `INTEGRITY_CHECK_HOURS`, see `Default` for the values
+    /// used when they're unset. Every one but `CONFIG_FILE` itself can be set
+    /// in the config file too, which wins over the environment.
+    pub fn from_env() -> Result<Self> {
+        let config_file = env::var_os("CONFIG_FILE")
+            .filter(|file| !file.is_empty())
+            .map(PathBuf::from);
+
+        Self::from_source(&Source::read(config_file.as_deref())?, config_file)
+    }
+
+    /// Reads the config again from the environment and the same config file,
+    /// for a reload. `read_only` is decided at startup, so it's kept.
+    pub fn reread(&self) -> Result<Self> {
+        let source = Source::read(self.config_file.as_deref())?;
+
+        Ok(Self {
+            read_only: self.read_only,
+            ..Self::from_source(&source, self.config_file.clone())?
+        })
+    }
+
+    /// The settings `new` changes that are only read at startup, by the
+    /// listeners, the router or the background tasks, so a reload can't apply
+    /// them.
+    pub fn fixed_changes(&self, new: &AppConfig) -> Vec<&'static str> {
+        let fields = [
+            ("database_url", self.database_url != new.database_url),
+            ("db_read_connections", self.db_read_connections != new.db_read_connections),
+            ("config_file", self.config_file != new.config_file),
+            ("listeners", self.listeners != new.listeners),
+            ("unix_socket_mode", self.unix_socket_mode != new.unix_socket_mode),
+            ("growth_sample_interval", self.growth_sample_interval != new.growth_sample_interval),
+            ("wal_checkpoint_interval", self.wal_checkpoint_interval != new.wal_checkpoint_interval),
+            ("vacuum_interval", self.vacuum_interval != new.vacuum_interval),
+            ("db_optimize_interval", self.db_optimize_interval != new.db_optimize_interval),
+            ("event_bus_capacity", self.event_bus_capacity != new.event_bus_capacity),
+            ("webhook_concurrency", self.webhook_concurrency != new.webhook_concurrency),
+            ("webhook_max_in_flight", self.webhook_max_in_flight != new.webhook_max_in_flight),
+            ("replay_rate_per_second", self.replay_rate_per_second != new.replay_rate_per_second),
+            ("webhook_http", self.webhook_http != new.webhook_http),
+            ("always_count", self.always_count != new.always_count),
+            ("bootstrap_api_key", self.bootstrap_api_key != new.bootstrap_api_key),
+            ("stats_refresh_interval", self.stats_refresh_interval != new.stats_refresh_interval),
+            ("features", self.features != new.features),
+            ("schema_drift", self.schema_drift != new.schema_drift),
+            ("integrity_check", self.integrity_check != new.integrity_check),
+            ("integrity_check_interval", self.integrity_check_interval != new.integrity_check_interval),
+        ];
+
+        fields
+            .into_iter()
+            .filter_map(|(field, changed)| changed.then_some(field))
+            .collect()
+    }
+
+    fn from_source(source: &Source, config_file: Option<PathBuf>) -> Result<Self> {
+        let environment = match source.var("ENVIRONMENT") {
+            Err(_) => Environment::default(),
+            Ok(value) => Environment::parse(&value)
+                .with_context(|| format!("ENVIRONMENT {value:?} should be dev, staging or prod"))?,
+        };
+
+        let database_url =
+            source.var("DATABASE_URL").unwrap_or_else(|_| DEFAULT_DATABASE_URL.to_string());
+
+        // 0, like unset, keeps the one pool
+        let db_read_connections = match source.var("DB_READ_CONNECTIONS") {
+            Err(_) => None,
+            Ok(value) => Some(
+                value
+                    .parse::<u32>()
+                    .with_context(|| format!("DB_READ_CONNECTIONS {value:?} is not a number"))?,
+            )
+            .filter(|connections| *connections > 0),
+        };
+
+        // BIND_ADDR is the one tcp address from before there could be several
+        let listeners = match (source.var("LISTEN"), source.var("BIND_ADDR")) {
+            (Ok(_), Ok(_)) => bail!("set LISTEN or BIND_ADDR, not both"),
+            (Ok(value), Err(_)) => {
+                ListenerSpec::parse_list(&value).with_context(|| format!("LISTEN {value:?}"))?
+            }
+            (Err(_), Ok(bind_addr)) => {
+                let bind_addr = bind_addr
+                    .parse()
+                    .with_context(|| format!("BIND_ADDR {bind_addr:?} is not a socket address"))?;
+
+                vec![ListenerSpec::Tcp(bind_addr)]
+            }
+            (Err(_), Err(_)) => vec![ListenerSpec::Tcp(DEFAULT_BIND_ADDR.parse().unwrap())],
+        };
+
+        let unix_socket_mode = match source.var("UNIX_SOCKET_MODE") {
+            Err(_) => listeners::DEFAULT_SOCKET_MODE,
+            Ok(value) => match u32::from_str_radix(&value, 8) {
+                Ok(mode) if mode <= 0o777 => mode,
+                _ => bail!("UNIX_SOCKET_MODE {value:?} should be octal permissions, like 660"),
+            },
+        };
+
+        let journal_enabled = env_flag(source, "JOURNAL_ENABLED")?;
+        let strict_accept = env_flag(source, "STRICT_ACCEPT")?;
+
+        let response_case = match source.var("RESPONSE_CASE") {
+            Err(_) => Case::default(),
+            Ok(value) => Case::parse(&value)
+                .with_context(|| format!("RESPONSE_CASE {value:?} should be snake or camel"))?,
+        };
+
+        let slow_request_ms = match source.var("SLOW_REQUEST_MS") {
+            Err(_) => DEFAULT_SLOW_REQUEST_MS,
+            Ok(value) => value
+                .parse()
+                .with_context(|| format!("SLOW_REQUEST_MS {value:?} is not a number"))?,
+        };
+
+        let request_timeout_ms = match source.var("REQUEST_TIMEOUT_MS") {
+            Err(_) => DEFAULT_REQUEST_TIMEOUT_MS,
+            Ok(value) => match value.parse::<u64>() {
+                Ok(ms) if ms > 0 => ms,
+                _ => bail!("REQUEST_TIMEOUT_MS {value:?} should be a number of milliseconds above 0"),
+            },
+        };
+
+        let growth_alert_per_hour = match source.var("GROWTH_ALERT_PER_HOUR") {
+            Err(_) => DEFAULT_GROWTH_ALERT_PER_HOUR,
+            Ok(value) => value
+                .parse()
+                .with_context(|| format!("GROWTH_ALERT_PER_HOUR {value:?} is not a number"))?,
+        };
+
+        let growth_sample_interval = match source.var("GROWTH_SAMPLE_MINUTES") {
+            Err(_) => growth::DEFAULT_SAMPLE_INTERVAL,
+            Ok(value) => match value.parse::<u64>() {
+                Ok(minutes) if minutes > 0 => Duration::from_secs(minutes * 60),
+                _ => bail!("GROWTH_SAMPLE_MINUTES {value:?} should be a number of minutes above 0"),
+            },
+        };
+
+        let error_rate_alert_percent = match source.var("ERROR_RATE_ALERT_PERCENT") {
+            Err(_) => error_rate::DEFAULT_ALERT_PERCENT,
+            Ok(value) => match value.parse::<u64>() {
+                Ok(percent) if (1..=100).contains(&percent) => percent,
+                _ => bail!("ERROR_RATE_ALERT_PERCENT {value:?} should be a percent from 1 to 100"),
+            },
+        };
+        let error_rate_alert_windows =
+            env_count(source, "ERROR_RATE_ALERT_WINDOWS", error_rate::DEFAULT_ALERT_WINDOWS)?;
+
+        let error_rate_alert_cooldown = match source.var("ERROR_RATE_ALERT_COOLDOWN_MINUTES") {
+            Err(_) => error_rate::DEFAULT_ALERT_COOLDOWN,
+            Ok(value) => match value.parse::<u64>() {
+                Ok(cooldown) if cooldown > 0 => minutes(cooldown),
+                _ => bail!(
+                    "ERROR_RATE_ALERT_COOLDOWN_MINUTES {value:?} should be a number of minutes above 0"
+                ),
+            },
+        };
+
+        // 0 turns either off, like DB_OPTIMIZE_HOURS
+        let wal_checkpoint_interval = match source.var("WAL_CHECKPOINT_MINUTES") {
+            Err(_) => Some(maintenance::DEFAULT_CHECKPOINT_INTERVAL),
+            Ok(value) => value
+                .parse()
+                .map(|interval| (interval > 0).then(|| minutes(interval)))
+                .with_context(|| format!("WAL_CHECKPOINT_MINUTES {value:?} is not a number"))?,
+        };
+        let vacuum_interval = match source.var("VACUUM_HOURS") {
+            Err(_) => Some(maintenance::DEFAULT_VACUUM_INTERVAL),
+            Ok(value) => value
+                .parse()
+                .map(|interval| (interval > 0).then(|| hours(interval)))
+                .with_context(|| format!("VACUUM_HOURS {value:?} is not a number"))?,
+        };
+
+        let vacuum_freelist_percent = match source.var("VACUUM_FREELIST_PERCENT") {
+            Err(_) => maintenance::DEFAULT_VACUUM_FREELIST_PERCENT,
+            Ok(value) => match value.parse::<u64>() {
+                Ok(percent) if (1..=100).contains(&percent) => percent,
+                _ => bail!("VACUUM_FREELIST_PERCENT {value:?} should be a percent from 1 to 100"),
+            },
+        };
+
+        // 0 only runs maintenance when nothing else is going on
+        // 0 turns the limit off
+        let write_backlog_max = match source.var("WRITE_BACKLOG_MAX") {
+            Err(_) => Some(DEFAULT_WRITE_BACKLOG_MAX),
+            Ok(value) => value
+                .parse()
+                .map(|max| (max > 0).then_some(max))
+                .with_context(|| format!("WRITE_BACKLOG_MAX {value:?} is not a number"))?,
+        };
+
+        let maintenance_max_in_flight = match source.var("MAINTENANCE_MAX_IN_FLIGHT") {
+            Err(_) => maintenance::DEFAULT_MAX_IN_FLIGHT,
+            Ok(value) => value
+                .parse()
+                .with_context(|| format!("MAINTENANCE_MAX_IN_FLIGHT {value:?} is not a number"))?,
+        };
+
+        let db_optimize_hours = match source.var("DB_OPTIMIZE_HOURS") {
+            Err(_) => DEFAULT_DB_OPTIMIZE_HOURS,
+            Ok(value) => value
+                .parse()
+                .with_context(|| format!("DB_OPTIMIZE_HOURS {value:?} is not a number"))?,
+        };
+
+        let pending_reminder_hours =
+            env_hours(source, "PENDING_REMINDER_HOURS", DEFAULT_PENDING_REMINDER_HOURS)?;
+        let pending_cancel_hours =
+            env_hours(source, "PENDING_CANCEL_HOURS", DEFAULT_PENDING_CANCEL_HOURS)?;
+
+        if pending_reminder_hours >= pending_cancel_hours {
+            bail!(
+                "PENDING_REMINDER_HOURS {pending_reminder_hours} should be less than PENDING_CANCEL_HOURS {pending_cancel_hours}"
+            );
+        }
+
+        let reopen_window_hours =
+            env_hours(source, "REOPEN_WINDOW_HOURS", DEFAULT_REOPEN_WINDOW_HOURS)?;
+        let draft_ttl_hours = env_hours(source, "DRAFT_TTL_HOURS", DEFAULT_DRAFT_TTL_HOURS)?;
+        let idempotency_ttl_hours =
+            env_hours(source, "IDEMPOTENCY_TTL_HOURS", DEFAULT_IDEMPOTENCY_TTL_HOURS)?;
+        let idempotency_max_rows = env_limit(source, "IDEMPOTENCY_MAX_ROWS", None)?;
+
+        // 0, like unset, keeps everything
+        let retention_years = match source.var("RETENTION_YEARS") {
+            Err(_) => None,
+            Ok(value) => match value.parse::<u32>() {
+                Ok(0) => None,
+                Ok(years) if years <= 100 => Some(years),
+                _ => bail!("RETENTION_YEARS {value:?} should be a number of years up to 100"),
+            },
+        };
+
+        let pii_metadata_keys = source.var("PII_METADATA_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                metadata::check_key(key)
+                    .map(|_| key.to_string())
+                    .with_context(|| format!("PII_METADATA_KEYS has an invalid key {key:?}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let pick_lease = match source.var("PICK_LEASE_MINUTES") {
+            Err(_) => minutes(DEFAULT_PICK_LEASE_MINUTES),
+            Ok(value) => match value.parse::<u64>() {
+                Ok(lease) if lease > 0 => minutes(lease),
+                _ => bail!("PICK_LEASE_MINUTES {value:?} should be a number of minutes above 0"),
+            },
+        };
+
+        let open_order_limits = OpenOrderLimits {
+            per_customer: env_limit(
+                source,
+                "OPEN_ORDERS_PER_CUSTOMER",
+                Some(DEFAULT_OPEN_ORDERS_PER_CUSTOMER),
+            )?,
+            per_anonymous_key: env_limit(source, "ANONYMOUS_OPEN_ORDERS_PER_KEY", None)?,
+        };
+
+        let report_utc_offset = match source.var("REPORT_TIMEZONE") {
+            Err(_) => 0,
+            Ok(value) => reports::parse_offset(&value).with_context(|| {
+                format!("REPORT_TIMEZONE {value:?} should be UTC or an offset like +02:00")
+            })?,
+        };
+
+        let event_bus_capacity = match source.var("EVENT_BUS_CAPACITY") {
+            Err(_) => bus::DEFAULT_CAPACITY,
+            Ok(value) => match value.parse::<usize>() {
+                Ok(capacity) if capacity > 0 => capacity,
+                _ => bail!("EVENT_BUS_CAPACITY {value:?} should be a number of events above 0"),
+            },
+        };
+
+        let webhook_concurrency =
+            env_count(source, "WEBHOOK_CONCURRENCY", webhooks::DEFAULT_CONCURRENCY)?;
+        let webhook_max_in_flight =
+            env_count(source, "WEBHOOK_MAX_IN_FLIGHT", webhooks::DEFAULT_MAX_IN_FLIGHT)?;
+        let replay_rate_per_second =
+            env_count(source, "REPLAY_RATE_PER_SECOND", webhooks::DEFAULT_REPLAY_RATE)?;
+
+        // the files are read when the clients are built, at startup
+        let webhook_http = webhooks::HttpSettings {
+            proxy: source.var("WEBHOOK_PROXY_URL").ok().filter(|url| !url.is_empty()),
+            ca_files: source.var("WEBHOOK_CA_FILES")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+            connect_timeout: env_millis(
+                source,
+                "WEBHOOK_CONNECT_TIMEOUT_MS",
+                webhooks::DEFAULT_CONNECT_TIMEOUT,
+            )?,
+            timeout: env_millis(source, "WEBHOOK_TIMEOUT_MS", webhooks::DEFAULT_TIMEOUT)?,
+        };
+
+        let backup_dir = source.var("BACKUP_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+
+        let allow_destructive_ops = env_flag(source, "ALLOW_DESTRUCTIVE_OPS")?;
+        let chaos_header = env_flag(source, "CHAOS_HEADER")?;
+        let always_count = env_flag(source, "ALWAYS_COUNT")?;
+        // 0 turns precomputing off
+        let stats_refresh_interval = match source.var("STATS_REFRESH_SECONDS") {
+            Err(_) => Some(stats::DEFAULT_REFRESH_INTERVAL),
+            Ok(value) => value
+                .parse()
+                .map(|seconds| (seconds > 0).then(|| Duration::from_secs(seconds)))
+                .with_context(|| format!("STATS_REFRESH_SECONDS {value:?} is not a number"))?,
+        };
+        let stats_debounce = env_millis(source, "STATS_DEBOUNCE_MS", stats::DEFAULT_DEBOUNCE)?;
+        let stats_budget = env_millis(source, "STATS_BUDGET_MS", stats::DEFAULT_BUDGET)?;
+        let request_log_enabled = env_flag(source, "REQUEST_LOG_ENABLED")?;
+        let request_log_retention_hours = env_hours(
+            source,
+            "REQUEST_LOG_RETENTION_HOURS",
+            DEFAULT_REQUEST_LOG_RETENTION_HOURS,
+        )?;
+        let request_log_max_rows =
+            env_limit(source, "REQUEST_LOG_MAX_ROWS", Some(DEFAULT_MAX_ROWS))?;
+
+        let journal_limits = TableLimits {
+            max_age: env_max_age(
+                source,
+                "JOURNAL_RETENTION_HOURS",
+                Some(DEFAULT_JOURNAL_RETENTION_HOURS),
+            )?,
+            max_rows: env_limit(source, "JOURNAL_MAX_ROWS", Some(DEFAULT_MAX_ROWS))?,
+        };
+        let webhook_delivery_limits = TableLimits {
+            max_age: env_max_age(
+                source,
+                "WEBHOOK_DELIVERY_RETENTION_HOURS",
+                Some(DEFAULT_WEBHOOK_DELIVERY_RETENTION_HOURS),
+            )?,
+            max_rows: env_limit(source, "WEBHOOK_DELIVERY_MAX_ROWS", Some(DEFAULT_MAX_ROWS))?,
+        };
+        let audit_log_limits = TableLimits {
+            max_age: env_max_age(source, "AUDIT_LOG_RETENTION_HOURS", None)?,
+            max_rows: env_limit(source, "AUDIT_LOG_MAX_ROWS", None)?,
+        };
+
+        let redaction = RedactionRules::parse(&source.var("REDACTED_FIELDS").unwrap_or_default())?;
+
+        // comma separated, compared the way mentions are, in lowercase
+        let mentionable_users = source.var("MENTIONABLE_USERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|username| username.trim().trim_start_matches('@').to_lowercase())
+            .filter(|username| !username.is_empty())
+            .collect();
+
+        let default_locale = match source.var("DEFAULT_LOCALE") {
+            Err(_) => Locale::default(),
+            Ok(value) => Locale::parse(&value).with_context(|| {
+                let supported: Vec<&str> = Locale::ALL.iter().map(|locale| locale.as_str()).collect();
+                format!("DEFAULT_LOCALE {value:?} should be one of {}", supported.join(", "))
+            })?,
+        };
+
+        let admin_csrf_key = source.var("ADMIN_CSRF_KEY")
+            .ok()
+            .filter(|key| !key.is_empty());
+
+        let require_api_key = env_flag(source, "REQUIRE_API_KEY")?;
+        let api_key_rotation_overlap_hours = env_hours(
+            source,
+            "API_KEY_ROTATION_OVERLAP_HOURS",
+            DEFAULT_API_KEY_ROTATION_OVERLAP_HOURS,
+        )?;
+        if api_key_rotation_overlap_hours > api_keys::MAX_ROTATION_OVERLAP_HOURS {
+            bail!(
+                "API_KEY_ROTATION_OVERLAP_HOURS {api_key_rotation_overlap_hours} should be at most {}",
+                api_keys::MAX_ROTATION_OVERLAP_HOURS
+            );
+        }
+        let bootstrap_api_key = source.var("BOOTSTRAP_API_KEY")
+            .ok()
+            .filter(|key| !key.is_empty());
+        // the value isn't in the message, it's a secret
+        if bootstrap_api_key.as_ref().is_some_and(|key| key.len() < MIN_BOOTSTRAP_API_KEY_LEN) {
+            bail!("BOOTSTRAP_API_KEY should be at least {MIN_BOOTSTRAP_API_KEY_LEN} characters");
+        }
+
+        let features = match source.var("DISABLED_FEATURES") {
+            Err(_) => Features::default(),
+            Ok(value) => Features::parse_disabled(&value)?,
+        };
+
+        let schema_drift = match source.var("SCHEMA_DRIFT") {
+            Err(_) => DriftPolicy::default(),
+            Ok(value) => DriftPolicy::parse(&value)
+                .with_context(|| format!("SCHEMA_DRIFT {value:?} should be refuse or maintenance"))?,
+        };
+
+        let integrity_check = match source.var("INTEGRITY_CHECK") {
+            Err(_) => IntegrityCheck::default(),
+            Ok(value) => IntegrityCheck::parse(&value)
+                .with_context(|| format!("INTEGRITY_CHECK {value:?} should be quick or full"))?,
+        };
+        // 0 turns the periodic check off, the one at startup always runs
+        let integrity_check_interval = match source.var("INTEGRITY_CHECK_HOURS") {
+            Err(_) => Some(integrity::DEFAULT_INTERVAL),
+            Ok(value) => value
+                .parse()
+                .map(|interval| (interval > 0).then(|| hours(interval)))
+                .with_context(|| format!("INTEGRITY_CHECK_HOURS {value:?} is not a number"))?,
+        };
+
+        Ok(Self {
+            environment,
+            database_url,
+            db_read_connections,
+            write_backlog_max,
+            config_file,
+            listeners,
+            unix_socket_mode,
+            journal_enabled,
+            strict_accept,
+            response_case,
+            slow_request_threshold: Duration::from_millis(slow_request_ms),
+            request_timeout: Duration::from_millis(request_timeout_ms),
+            growth_alert_per_hour,
+            growth_sample_interval,
+            error_rate_alert_percent,
+            error_rate_alert_windows,
+            error_rate_alert_cooldown,
+            wal_checkpoint_interval,
+            vacuum_interval,
+            vacuum_freelist_percent,
+            maintenance_max_in_flight,
+            db_optimize_interval: optimize_interval(db_optimize_hours),
+            pending_reminder_after: hours(pending_reminder_hours),
+            pending_cancel_after: hours(pending_cancel_hours),
+            reopen_window: hours(reopen_window_hours),
+            draft_ttl: hours(draft_ttl_hours),
+            idempotency_ttl: hours(idempotency_ttl_hours),
+            idempotency_max_rows,
+            retention_years,
+            pii_metadata_keys,
+            pick_lease,
+            open_order_limits,
+            report_utc_offset,
+            event_bus_capacity,
+            webhook_concurrency,
+            webhook_max_in_flight,
+            replay_rate_per_second,
+            webhook_http,
+            backup_dir,
+            allow_destructive_ops,
+            chaos_header,
+            always_count,
+            stats_refresh_interval,
+            stats_debounce,
+            stats_budget,
+            request_log_enabled,
+            request_log_retention: hours(request_log_retention_hours),
+            request_log_max_rows,
+            journal_limits,
+            webhook_delivery_limits,
+            audit_log_limits,
+            redaction,
+            mentionable_users,
+            default_locale,
+            admin_csrf_key,
+            require_api_key,
+            api_key_rotation_overlap: hours(api_key_rotation_overlap_hours),
+            bootstrap_api_key,
+            features,
+            schema_drift,
+            integrity_check,
+            integrity_check_interval,
+            read_only: false,
+        })
+    }
+}
+
+fn env_flag(source: &Source, name: &str) -> Result<bool> {
+    match source.var(name) {
+        Err(_) => Ok(false),
+        Ok(value) => match value.as_str() {
+            "1" | "true" => Ok(true),
+            "0" | "false" | "" => Ok(false),
+            _ => bail!("{name} {value:?} should be true or false"),
+        },
+    }
+}
+
+/// A whole number of hours above 0.
+fn env_hours(source: &Source, name: &str, default: u64) -> Result<u64> {
+    match source.var(name) {
+        Err(_) => Ok(default),
+        Ok(value) => match value.parse::<u64>() {
+            Ok(hours) if hours > 0 => Ok(hours),
+            _ => bail!("{name} {value:?} should be a number of hours above 0"),
+        },
+    }
+}
+
+/// A whole number above 0, or 0 for no limit.
+fn env_limit(source: &Source, name: &str, default: Option<i64>) -> Result<Option<i64>> {
+    match source.var(name) {
+        Err(_) => Ok(default),
+        Ok(value) => match value.parse::<i64>() {
+            Ok(0) => Ok(None),
+            Ok(limit) if limit > 0 => Ok(Some(limit)),
+            _ => bail!("{name} {value:?} should be a number above 0, or 0 for no limit"),
+        },
+    }
+}
+
+/// A whole number of hours, or 0 to keep rows however old they are.
+fn env_max_age(source: &Source, name: &str, default: Option<u64>) -> Result<Option<Duration>> {
+    let hours = match source.var(name) {
+        Err(_) => default,
+        Ok(value) => match value.parse::<u64>() {
+            Ok(0) => None,
+            Ok(hours) => Some(hours),
+            _ => bail!("{name} {value:?} should be a number of hours, or 0 to keep every row"),
+        },
+    };
+
+    Ok(hours.map(self::hours))
+}
+
+fn env_count(source: &Source, name: &str, default: usize) -> Result<usize> {
+    match source.var(name) {
+        Err(_) => Ok(default),
+        Ok(value) => match value.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => bail!("{name} {value:?} should be a number above 0"),
+        },
+    }
+}
+
+/// A whole number of milliseconds above 0.
+fn env_millis(source: &Source, name: &str, default: Duration) -> Result<Duration> {
+    match source.var(name) {
+        Err(_) => Ok(default),
+        Ok(value) => match value.parse::<u64>() {
+            Ok(ms) if ms > 0 => Ok(Duration::from_millis(ms)),
+            _ => bail!("{name} {value:?} should be a number of milliseconds above 0"),
+        },
+    }
+}
+
+fn minutes(minutes: u64) -> Duration {
+    Duration::from_secs(minutes * 60)
+}
+
+fn hours(hours: u64) -> Duration {
+    Duration::from_secs(hours * 60 * 60)
+}
+
+/// 0 hours turns optimizing off.
+fn optimize_interval(hours: u64) -> Option<Duration> {
+    (hours > 0).then(|| self::hours(hours))
+}
+
+/// The variables the config is read from, the environment's with the config
+/// file's on top.
+struct Source {
+    vars: HashMap<String, String>,
+}
+
+impl Source {
+    fn read(config_file: Option<&Path>) -> Result<Self> {
+        // a variable that isn't unicode is treated as unset, as env::var does
+        let mut vars: HashMap<String, String> = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+
+        if let Some(path) = config_file {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("reading CONFIG_FILE {}", path.display()))?;
+
+            for (number, line) in contents.lines().enumerate() {
+                let line = line.trim();
+
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let Some((name, value)) = line.split_once('=') else {
+                    bail!(
+                        "CONFIG_FILE {} line {} should be NAME=value",
+                        path.display(),
+                        number + 1
+                    );
+                };
+
+                vars.insert(name.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        Ok(Self { vars })
+    }
+
+    fn var(&self, name: &str) -> Result<String, env::VarError> {
+        self.vars.get(name).cloned().ok_or(env::VarError::NotPresent)
+    }
+}
+
+/// The config requests are served with, swapped whole by a reload so a
+/// request sees either the old config or the new one. Each reload bumps the
+/// generation, which `/meta/config` and the logs show.
+#[derive(Debug)]
+pub struct LiveConfig {
+    current: RwLock<Arc<AppConfig>>,
+    generation: AtomicU64,
+}
+
+/// Why a reload was refused, the old config stays in use.
+#[derive(Debug, thiserror::Error)]
+pub enum ReloadError {
+    #[error("the new config is invalid: {0:#}")]
+    Invalid(anyhow::Error),
+    #[error("these settings can't change without a restart: {}", .0.join(", "))]
+    Fixed(Vec<&'static str>),
+}
+
+impl LiveConfig {
+    pub fn new(config: AppConfig) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config)),
+            generation: AtomicU64::new(1),
+        }
+    }
+
+    /// The config as of now. Read it once per request or pass, a reload
+    /// doesn't change a config already loaded.
+    pub fn load(&self) -> Arc<AppConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Reads the config again and swaps it in, returning the new generation.
+    pub fn reload(&self) -> Result<u64, ReloadError> {
+        let mut current = self.current.write().unwrap();
+
+        let swapped = current
+            .reread()
+            .map_err(ReloadError::Invalid)
+            .and_then(|new| match current.fixed_changes(&new) {
+                fixed if fixed.is_empty() => Ok(new),
+                fixed => Err(ReloadError::Fixed(fixed)),
+            });
+
+        match swapped {
+            Ok(new) => {
+                *current = Arc::new(new);
+                let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+                tracing::info!("reloaded the config, now at generation {generation}");
+                Ok(generation)
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "refused to reload the config, staying at generation {}: {err}",
+                    self.generation()
+                );
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Reloads the config on every `SIGHUP` until the process exits.
+pub async fn reload_on_hangup(config: Arc<LiveConfig>) {
+    let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            tracing::warn!("can't listen for SIGHUP, reload with POST /admin/config/reload: {err}");
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        // a refused reload is logged, the old config carries on
+        let _ = config.reload();
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            environment: Environment::default(),
+            database_url: DEFAULT_DATABASE_URL.to_string(),
+            db_read_connections: None,
+            write_backlog_max: Some(DEFAULT_WRITE_BACKLOG_MAX),
+            config_file: None,
+            listeners: vec![ListenerSpec::Tcp(DEFAULT_BIND_ADDR.parse().unwrap())],
+            unix_socket_mode: listeners::DEFAULT_SOCKET_MODE,
+            journal_enabled: false,
+            strict_accept: false,
+            response_case: Case::default(),
+            slow_request_threshold: Duration::from_millis(DEFAULT_SLOW_REQUEST_MS),
+            request_timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
+            growth_alert_per_hour: DEFAULT_GROWTH_ALERT_PER_HOUR,
+            growth_sample_interval: growth::DEFAULT_SAMPLE_INTERVAL,
+            error_rate_alert_percent: error_rate::DEFAULT_ALERT_PERCENT,
+            error_rate_alert_windows: error_rate::DEFAULT_ALERT_WINDOWS,
+            error_rate_alert_cooldown: error_rate::DEFAULT_ALERT_COOLDOWN,
+            wal_checkpoint_interval: Some(maintenance::DEFAULT_CHECKPOINT_INTERVAL),
+            vacuum_interval: Some(maintenance::DEFAULT_VACUUM_INTERVAL),
+            vacuum_freelist_percent: maintenance::DEFAULT_VACUUM_FREELIST_PERCENT,
+            maintenance_max_in_flight: maintenance::DEFAULT_MAX_IN_FLIGHT,
+            db_optimize_interval: optimize_interval(DEFAULT_DB_OPTIMIZE_HOURS),
+            pending_reminder_after: hours(DEFAULT_PENDING_REMINDER_HOURS),
+            pending_cancel_after: hours(DEFAULT_PENDING_CANCEL_HOURS),
+            reopen_window: hours(DEFAULT_REOPEN_WINDOW_HOURS),
+            draft_ttl: hours(DEFAULT_DRAFT_TTL_HOURS),
+            idempotency_ttl: hours(DEFAULT_IDEMPOTENCY_TTL_HOURS),
+            idempotency_max_rows: None,
+            retention_years: None,
+            pii_metadata_keys: Vec::new(),
+            pick_lease: minutes(DEFAULT_PICK_LEASE_MINUTES),
+            open_order_limits: OpenOrderLimits {
+                per_customer: Some(DEFAULT_OPEN_ORDERS_PER_CUSTOMER),
+                per_anonymous_key: None,
+            },
+            report_utc_offset: 0,
+            event_bus_capacity: bus::DEFAULT_CAPACITY,
+            webhook_concurrency: webhooks::DEFAULT_CONCURRENCY,
+            webhook_max_in_flight: webhooks::DEFAULT_MAX_IN_FLIGHT,
+            replay_rate_per_second: webhooks::DEFAULT_REPLAY_RATE,
+            webhook_http: webhooks::HttpSettings::default(),
+            backup_dir: None,
+            allow_destructive_ops: false,
+            chaos_header: false,
+            always_count: false,
+            stats_refresh_interval: Some(stats::DEFAULT_REFRESH_INTERVAL),
+            stats_debounce: stats::DEFAULT_DEBOUNCE,
+            stats_budget: stats::DEFAULT_BUDGET,
+            request_log_enabled: false,
+            request_log_retention: hours(DEFAULT_REQUEST_LOG_RETENTION_HOURS),
+            request_log_max_rows: Some(DEFAULT_MAX_ROWS),
+            journal_limits: TableLimits {
+                max_age: Some(hours(DEFAULT_JOURNAL_RETENTION_HOURS)),
+                max_rows: Some(DEFAULT_MAX_ROWS),
+            },
+            webhook_delivery_limits: TableLimits {
+                max_age: Some(hours(DEFAULT_WEBHOOK_DELIVERY_RETENTION_HOURS)),
+                max_rows: Some(DEFAULT_MAX_ROWS),
+            },
+            audit_log_limits: TableLimits::default(),
+            redaction: RedactionRules::default(),
+            mentionable_users: Vec::new(),
+            default_locale: Locale::default(),
+            admin_csrf_key: None,
+            require_api_key: false,
+            api_key_rotation_overlap: hours(DEFAULT_API_KEY_ROTATION_OVERLAP_HOURS),
+            bootstrap_api_key: None,
+            features: Features::default(),
+            schema_drift: DriftPolicy::default(),
+            integrity_check: IntegrityCheck::default(),
+            integrity_check_interval: Some(integrity::DEFAULT_INTERVAL),
+            read_only: false,
+        }
+    }
+}
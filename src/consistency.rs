@@ -0,0 +1,144 @@
+//! Assertions about the data that the schema can't enforce, run on demand from
+//! `/admin/consistency-check`. To add a rule, add a `Check` to `CHECKS`.
+
+use anyhow::Result;
+
+use crate::{db::Db, timing::Timed};
+
+/// Violating order ids listed per check, the total is counted regardless.
+pub const MAX_LISTED: i64 = 100;
+
+pub struct Check {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Selects the distinct ids of orders that break the rule.
+    sql: &'static str,
+}
+
+pub const CHECKS: &[Check] = &[
+    Check {
+        name: "order_amount_matches_items",
+        description: "orders with items have an amount equal to the sum of their items",
+        sql: "select o.id from orders o
+            join order_items i on i.order_id = o.id
+            group by o.id
+            having o.amount != sum(i.quantity * i.unit_price)",
+    },
+    Check {
+        name: "item_quantity_positive",
+        description: "every item has a quantity of at least 1",
+        sql: "select distinct order_id from order_items where quantity < 1",
+    },
+];
+
+#[derive(Debug)]
+pub struct Violations {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Every violating order, including ones past `MAX_LISTED`.
+    pub total: i64,
+    pub order_ids: Vec<i64>,
+}
+
+impl Check {
+    pub async fn run(&self, db: &Db) -> Result<Violations> {
+        let total = sqlx::query_scalar(&format!("select count(*) from ({})", self.sql))
+            .fetch_one(db.reader())
+            .timed()
+            .await?;
+
+        let order_ids = sqlx::query_scalar(&format!("{} order by 1 limit ?", self.sql))
+            .bind(MAX_LISTED)
+            .fetch_all(db.reader())
+            .timed()
+            .await?;
+
+        Ok(Violations {
+            name: self.name,
+            description: self.description,
+            total,
+            order_ids,
+        })
+    }
+}
+
+/// Runs every check, passing ones are included with a total of 0.
+pub async fn run_all(db: &Db) -> Result<Vec<Violations>> {
+    let mut report = Vec::with_capacity(CHECKS.len());
+
+    for check in CHECKS {
+        report.push(check.run(db).await?);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, items::Item, orders::Order};
+
+    use super::*;
+
+    async fn order_with_item(db: &Db, amount: i64, quantity: i64, unit_price: i64) -> i64 {
+        let mut order = Order::new(amount);
+        order.save(db.writer()).await.unwrap();
+
+        let mut item = Item {
+            order_id: order.id.unwrap(),
+            description: "Widget".to_string(),
+            quantity,
+            unit_price,
+            ..Default::default()
+        };
+        item.save(db).await.unwrap();
+
+        order.id.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_consistent_data_passes() {
+        let db = test_db().await;
+
+        order_with_item(&db, 500, 2, 250).await;
+        Order::new(300).save(db.writer()).await.unwrap();
+
+        for violations in run_all(&db).await.unwrap() {
+            assert_eq!(violations.total, 0, "{} should pass", violations.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flags_corrupted_rows() {
+        let db = test_db().await;
+
+        order_with_item(&db, 500, 2, 250).await;
+        let corrupted = order_with_item(&db, 500, 2, 250).await;
+
+        sqlx::query("update order_items set quantity = 0 where order_id = ?")
+            .bind(corrupted)
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        let report = run_all(&db).await.unwrap();
+
+        for violations in report {
+            assert_eq!(violations.total, 1, "{} should fail", violations.name);
+            assert_eq!(violations.order_ids, vec![corrupted]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lists_at_most_max_listed() {
+        let db = test_db().await;
+
+        for _ in 0..MAX_LISTED + 5 {
+            order_with_item(&db, 1, 2, 250).await;
+        }
+
+        let violations = CHECKS[0].run(&db).await.unwrap();
+
+        assert_eq!(violations.total, MAX_LISTED + 5);
+        assert_eq!(violations.order_ids.len(), MAX_LISTED as usize);
+    }
+}
@@ -0,0 +1,49 @@
+//! Limits on request fields. The handlers enforce these and `/meta/schemas`
+//! publishes them, so both read from here to stay in agreement.
+
+use crate::error::{CustomError, Result};
+
+pub const AMOUNT_MIN: i64 = 1;
+pub const QUANTITY_MIN: i64 = 1;
+pub const DESCRIPTION_MAX_LEN: usize = 256;
+pub const NOTE_MAX_LEN: usize = 2000;
+pub const ACTOR_MAX_LEN: usize = 100;
+pub const CUSTOMER_ID_MAX_LEN: usize = 64;
+pub const REASON_MAX_LEN: usize = 256;
+pub const API_KEY_NAME_MAX_LEN: usize = 100;
+/// Operations in one `POST /orders/transactions`.
+pub const TRANSACTION_MAX_OPERATIONS: usize = 10;
+pub const METADATA_MAX_KEYS: usize = 20;
+pub const METADATA_KEY_MAX_LEN: usize = 64;
+/// Keys end up in sqlite json paths, so they're kept to characters that need
+/// no quoting. `metadata::check_key` enforces the same thing.
+pub const METADATA_KEY_PATTERN: &str = "^[A-Za-z0-9_-]+$";
+pub const METADATA_VALUE_MAX_LEN: usize = 256;
+pub const TAGS_MAX_PER_ORDER: usize = 20;
+/// `tags::check_tag` also keeps tags to letters, digits, `_` and `-`.
+pub const TAG_MAX_LEN: usize = 64;
+/// `Currency::parse` enforces the same thing.
+pub const CURRENCY_PATTERN: &str = "^[A-Z]{3}$";
+pub const ADDRESS_FIELD_MAX_LEN: usize = 128;
+/// `address::country` checks the code is an assigned one too.
+pub const COUNTRY_PATTERN: &str = "^[A-Za-z]{2}$";
+
+pub fn check_min(field: &str, value: i64, min: i64) -> Result<()> {
+    if value < min {
+        return Err(CustomError::Validation(format!(
+            "{field} must be at least {min}"
+        )));
+    }
+
+    Ok(())
+}
+
+pub fn check_max_len(field: &str, value: &str, max: usize) -> Result<()> {
+    if value.chars().count() > max {
+        return Err(CustomError::Validation(format!(
+            "{field} must be at most {max} characters"
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! Cached order counts by status, for `/orders/count`. A change to one
+//! order's status adjusts the cache as it's published, see `caches.rs`, a
+//! bigger change like an import marks it stale, and `reconcile` replaces it
+//! with a real `COUNT` now and then, which repairs whatever the adjustments
+//! missed: archiving, or a write racing a reconcile.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    AppState,
+    caches::Lookups,
+    db::{self, Db},
+    orders::OrderStatus,
+    timing::Timed,
+};
+
+/// How often `reconcile_every` recounts the table.
+pub const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+pub struct StatusCounts {
+    cache: Mutex<Option<Cache>>,
+    pub(crate) lookups: Lookups,
+}
+
+#[derive(Debug)]
+struct Cache {
+    counts: HashMap<OrderStatus, i64>,
+    reconciled_at: Instant,
+    /// Set when orders changed in a way the counts couldn't be adjusted for.
+    stale: bool,
+}
+
+impl StatusCounts {
+    /// The cached count of orders in `status`, or of every placed order when
+    /// `None`, with how long ago it was last reconciled. `None` until the
+    /// first reconcile and while it's stale.
+    pub fn get(&self, status: Option<OrderStatus>) -> Option<(i64, Duration)> {
+        let cache = self.cache.lock().unwrap();
+        let cache = cache.as_ref().filter(|cache| !cache.stale)?;
+
+        Some((pick(&cache.counts, status), cache.reconciled_at.elapsed()))
+    }
+
+    /// `get`, reconciling first when there's nothing to get.
+    pub async fn get_or_count(&self, db: &Db, status: Option<OrderStatus>) -> Result<(i64, Duration)> {
+        let cached = self.get(status);
+        self.lookups.record(cached.is_some());
+
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+
+        self.reconcile(db).await?;
+
+        Ok(self.get(status).unwrap_or_default())
+    }
+
+    /// Every cached count, `None` until the first reconcile. A stale cache's
+    /// counts are kept until the next one.
+    pub fn snapshot(&self) -> Option<HashMap<OrderStatus, i64>> {
+        let cache = self.cache.lock().unwrap();
+
+        cache.as_ref().map(|cache| cache.counts.clone())
+    }
+
+    pub fn created(&self, status: OrderStatus) {
+        self.adjust(status, 1);
+    }
+
+    pub fn moved(&self, from: OrderStatus, to: OrderStatus) {
+        if from != to {
+            self.adjust(from, -1);
+            self.adjust(to, 1);
+        }
+    }
+
+    pub fn removed(&self, status: OrderStatus, orders: i64) {
+        self.adjust(status, -orders);
+    }
+
+    fn adjust(&self, status: OrderStatus, by: i64) {
+        // nothing to adjust before the first reconcile, it'll count this anyway
+        if let Some(cache) = self.cache.lock().unwrap().as_mut() {
+            *cache.counts.entry(status).or_default() += by;
+        }
+    }
+
+    /// Has the next `get_or_count` recount.
+    pub fn mark_stale(&self) {
+        if let Some(cache) = self.cache.lock().unwrap().as_mut() {
+            cache.stale = true;
+        }
+    }
+
+    /// Replaces the cache with a fresh count, returning how many statuses'
+    /// cached counts were wrong.
+    pub async fn reconcile(&self, db: &Db) -> Result<usize> {
+        let counts = count(db).await?;
+        let mut cache = self.cache.lock().unwrap();
+
+        let corrected = match cache.as_ref() {
+            Some(cached) => OrderStatus::ALL
+                .into_iter()
+                .chain([OrderStatus::Draft])
+                .filter(|status| {
+                    cached.counts.get(status).copied().unwrap_or(0)
+                        != counts.get(status).copied().unwrap_or(0)
+                })
+                .count(),
+            None => 0,
+        };
+
+        *cache = Some(Cache {
+            counts,
+            reconciled_at: Instant::now(),
+            stale: false,
+        });
+
+        Ok(corrected)
+    }
+
+    #[cfg(test)]
+    pub fn corrupt(&self, status: OrderStatus, count: i64) {
+        if let Some(cache) = self.cache.lock().unwrap().as_mut() {
+            cache.counts.insert(status, count);
+        }
+    }
+}
+
+/// Counts orders by status with a full scan.
+pub async fn count(db: &Db) -> Result<HashMap<OrderStatus, i64>> {
+    let rows = db::read(|| {
+        sqlx::query!(
+            r#"select status, count(*) as "count!: i64"
+            from orders group by status"#
+        )
+        .fetch_all(db.reader())
+        .timed()
+    })
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (OrderStatus::from(row.status), row.count))
+        .collect())
+}
+
+/// The count for `status`, or the total of every status but drafts when `None`.
+pub fn pick(counts: &HashMap<OrderStatus, i64>, status: Option<OrderStatus>) -> i64 {
+    match status {
+        Some(status) => counts.get(&status).copied().unwrap_or(0),
+        None => counts
+            .iter()
+            .filter(|(status, _)| **status != OrderStatus::Draft)
+            .map(|(_, count)| count)
+            .sum(),
+    }
+}
+
+/// Recounts every `RECONCILE_INTERVAL`, starting straight away, until the
+/// process exits.
+pub async fn reconcile_every(state: AppState) {
+    let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match state.counts.reconcile(&state.db).await {
+            Ok(corrected) if corrected > 0 => {
+                tracing::warn!("corrected the cached order counts of {corrected} statuses");
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("failed to reconcile order counts: {err:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, orders::Order};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reconcile_repairs_a_corrupted_count() {
+        let db = test_db().await;
+        let counts = StatusCounts::default();
+
+        assert_eq!(counts.get(Some(OrderStatus::Pending)), None);
+
+        Order::new(500).save(db.writer()).await.unwrap();
+        Order::new(700).save(db.writer()).await.unwrap();
+
+        assert_eq!(counts.reconcile(&db).await.unwrap(), 0);
+        assert_eq!(counts.get(Some(OrderStatus::Pending)).unwrap().0, 2);
+
+        counts.corrupt(OrderStatus::Pending, 40);
+        assert_eq!(counts.get(None).unwrap().0, 40);
+
+        assert_eq!(counts.reconcile(&db).await.unwrap(), 1);
+        assert_eq!(counts.get(Some(OrderStatus::Pending)).unwrap().0, 2);
+        assert_eq!(counts.get(Some(OrderStatus::Complete)).unwrap().0, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_counts_are_recounted() {
+        let db = test_db().await;
+        let counts = StatusCounts::default();
+
+        assert_eq!(counts.get_or_count(&db, None).await.unwrap().0, 0);
+
+        // like an import, which doesn't adjust the counts
+        Order::new(500).save(db.writer()).await.unwrap();
+        assert_eq!(counts.get_or_count(&db, None).await.unwrap().0, 0);
+
+        counts.mark_stale();
+        assert_eq!(counts.get(None), None);
+        assert_eq!(counts.get_or_count(&db, None).await.unwrap().0, 1);
+
+        assert_eq!((counts.lookups.hits(), counts.lookups.misses()), (1, 2));
+    }
+}
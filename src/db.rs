@@ -1,35 +1,753 @@
-use anyhow::Result;
-use sqlx::{Pool, Sqlite, migrate::MigrateDatabase, sqlite::SqlitePoolOptions};
+use std::{
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
 
-pub type Db = Pool<Sqlite>;
+use anyhow::{Context, Result, bail};
+use futures_util::{
+    StreamExt, TryStreamExt,
+    future::BoxFuture,
+    stream::{self, BoxStream},
+};
+use rand::Rng;
+use sqlx::{
+    Acquire, Describe, Either, Execute, Executor, Pool, Sqlite, SqliteConnection, Transaction,
+    migrate::{MigrateDatabase, Migrator},
+    pool::PoolConnection,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteQueryResult, SqliteRow, SqliteStatement, SqliteTypeInfo},
+};
 
-pub async fn setup_db() -> Db {
-    Sqlite::create_database("sqlite:db/db.sqlite")
-        .await
-        .expect("failed to create database");
+use crate::timing::Timed;
+
+/// The connections the data functions run on. Selects go through `reader`,
+/// anything that writes, and every transaction, through `writer`. Usually
+/// both are the same pool, see `setup_db` for splitting them.
+#[derive(Debug, Clone)]
+pub struct Db {
+    reader: Pool<Sqlite>,
+    writer: Writer,
+}
+
+/// The pool writes go through, counting how many are waiting for one of its
+/// connections. A write holds its connection, and so its turn, until the
+/// connection or the transaction it began is dropped. sqlite has a single
+/// writer, so writes pile up here while reads carry on, and the depth tells
+/// a write backlog apart from load in general.
+#[derive(Debug, Clone)]
+pub struct Writer {
+    pool: Pool<Sqlite>,
+    waiting: Arc<AtomicUsize>,
+}
+
+impl Writer {
+    fn new(pool: Pool<Sqlite>) -> Self {
+        Self {
+            pool,
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Waits for a connection, counted in `depth` until it has one.
+    pub async fn acquire(&self) -> sqlx::Result<PoolConnection<Sqlite>> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        // counted out again if the caller is dropped while it waits
+        let _waiting = Waiting(&self.waiting);
+
+        self.pool.acquire().await
+    }
+
+    /// Begins a transaction on a connection from `acquire`.
+    pub async fn begin(&self) -> sqlx::Result<Transaction<'static, Sqlite>> {
+        let conn = self.acquire().await?;
+
+        Transaction::begin(conn, None).await
+    }
+
+    /// How many writes are waiting for a connection right now.
+    pub fn depth(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
+
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Runs a statement like the pool would, on a connection from `acquire`.
+impl<'c> Executor<'c> for &Writer {
+    type Database = Sqlite;
+
+    /// Collected before they're handed on, the stream can't hold the
+    /// connection it reads from. What goes through the writer returns few rows.
+    fn fetch_many<'e, 'q: 'e, E>(self, query: E) -> BoxStream<'e, sqlx::Result<Either<SqliteQueryResult, SqliteRow>>>
+    where
+        E: 'q + Execute<'q, Sqlite>,
+    {
+        let writer = self.clone();
+
+        stream::once(async move {
+            let mut conn = writer.acquire().await?;
+
+            conn.fetch_many(query).try_collect::<Vec<_>>().await
+        })
+        .map_ok(|results| stream::iter(results.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(self, query: E) -> BoxFuture<'e, sqlx::Result<Option<SqliteRow>>>
+    where
+        E: 'q + Execute<'q, Sqlite>,
+    {
+        let writer = self.clone();
+
+        Box::pin(async move { writer.acquire().await?.fetch_optional(query).await })
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [SqliteTypeInfo],
+    ) -> BoxFuture<'e, sqlx::Result<SqliteStatement<'q>>> {
+        let writer = self.clone();
+
+        Box::pin(async move { writer.acquire().await?.prepare_with(sql, parameters).await })
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, sqlx::Result<Describe<Sqlite>>> {
+        let writer = self.clone();
+
+        Box::pin(async move { writer.acquire().await?.describe(sql).await })
+    }
+}
+
+impl<'a> Acquire<'a> for &Writer {
+    type Database = Sqlite;
+    type Connection = PoolConnection<Sqlite>;
+
+    fn acquire(self) -> BoxFuture<'static, sqlx::Result<Self::Connection>> {
+        let writer = self.clone();
+
+        Box::pin(async move { writer.acquire().await })
+    }
+
+    fn begin(self) -> BoxFuture<'static, sqlx::Result<Transaction<'a, Sqlite>>> {
+        let writer = self.clone();
+
+        Box::pin(async move { writer.begin().await })
+    }
+}
+
+impl Db {
+    pub fn reader(&self) -> &Pool<Sqlite> {
+        &self.reader
+    }
+
+    pub fn writer(&self) -> &Writer {
+        &self.writer
+    }
+
+    pub async fn close(&self) {
+        self.reader.close().await;
+        self.writer.pool.close().await;
+    }
+}
+
+/// One pool for both reads and writes.
+impl From<Pool<Sqlite>> for Db {
+    fn from(pool: Pool<Sqlite>) -> Self {
+        Self {
+            reader: pool.clone(),
+            writer: Writer::new(pool),
+        }
+    }
+}
+
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Tries a read gets before a lock error is returned.
+const READ_ATTEMPTS: u32 = 3;
+/// Bounds of the jittered wait between read attempts, in milliseconds.
+const READ_RETRY_MS: (u64, u64) = (10, 50);
+
+/// Read attempts that hit a locked database and were retried. Kept process
+/// wide rather than in `Metrics` since the data functions only see the pool.
+static READ_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Sqlite steps between checks of whether an interruptible read is still
+/// wanted, well under a millisecond of work.
+const INTERRUPT_CHECK_STEPS: i32 = 1000;
+
+/// Interruptible reads stopped because whoever was waiting on them went away,
+/// process wide like `READ_RETRIES`.
+static CANCELLED_QUERIES: AtomicU64 = AtomicU64::new(0);
 
-    let db = SqlitePoolOptions::new()
-        .connect("sqlite:db/db.sqlite")
+/// Statements run once by `warm_up`, named for the logs. Where the text matches
+/// the query a handler runs, the prepared statement is reused from the
+/// connection's cache. Every `?` is bound to 0.
+const WARM_UP: &[(&str, &str)] = &[
+    ("order_by_id", "select * from orders where id = ?"),
+    ("all_orders", "select * from orders limit 0"),
+    ("order_count", "select count(*) from orders"),
+    ("orders_by_status", "select * from orders where 1 = 1 and status = ?"),
+    ("max_order_id", r#"select max(id) as "max_id: i64" from orders"#),
+];
+
+/// Runs a read, trying it again after a short random wait when sqlite reports
+/// the database busy or locked, which a long write like an import batch can
+/// cause. Only for reads: a write that failed part way is left for the caller
+/// to decide about.
+pub async fn read<T, F, Fut>(mut query: F) -> sqlx::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = sqlx::Result<T>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match query().await {
+            Err(err) if attempt < READ_ATTEMPTS && is_locked(&err) => {
+                READ_RETRIES.fetch_add(1, Ordering::Relaxed);
+                attempt += 1;
+
+                let wait = rand::thread_rng().gen_range(READ_RETRY_MS.0..=READ_RETRY_MS.1);
+                tokio::time::sleep(Duration::from_millis(wait)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// How many reads have been retried by `read` since startup.
+pub fn read_retries() -> u64 {
+    READ_RETRIES.load(Ordering::Relaxed)
+}
+
+/// A reader connection for reads that can run long, like exports and
+/// searches, which stops them when it's dropped before `release`: the client
+/// hung up or the request's deadline passed, and axum dropped the handler or
+/// the response body. sqlite checks back every few steps and abandons the
+/// statement, rather than reading on for nobody while the connection stays
+/// out of the pool.
+///
+/// A dropped one is closed instead of going back to the pool, so its
+/// interrupt can't reach the next read. Transactions need nothing like it,
+/// sqlx rolls back one that's dropped open.
+pub struct Interruptible {
+    conn: Option<PoolConnection<Sqlite>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Interruptible {
+    pub async fn acquire(db: &Db) -> sqlx::Result<Self> {
+        let mut conn = db.reader().acquire().await?;
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let stop = cancelled.clone();
+        conn.lock_handle()
+            .await?
+            .set_progress_handler(INTERRUPT_CHECK_STEPS, move || !stop.load(Ordering::Relaxed));
+
+        Ok(Self {
+            conn: Some(conn),
+            cancelled,
+        })
+    }
+
+    pub fn conn(&mut self) -> &mut SqliteConnection {
+        self.conn.as_mut().expect("an interruptible read is only released once")
+    }
+
+    /// Gives the connection back to the pool once the reads are done, whether
+    /// they worked or not.
+    pub async fn release(mut self) {
+        let Some(mut conn) = self.conn.take() else {
+            return;
+        };
+
+        if let Ok(mut handle) = conn.lock_handle().await {
+            handle.remove_progress_handler();
+            return;
+        }
+
+        conn.close_on_drop();
+    }
+}
+
+impl Drop for Interruptible {
+    fn drop(&mut self) {
+        let Some(mut conn) = self.conn.take() else {
+            return;
+        };
+
+        self.cancelled.store(true, Ordering::Relaxed);
+        CANCELLED_QUERIES.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!("interrupted a read nobody is waiting for");
+
+        conn.close_on_drop();
+    }
+}
+
+/// Runs `query` on an `Interruptible` connection, so dropping the returned
+/// future stops it part way.
+pub async fn interruptible<T>(
+    db: &Db,
+    query: impl AsyncFnOnce(&mut SqliteConnection) -> sqlx::Result<T>,
+) -> sqlx::Result<T> {
+    let mut read = Interruptible::acquire(db).await?;
+    let result = query(read.conn()).await;
+    read.release().await;
+
+    result
+}
+
+/// How many interruptible reads have been cancelled since startup.
+pub fn cancelled_queries() -> u64 {
+    CANCELLED_QUERIES.load(Ordering::Relaxed)
+}
+
+/// `SQLITE_BUSY` or `SQLITE_LOCKED`, including their extended codes.
+fn is_locked(err: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(err) = err else {
+        return false;
+    };
+
+    err.code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, 5 | 6))
+}
+
+/// Creates and migrates the database at `url`. With `read_connections` the
+/// file gets two pools: that many read only connections for selects and a
+/// single connection for writes, so writes queue in the process instead of
+/// fighting over sqlite's lock while reads carry on. That switches the file
+/// to WAL, the journal that lets readers and the writer work at once, and it
+/// stays in WAL after. Without, one pool of sqlx's default size does both.
+pub async fn setup_db(url: &str, read_connections: Option<u32>) -> Result<Db> {
+    Sqlite::create_database(url)
         .await
-        .unwrap();
+        .with_context(|| format!("creating database {url}"))?;
 
-    run_migrations(&db).await.expect("failed to run migrations");
+    let db = match read_connections {
+        None => SqlitePoolOptions::new()
+            .connect(url)
+            .await
+            .with_context(|| format!("connecting to {url}"))?
+            .into(),
+        Some(readers) => connect_split(url, readers)
+            .await
+            .with_context(|| format!("connecting to {url}"))?,
+    };
 
-    db
+    run_migrations(&db).await.context("running migrations")?;
+
+    Ok(db)
+}
+
+async fn connect_split(url: &str, readers: u32) -> Result<Db> {
+    if url.contains(":memory:") {
+        bail!("an in-memory database can't be shared by a read and a write pool");
+    }
+
+    let options: SqliteConnectOptions = url.parse()?;
+
+    // kept open, read only connections can't set up the WAL index themselves
+    let writer = SqlitePoolOptions::new()
+        .max_connections(1)
+        .min_connections(1)
+        .connect_with(options.clone().journal_mode(SqliteJournalMode::Wal))
+        .await?;
+
+    let reader = SqlitePoolOptions::new()
+        .max_connections(readers)
+        .connect_with(options.read_only(true))
+        .await?;
+
+    Ok(Db {
+        reader,
+        writer: Writer::new(writer),
+    })
+}
+
+/// Prepares the hot statements so the first requests after a start don't pay
+/// for compiling them. Runs every statement even if one fails, the error lists
+/// the ones that did.
+pub async fn warm_up(db: &Db) -> Result<usize> {
+    let mut failed = Vec::new();
+
+    for (name, sql) in WARM_UP {
+        let mut query = sqlx::query(sql);
+
+        for _ in 0..sql.matches('?').count() {
+            query = query.bind(0);
+        }
+
+        if let Err(err) = query.fetch_all(db.reader()).await {
+            tracing::warn!("warm up of {name} failed: {err}");
+            failed.push(*name);
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!("warm up failed for {}", failed.join(", "));
+    }
+
+    Ok(WARM_UP.len())
+}
+
+/// Refreshes the statistics the query planner uses to pick indexes. The
+/// analysis limit keeps it quick on large tables at the cost of precision.
+pub async fn optimize(db: &Db) -> Result<()> {
+    sqlx::raw_sql("PRAGMA analysis_limit = 1000; ANALYZE; PRAGMA optimize;")
+        .execute(db.writer())
+        .await?;
+
+    Ok(())
+}
+
+/// Runs `optimize` now and then every `interval` until the process exits.
+pub async fn optimize_every(db: Arc<Db>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = optimize(&db).await {
+            tracing::warn!("failed to optimize the database: {err:#}");
+        }
+    }
 }
 
 async fn run_migrations(db: &Db) -> Result<()> {
-    sqlx::migrate!("./migrations").run(db).await?;
+    MIGRATOR.run(db.writer()).await?;
+
+    Ok(())
+}
+
+/// The versions of every migration this build carries.
+pub fn migration_versions() -> Vec<i64> {
+    MIGRATOR.iter().map(|migration| migration.version).collect()
+}
+
+/// Hands out the next value of the named sequence, 1 the first time. It's
+/// taken in the caller's transaction: sqlite lets one writer in at a time, so
+/// no two callers get the same value, and a rollback gives it back.
+pub async fn next_value(tx: &mut Transaction<'_, Sqlite>, name: &str) -> Result<i64> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO sequences (name, value) VALUES (?, 1)
+        ON CONFLICT (name) DO UPDATE SET value = value + 1
+        RETURNING value;",
+        name
+    )
+    .fetch_one(&mut **tx)
+    .timed()
+    .await?)
+}
+
+/// The last value the sequence handed out, `None` if it never has.
+pub async fn peek(db: &Db, name: &str) -> Result<Option<i64>> {
+    Ok(read(|| {
+        sqlx::query_scalar!("select value from sequences where name = ?", name)
+            .fetch_optional(db.reader())
+            .timed()
+    })
+    .await?)
+}
+
+/// Sets the last value handed out, so the next one is `value + 1`.
+pub async fn reset(db: &Db, name: &str, value: i64) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO sequences (name, value) VALUES (?, ?)
+        ON CONFLICT (name) DO UPDATE SET value = excluded.value;",
+        name,
+        value
+    )
+    .execute(db.writer())
+    .timed()
+    .await?;
+
+    Ok(())
+}
+
+/// Starts every autoincrement id and named sequence over, so after clearing the tables the same
+/// scenario gets the same ids again.
+#[cfg(any(test, feature = "deterministic"))]
+pub async fn reset_sequences(db: &Db) -> Result<()> {
+    sqlx::query("delete from sqlite_sequence").execute(db.writer()).await?;
+    sqlx::query("delete from sequences").execute(db.writer()).await?;
 
     Ok(())
 }
 
 #[cfg(test)]
 pub async fn test_db() -> Db {
-    let db = SqlitePoolOptions::new().connect(":memory:").await.unwrap();
+    let db = SqlitePoolOptions::new().connect(":memory:").await.unwrap().into();
 
     run_migrations(&db).await.expect("failed to run migrations");
 
     db
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::orders::Order;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_warm_up_runs_every_statement() {
+        let db = test_db().await;
+
+        assert_eq!(warm_up(&db).await.unwrap(), WARM_UP.len());
+    }
+
+    #[tokio::test]
+    async fn test_read_retries_while_locked() {
+        let path = std::env::temp_dir().join(format!("read-retry-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // no busy timeout, so a locked database is an error straight away
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Delete)
+            .busy_timeout(Duration::ZERO);
+        let db: Db = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .unwrap()
+            .into();
+
+        sqlx::query("create table t (x integer)")
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        let mut writer = db.writer().acquire().await.unwrap();
+        sqlx::query("begin exclusive")
+            .execute(&mut *writer)
+            .await
+            .unwrap();
+
+        let count = || sqlx::query_scalar::<_, i64>("select count(*) from t").fetch_one(db.reader());
+
+        assert!(is_locked(&count().await.unwrap_err()));
+
+        let retries = read_retries();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            sqlx::query("commit").execute(&mut *writer).await.unwrap();
+        });
+
+        assert_eq!(read(count).await.unwrap(), 0);
+        assert!(read_retries() > retries);
+
+        db.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_interruptible_read_stops() {
+        let db: Db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap()
+            .into();
+
+        // minutes of counting if nothing stopped it
+        let endless = async |conn: &mut SqliteConnection| {
+            sqlx::query_scalar::<_, i64>(
+                "with recursive n(i) as (select 1 union all select i + 1 from n where i < 10000000000)
+                select count(*) from n",
+            )
+            .fetch_one(conn)
+            .await
+        };
+
+        let cancelled = cancelled_queries();
+        let dropped = tokio::time::timeout(Duration::from_millis(50), interruptible(&db, endless)).await;
+        assert!(dropped.is_err());
+        assert!(cancelled_queries() > cancelled);
+
+        // the only connection is given up once the statement has stopped
+        let next = tokio::time::timeout(Duration::from_secs(5), interruptible(&db, async |conn| {
+            sqlx::query_scalar::<_, i64>("select 1").fetch_one(conn).await
+        }))
+        .await;
+        assert_eq!(next.unwrap().unwrap(), 1);
+
+        // and one released normally doesn't interrupt what runs on it next
+        let count = sqlx::query_scalar::<_, i64>(
+            "with recursive n(i) as (select 1 union all select i + 1 from n where i < 100000)
+            select count(*) from n",
+        )
+        .fetch_one(db.reader())
+        .await
+        .unwrap();
+        assert_eq!(count, 100000);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_next_values_are_distinct_and_contiguous() {
+        let path = std::env::temp_dir().join(format!("sequences-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = setup_db(&format!("sqlite:{}", path.display()), None)
+            .await
+            .unwrap();
+
+        let tasks: Vec<_> = (0..100)
+            .map(|_| {
+                let db = db.clone();
+
+                tokio::spawn(async move {
+                    let mut tx = db.writer().begin().await.unwrap();
+                    let value = next_value(&mut tx, "stress").await.unwrap();
+                    tx.commit().await.unwrap();
+
+                    value
+                })
+            })
+            .collect();
+
+        let mut values = Vec::new();
+        for task in tasks {
+            values.push(task.await.unwrap());
+        }
+        values.sort();
+
+        assert_eq!(values, (1..=100).collect::<Vec<_>>());
+        assert_eq!(peek(&db, "stress").await.unwrap(), Some(100));
+
+        db.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    async fn split_db(name: &str) -> (Db, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("{name}-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = setup_db(&format!("sqlite:{}", path.display()), Some(4))
+            .await
+            .unwrap();
+
+        (db, path)
+    }
+
+    async fn remove(db: Db, path: std::path::PathBuf) {
+        db.close().await;
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_split_writes_queue_while_reads_carry_on() {
+        let (db, path) = split_db("split-queue").await;
+
+        let tasks: Vec<_> = (0..50)
+            .map(|i| {
+                let db = db.clone();
+
+                tokio::spawn(async move { reset(&db, &format!("seq-{i}"), i).await.unwrap() })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let count = || sqlx::query_scalar::<_, i64>("select count(*) from sequences").fetch_one(db.reader());
+
+        assert_eq!(count().await.unwrap(), 50);
+
+        // the one write connection is held, so another write waits for it...
+        let mut tx = db.writer().begin().await.unwrap();
+        next_value(&mut tx, "held").await.unwrap();
+
+        let waiting = tokio::time::timeout(Duration::from_millis(50), reset(&db, "queued", 1)).await;
+        assert!(waiting.is_err());
+
+        // ...while reads go ahead, without what isn't committed yet
+        let read = tokio::time::timeout(Duration::from_secs(1), count()).await;
+        assert_eq!(read.unwrap().unwrap(), 50);
+
+        tx.commit().await.unwrap();
+        reset(&db, "queued", 1).await.unwrap();
+
+        assert_eq!(count().await.unwrap(), 52);
+
+        remove(db, path).await;
+    }
+
+    #[tokio::test]
+    async fn test_split_reader_refuses_writes() {
+        let (db, path) = split_db("split-readonly").await;
+
+        let err = sqlx::query("insert into sequences (name, value) values ('stray', 1)")
+            .execute(db.reader())
+            .await
+            .unwrap_err();
+
+        // SQLITE_READONLY
+        let code = err
+            .as_database_error()
+            .and_then(|err| err.code())
+            .and_then(|code| code.parse::<i32>().ok());
+        assert_eq!(code.map(|code| code & 0xff), Some(8));
+
+        assert!(setup_db("sqlite::memory:", Some(4)).await.is_err());
+
+        remove(db, path).await;
+    }
+
+    #[tokio::test]
+    async fn test_peek_and_reset() {
+        let db = test_db().await;
+
+        assert_eq!(peek(&db, "numbers").await.unwrap(), None);
+
+        let mut tx = db.writer().begin().await.unwrap();
+        assert_eq!(next_value(&mut tx, "numbers").await.unwrap(), 1);
+        assert_eq!(next_value(&mut tx, "numbers").await.unwrap(), 2);
+        tx.commit().await.unwrap();
+
+        // a rolled back value is handed out again
+        let mut tx = db.writer().begin().await.unwrap();
+        assert_eq!(next_value(&mut tx, "numbers").await.unwrap(), 3);
+        tx.rollback().await.unwrap();
+        assert_eq!(peek(&db, "numbers").await.unwrap(), Some(2));
+
+        reset(&db, "numbers", 41).await.unwrap();
+        let mut tx = db.writer().begin().await.unwrap();
+        assert_eq!(next_value(&mut tx, "numbers").await.unwrap(), 42);
+        assert_eq!(next_value(&mut tx, "others").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_optimize_analyzes() {
+        let db = test_db().await;
+
+        for _ in 0..10 {
+            Order::new(500).save(db.writer()).await.unwrap();
+        }
+
+        optimize(&db).await.unwrap();
+
+        let stats: i64 = sqlx::query_scalar("select count(*) from sqlite_stat1")
+            .fetch_one(db.reader())
+            .await
+            .unwrap();
+
+        assert!(stats > 0);
+    }
+}
@@ -0,0 +1,308 @@
+//! Request time limits. Every request gets `request_timeout`, and a gateway can
+//! send a shorter deadline in `X-Request-Deadline` so no work is done after the
+//! caller has stopped waiting. Whichever ends first applies.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, error::CustomError};
+
+pub const DEADLINE_HEADER: &str = "x-request-deadline";
+
+/// When the caller stops waiting, put in the request's extensions for
+/// handlers that want to pass what's left of it on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    /// Time left before the deadline, zero once it's passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Reads a deadline header, either the milliseconds remaining or an RFC 3339
+/// timestamp like `2025-09-12T10:00:00.250Z`. A timestamp in the past gives a
+/// deadline that's already over.
+pub fn parse(value: &str, now: Instant, now_system: SystemTime) -> Option<Deadline> {
+    let value = value.trim();
+
+    if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) {
+        let millis: u64 = value.parse().ok()?;
+
+        return now.checked_add(Duration::from_millis(millis)).map(Deadline);
+    }
+
+    let at = parse_rfc3339(value)?;
+
+    match at.duration_since(now_system) {
+        Ok(remaining) => now.checked_add(remaining).map(Deadline),
+        Err(_) => Some(Deadline(now)),
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SS`, optional fractional seconds, then `Z` or `±HH:MM`.
+pub(crate) fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let bytes = value.as_bytes();
+
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = value.get(range)?;
+
+        match digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            true => digits.parse().ok(),
+            false => None,
+        }
+    };
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &value[19..];
+    let mut nanos = 0;
+
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+
+        if len == 0 {
+            return None;
+        }
+
+        let digits = &fraction[..len.min(9)];
+        nanos = digits.parse::<u32>().ok()? * 10_u32.pow(9 - digits.len() as u32);
+        rest = &fraction[len..];
+    }
+
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+
+            let hours: i64 = rest[1..3].parse().ok()?;
+            let minutes: i64 = rest[4..6].parse().ok()?;
+
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset;
+
+    UNIX_EPOCH.checked_add(Duration::new(u64::try_from(seconds).ok()?, nanos))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Runs the rest of the request under `request_timeout`, or the request's own
+/// deadline when that's sooner. A deadline that's already passed is refused
+/// before anything else runs.
+pub async fn enforce(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let now = Instant::now();
+    let limit = state.config().request_timeout;
+
+    let deadline = match request.headers().get(DEADLINE_HEADER) {
+        None => None,
+        Some(value) => match value
+            .to_str()
+            .ok()
+            .and_then(|value| parse(value, now, SystemTime::now()))
+        {
+            Some(deadline) => Some(deadline),
+            None => {
+                return CustomError::BadRequest(format!(
+                    "{DEADLINE_HEADER} should be milliseconds remaining or an RFC 3339 timestamp"
+                ))
+                .into_response();
+            }
+        },
+    };
+
+    let Some(deadline) = deadline else {
+        return match tokio::time::timeout(limit, next.run(request)).await {
+            Ok(response) => response,
+            Err(_) => CustomError::Timeout(limit).into_response(),
+        };
+    };
+
+    let remaining = deadline.remaining();
+
+    if remaining.is_zero() {
+        return CustomError::DeadlineExceeded.into_response();
+    }
+
+    request.extensions_mut().insert(deadline);
+
+    match tokio::time::timeout(remaining.min(limit), next.run(request)).await {
+        Ok(response) => response,
+        Err(_) if remaining < limit => CustomError::DeadlineExceeded.into_response(),
+        Err(_) => CustomError::Timeout(limit).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Extension, Router,
+        body::Body,
+        http::{Request, StatusCode},
+        middleware,
+        routing::get,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::{app_and_state, config::AppConfig, db::test_db};
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let now = Instant::now();
+        let now_system = UNIX_EPOCH + Duration::from_secs(1_757_671_200); // 2025-09-12T10:00:00Z
+
+        assert_eq!(
+            parse("250", now, now_system),
+            Some(Deadline(now + Duration::from_millis(250)))
+        );
+        assert_eq!(
+            parse("2025-09-12T10:00:01.5Z", now, now_system),
+            Some(Deadline(now + Duration::from_millis(1500)))
+        );
+        assert_eq!(
+            parse("2025-09-12T12:00:02+02:00", now, now_system),
+            Some(Deadline(now + Duration::from_secs(2)))
+        );
+        assert_eq!(
+            parse("2025-09-12T09:59:59Z", now, now_system),
+            Some(Deadline(now))
+        );
+
+        for invalid in [
+            "",
+            "-5",
+            "soon",
+            "2025-09-12",
+            "2025-13-12T10:00:00Z",
+            "2025-09-12T10:00:00",
+        ] {
+            assert_eq!(parse(invalid, now, now_system), None, "{invalid:?}");
+        }
+    }
+
+    /// Serves `/slow`, which takes 200ms and answers with the deadline budget
+    /// it was given.
+    async fn app(request_timeout: Duration) -> Router {
+        let config = AppConfig {
+            request_timeout,
+            ..Default::default()
+        };
+        let (_, state) = app_and_state(test_db().await, config);
+
+        let slow = |deadline: Option<Extension<Deadline>>| async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+
+            match deadline {
+                Some(Extension(deadline)) => format!("{}", deadline.remaining().as_millis()),
+                None => "none".to_string(),
+            }
+        };
+
+        Router::new()
+            .route("/slow", get(slow))
+            .layer(middleware::from_fn_with_state(state.clone(), enforce))
+            .with_state(state)
+    }
+
+    async fn send(app: Router, deadline: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::builder().uri("/slow");
+
+        if let Some(deadline) = deadline {
+            request = request.header(DEADLINE_HEADER, deadline);
+        }
+
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_deadlines() {
+        let app = app(Duration::from_secs(30)).await;
+
+        let (status, body) = send(app.clone(), Some("2000-01-01T00:00:00Z")).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(body.contains("deadline_exceeded"));
+
+        let (status, body) = send(app.clone(), Some("20")).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(body.contains("deadline_exceeded"));
+
+        let (status, body) = send(app.clone(), Some("60000")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.parse::<u64>().unwrap() < 60_000);
+
+        assert_eq!(
+            send(app.clone(), None).await,
+            (StatusCode::OK, "none".to_string())
+        );
+        assert_eq!(send(app, Some("tomorrow")).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_shorter_timeout_wins() {
+        let app = app(Duration::from_millis(20)).await;
+
+        for deadline in [None, Some("60000")] {
+            let (status, body) = send(app.clone(), deadline).await;
+
+            assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+            assert!(body.contains(r#""code":"timeout""#));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Behaviors that still work but are going away. The code that notices a
+//! request relying on one calls `record` with its `Behavior`, and `mark` turns
+//! that into `Deprecation`, `Sunset` and `Link` headers on the response and a
+//! count in `/metrics`, so clients get warned and we can see who's left.
+
+use std::{cell::RefCell, collections::BTreeSet};
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{AppState, api_keys::Caller};
+
+pub const DEPRECATION_HEADER: &str = "deprecation";
+pub const SUNSET_HEADER: &str = "sunset";
+pub const LINK_HEADER: &str = "link";
+
+/// Where the migration notes are, each behavior's under a heading named by
+/// its key.
+const DOCS_URL: &str = "https://github.com/ChristianPavilonis/sp-exercise";
+
+/// Declared in order of sunset, so the first of a set is the soonest to go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Behavior {
+    /// A status spelled other than its json name, like `Pending` or
+    /// `in-progress` instead of `pending` or `inprogress`.
+    StatusCasing,
+}
+
+impl Behavior {
+    /// The name used in metrics and as the docs fragment.
+    pub fn key(self) -> &'static str {
+        match self {
+            Behavior::StatusCasing => "status_casing",
+        }
+    }
+
+    /// When it stops working, as an http date.
+    pub fn sunset(self) -> &'static str {
+        match self {
+            Behavior::StatusCasing => "Sun, 31 Jan 2027 00:00:00 GMT",
+        }
+    }
+
+    pub fn docs(self) -> String {
+        format!("{DOCS_URL}#{}", self.key())
+    }
+}
+
+tokio::task_local! {
+    /// The deprecated behaviors the current request has relied on so far.
+    static USED: RefCell<BTreeSet<Behavior>>;
+}
+
+/// Notes that the current request relies on `behavior`. Does nothing outside
+/// a request, e.g. in an import job.
+pub fn record(behavior: Behavior) {
+    let _ = USED.try_with(|used| used.borrow_mut().insert(behavior));
+}
+
+/// Adds the deprecation headers for whatever behaviors the request relied
+/// on, with the soonest sunset when there's more than one, and counts each.
+/// Logs the api key too, to find who's still relying on them.
+pub async fn mark(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let api_key = Caller::name(request.extensions());
+    let route = request.uri().path().to_string();
+
+    let (mut response, used) = USED
+        .scope(RefCell::new(BTreeSet::new()), async {
+            let response = next.run(request).await;
+
+            (response, USED.with(RefCell::take))
+        })
+        .await;
+
+    let Some(soonest) = used.first() else {
+        return response;
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(DEPRECATION_HEADER, HeaderValue::from_static("true"));
+    headers.insert(SUNSET_HEADER, HeaderValue::from_static(soonest.sunset()));
+
+    for behavior in &used {
+        state.metrics.record_deprecation(behavior.key());
+
+        tracing::info!(
+            behavior = behavior.key(),
+            route,
+            api_key = api_key.as_deref().unwrap_or("none"),
+            "deprecated behavior used"
+        );
+
+        let link = format!("<{}>; rel=\"deprecation\"", behavior.docs());
+
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.append(LINK_HEADER, link);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_behaviors_are_in_sunset_order() {
+        let behaviors = [Behavior::StatusCasing];
+        let sunsets = behaviors
+            .map(|behavior| httpdate::parse_http_date(behavior.sunset()).expect("an http date"));
+
+        assert!(sunsets.is_sorted());
+    }
+}
@@ -0,0 +1,484 @@
+//! Startup check that the database still has the columns the queries were
+//! compiled against. `query_as!` is checked at build time against
+//! `db/db.sqlite`, so a hand edited production database otherwise only shows
+//! up as confusing decode errors on whichever request touches it first.
+
+use std::fmt::Display;
+
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, db::Db, error::CustomError, journal};
+
+/// Every table the code reads or writes, with its columns as `PRAGMA
+/// table_info` reports them: the declared type, plus `NOT NULL` where set.
+/// Update alongside the migrations.
+pub const TABLES: &[(&str, &[(&str, &str)])] = &[
+    (
+        "orders",
+        &[
+            ("id", "INTEGER"),
+            ("status", "TEXT NOT NULL"),
+            ("amount", "INTEGER NOT NULL"),
+            ("updated_at", "TEXT"),
+            ("metadata", "TEXT NOT NULL"),
+            ("reminder_sent_at", "TEXT"),
+            ("customer_id", "TEXT"),
+            ("api_key", "TEXT"),
+            ("created_at", "TEXT"),
+            ("completed_at", "TEXT"),
+            ("currency", "TEXT NOT NULL"),
+            ("assigned_to", "TEXT"),
+            ("assigned_at", "TEXT"),
+            ("shipping_name", "TEXT"),
+            ("shipping_line1", "TEXT"),
+            ("shipping_line2", "TEXT"),
+            ("shipping_city", "TEXT"),
+            ("shipping_region", "TEXT"),
+            ("shipping_postal_code", "TEXT"),
+            ("shipping_country", "TEXT"),
+            ("tags", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "order_items",
+        &[
+            ("id", "INTEGER"),
+            ("order_id", "INTEGER NOT NULL"),
+            ("sku", "TEXT"),
+            ("description", "TEXT NOT NULL"),
+            ("quantity", "INTEGER NOT NULL"),
+            ("unit_price", "INTEGER NOT NULL"),
+            ("currency", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "order_events",
+        &[
+            ("id", "INTEGER"),
+            ("order_id", "INTEGER NOT NULL"),
+            ("kind", "TEXT NOT NULL"),
+            ("detail", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+            ("actor", "TEXT"),
+        ],
+    ),
+    (
+        "orders_archive",
+        &[
+            ("id", "INTEGER"),
+            ("status", "TEXT NOT NULL"),
+            ("amount", "INTEGER NOT NULL"),
+            ("updated_at", "TEXT"),
+            ("metadata", "TEXT NOT NULL"),
+            ("reminder_sent_at", "TEXT"),
+            ("customer_id", "TEXT"),
+            ("api_key", "TEXT"),
+            ("created_at", "TEXT"),
+            ("completed_at", "TEXT"),
+            ("currency", "TEXT NOT NULL"),
+            ("assigned_to", "TEXT"),
+            ("assigned_at", "TEXT"),
+            ("shipping_name", "TEXT"),
+            ("shipping_line1", "TEXT"),
+            ("shipping_line2", "TEXT"),
+            ("shipping_city", "TEXT"),
+            ("shipping_region", "TEXT"),
+            ("shipping_postal_code", "TEXT"),
+            ("shipping_country", "TEXT"),
+            ("tags", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "order_items_archive",
+        &[
+            ("id", "INTEGER"),
+            ("order_id", "INTEGER NOT NULL"),
+            ("sku", "TEXT"),
+            ("description", "TEXT NOT NULL"),
+            ("quantity", "INTEGER NOT NULL"),
+            ("unit_price", "INTEGER NOT NULL"),
+            ("currency", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "order_events_archive",
+        &[
+            ("id", "INTEGER"),
+            ("order_id", "INTEGER NOT NULL"),
+            ("kind", "TEXT NOT NULL"),
+            ("detail", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+            ("actor", "TEXT"),
+        ],
+    ),
+    (
+        "order_notes",
+        &[
+            ("id", "INTEGER"),
+            ("order_id", "INTEGER NOT NULL"),
+            ("author", "TEXT"),
+            ("body", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "note_mentions",
+        &[
+            ("id", "INTEGER"),
+            ("note_id", "INTEGER NOT NULL"),
+            ("username", "TEXT NOT NULL"),
+            ("known", "INTEGER NOT NULL"),
+        ],
+    ),
+    (
+        "order_notes_archive",
+        &[
+            ("id", "INTEGER"),
+            ("order_id", "INTEGER NOT NULL"),
+            ("author", "TEXT"),
+            ("body", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "note_mentions_archive",
+        &[
+            ("id", "INTEGER"),
+            ("note_id", "INTEGER NOT NULL"),
+            ("username", "TEXT NOT NULL"),
+            ("known", "INTEGER NOT NULL"),
+        ],
+    ),
+    (
+        "order_summaries",
+        &[
+            ("order_id", "INTEGER"),
+            ("status", "TEXT NOT NULL"),
+            ("amount", "INTEGER NOT NULL"),
+            ("item_count", "INTEGER NOT NULL"),
+            ("customer_id", "TEXT"),
+            ("updated_at", "TEXT"),
+        ],
+    ),
+    (
+        "products",
+        &[
+            ("id", "INTEGER"),
+            ("sku", "TEXT NOT NULL"),
+            ("name", "TEXT NOT NULL"),
+            ("unit_price", "INTEGER NOT NULL"),
+        ],
+    ),
+    (
+        "idempotency_keys",
+        &[("key", "TEXT NOT NULL"), ("order_id", "INTEGER NOT NULL")],
+    ),
+    (
+        "idempotency_responses",
+        &[
+            ("key", "TEXT NOT NULL"),
+            ("request_hash", "TEXT NOT NULL"),
+            ("status", "INTEGER NOT NULL"),
+            ("headers", "TEXT NOT NULL"),
+            ("body", "BLOB"),
+            ("body_hash", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "key_quotas",
+        &[
+            ("api_key", "TEXT NOT NULL"),
+            ("monthly_limit", "INTEGER NOT NULL"),
+        ],
+    ),
+    (
+        "import_jobs",
+        &[
+            ("id", "INTEGER"),
+            ("status", "TEXT NOT NULL"),
+            ("body", "TEXT NOT NULL"),
+            ("total_rows", "INTEGER NOT NULL"),
+            ("processed_rows", "INTEGER NOT NULL"),
+            ("imported_rows", "INTEGER NOT NULL"),
+            ("failed_rows", "INTEGER NOT NULL"),
+            ("row_errors", "TEXT NOT NULL"),
+            ("failure", "TEXT"),
+            ("created_at", "TEXT NOT NULL"),
+            ("locale", "TEXT"),
+        ],
+    ),
+    (
+        "request_journal",
+        &[
+            ("id", "INTEGER"),
+            ("method", "TEXT NOT NULL"),
+            ("path", "TEXT NOT NULL"),
+            ("body", "TEXT NOT NULL"),
+            ("api_key", "TEXT"),
+            ("idempotency_key", "TEXT"),
+            ("created_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "request_log",
+        &[
+            ("id", "INTEGER"),
+            ("method", "TEXT NOT NULL"),
+            ("path", "TEXT NOT NULL"),
+            ("status", "INTEGER NOT NULL"),
+            ("latency_ms", "INTEGER NOT NULL"),
+            ("api_key", "TEXT"),
+            ("request_id", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "webhooks",
+        &[
+            ("id", "INTEGER"),
+            ("url", "TEXT NOT NULL"),
+            ("payload_version", "INTEGER NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+            ("verify_tls", "BOOLEAN NOT NULL"),
+        ],
+    ),
+    (
+        "webhook_deliveries",
+        &[
+            ("id", "INTEGER"),
+            ("webhook_id", "INTEGER NOT NULL"),
+            ("event_id", "INTEGER"),
+            ("system_event_id", "INTEGER"),
+            ("status", "TEXT NOT NULL"),
+            ("attempts", "INTEGER NOT NULL"),
+            ("last_error", "TEXT"),
+            ("created_at", "TEXT NOT NULL"),
+            ("replay_id", "INTEGER"),
+        ],
+    ),
+    (
+        "event_replays",
+        &[
+            ("id", "INTEGER"),
+            ("webhook_id", "INTEGER NOT NULL"),
+            ("after_id", "INTEGER"),
+            ("before_id", "INTEGER"),
+            ("event_types", "TEXT"),
+            ("total", "INTEGER NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "system_events",
+        &[
+            ("id", "INTEGER"),
+            ("kind", "TEXT NOT NULL"),
+            ("detail", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "sequences",
+        &[("name", "TEXT"), ("value", "INTEGER NOT NULL")],
+    ),
+    (
+        "order_changes",
+        &[
+            ("id", "INTEGER"),
+            ("order_id", "INTEGER NOT NULL"),
+            ("field", "TEXT NOT NULL"),
+            ("old_value", "TEXT"),
+            ("new_value", "TEXT"),
+            ("changed_at", "TEXT NOT NULL"),
+            ("prev_hash", "TEXT"),
+            ("row_hash", "TEXT"),
+        ],
+    ),
+    (
+        "audit_chain_anchors",
+        &[
+            ("last_id", "INTEGER"),
+            ("last_hash", "TEXT NOT NULL"),
+            ("pruned_at", "TEXT NOT NULL"),
+        ],
+    ),
+    (
+        "api_keys",
+        &[
+            ("id", "INTEGER"),
+            ("name", "TEXT NOT NULL"),
+            ("prefix", "TEXT NOT NULL"),
+            ("secret_hash", "TEXT NOT NULL"),
+            ("scopes", "TEXT NOT NULL"),
+            ("created_at", "TEXT NOT NULL"),
+            ("expires_at", "TEXT"),
+            ("revoked_at", "TEXT"),
+            ("previous_secret_hash", "TEXT"),
+            ("previous_expires_at", "TEXT"),
+            ("rotated_at", "TEXT"),
+        ],
+    ),
+];
+
+/// How the live schema differs from `TABLES`, one `table.column` line each.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Drift {
+    /// Columns the code needs that aren't there.
+    pub missing: Vec<String>,
+    /// Columns whose type or nullability changed.
+    pub retyped: Vec<String>,
+    /// Columns the code doesn't know about. Harmless to the queries, so only
+    /// worth a warning.
+    pub extra: Vec<String>,
+}
+
+impl Drift {
+    /// Whether queries will fail against this schema.
+    pub fn is_fatal(&self) -> bool {
+        !self.missing.is_empty() || !self.retyped.is_empty()
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines = self.missing.iter().chain(&self.retyped).chain(&self.extra);
+
+        for (i, line) in lines.enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compares every table in `TABLES` with what sqlite reports for it.
+pub async fn check(db: &Db) -> Result<Drift> {
+    let mut drift = Drift::default();
+
+    for (table, expected) in TABLES {
+        let found: Vec<(String, String, bool)> =
+            sqlx::query_as(r#"select name, type, "notnull" from pragma_table_info(?)"#)
+                .bind(table)
+                .fetch_all(db.reader())
+                .await?;
+
+        let found: Vec<(String, String)> = found
+            .into_iter()
+            .map(|(name, kind, not_null)| {
+                let kind = kind.to_ascii_uppercase();
+
+                match not_null {
+                    true => (name, format!("{kind} NOT NULL")),
+                    false => (name, kind),
+                }
+            })
+            .collect();
+
+        for (column, kind) in *expected {
+            match found.iter().find(|(name, _)| name == column) {
+                None => drift
+                    .missing
+                    .push(format!("{table}.{column} is missing, expected {kind}")),
+                Some((_, actual)) if actual != kind => drift
+                    .retyped
+                    .push(format!("{table}.{column} is {actual}, expected {kind}")),
+                Some(_) => {}
+            }
+        }
+
+        for (name, kind) in &found {
+            if !expected.iter().any(|(column, _)| column == name) {
+                drift
+                    .extra
+                    .push(format!("{table}.{name} ({kind}) isn't used by the code"));
+            }
+        }
+    }
+
+    Ok(drift)
+}
+
+/// Refuses anything but reads with a 503 while `read_only` is set, or since
+/// the database failed an integrity check.
+pub async fn maintenance(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if journal::is_read_only(request.method()) {
+        return next.run(request).await;
+    }
+
+    let reason = match (state.config().read_only, state.integrity.failed()) {
+        (_, true) => "the database file failed an integrity check",
+        (true, false) => "the database schema needs fixing",
+        (false, false) => return next.run(request).await,
+    };
+
+    CustomError::Maintenance(format!("the api is read only for maintenance, {reason}")).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::test_db;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_migrated_schema_matches() {
+        let db = test_db().await;
+
+        assert_eq!(check(&db).await.unwrap(), Drift::default());
+    }
+
+    #[tokio::test]
+    async fn test_reports_drift() {
+        let db = test_db().await;
+
+        sqlx::raw_sql(
+            "ALTER TABLE orders ADD COLUMN notes TEXT;
+            ALTER TABLE products DROP COLUMN name;
+            DROP TABLE key_quotas;
+            CREATE TABLE key_quotas (api_key TEXT NOT NULL, monthly_limit TEXT);",
+        )
+        .execute(db.writer())
+        .await
+        .unwrap();
+
+        let drift = check(&db).await.unwrap();
+
+        assert!(drift.is_fatal());
+        assert_eq!(
+            drift.missing,
+            vec!["products.name is missing, expected TEXT NOT NULL"]
+        );
+        assert_eq!(
+            drift.retyped,
+            vec!["key_quotas.monthly_limit is TEXT, expected INTEGER NOT NULL"]
+        );
+        assert_eq!(
+            drift.extra,
+            vec!["orders.notes (TEXT) isn't used by the code"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extra_columns_are_not_fatal() {
+        let db = test_db().await;
+
+        sqlx::raw_sql("ALTER TABLE orders ADD COLUMN notes TEXT;")
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        let drift = check(&db).await.unwrap();
+
+        assert!(!drift.is_fatal());
+        assert_eq!(drift.extra.len(), 1);
+    }
+}
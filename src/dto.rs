@@ -0,0 +1,2015 @@
+//! Wire types for request and response bodies, kept apart from the database
+//! structs so a schema change doesn't silently change the api.
+//!
+//! Responses are snake_case unless the client or config asks for camelCase, so
+//! every response type has a camelCase twin generated by `wire!`. Request
+//! fields with more than one word accept either casing through aliases.
+//!
+//! Every response field is always sent, as `null` when there's no value,
+//! so a response's keys never depend on the record. Don't add
+//! `skip_serializing_if` to a response type.
+
+use axum::{
+    Json,
+    extract::FromRequestParts,
+    http::request::Parts,
+    response::{IntoResponse, Response},
+};
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Deserializer, Serialize, de::Error as _};
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    AppState, IMPORT_MAX_BYTES,
+    address::ShippingAddress,
+    api_keys::{ApiKey, Scope},
+    audit::Verification,
+    backup::Backup,
+    bus::{BusStats, Published},
+    caches::CacheReport,
+    clock,
+    config::{AppConfig, Environment},
+    consistency::Violations,
+    counts,
+    error::CustomError,
+    error_rate::{self, ErrorStats},
+    events::{EventKind, OrderEvent},
+    exports::AmountColumns,
+    extract::{MAX_JSON_DEPTH, MAX_JSON_ELEMENTS},
+    graphs,
+    growth::GrowthStats,
+    idempotency,
+    imports::{self, ImportJob, ImportStatus, RowError},
+    items::Item,
+    integrity::Report,
+    maintenance::MaintenanceStats,
+    metadata::Metadata,
+    money::Currency,
+    notes::Note,
+    orders::{Order, OrderSort, OrderStatus, history::FieldAsOf, pricing::Totals, recompute::Correction, retag::Retagged},
+    products::Product,
+    quotas::{KeyQuota, Usage},
+    reports::{self, DailyReport},
+    request_log::{KeyLatency, LoggedRequest},
+    sla::{self, Period, SlaReport, StatusTimes},
+    stats::{AMOUNT_BUCKETS, OrderStats},
+    webhooks::{Replay, ReplayStatus, Webhook},
+};
+
+/// Picks the casing of response keys, overriding the configured default.
+pub const RESPONSE_CASE_HEADER: &str = "x-response-case";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Case {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl Case {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "snake" | "snake_case" => Some(Case::Snake),
+            "camel" | "camelCase" => Some(Case::Camel),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Case::Snake => "snake",
+            Case::Camel => "camel",
+        }
+    }
+
+    pub fn apply<T: Wire>(self, body: T) -> Cased<T> {
+        Cased { body, case: self }
+    }
+}
+
+impl FromRequestParts<AppState> for Case {
+    type Rejection = CustomError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(RESPONSE_CASE_HEADER) else {
+            return Ok(state.config().response_case);
+        };
+
+        value.to_str().ok().and_then(Case::parse).ok_or_else(|| {
+            CustomError::Validation("X-Response-Case must be snake or camel".to_string())
+        })
+    }
+}
+
+/// A response type that can also be written with camelCase keys.
+pub trait Wire: Serialize + Sized {
+    type Camel: Serialize;
+
+    fn camel(self) -> Self::Camel;
+}
+
+impl<T: Wire> Wire for Vec<T> {
+    type Camel = Vec<T::Camel>;
+
+    fn camel(self) -> Self::Camel {
+        self.into_iter().map(Wire::camel).collect()
+    }
+}
+
+/// A json response written in the requested casing.
+pub struct Cased<T> {
+    body: T,
+    case: Case,
+}
+
+impl<T: Wire> IntoResponse for Cased<T> {
+    fn into_response(self) -> Response {
+        match self.case {
+            Case::Snake => Json(self.body).into_response(),
+            Case::Camel => Json(self.body.camel()).into_response(),
+        }
+    }
+}
+
+/// Declares a response struct along with its camelCase twin.
+macro_rules! wire {
+    ($(#[$meta:meta])* pub struct $name:ident, $camel:ident {
+        $($(#[$field_meta:meta])* pub $field:ident: $ty:ty,)*
+    }) => {
+        $(#[$meta])*
+        #[derive(Debug, Serialize, Deserialize)]
+        pub struct $name {
+            $($(#[$field_meta])* pub $field: $ty,)*
+        }
+
+        #[derive(Debug, Serialize)]
+        #[serde(rename_all = "camelCase")]
+        pub struct $camel {
+            $($(#[$field_meta])* pub $field: $ty,)*
+        }
+
+        impl Wire for $name {
+            type Camel = $camel;
+
+            fn camel(self) -> Self::Camel {
+                $camel {
+                    $($field: self.$field,)*
+                }
+            }
+        }
+    };
+}
+
+/// An order as every channel sends it: the api's responses, the event stream
+/// and the webhooks. They all build it with `OrderView::from`, so a field
+/// added here shows up everywhere at once. Written out by hand rather than by
+/// `wire!` so embedded items are cased too.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderView {
+    pub id: i64,
+    pub amount: i64,
+    pub status: OrderStatus,
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub customer_id: Option<String>,
+    #[serde(default)]
+    pub currency: Currency,
+    /// `true` for orders read from the archive.
+    #[serde(default)]
+    pub archived: bool,
+    /// Only filled in by `GET /orders`, for orders that aren't archived.
+    #[serde(default)]
+    pub item_count: Option<i64>,
+    /// The worker a pick list handed the order to.
+    #[serde(default)]
+    pub assigned_to: Option<String>,
+    #[serde(default)]
+    pub shipping_address: Option<ShippingAddress>,
+    /// Set by `POST /admin/orders/retag`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    /// The order's `ETag`, see `HEAD /orders/{id}`.
+    #[serde(default)]
+    pub version: String,
+    /// `null` unless the view embeds them, see `ViewOptions`.
+    #[serde(default)]
+    pub items: Option<Vec<ItemResponse>>,
+    /// Seconds since `created_at`, worked out when it's sent rather than stored.
+    #[serde(default)]
+    pub age_seconds: Option<i64>,
+    /// Seconds since the order's last status change, or since `created_at`
+    /// when it's had none. Only sent by the responses that load when that
+    /// was, see `OrderChildren::status_since`, `null` elsewhere.
+    #[serde(default)]
+    pub time_in_status_seconds: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderViewCamel {
+    pub id: i64,
+    pub amount: i64,
+    pub status: OrderStatus,
+    pub metadata: Metadata,
+    pub customer_id: Option<String>,
+    pub currency: Currency,
+    pub archived: bool,
+    pub item_count: Option<i64>,
+    pub assigned_to: Option<String>,
+    pub shipping_address: Option<ShippingAddress>,
+    pub tags: Vec<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub version: String,
+    pub items: Option<Vec<ItemResponseCamel>>,
+    pub age_seconds: Option<i64>,
+    pub time_in_status_seconds: Option<i64>,
+}
+
+impl Wire for OrderView {
+    type Camel = OrderViewCamel;
+
+    fn camel(self) -> Self::Camel {
+        OrderViewCamel {
+            id: self.id,
+            amount: self.amount,
+            status: self.status,
+            metadata: self.metadata,
+            customer_id: self.customer_id,
+            currency: self.currency,
+            archived: self.archived,
+            item_count: self.item_count,
+            assigned_to: self.assigned_to,
+            shipping_address: self.shipping_address,
+            tags: self.tags,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            version: self.version,
+            items: self.items.map(Wire::camel),
+            age_seconds: self.age_seconds,
+            time_in_status_seconds: self.time_in_status_seconds,
+        }
+    }
+}
+
+/// A page of `GET /orders`. Written out by hand like `OrderView`, so the
+/// orders are cased too.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaginatedOrders {
+    pub items: Vec<OrderView>,
+    /// How many orders match the filters, on every page.
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaginatedOrdersCamel {
+    pub items: Vec<OrderViewCamel>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+impl Wire for PaginatedOrders {
+    type Camel = PaginatedOrdersCamel;
+
+    fn camel(self) -> Self::Camel {
+        PaginatedOrdersCamel {
+            items: self.items.camel(),
+            total: self.total,
+            page: self.page,
+            per_page: self.per_page,
+        }
+    }
+}
+
+/// What the caller loaded alongside an order, for `OrderView::from`.
+#[derive(Debug, Default)]
+pub struct OrderChildren {
+    pub items: Vec<Item>,
+    /// `GET /orders` counts the items rather than loading them.
+    pub item_count: Option<i64>,
+    /// When the order entered its status, from `sla::status_since`. Loaded
+    /// for the list, the detail and the pick list.
+    pub status_since: Option<String>,
+}
+
+/// How `OrderView::from` shows an order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ViewOptions {
+    /// The order was read from the archive.
+    pub archived: bool,
+    /// Embeds the children's items, otherwise `items` is `null`.
+    pub embed_items: bool,
+}
+
+impl ViewOptions {
+    pub const ARCHIVED: Self = Self {
+        archived: true,
+        embed_items: false,
+    };
+}
+
+impl OrderView {
+    pub fn from(order: Order, children: OrderChildren, options: ViewOptions) -> Self {
+        let now = clock::current();
+        let seconds_since = |since: Option<&str>| since.and_then(|since| sla::seconds_since(since, now));
+
+        Self {
+            age_seconds: seconds_since(order.created_at.as_deref()),
+            time_in_status_seconds: seconds_since(children.status_since.as_deref()),
+            shipping_address: order.shipping_address(),
+            version: order.etag(),
+            id: order.id.expect("saved orders have an id"),
+            amount: order.amount,
+            status: order.status,
+            metadata: order.metadata,
+            customer_id: order.customer_id,
+            currency: order.currency,
+            archived: options.archived,
+            item_count: children.item_count,
+            assigned_to: order.assigned_to,
+            tags: order.tags.to_vec(),
+            created_at: order.created_at,
+            updated_at: order.updated_at,
+            items: options.embed_items.then(|| list(children.items)),
+        }
+    }
+
+    /// An order without its children, the way most responses send it.
+    pub fn of(order: Order) -> Self {
+        Self::from(order, OrderChildren::default(), ViewOptions::default())
+    }
+
+    /// `of` for a list of orders.
+    pub fn list(orders: Vec<Order>) -> Vec<Self> {
+        orders.into_iter().map(Self::of).collect()
+    }
+}
+
+/// What a `Prefer: return=minimal-diff` response compares before and after a
+/// change: the order as `GET /orders/{id}` sends it.
+pub fn order_diff_view(order: Order, case: Case) -> Result<Map<String, Value>, serde_json::Error> {
+    let view = OrderView::of(order);
+
+    let view = match case {
+        Case::Snake => serde_json::to_value(view)?,
+        Case::Camel => serde_json::to_value(view.camel())?,
+    };
+
+    let Value::Object(view) = view else {
+        unreachable!("an order is a json object");
+    };
+
+    Ok(view)
+}
+
+wire! {
+    pub struct ItemResponse, ItemResponseCamel {
+        pub id: i64,
+        pub order_id: i64,
+        pub sku: Option<String>,
+        pub description: String,
+        pub quantity: i64,
+        pub unit_price: i64,
+        #[serde(default)]
+        pub currency: Currency,
+    }
+}
+
+impl From<Item> for ItemResponse {
+    fn from(item: Item) -> Self {
+        Self {
+            id: item.id.expect("saved items have an id"),
+            order_id: item.order_id,
+            sku: item.sku,
+            description: item.description,
+            quantity: item.quantity,
+            unit_price: item.unit_price,
+            currency: item.currency,
+        }
+    }
+}
+
+wire! {
+    pub struct EventResponse, EventResponseCamel {
+        pub id: i64,
+        pub order_id: i64,
+        pub kind: EventKind,
+        pub detail: String,
+        pub actor: Option<String>,
+        pub created_at: String,
+    }
+}
+
+impl From<OrderEvent> for EventResponse {
+    fn from(event: OrderEvent) -> Self {
+        Self {
+            id: event.id.expect("saved events have an id"),
+            order_id: event.order_id,
+            kind: event.kind,
+            detail: event.detail,
+            actor: event.actor,
+            created_at: event.created_at,
+        }
+    }
+}
+
+/// An order event as `/events/stream` sends it, with its order as it was when
+/// the event was published, `None` when the order is gone.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamEventResponse {
+    #[serde(flatten)]
+    pub event: EventResponse,
+    pub order: Option<OrderView>,
+}
+
+impl From<&Published> for StreamEventResponse {
+    fn from(published: &Published) -> Self {
+        Self {
+            event: published.event.clone().into(),
+            order: published.order.clone().map(OrderView::of),
+        }
+    }
+}
+
+wire! {
+    pub struct NoteResponse, NoteResponseCamel {
+        pub id: i64,
+        pub order_id: i64,
+        pub author: Option<String>,
+        pub body: String,
+        pub mentions: Vec<MentionResponse>,
+        pub created_at: String,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MentionResponse {
+    pub username: String,
+    pub known: bool,
+}
+
+impl From<Note> for NoteResponse {
+    fn from(note: Note) -> Self {
+        Self {
+            id: note.id,
+            order_id: note.order_id,
+            author: note.author,
+            body: note.body,
+            mentions: note
+                .mentions
+                .into_iter()
+                .map(|mention| MentionResponse {
+                    username: mention.username,
+                    known: mention.known,
+                })
+                .collect(),
+            created_at: note.created_at,
+        }
+    }
+}
+
+wire! {
+    pub struct WebhookResponse, WebhookResponseCamel {
+        pub id: i64,
+        pub url: String,
+        pub payload_version: i64,
+        pub created_at: String,
+        pub verify_tls: bool,
+    }
+}
+
+impl From<Webhook> for WebhookResponse {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            payload_version: webhook.payload_version,
+            created_at: webhook.created_at,
+            verify_tls: webhook.verify_tls,
+        }
+    }
+}
+
+wire! {
+    pub struct ReplayResponse, ReplayResponseCamel {
+        pub id: i64,
+        pub webhook_id: i64,
+        pub after_id: Option<i64>,
+        pub before_id: Option<i64>,
+        pub event_types: Option<Vec<String>>,
+        pub status: ReplayStatus,
+        pub total: i64,
+        pub delivered: i64,
+        pub failed: i64,
+        pub pending: i64,
+        pub created_at: String,
+    }
+}
+
+impl From<Replay> for ReplayResponse {
+    fn from(replay: Replay) -> Self {
+        Self {
+            event_types: replay.types(),
+            status: replay.status(),
+            id: replay.id,
+            webhook_id: replay.webhook_id,
+            after_id: replay.after_id,
+            before_id: replay.before_id,
+            total: replay.total,
+            delivered: replay.delivered,
+            failed: replay.failed,
+            pending: replay.pending,
+            created_at: replay.created_at,
+        }
+    }
+}
+
+wire! {
+    pub struct ProductResponse, ProductResponseCamel {
+        pub id: i64,
+        pub sku: String,
+        pub name: String,
+        pub unit_price: i64,
+    }
+}
+
+impl From<Product> for ProductResponse {
+    fn from(product: Product) -> Self {
+        Self {
+            id: product.id.expect("saved products have an id"),
+            sku: product.sku,
+            name: product.name,
+            unit_price: product.unit_price,
+        }
+    }
+}
+
+wire! {
+    pub struct UsageResponse, UsageResponseCamel {
+        pub api_key: String,
+        pub period: String,
+        pub count: i64,
+        pub monthly_limit: Option<i64>,
+    }
+}
+
+impl From<Usage> for UsageResponse {
+    fn from(usage: Usage) -> Self {
+        Self {
+            api_key: usage.api_key,
+            period: usage.period,
+            count: usage.count,
+            monthly_limit: usage.monthly_limit,
+        }
+    }
+}
+
+wire! {
+    pub struct GrowthResponse, GrowthResponseCamel {
+        pub max_id: Option<i64>,
+        pub orders_per_hour: Option<f64>,
+        pub alert_threshold_per_hour: u64,
+        pub alerting: bool,
+        pub alerts: u64,
+    }
+}
+
+impl GrowthResponse {
+    pub fn new(stats: GrowthStats, alert_threshold_per_hour: u64) -> Self {
+        Self {
+            max_id: stats.max_id,
+            orders_per_hour: stats.orders_per_hour,
+            alert_threshold_per_hour,
+            alerting: stats.alerting,
+            alerts: stats.alerts,
+        }
+    }
+}
+
+wire! {
+    /// The error rate alert's windows, see `error_rate.rs`. `requests` and
+    /// `errors` are the minute still going.
+    pub struct ErrorStatsResponse, ErrorStatsResponseCamel {
+        pub window_seconds: u64,
+        pub alert_percent: u64,
+        pub alert_windows: usize,
+        pub requests: u64,
+        pub errors: u64,
+        /// The last closed windows, newest first.
+        pub windows: Vec<ErrorWindow>,
+        /// Closed windows in a row over `alert_percent`.
+        pub over_threshold: usize,
+        pub alerting: bool,
+        pub alerts: u64,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ErrorWindow {
+    pub requests: u64,
+    pub errors: u64,
+    pub percent: f64,
+}
+
+impl ErrorStatsResponse {
+    pub fn new(stats: ErrorStats, config: &AppConfig) -> Self {
+        Self {
+            window_seconds: error_rate::WINDOW.as_secs(),
+            alert_percent: config.error_rate_alert_percent,
+            alert_windows: config.error_rate_alert_windows,
+            requests: stats.current.requests,
+            errors: stats.current.errors,
+            windows: stats
+                .recent
+                .into_iter()
+                .map(|window| ErrorWindow {
+                    requests: window.requests,
+                    errors: window.errors,
+                    percent: window.percent(),
+                })
+                .collect(),
+            over_threshold: stats.over,
+            alerting: stats.alerting,
+            alerts: stats.alerts,
+        }
+    }
+}
+
+wire! {
+    /// The maintenance tasks' runs since startup, see `maintenance.rs`. The
+    /// page counts are from the last run that worked.
+    pub struct MaintenanceResponse, MaintenanceResponseCamel {
+        pub checkpoint_last_run_at: Option<String>,
+        pub checkpoint_duration_ms: Option<u128>,
+        pub checkpoint_runs: u64,
+        pub checkpoint_failures: u64,
+        pub checkpoint_skipped: u64,
+        /// `null` outside of wal mode.
+        pub wal_pages: Option<i64>,
+        pub checkpointed_pages: Option<i64>,
+        pub vacuum_last_run_at: Option<String>,
+        pub vacuum_duration_ms: Option<u128>,
+        pub vacuum_runs: u64,
+        pub vacuum_failures: u64,
+        pub vacuum_skipped: u64,
+        /// Whether the last check vacuumed, or found too little free.
+        pub vacuumed: Option<bool>,
+        pub page_count: Option<i64>,
+        pub freelist_pages: Option<i64>,
+        pub vacuum_freelist_percent: u64,
+    }
+}
+
+impl MaintenanceResponse {
+    pub fn new(stats: MaintenanceStats, config: &AppConfig) -> Self {
+        let duration_ms = |duration: Option<std::time::Duration>| duration.map(|took| took.as_millis());
+
+        Self {
+            checkpoint_last_run_at: stats.checkpoint.last_run_timestamp(),
+            checkpoint_duration_ms: duration_ms(stats.checkpoint.last_duration),
+            checkpoint_runs: stats.checkpoint.runs,
+            checkpoint_failures: stats.checkpoint.failures,
+            checkpoint_skipped: stats.checkpoint.skipped,
+            wal_pages: stats.last_checkpoint.and_then(|checkpoint| checkpoint.wal_pages),
+            checkpointed_pages: stats
+                .last_checkpoint
+                .and_then(|checkpoint| checkpoint.checkpointed_pages),
+            vacuum_last_run_at: stats.vacuum.last_run_timestamp(),
+            vacuum_duration_ms: duration_ms(stats.vacuum.last_duration),
+            vacuum_runs: stats.vacuum.runs,
+            vacuum_failures: stats.vacuum.failures,
+            vacuum_skipped: stats.vacuum.skipped,
+            vacuumed: stats.last_vacuum.map(|vacuum| vacuum.vacuumed),
+            page_count: stats.last_vacuum.map(|vacuum| vacuum.after.page_count),
+            freelist_pages: stats.last_vacuum.map(|vacuum| vacuum.after.freelist_count),
+            vacuum_freelist_percent: config.vacuum_freelist_percent,
+        }
+    }
+}
+
+wire! {
+    /// A cache and how it's doing since startup, see `caches.rs`.
+    pub struct CacheResponse, CacheResponseCamel {
+        pub name: String,
+        pub entries: usize,
+        pub hits: u64,
+        pub misses: u64,
+        /// How many published changes it had to handle.
+        pub invalidations: u64,
+        /// The kind of the last of them, like `order_changed`, and when.
+        pub last_invalidation: Option<String>,
+        pub last_invalidated_at: Option<String>,
+    }
+}
+
+impl From<CacheReport> for CacheResponse {
+    fn from(report: CacheReport) -> Self {
+        let (last_invalidation, last_invalidated_at) = match report.last_invalidation {
+            Some(last) => (Some(last.event.to_string()), Some(last.at)),
+            None => (None, None),
+        };
+
+        Self {
+            name: report.name.to_string(),
+            entries: report.entries,
+            hits: report.hits,
+            misses: report.misses,
+            invalidations: report.invalidations,
+            last_invalidation,
+            last_invalidated_at,
+        }
+    }
+}
+
+wire! {
+    /// For orchestration to decide whether the node gets traffic, with the
+    /// last integrity check of the database file, see `integrity.rs`. The
+    /// check's fields are `null` until the first one.
+    pub struct ReadinessResponse, ReadinessResponseCamel {
+        /// False once an integrity check has failed.
+        pub ready: bool,
+        /// Whether writes are refused, for a drifted schema too.
+        pub read_only: bool,
+        /// `quick` or `full`.
+        pub integrity_check: Option<String>,
+        pub integrity_ok: Option<bool>,
+        pub integrity_checked_at: Option<String>,
+        pub integrity_duration_ms: Option<u128>,
+        /// What sqlite found wrong, at most 100 of them.
+        pub integrity_problems: Vec<String>,
+    }
+}
+
+impl ReadinessResponse {
+    pub fn new(integrity: Option<Report>, read_only: bool) -> Self {
+        Self {
+            ready: integrity.as_ref().is_none_or(Report::is_intact),
+            read_only,
+            integrity_check: integrity.as_ref().map(|report| report.check.as_str().to_string()),
+            integrity_ok: integrity.as_ref().map(Report::is_intact),
+            integrity_checked_at: integrity.as_ref().map(Report::checked_at_timestamp),
+            integrity_duration_ms: integrity.as_ref().map(|report| report.took.as_millis()),
+            integrity_problems: integrity.map(|report| report.problems).unwrap_or_default(),
+        }
+    }
+}
+
+wire! {
+    pub struct BackupResponse, BackupResponseCamel {
+        pub path: String,
+        pub size_bytes: u64,
+    }
+}
+
+impl From<Backup> for BackupResponse {
+    fn from(backup: Backup) -> Self {
+        Self {
+            path: backup.path.display().to_string(),
+            size_bytes: backup.size_bytes,
+        }
+    }
+}
+
+wire! {
+    /// Totals for one day at `timezone`, see `reports::daily`.
+    pub struct DailyReportResponse, DailyReportResponseCamel {
+        pub date: String,
+        pub timezone: String,
+        pub orders_created: i64,
+        pub orders_completed: i64,
+        pub gross_amount: i64,
+        pub refunded_amount: i64,
+        pub net_amount: i64,
+    }
+}
+
+impl DailyReportResponse {
+    pub fn new(date: String, utc_offset: i32, report: DailyReport) -> Self {
+        Self {
+            date,
+            timezone: reports::format_offset(utc_offset),
+            orders_created: report.orders_created,
+            orders_completed: report.orders_completed,
+            gross_amount: report.gross_amount,
+            refunded_amount: report.refunded_amount,
+            net_amount: report.net_amount,
+        }
+    }
+}
+
+wire! {
+    /// Time in each status of the orders completed from `from` up to `to`,
+    /// see `sla.rs`.
+    pub struct SlaReportResponse, SlaReportResponseCamel {
+        pub from: String,
+        pub to: String,
+        pub orders: i64,
+        /// Completed in the window but created before status changes were
+        /// recorded, so not in `statuses`.
+        pub missing_history: i64,
+        pub statuses: Vec<StatusTimes>,
+    }
+}
+
+impl SlaReportResponse {
+    pub fn new(from: String, to: String, report: SlaReport) -> Self {
+        Self {
+            from,
+            to,
+            orders: report.orders,
+            missing_history: report.missing_history,
+            statuses: report.statuses,
+        }
+    }
+}
+
+wire! {
+    /// A request from the request log, see `request_log.rs`.
+    pub struct LoggedRequestResponse, LoggedRequestResponseCamel {
+        pub id: i64,
+        pub method: String,
+        /// The route's template, like `/orders/{id}`.
+        pub path: String,
+        pub status: i64,
+        pub latency_ms: i64,
+        pub api_key: Option<String>,
+        pub request_id: String,
+        pub created_at: String,
+    }
+}
+
+impl From<LoggedRequest> for LoggedRequestResponse {
+    fn from(request: LoggedRequest) -> Self {
+        Self {
+            id: request.id.unwrap_or_default(),
+            method: request.method,
+            path: request.path,
+            status: request.status,
+            latency_ms: request.latency_ms,
+            api_key: request.api_key,
+            request_id: request.request_id,
+            created_at: request.created_at,
+        }
+    }
+}
+
+wire! {
+    /// Latency per api key of the requests logged from `from` up to `to`.
+    pub struct RequestStatsResponse, RequestStatsResponseCamel {
+        pub from: String,
+        pub to: String,
+        pub keys: Vec<KeyLatency>,
+    }
+}
+
+wire! {
+    /// How the process's internals are holding up.
+    pub struct RuntimeResponse, RuntimeResponseCamel {
+        pub event_bus_capacity: usize,
+        pub event_bus_subscribers: usize,
+        /// Events dropped before a subscriber got to them, since startup.
+        pub event_bus_missed_events: u64,
+        /// Current subscribers that have missed events.
+        pub event_bus_lagging: Vec<SubscriberLag>,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SubscriberLag {
+    pub subscriber: u64,
+    pub missed: u64,
+}
+
+impl From<BusStats> for RuntimeResponse {
+    fn from(stats: BusStats) -> Self {
+        Self {
+            event_bus_capacity: stats.capacity,
+            event_bus_subscribers: stats.subscribers,
+            event_bus_missed_events: stats.missed_total,
+            event_bus_lagging: stats
+                .missed
+                .into_iter()
+                .filter(|(_, missed)| *missed > 0)
+                .map(|(subscriber, missed)| SubscriberLag { subscriber, missed })
+                .collect(),
+        }
+    }
+}
+
+wire! {
+    /// The configuration support can see. Fields are copied over one by one
+    /// in `new`, so nothing is exposed unless it's added here, which keeps
+    /// database urls and any future secrets out of the response.
+    pub struct ConfigResponse, ConfigResponseCamel {
+        /// Bumped by every reload, 1 for the config read at startup.
+        pub generation: u64,
+        pub environment: Environment,
+        pub journal_enabled: bool,
+        pub strict_accept: bool,
+        pub response_case: String,
+        pub slow_request_ms: u128,
+        pub request_timeout_ms: u128,
+        pub growth_alert_per_hour: u64,
+        pub growth_sample_minutes: u64,
+        pub error_rate_alert_percent: u64,
+        pub error_rate_alert_windows: usize,
+        pub error_rate_alert_cooldown_minutes: u64,
+        pub wal_checkpoint_minutes: Option<u64>,
+        pub vacuum_hours: Option<u64>,
+        pub vacuum_freelist_percent: u64,
+        pub maintenance_max_in_flight: usize,
+        pub integrity_check: String,
+        pub integrity_check_hours: Option<u64>,
+        pub db_read_connections: Option<u32>,
+        pub write_backlog_max: Option<usize>,
+        pub db_optimize_hours: Option<u64>,
+        pub import_max_bytes: usize,
+        pub import_batch_size: usize,
+        pub json_max_depth: usize,
+        pub json_max_elements: usize,
+        pub pending_reminder_hours: u64,
+        pub pending_cancel_hours: u64,
+        pub reopen_window_hours: u64,
+        pub draft_ttl_hours: u64,
+        pub idempotency_ttl_hours: u64,
+        pub idempotency_body_max_bytes: usize,
+        pub idempotency_max_rows: Option<i64>,
+        pub retention_years: Option<u32>,
+        pub pii_metadata_keys: Vec<String>,
+        pub pick_lease_minutes: u64,
+        pub open_orders_per_customer: Option<i64>,
+        pub anonymous_open_orders_per_key: Option<i64>,
+        pub report_timezone: String,
+        pub event_bus_capacity: usize,
+        pub webhook_concurrency: usize,
+        pub webhook_max_in_flight: usize,
+        pub replay_rate_per_second: usize,
+        /// Whether deliveries go through a proxy, its url can hold credentials.
+        pub webhook_proxy_enabled: bool,
+        pub webhook_connect_timeout_ms: u128,
+        pub webhook_timeout_ms: u128,
+        pub allow_destructive_ops: bool,
+        pub chaos_header: bool,
+        pub always_count: bool,
+        pub stats_refresh_seconds: Option<u64>,
+        pub stats_debounce_ms: u128,
+        pub stats_budget_ms: u128,
+        pub request_log_enabled: bool,
+        pub request_log_retention_hours: u64,
+        pub request_log_max_rows: Option<i64>,
+        pub journal_retention_hours: Option<u64>,
+        pub journal_max_rows: Option<i64>,
+        pub webhook_delivery_retention_hours: Option<u64>,
+        pub webhook_delivery_max_rows: Option<i64>,
+        pub audit_log_retention_hours: Option<u64>,
+        pub audit_log_max_rows: Option<i64>,
+        pub redacted_fields: Vec<String>,
+        pub mentionable_users: Vec<String>,
+        pub default_locale: String,
+        pub require_api_key: bool,
+        pub api_key_rotation_overlap_hours: u64,
+        pub enabled_features: Vec<String>,
+    }
+}
+
+impl ConfigResponse {
+    pub fn new(config: &AppConfig, generation: u64) -> Self {
+        Self {
+            generation,
+            environment: config.environment,
+            journal_enabled: config.journal_enabled,
+            strict_accept: config.strict_accept,
+            response_case: config.response_case.as_str().to_string(),
+            slow_request_ms: config.slow_request_threshold.as_millis(),
+            request_timeout_ms: config.request_timeout.as_millis(),
+            growth_alert_per_hour: config.growth_alert_per_hour,
+            growth_sample_minutes: config.growth_sample_interval.as_secs() / 60,
+            error_rate_alert_percent: config.error_rate_alert_percent,
+            error_rate_alert_windows: config.error_rate_alert_windows,
+            error_rate_alert_cooldown_minutes: config.error_rate_alert_cooldown.as_secs() / 60,
+            wal_checkpoint_minutes: config
+                .wal_checkpoint_interval
+                .map(|interval| interval.as_secs() / 60),
+            vacuum_hours: config.vacuum_interval.map(|interval| interval.as_secs() / 60 / 60),
+            vacuum_freelist_percent: config.vacuum_freelist_percent,
+            maintenance_max_in_flight: config.maintenance_max_in_flight,
+            integrity_check: config.integrity_check.as_str().to_string(),
+            integrity_check_hours: config
+                .integrity_check_interval
+                .map(|interval| interval.as_secs() / 60 / 60),
+            db_read_connections: config.db_read_connections,
+            write_backlog_max: config.write_backlog_max,
+            db_optimize_hours: config
+                .db_optimize_interval
+                .map(|interval| interval.as_secs() / 60 / 60),
+            import_max_bytes: IMPORT_MAX_BYTES,
+            import_batch_size: imports::BATCH_SIZE,
+            json_max_depth: MAX_JSON_DEPTH,
+            json_max_elements: MAX_JSON_ELEMENTS,
+            pending_reminder_hours: config.pending_reminder_after.as_secs() / 60 / 60,
+            pending_cancel_hours: config.pending_cancel_after.as_secs() / 60 / 60,
+            reopen_window_hours: config.reopen_window.as_secs() / 60 / 60,
+            draft_ttl_hours: config.draft_ttl.as_secs() / 60 / 60,
+            idempotency_ttl_hours: config.idempotency_ttl.as_secs() / 60 / 60,
+            idempotency_body_max_bytes: idempotency::BODY_MAX_BYTES,
+            idempotency_max_rows: config.idempotency_max_rows,
+            retention_years: config.retention_years,
+            pii_metadata_keys: config.pii_metadata_keys.clone(),
+            pick_lease_minutes: config.pick_lease.as_secs() / 60,
+            open_orders_per_customer: config.open_order_limits.per_customer,
+            anonymous_open_orders_per_key: config.open_order_limits.per_anonymous_key,
+            report_timezone: reports::format_offset(config.report_utc_offset),
+            event_bus_capacity: config.event_bus_capacity,
+            webhook_concurrency: config.webhook_concurrency,
+            webhook_max_in_flight: config.webhook_max_in_flight,
+            replay_rate_per_second: config.replay_rate_per_second,
+            webhook_proxy_enabled: config.webhook_http.proxy.is_some(),
+            webhook_connect_timeout_ms: config.webhook_http.connect_timeout.as_millis(),
+            webhook_timeout_ms: config.webhook_http.timeout.as_millis(),
+            allow_destructive_ops: config.allow_destructive_ops,
+            chaos_header: config.chaos_header,
+            always_count: config.always_count,
+            stats_refresh_seconds: config.stats_refresh_interval.map(|interval| interval.as_secs()),
+            stats_debounce_ms: config.stats_debounce.as_millis(),
+            stats_budget_ms: config.stats_budget.as_millis(),
+            request_log_enabled: config.request_log_enabled,
+            request_log_retention_hours: config.request_log_retention.as_secs() / 60 / 60,
+            request_log_max_rows: config.request_log_max_rows,
+            journal_retention_hours: config.journal_limits.max_age.map(|age| age.as_secs() / 60 / 60),
+            journal_max_rows: config.journal_limits.max_rows,
+            webhook_delivery_retention_hours: config
+                .webhook_delivery_limits
+                .max_age
+                .map(|age| age.as_secs() / 60 / 60),
+            webhook_delivery_max_rows: config.webhook_delivery_limits.max_rows,
+            audit_log_retention_hours: config.audit_log_limits.max_age.map(|age| age.as_secs() / 60 / 60),
+            audit_log_max_rows: config.audit_log_limits.max_rows,
+            redacted_fields: config.redaction.paths(),
+            mentionable_users: config.mentionable_users.clone(),
+            default_locale: config.default_locale.to_string(),
+            require_api_key: config.require_api_key,
+            api_key_rotation_overlap_hours: config.api_key_rotation_overlap.as_secs() / 60 / 60,
+            enabled_features: config
+                .features
+                .enabled()
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+wire! {
+    pub struct ConsistencyCheckResponse, ConsistencyCheckResponseCamel {
+        pub name: String,
+        pub description: String,
+        pub passed: bool,
+        pub violations: i64,
+        pub order_ids: Vec<i64>,
+    }
+}
+
+impl From<Violations> for ConsistencyCheckResponse {
+    fn from(violations: Violations) -> Self {
+        Self {
+            name: violations.name.to_string(),
+            description: violations.description.to_string(),
+            passed: violations.total == 0,
+            violations: violations.total,
+            order_ids: violations.order_ids,
+        }
+    }
+}
+
+wire! {
+    /// The audit log's hash chain, see `audit.rs`.
+    pub struct AuditVerificationResponse, AuditVerificationResponseCamel {
+        pub intact: bool,
+        pub checked: i64,
+        pub unchained: i64,
+        /// Worth keeping outside the database, removing rows from the end of
+        /// the chain only shows as a different last hash.
+        pub last_hash: Option<String>,
+        pub first_break: Option<ChainBreak>,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChainBreak {
+    /// The `order_changes` row.
+    pub id: i64,
+    pub reason: String,
+}
+
+impl From<Verification> for AuditVerificationResponse {
+    fn from(verification: Verification) -> Self {
+        Self {
+            intact: verification.is_intact(),
+            checked: verification.checked,
+            unchained: verification.unchained,
+            last_hash: verification.last_hash,
+            first_break: verification.first_break.map(|found| ChainBreak {
+                id: found.id,
+                reason: found.reason,
+            }),
+        }
+    }
+}
+
+wire! {
+    /// What `recompute` corrected, see `orders/recompute.rs`.
+    pub struct RecomputeResponse, RecomputeResponseCamel {
+        /// False when every derived field was already right and nothing was
+        /// written.
+        pub changed: bool,
+        pub corrections: Vec<CorrectedField>,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct CorrectedField {
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+impl From<Vec<Correction>> for RecomputeResponse {
+    fn from(corrections: Vec<Correction>) -> Self {
+        Self {
+            changed: !corrections.is_empty(),
+            corrections: corrections
+                .into_iter()
+                .map(|correction| CorrectedField {
+                    field: correction.field.to_string(),
+                    before: correction.before,
+                    after: correction.after,
+                })
+                .collect(),
+        }
+    }
+}
+
+wire! {
+    pub struct ImportJobResponse, ImportJobResponseCamel {
+        pub id: i64,
+        pub status: ImportStatus,
+        pub total_rows: i64,
+        pub processed_rows: i64,
+        pub imported_rows: i64,
+        pub failed_rows: i64,
+        pub row_errors: Vec<RowError>,
+        pub failure: Option<String>,
+        /// The locale `amount_decimal` was read in, plain decimals when null.
+        pub locale: Option<String>,
+    }
+}
+
+impl From<ImportJob> for ImportJobResponse {
+    fn from(job: ImportJob) -> Self {
+        Self {
+            row_errors: job.row_errors(),
+            id: job.id,
+            status: job.status,
+            total_rows: job.total_rows,
+            processed_rows: job.processed_rows,
+            imported_rows: job.imported_rows,
+            failed_rows: job.failed_rows,
+            failure: job.failure,
+            locale: job.locale,
+        }
+    }
+}
+
+wire! {
+    /// What `POST /orders/import/graph` did, see `graphs.rs`.
+    pub struct GraphImportResponse, GraphImportResponseCamel {
+        pub imported: i64,
+        pub overwritten: i64,
+        /// Orders already here, left as they are.
+        pub skipped: i64,
+        pub failed: i64,
+        /// One for each line skipped or failed.
+        pub errors: Vec<RowError>,
+    }
+}
+
+impl From<graphs::Report> for GraphImportResponse {
+    fn from(report: graphs::Report) -> Self {
+        Self {
+            imported: report.imported,
+            overwritten: report.overwritten,
+            skipped: report.skipped,
+            failed: report.failed,
+            errors: report.errors,
+        }
+    }
+}
+
+wire! {
+    pub struct QuotaResponse, QuotaResponseCamel {
+        pub api_key: String,
+        pub monthly_limit: i64,
+    }
+}
+
+impl From<KeyQuota> for QuotaResponse {
+    fn from(quota: KeyQuota) -> Self {
+        Self {
+            api_key: quota.api_key,
+            monthly_limit: quota.monthly_limit,
+        }
+    }
+}
+
+wire! {
+    /// An api key without its secret, see `api_keys.rs`.
+    pub struct ApiKeyResponse, ApiKeyResponseCamel {
+        pub id: i64,
+        pub name: String,
+        /// The start of the current secret.
+        pub prefix: String,
+        pub scopes: Vec<Scope>,
+        pub created_at: String,
+        pub expires_at: Option<String>,
+        pub revoked_at: Option<String>,
+        pub rotated_at: Option<String>,
+        /// Until when the secret the last rotation replaced still works.
+        pub previous_expires_at: Option<String>,
+    }
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            revoked_at: key.revoked_at,
+            rotated_at: key.rotated_at,
+            previous_expires_at: key.previous_expires_at,
+        }
+    }
+}
+
+wire! {
+    /// A key just created or rotated, with the secret to send in `X-Api-Key`.
+    /// Only a hash of it is kept, so it can't be had again.
+    pub struct ApiKeySecretResponse, ApiKeySecretResponseCamel {
+        pub id: i64,
+        pub name: String,
+        pub prefix: String,
+        pub scopes: Vec<Scope>,
+        pub created_at: String,
+        pub expires_at: Option<String>,
+        pub rotated_at: Option<String>,
+        pub previous_expires_at: Option<String>,
+        pub secret: String,
+    }
+}
+
+impl ApiKeySecretResponse {
+    pub fn new(key: ApiKey, secret: String) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            prefix: key.prefix,
+            scopes: key.scopes,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            rotated_at: key.rotated_at,
+            previous_expires_at: key.previous_expires_at,
+            secret,
+        }
+    }
+}
+
+wire! {
+    /// `value` is the last one handed out, null if the sequence never has.
+    pub struct SequenceResponse, SequenceResponseCamel {
+        pub name: String,
+        pub value: Option<i64>,
+    }
+}
+
+/// Converts each record into its response type.
+pub fn list<T, R: From<T>>(records: Vec<T>) -> Vec<R> {
+    records.into_iter().map(R::from).collect()
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateOrderRequest {
+    pub amount: i64,
+    /// Only `pending`, which is also the default. An `id` sent along is
+    /// ignored, the database picks it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<OrderStatus>,
+    /// Creates the order as a draft, validated when it's confirmed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub draft: bool,
+    /// Who the order is for, their open orders are limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_id: Option<String>,
+    /// `DEFAULT_CURRENCY` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    /// Checked and normalized by `ShippingAddress::patch`.
+    #[serde(default, alias = "shippingAddress", skip_serializing_if = "Option::is_none")]
+    pub shipping_address: Option<Value>,
+}
+
+/// Query parameters for `GET /orders/count`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CountFilter {
+    /// Counts every order but drafts when unset.
+    pub status: Option<OrderStatus>,
+}
+
+wire! {
+    pub struct CountResponse, CountResponseCamel {
+        pub status: Option<OrderStatus>,
+        pub count: i64,
+    }
+}
+
+/// Query parameters for `GET /orders/stats`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StatsParams {
+    /// Works the stats out now instead of serving the precomputed ones.
+    #[serde(default)]
+    pub fresh: bool,
+    /// RFC 3339 times, created from (inclusive) and to (exclusive). Either
+    /// one is worked out on the request too.
+    #[serde(default, alias = "createdFrom")]
+    pub created_from: Option<String>,
+    #[serde(default, alias = "createdTo")]
+    pub created_to: Option<String>,
+}
+
+wire! {
+    /// The totals of the placed orders, see `stats.rs`.
+    pub struct OrderStatsResponse, OrderStatsResponseCamel {
+        /// When they were worked out, the same as `X-Stats-As-Of`.
+        pub as_of: String,
+        pub orders: i64,
+        pub amount: i64,
+        /// Every status a request can set, in the order of `OrderStatus`.
+        pub orders_by_status: BTreeMap<OrderStatus, i64>,
+        pub amount_by_status: BTreeMap<OrderStatus, i64>,
+        pub amount_histogram: Vec<AmountBucket>,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AmountBucket {
+    /// Inclusive.
+    pub min: i64,
+    /// Exclusive, `null` for the last bucket.
+    pub max: Option<i64>,
+    pub orders: i64,
+}
+
+impl From<OrderStats> for OrderStatsResponse {
+    fn from(stats: OrderStats) -> Self {
+        let by_status = |totals: &BTreeMap<OrderStatus, i64>| {
+            OrderStatus::ALL
+                .into_iter()
+                .map(|status| (status, totals.get(&status).copied().unwrap_or(0)))
+                .collect()
+        };
+
+        Self {
+            orders_by_status: by_status(&stats.orders_by_status),
+            amount_by_status: by_status(&stats.amount_by_status),
+            amount_histogram: AMOUNT_BUCKETS
+                .iter()
+                .enumerate()
+                .map(|(bucket, &min)| AmountBucket {
+                    min,
+                    max: AMOUNT_BUCKETS.get(bucket + 1).copied(),
+                    orders: stats.buckets[bucket],
+                })
+                .collect(),
+            as_of: stats.as_of,
+            orders: stats.orders,
+            amount: stats.amount,
+        }
+    }
+}
+
+/// Query parameters for `GET /orders`, every field narrows the list.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct OrderFilter {
+    pub status: Option<OrderStatus>,
+    /// The shipping address's country code, in any case.
+    pub country: Option<String>,
+    /// `metadata.<key>=<value>` parameters, by key.
+    #[serde(flatten, with = "metadata_params")]
+    pub metadata: BTreeMap<String, String>,
+    /// Also list matching orders from the archive.
+    #[serde(
+        default,
+        alias = "includeArchived",
+        deserialize_with = "query_flag",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub include_archived: bool,
+    /// Also list drafts.
+    #[serde(
+        default,
+        alias = "includeDrafts",
+        deserialize_with = "query_flag",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub include_drafts: bool,
+}
+
+/// Query parameters asking `GET /orders` for a page rather than every order.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PageParams {
+    /// Counted from 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<i64>,
+    #[serde(alias = "perPage", skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<i64>,
+}
+
+/// Query parameters picking the order `GET /orders` lists in.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SortParams {
+    #[serde(default)]
+    pub sort: OrderSort,
+}
+
+/// A `true` or `false` query parameter. `flatten` buffers every parameter as
+/// a string, so a plain `bool` field won't deserialize next to one.
+fn query_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" | "" => Ok(false),
+        other => Err(D::Error::custom(format!("expected true or false, got {other:?}"))),
+    }
+}
+
+mod metadata_params {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const PREFIX: &str = "metadata.";
+
+    pub fn serialize<S: Serializer>(
+        metadata: &BTreeMap<String, String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            metadata
+                .iter()
+                .map(|(key, value)| (format!("{PREFIX}{key}"), value)),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, String>, D::Error> {
+        let params = BTreeMap::<String, String>::deserialize(deserializer)?;
+
+        Ok(params
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.strip_prefix(PREFIX)?.to_string(), value)))
+            .collect())
+    }
+}
+
+/// Query parameters for `GET /orders/export`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ExportParams {
+    pub status: Option<OrderStatus>,
+    #[serde(default)]
+    pub amounts: AmountColumns,
+    /// Adds an `id` column first, the cursor for `resume_after`.
+    #[serde(default)]
+    pub ids: bool,
+    /// Carries on a cut off export after the last id it got, see `exports`.
+    #[serde(alias = "resumeAfter")]
+    pub resume_after: Option<i64>,
+    /// Writes `amount_decimal` like this locale does, `de-DE` for `1.234,56`.
+    pub locale: Option<String>,
+}
+
+/// Query parameters for `GET /orders/pick-list`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PickListParams {
+    pub limit: Option<i64>,
+    /// Assigns the orders on the list to this worker.
+    #[serde(alias = "assignTo")]
+    pub assign_to: Option<String>,
+}
+
+/// Query parameters for `GET /events/stream`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StreamParams {
+    /// Also send a `counts` event every `counts_interval`.
+    #[serde(default, alias = "includeCounts")]
+    pub include_counts: bool,
+    /// Seconds, like `10s` or `10`.
+    #[serde(alias = "countsInterval")]
+    pub counts_interval: Option<String>,
+}
+
+/// The data of the event stream's `counts` events, from the cached counts.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StreamCountsResponse {
+    /// Every status a request can set, drafts aren't counted. In the order
+    /// of `OrderStatus`, so the same counts are the same bytes.
+    pub counts: BTreeMap<OrderStatus, i64>,
+    pub total: i64,
+}
+
+impl From<HashMap<OrderStatus, i64>> for StreamCountsResponse {
+    fn from(cached: HashMap<OrderStatus, i64>) -> Self {
+        Self {
+            counts: OrderStatus::ALL
+                .into_iter()
+                .map(|status| (status, counts::pick(&cached, Some(status))))
+                .collect(),
+            total: counts::pick(&cached, None),
+        }
+    }
+}
+
+/// Query parameters for `GET /reports/daily`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DailyReportParams {
+    /// A calendar day like `2024-05-01`.
+    pub date: String,
+}
+
+/// Query parameters for `GET /reports/sla`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SlaParams {
+    /// RFC 3339 times, the window runs from `from` up to but not including
+    /// `to`.
+    pub from: String,
+    pub to: String,
+}
+
+/// Query parameters for `GET /admin/requests`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RequestLogParams {
+    #[serde(alias = "apiKey")]
+    pub api_key: Option<String>,
+    /// An RFC 3339 time.
+    pub since: Option<String>,
+    pub status: Option<u16>,
+    /// The id of the last request of the page before.
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Query parameters for `GET /admin/requests/stats`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RequestStatsParams {
+    /// RFC 3339 times, the window runs from `from` up to but not including
+    /// `to`.
+    pub from: String,
+    pub to: String,
+}
+
+/// Query parameters for `GET /orders/{id}/as-of`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AsOfParams {
+    /// An RFC 3339 time, like `2025-09-12T10:00:00Z`.
+    pub at: String,
+}
+
+wire! {
+    /// An order as it was at `at`, see `orders::history`.
+    pub struct OrderAsOfResponse, OrderAsOfResponseCamel {
+        pub id: i64,
+        /// `at` in UTC, as the timestamp columns hold it.
+        pub at: String,
+        /// By column name, each with its value then and whether it's tracked.
+        pub fields: BTreeMap<String, FieldAsOf>,
+    }
+}
+
+wire! {
+    /// The time an order has spent in each status, see `sla.rs`.
+    pub struct OrderDurationsResponse, OrderDurationsResponseCamel {
+        pub id: i64,
+        pub status: OrderStatus,
+        /// Oldest first, the last is the status the order's in now.
+        pub periods: Vec<Period>,
+        /// Milliseconds in each status, over all its periods.
+        pub totals: BTreeMap<OrderStatus, i64>,
+    }
+}
+
+wire! {
+    /// An order priced with hypothetical changes, see `orders/pricing.rs`.
+    pub struct PreviewTotalsResponse, PreviewTotalsResponseCamel {
+        pub id: i64,
+        pub currency: Currency,
+        pub lines: Vec<PreviewLine>,
+        pub subtotal: i64,
+        pub discount: i64,
+        pub tax: i64,
+        /// What the order's amount would be.
+        pub total: i64,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct PreviewLine {
+    pub sku: Option<String>,
+    pub description: String,
+    pub quantity: i64,
+    /// Per unit.
+    pub price: i64,
+    pub total: i64,
+}
+
+impl PreviewTotalsResponse {
+    pub fn new(id: i64, currency: Currency, totals: Totals) -> Self {
+        Self {
+            id,
+            currency,
+            lines: totals
+                .lines
+                .into_iter()
+                .map(|line| PreviewLine {
+                    sku: line.line.sku,
+                    description: line.line.description,
+                    quantity: line.line.quantity,
+                    price: line.line.unit_price,
+                    total: line.total,
+                })
+                .collect(),
+            subtotal: totals.subtotal,
+            discount: totals.discount,
+            tax: totals.tax,
+            total: totals.total,
+        }
+    }
+}
+
+/// Query parameters for `POST /orders/import`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ImportParams {
+    /// Answer with a 202 and work through the upload in the background.
+    #[serde(rename = "async", default)]
+    pub defer: bool,
+    /// Reads `amount_decimal` written like this locale does.
+    pub locale: Option<String>,
+}
+
+/// Query parameters for `POST /orders/import/graph`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GraphImportParams {
+    /// Replace orders already here rather than skip them.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// Any field can be left out. `metadata` and `shipping_address` are json
+/// merge patches, a null value removes that key, and a null
+/// `shipping_address` removes the address.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct UpdateOrderStatusRequest {
+    pub status: Option<OrderStatus>,
+    pub metadata: Option<Map<String, Value>>,
+    #[serde(
+        default,
+        alias = "shippingAddress",
+        deserialize_with = "present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub shipping_address: Option<Value>,
+}
+
+/// A field that's `Some` whenever it's in the body, `null` included, so a
+/// null can be told apart from leaving the field out.
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// Body of `POST /orders/search`, every field narrows the list.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SearchOrdersRequest {
+    pub status: Option<OrderStatus>,
+    #[serde(default, alias = "minAmount")]
+    pub min_amount: Option<i64>,
+    #[serde(default, alias = "maxAmount")]
+    pub max_amount: Option<i64>,
+    /// RFC 3339, inclusive.
+    #[serde(default, alias = "createdFrom")]
+    pub created_from: Option<String>,
+    /// RFC 3339, exclusive.
+    #[serde(default, alias = "createdTo")]
+    pub created_to: Option<String>,
+    /// Clauses by key, like `{"source": {"eq": "shopify"}}`, see `search.rs`.
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    #[serde(default, alias = "includeDrafts")]
+    pub include_drafts: bool,
+}
+
+/// Body of `POST /admin/events/replay`. Both ends of the range are
+/// exclusive and either can be left out.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplayEventsRequest {
+    #[serde(alias = "webhookId")]
+    pub webhook_id: i64,
+    #[serde(default, alias = "afterId")]
+    pub after_id: Option<i64>,
+    #[serde(default, alias = "beforeId")]
+    pub before_id: Option<i64>,
+    /// Payload types like `order.canceled`, every type when unset.
+    #[serde(default, alias = "eventTypes")]
+    pub event_types: Option<Vec<String>>,
+}
+
+/// Body of `POST /admin/orders/retag`. The orders are picked by the query
+/// parameters, the same ones as `GET /orders`'.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RetagRequest {
+    #[serde(default, alias = "addTags")]
+    pub add_tags: Vec<String>,
+    /// Removed after `add_tags` are added.
+    #[serde(default, alias = "removeTags")]
+    pub remove_tags: Vec<String>,
+}
+
+wire! {
+    /// What `POST /admin/orders/retag` changed, see `orders/retag.rs`.
+    pub struct RetagResponse, RetagResponseCamel {
+        pub updated: i64,
+        /// Orders left out because they'd have had too many tags, by id.
+        pub skipped: Vec<i64>,
+    }
+}
+
+impl From<Retagged> for RetagResponse {
+    fn from(retagged: Retagged) -> Self {
+        Self {
+            updated: retagged.updated,
+            skipped: retagged.skipped,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MergeOrderRequest {
+    /// The duplicate, canceled once its items and amount are moved over.
+    #[serde(alias = "sourceId")]
+    pub source_id: i64,
+}
+
+/// One operation of `POST /orders/transactions`, named by its `op`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum OrderOperation {
+    /// Creates an order along with its items.
+    Create {
+        order: CreateOrderRequest,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        items: Vec<AddItemRequest>,
+    },
+    /// Cancels an order, giving the `reason` in its `canceled` event.
+    Cancel {
+        id: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Create,
+    Cancel,
+}
+
+/// What an operation of `POST /orders/transactions` did. Written out by hand
+/// rather than by `wire!` so the order and items are cased too.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OperationResponse {
+    pub op: OperationKind,
+    /// The order as the operation left it.
+    pub order: OrderView,
+    /// The items a create added, none for a cancel.
+    pub items: Vec<ItemResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OperationResponseCamel {
+    pub op: OperationKind,
+    pub order: OrderViewCamel,
+    pub items: Vec<ItemResponseCamel>,
+}
+
+impl Wire for OperationResponse {
+    type Camel = OperationResponseCamel;
+
+    fn camel(self) -> Self::Camel {
+        OperationResponseCamel {
+            op: self.op,
+            order: self.order.camel(),
+            items: self.items.camel(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    /// Defaults to the latest version.
+    #[serde(default, alias = "payloadVersion")]
+    pub payload_version: Option<i64>,
+    /// Defaults to true. False accepts any certificate from the receiver.
+    #[serde(default, alias = "verifyTls")]
+    pub verify_tls: Option<bool>,
+}
+
+/// Changes whichever fields are set, at least one has to be.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UpdateWebhookRequest {
+    #[serde(default, alias = "payloadVersion")]
+    pub payload_version: Option<i64>,
+    #[serde(default, alias = "verifyTls")]
+    pub verify_tls: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReopenOrderRequest {
+    /// Who is reopening the order, kept on the `reopened` event.
+    pub actor: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AdjustItemRequest {
+    /// At least 1.
+    pub quantity: i64,
+    /// Who made the correction, kept on the `item_adjusted` event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+}
+
+/// Body of `POST /orders/{id}/preview-totals`, what to price the order with
+/// besides what it has. Every field can be left out.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PreviewTotalsRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discount: Option<DiscountRequest>,
+    /// A percent of the discounted subtotal, like `8` or `8.25`.
+    #[serde(default, alias = "taxRate", skip_serializing_if = "Option::is_none")]
+    pub tax_rate: Option<Number>,
+    /// Catalog items to add, priced at the catalog's price.
+    #[serde(default)]
+    pub add: Vec<PreviewItem>,
+    /// Skus whose items are taken off the order.
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+/// `{"percent": 10}` or `{"amount": 500}`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscountRequest {
+    /// A percent of the subtotal, like `10` or `12.5`.
+    Percent(Number),
+    /// Minor units off the subtotal.
+    Amount(i64),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PreviewItem {
+    pub sku: String,
+    pub quantity: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateNoteRequest {
+    /// `@username` mentions in it notify the person.
+    pub body: String,
+    /// Who wrote it, kept on the note and its `note_mention` events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+/// An item is either priced explicitly or looked up from the catalog by sku.
+/// Either is in the order's currency unless `currency` says otherwise, which
+/// is refused.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum AddItemRequest {
+    Sku {
+        sku: String,
+        quantity: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<String>,
+    },
+    Priced {
+        description: String,
+        quantity: i64,
+        #[serde(alias = "unitPrice")]
+        unit_price: i64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateProductRequest {
+    pub sku: String,
+    pub name: String,
+    #[serde(alias = "unitPrice")]
+    pub unit_price: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UpdateProductRequest {
+    pub name: Option<String>,
+    #[serde(alias = "unitPrice")]
+    pub unit_price: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SetQuotaRequest {
+    #[serde(alias = "monthlyLimit")]
+    pub monthly_limit: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// An RFC 3339 time, the key works until it's revoked when it's left out.
+    #[serde(default, alias = "expiresAt")]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RotateApiKeyParams {
+    /// How long the replaced secret keeps working, `API_KEY_ROTATION_OVERLAP_HOURS`
+    /// when it's left out. 0 stops it working straight away.
+    #[serde(default, alias = "overlapHours")]
+    pub overlap_hours: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ResetSequenceRequest {
+    pub value: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camel_keys() {
+        let item = ItemResponse {
+            id: 1,
+            order_id: 2,
+            sku: None,
+            description: "Widget".to_string(),
+            quantity: 1,
+            unit_price: 250,
+            currency: Currency::default(),
+        };
+
+        let snake = serde_json::to_value(&item).unwrap();
+        let camel = serde_json::to_value(item.camel()).unwrap();
+
+        assert_eq!(snake["order_id"], 2);
+        assert_eq!(snake["unit_price"], 250);
+        assert_eq!(camel["orderId"], 2);
+        assert_eq!(camel["unitPrice"], 250);
+        assert!(camel.get("order_id").is_none());
+    }
+
+    #[test]
+    fn test_request_aliases() {
+        let snake: SetQuotaRequest = serde_json::from_str(r#"{"monthly_limit": 5}"#).unwrap();
+        let camel: SetQuotaRequest = serde_json::from_str(r#"{"monthlyLimit": 5}"#).unwrap();
+
+        assert_eq!(snake.monthly_limit, camel.monthly_limit);
+    }
+}
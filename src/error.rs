@@ -1,26 +1,499 @@
-use axum::{body::Body, http::{Response, StatusCode}, response::IntoResponse};
+use axum::{
+    Json,
+    body::Body,
+    extract::Request,
+    http::{
+        Response, StatusCode,
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+    },
+    middleware::Next,
+    response::IntoResponse,
+};
+use futures_util::StreamExt;
+use serde_json::{Value, json};
+use std::time::Duration;
+
 use thiserror::Error;
 
+use crate::orders::OrderStatus;
+
 pub type Result<T> = std::result::Result<T, CustomError>;
 
 #[derive(Debug, Error)]
 pub enum CustomError {
     #[error("Record not found")]
     RecordNotFound,
+    /// No route has the path, or the feature it's part of is turned off.
+    #[error("no route matches the path")]
+    RouteNotFound,
+    /// The path is routed, but not for the request's method.
+    #[error("the route doesn't take this method")]
+    MethodNotAllowed,
+    /// The body is bigger than the route takes.
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    #[error("{0}")]
+    BadRequest(String),
+    /// A path id no row can have, so there's nothing to find.
+    #[error("id {0} is out of range, ids are between 1 and {max}", max = i64::MAX)]
+    IdOutOfRange(String),
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Conflict(String),
+    /// The record existed but has been deleted.
+    #[error("{0}")]
+    Gone(String),
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    /// A json body nested too deeply or holding too many values to parse.
+    #[error("{0}")]
+    PayloadTooComplex(String),
+    /// The `Accept` header rules out every media type the route produces.
+    #[error("this route can only respond with {}", .0.join(", "))]
+    NotAcceptable(&'static [&'static str]),
+    #[error("Quota exceeded: {usage} of {limit} orders used this month, resets on {resets_on}")]
+    QuotaExceeded {
+        limit: i64,
+        usage: i64,
+        resets_on: String,
+    },
+    /// A status change the lifecycle doesn't allow, see
+    /// `OrderStatus::can_transition_to`.
+    #[error("cannot transition from {from} to {to}")]
+    InvalidTransition { from: OrderStatus, to: OrderStatus },
+    /// The order's customer, or api key when it has none, already has as many
+    /// open orders as it's allowed.
+    #[error("{open} orders are already open, the limit is {limit}")]
+    OpenOrderLimit { open: i64, limit: i64 },
+    /// Something in one currency was added to, or merged into, an order in
+    /// another.
+    #[error("the order is in {order_currency}, not {currency}")]
+    CurrencyMismatch {
+        order_currency: String,
+        currency: String,
+    },
+    /// The caller's `X-Request-Deadline` passed before the request finished.
+    #[error("the request's deadline passed before it finished")]
+    DeadlineExceeded,
+    /// The request ran past `request_timeout`.
+    #[error("the request took longer than {} ms", .0.as_millis())]
+    Timeout(Duration),
+    /// Writes are off while the database schema is being fixed.
+    #[error("{0}")]
+    Maintenance(String),
+    /// A destructive operation in prod without everything that unlocks it,
+    /// see `interlock.rs`. `missing` names what's still needed.
+    #[error("destructive operations are locked in prod, this needs {}", .missing.join("; "))]
+    DestructiveOpLocked { missing: Vec<&'static str> },
+    /// `REQUIRE_API_KEY` is on and the request's `X-Api-Key` is missing or
+    /// doesn't work, see `api_keys::require`.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The api key works but doesn't have the scope the request needs.
+    #[error("{0}")]
+    Forbidden(String),
+    /// No database connection could be had, the pool timed out or is closed.
+    #[error("the database is unavailable, try again shortly")]
+    DatabaseUnavailable,
+    /// More writes are waiting for the database than `write_backlog_max`,
+    /// see `db::Writer`.
+    #[error("{depth} writes are already waiting for the database, the most is {max}, try again shortly")]
+    WriteBacklog { depth: usize, max: usize },
+    /// A search whose filters break rules across fields, every rule broken,
+    /// see `search::RULES`.
+    #[error("{}", .0.iter().map(|violation| violation.message.as_str()).collect::<Vec<_>>().join("; "))]
+    SearchRules(Vec<crate::search::Violation>),
+    /// An operation of `POST /orders/transactions` failed, so none of them
+    /// were made. It's sent with `error`'s code and status.
+    #[error("operation {index} failed: {error}")]
+    Operation { index: usize, error: Box<CustomError> },
     #[error("Something went wrong!")]
-    Other(#[from] anyhow::Error),
+    Other(#[source] anyhow::Error),
+}
+
+/// Failing to get a connection is the one database error worth a retry, so
+/// it's a 503 rather than the 500 of everything else.
+impl From<anyhow::Error> for CustomError {
+    fn from(err: anyhow::Error) -> Self {
+        let unavailable = err.chain().any(|cause| {
+            matches!(
+                cause.downcast_ref::<sqlx::Error>(),
+                Some(sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed)
+            )
+        });
+
+        match unavailable {
+            true => CustomError::DatabaseUnavailable,
+            false => CustomError::Other(err),
+        }
+    }
 }
 
+/// Attached to error responses so middleware can see which error produced them.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorCode(pub &'static str);
+
+/// Every code `CustomError::code` returns, with the status it's sent with and
+/// what it means, for `/meta/error-codes` and `export-error-codes`.
+pub const CODES: &[(&str, u16, &str)] = &[
+    ("record_not_found", 404, "Nothing has that id."),
+    ("route_not_found", 404, "No route has the path, or its feature is turned off."),
+    ("method_not_allowed", 405, "The route doesn't take the request's method."),
+    ("payload_too_large", 413, "The body is bigger than the route takes."),
+    ("bad_request", 400, "The request couldn't be read, e.g. a malformed header."),
+    ("id_out_of_range", 404, "A path id no record can have, 0, negative or too big."),
+    ("validation_failed", 422, "The body was read but a field is invalid."),
+    ("conflict", 409, "The change doesn't fit the record's current state."),
+    ("invalid_transition", 409, "The order's status can't change to the one asked for."),
+    ("gone", 410, "The record was deleted."),
+    ("unsupported_media_type", 415, "The body's Content-Type isn't json."),
+    ("payload_too_complex", 422, "The json body is nested too deeply or too large."),
+    ("not_acceptable", 406, "The Accept header rules out everything the route produces."),
+    ("quota_exceeded", 429, "The api key's monthly create quota is used up."),
+    ("open_order_limit", 409, "The customer or api key has too many open orders."),
+    ("currency_mismatch", 422, "An item or merged order isn't in the order's currency."),
+    ("deadline_exceeded", 504, "X-Request-Deadline passed before the request finished."),
+    ("timeout", 504, "The request took longer than the server's timeout."),
+    ("maintenance", 503, "Writes are off while the database is fixed."),
+    ("destructive_op_locked", 403, "A destructive admin operation in prod wasn't unlocked and confirmed."),
+    ("unauthorized", 401, "The X-Api-Key is missing, unknown, expired or revoked."),
+    ("forbidden", 403, "The api key doesn't have the scope the request needs."),
+    ("database_unavailable", 503, "No database connection could be had, worth retrying."),
+    ("write_backlog", 503, "Too many writes are waiting for the database, reads still work."),
+    ("search_rules_violated", 422, "A search's filters contradict each other or ask for too much."),
+    ("internal_error", 500, "Something went wrong on the server."),
+];
+
+/// `CODES` as it's served and exported.
+pub fn codes_document() -> String {
+    let codes: Vec<_> = CODES
+        .iter()
+        .map(|(code, status, description)| {
+            serde_json::json!({ "code": code, "status": status, "description": description })
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&codes).expect("the codes are plain json")
+}
+
+impl CustomError {
+    /// A stable, machine-readable name for the error, listed in `CODES`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CustomError::RecordNotFound => "record_not_found",
+            CustomError::RouteNotFound => "route_not_found",
+            CustomError::MethodNotAllowed => "method_not_allowed",
+            CustomError::PayloadTooLarge(_) => "payload_too_large",
+            CustomError::BadRequest(_) => "bad_request",
+            CustomError::IdOutOfRange(_) => "id_out_of_range",
+            CustomError::Validation(_) => "validation_failed",
+            CustomError::Conflict(_) => "conflict",
+            CustomError::InvalidTransition { .. } => "invalid_transition",
+            CustomError::Gone(_) => "gone",
+            CustomError::UnsupportedMediaType(_) => "unsupported_media_type",
+            CustomError::PayloadTooComplex(_) => "payload_too_complex",
+            CustomError::NotAcceptable(_) => "not_acceptable",
+            CustomError::QuotaExceeded { .. } => "quota_exceeded",
+            CustomError::OpenOrderLimit { .. } => "open_order_limit",
+            CustomError::CurrencyMismatch { .. } => "currency_mismatch",
+            CustomError::DeadlineExceeded => "deadline_exceeded",
+            CustomError::Timeout(_) => "timeout",
+            CustomError::Maintenance(_) => "maintenance",
+            CustomError::DestructiveOpLocked { .. } => "destructive_op_locked",
+            CustomError::Unauthorized(_) => "unauthorized",
+            CustomError::Forbidden(_) => "forbidden",
+            CustomError::DatabaseUnavailable => "database_unavailable",
+            CustomError::WriteBacklog { .. } => "write_backlog",
+            CustomError::SearchRules(_) => "search_rules_violated",
+            CustomError::Operation { error, .. } => error.code(),
+            CustomError::Other(_) => "internal_error",
+        }
+    }
+}
+
+impl CustomError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            CustomError::RecordNotFound
+            | CustomError::RouteNotFound
+            | CustomError::IdOutOfRange(_) => StatusCode::NOT_FOUND,
+            CustomError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            CustomError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            CustomError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            CustomError::Validation(_)
+            | CustomError::PayloadTooComplex(_)
+            | CustomError::CurrencyMismatch { .. }
+            | CustomError::SearchRules(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            CustomError::Conflict(_)
+            | CustomError::InvalidTransition { .. }
+            | CustomError::OpenOrderLimit { .. } => StatusCode::CONFLICT,
+            CustomError::Gone(_) => StatusCode::GONE,
+            CustomError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            CustomError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            CustomError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            CustomError::DeadlineExceeded | CustomError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            CustomError::Maintenance(_)
+            | CustomError::DatabaseUnavailable
+            | CustomError::WriteBacklog { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            CustomError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            CustomError::DestructiveOpLocked { .. } | CustomError::Forbidden(_) => StatusCode::FORBIDDEN,
+            CustomError::Operation { error, .. } => error.status(),
+            CustomError::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The json body the error is sent with. Every one has its code and
+    /// message under `error`, some say more.
+    pub fn body(&self) -> Value {
+        let code = self.code();
+        let message = self.to_string();
+
+        let more = match self {
+            CustomError::NotAcceptable(produces) => json!({ "accepted": produces }),
+            CustomError::QuotaExceeded {
+                limit,
+                usage,
+                resets_on,
+            } => json!({ "limit": limit, "usage": usage, "resets_on": resets_on }),
+            CustomError::DestructiveOpLocked { missing } => json!({ "missing": missing }),
+            CustomError::SearchRules(violations) => json!({ "violations": violations }),
+            CustomError::Operation { index, .. } => json!({ "operation": index }),
+            CustomError::WriteBacklog { depth, max } => json!({ "depth": depth, "max": max }),
+            CustomError::InvalidTransition { from, to } => json!({ "from": from, "to": to }),
+            CustomError::OpenOrderLimit { open, limit } => json!({ "open": open, "limit": limit }),
+            CustomError::CurrencyMismatch {
+                order_currency,
+                currency,
+            } => json!({ "order_currency": order_currency, "currency": currency }),
+            _ => json!({}),
+        };
+
+        let mut error = json!({ "code": code, "message": message });
+
+        if let (Value::Object(error), Value::Object(more)) = (&mut error, more) {
+            error.extend(more);
+        }
+
+        json!({ "error": error })
+    }
+
+    /// One of each error, for the examples in the OpenAPI document.
+    pub fn examples() -> Vec<CustomError> {
+        vec![
+            CustomError::RecordNotFound,
+            CustomError::RouteNotFound,
+            CustomError::MethodNotAllowed,
+            CustomError::PayloadTooLarge("the body is over 65536 bytes".to_string()),
+            CustomError::BadRequest("X-Request-Deadline must be an RFC 3339 time".to_string()),
+            CustomError::IdOutOfRange("0".to_string()),
+            CustomError::Validation("amount must be at least 1".to_string()),
+            CustomError::Conflict("only a canceled order can be reopened".to_string()),
+            CustomError::InvalidTransition {
+                from: OrderStatus::Complete,
+                to: OrderStatus::Pending,
+            },
+            CustomError::Gone("order 1 was deleted".to_string()),
+            CustomError::UnsupportedMediaType("Content-Type must be application/json".to_string()),
+            CustomError::PayloadTooComplex("the json body is nested deeper than 32".to_string()),
+            CustomError::NotAcceptable(&[crate::negotiate::JSON]),
+            CustomError::QuotaExceeded {
+                limit: 1000,
+                usage: 1000,
+                resets_on: "2025-11-01".to_string(),
+            },
+            CustomError::OpenOrderLimit { open: 5, limit: 5 },
+            CustomError::CurrencyMismatch {
+                order_currency: "EUR".to_string(),
+                currency: "USD".to_string(),
+            },
+            CustomError::DeadlineExceeded,
+            CustomError::Timeout(Duration::from_secs(30)),
+            CustomError::Maintenance("writes are off while the schema is fixed".to_string()),
+            CustomError::DestructiveOpLocked {
+                missing: crate::interlock::missing(false, false),
+            },
+            CustomError::Unauthorized("the api key has expired".to_string()),
+            CustomError::Forbidden("the api key doesn't have the admin scope".to_string()),
+            CustomError::DatabaseUnavailable,
+            CustomError::WriteBacklog { depth: 65, max: 64 },
+            CustomError::SearchRules(vec![crate::search::Violation {
+                rule: "amount_range_empty",
+                message: "min_amount 500 is more than max_amount 100".to_string(),
+            }]),
+            CustomError::Other(anyhow::anyhow!("disk I/O error")),
+        ]
+    }
+}
+
+impl CustomError {
+    /// This error as the failure of the `index`th operation of a transaction.
+    pub fn in_operation(self, index: usize) -> Self {
+        CustomError::Operation {
+            index,
+            error: Box::new(self),
+        }
+    }
+}
 
 impl IntoResponse for CustomError {
     fn into_response(self) -> Response<Body> {
-        let (status, message) = match self {
-            CustomError::RecordNotFound => (StatusCode::NOT_FOUND, "404 Record not found"),
-            CustomError::Other(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong!"),
-        };
+        let code = ErrorCode(self.code());
+        let status = self.status();
+
+        let mut response = (status, Json(self.body())).into_response();
+
+        response.extensions_mut().insert(code);
+
+        response
+    }
+}
+
+/// How much of a rejection's plain text body is kept as the message.
+const REJECTION_MAX_BYTES: usize = 4096;
+
+/// Sends the error responses that weren't made from a `CustomError` in its
+/// json body too: the router's 404 and 405, a body over the route's limit,
+/// and axum's rejections of a path, query or json body it couldn't read,
+/// their text becoming the message, cut at `REJECTION_MAX_BYTES`. Html pages
+/// and anything else with a body that isn't plain text go out as they are.
+pub async fn envelope(request: Request, next: Next) -> Response<Body> {
+    let response = next.run(request).await;
+    let status = response.status();
+
+    let plain = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_none_or(|content_type| content_type.as_bytes().starts_with(b"text/plain"));
+
+    if response.extensions().get::<ErrorCode>().is_some() || !plain {
+        return response;
+    }
+
+    let error: fn(String) -> CustomError = match status {
+        StatusCode::NOT_FOUND => |_| CustomError::RouteNotFound,
+        StatusCode::METHOD_NOT_ALLOWED => |_| CustomError::MethodNotAllowed,
+        StatusCode::PAYLOAD_TOO_LARGE => CustomError::PayloadTooLarge,
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => CustomError::UnsupportedMediaType,
+        StatusCode::UNPROCESSABLE_ENTITY => CustomError::Validation,
+        StatusCode::BAD_REQUEST => CustomError::BadRequest,
+        status if status.is_server_error() => |message| CustomError::Other(anyhow::anyhow!(message)),
+        _ => return response,
+    };
+
+    let (mut parts, body) = response.into_parts();
+
+    // no more than the message keeps, what was read before an error is kept
+    let mut data = body.into_data_stream();
+    let mut text = Vec::new();
+    while text.len() <= REJECTION_MAX_BYTES
+        && let Some(Ok(chunk)) = data.next().await
+    {
+        text.extend_from_slice(&chunk);
+    }
+
+    let mut message = String::from_utf8_lossy(&text).into_owned();
+    if text.len() > REJECTION_MAX_BYTES {
+        let mut end = REJECTION_MAX_BYTES.min(message.len());
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+        message.push('…');
+    }
+
+    let mut enveloped = error(message.trim().to_string()).into_response();
+
+    // e.g. the 405's `Allow`
+    parts.headers.remove(CONTENT_TYPE);
+    parts.headers.remove(CONTENT_LENGTH);
+    enveloped.headers_mut().extend(parts.headers);
+
+    enveloped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_code_is_listed_with_its_status() {
+        let errors = [
+            CustomError::RecordNotFound,
+            CustomError::RouteNotFound,
+            CustomError::MethodNotAllowed,
+            CustomError::PayloadTooLarge(String::new()),
+            CustomError::BadRequest(String::new()),
+            CustomError::IdOutOfRange(String::new()),
+            CustomError::Validation(String::new()),
+            CustomError::Conflict(String::new()),
+            CustomError::InvalidTransition {
+                from: OrderStatus::Complete,
+                to: OrderStatus::Pending,
+            },
+            CustomError::Gone(String::new()),
+            CustomError::UnsupportedMediaType(String::new()),
+            CustomError::PayloadTooComplex(String::new()),
+            CustomError::NotAcceptable(&[]),
+            CustomError::QuotaExceeded {
+                limit: 1,
+                usage: 1,
+                resets_on: String::new(),
+            },
+            CustomError::OpenOrderLimit { open: 1, limit: 1 },
+            CustomError::CurrencyMismatch {
+                order_currency: String::new(),
+                currency: String::new(),
+            },
+            CustomError::DeadlineExceeded,
+            CustomError::Timeout(Duration::ZERO),
+            CustomError::Maintenance(String::new()),
+            CustomError::DestructiveOpLocked { missing: vec![] },
+            CustomError::Unauthorized(String::new()),
+            CustomError::Forbidden(String::new()),
+            CustomError::DatabaseUnavailable,
+            CustomError::WriteBacklog { depth: 1, max: 0 },
+            CustomError::SearchRules(vec![]),
+            CustomError::Other(anyhow::anyhow!("")),
+        ];
+
+        assert_eq!(errors.len(), CODES.len());
+
+        for error in errors {
+            let code = error.code();
+            let status = error.into_response().status().as_u16();
+
+            assert!(
+                CODES.iter().any(|listed| listed.0 == code && listed.1 == status),
+                "{code} isn't listed with {status}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_long_plain_text_errors_are_cut_short() {
+        use axum::{Router, body::Bytes, middleware, routing::get};
+        use futures_util::stream;
+        use http_body_util::BodyExt;
+        use tower::ServiceExt;
+
+        // streamed, so there's no length to go by
+        let chunks = (0..REJECTION_MAX_BYTES).map(|_| Ok::<_, std::io::Error>(Bytes::from_static(b"xx")));
+        let app = Router::new()
+            .route(
+                "/",
+                get(move || async move { (StatusCode::BAD_REQUEST, Body::from_stream(stream::iter(chunks))) }),
+            )
+            .layer(middleware::from_fn(envelope));
+
+        let response = app.oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap();
 
-        let body = Body::from(message.to_string());
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.extensions().get::<ErrorCode>().map(|code| code.0), Some("bad_request"));
 
-        Response::builder().status(status).body(body).unwrap()
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "bad_request");
+        assert_eq!(body["error"]["message"], format!("{}…", "x".repeat(REJECTION_MAX_BYTES)));
     }
 }
@@ -0,0 +1,294 @@
+//! Watches the share of requests that end in a 5xx so an incident alerts
+//! someone before users write in. `metrics::track` counts every response with
+//! a couple of atomic adds, and `watch` closes a window every minute. When
+//! enough windows in a row are over the threshold a `system.error_rate` event
+//! goes to the webhooks, once per episode.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use axum::http::StatusCode;
+
+use crate::{
+    AppState,
+    config::AppConfig,
+    events::{SystemEvent, SystemEventKind},
+};
+
+/// How long each window counts requests for.
+pub const WINDOW: Duration = Duration::from_secs(60);
+/// Percent of a window's requests that have to be 5xx for it to be over.
+pub const DEFAULT_ALERT_PERCENT: u64 = 20;
+/// Windows in a row that have to be over before an alert fires.
+pub const DEFAULT_ALERT_WINDOWS: usize = 3;
+/// How long after an alert another one can fire.
+pub const DEFAULT_ALERT_COOLDOWN: Duration = Duration::from_secs(30 * 60);
+
+/// Closed windows kept for `/admin/stats/errors`.
+const RECENT_WINDOWS: usize = 10;
+/// A window with fewer requests than this is never over, so a couple of
+/// failures on a quiet night don't alert anyone.
+const MIN_REQUESTS: u64 = 10;
+
+#[derive(Debug, Default)]
+pub struct ErrorRate {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    windows: Mutex<Windows>,
+}
+
+#[derive(Debug, Default)]
+struct Windows {
+    /// Newest first.
+    recent: VecDeque<Window>,
+    /// Windows in a row over the threshold, up to the last one closed.
+    over: usize,
+    /// An alert fired and no window has been under the threshold since.
+    alerting: bool,
+    last_alert: Option<Instant>,
+    alerts: u64,
+}
+
+/// The requests a window counted, and how many of them were 5xx.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Window {
+    pub requests: u64,
+    pub errors: u64,
+}
+
+impl Window {
+    pub fn percent(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+
+        self.errors as f64 / self.requests as f64 * 100.0
+    }
+
+    fn over(&self, policy: &AlertPolicy) -> bool {
+        self.requests >= MIN_REQUESTS && self.percent() > policy.percent as f64
+    }
+}
+
+/// When an alert fires, from `AppConfig`.
+#[derive(Debug, Clone, Copy)]
+pub struct AlertPolicy {
+    pub percent: u64,
+    pub windows: usize,
+    pub cooldown: Duration,
+}
+
+impl AlertPolicy {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            percent: config.error_rate_alert_percent,
+            windows: config.error_rate_alert_windows,
+            cooldown: config.error_rate_alert_cooldown,
+        }
+    }
+}
+
+/// Why an alert fired: the last window and how many in a row were over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alert {
+    pub window: Window,
+    pub windows: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorStats {
+    /// The window still open.
+    pub current: Window,
+    /// The last closed windows, newest first.
+    pub recent: Vec<Window>,
+    pub over: usize,
+    pub alerting: bool,
+    /// Alerts fired since startup.
+    pub alerts: u64,
+}
+
+impl ErrorRate {
+    pub fn record(&self, status: StatusCode) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        if status.is_server_error() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Ends the open window and starts the next one. Returns an alert when
+    /// this window makes `policy.windows` in a row over the threshold, unless
+    /// one already fired for the episode or the last one was within the
+    /// cool-down.
+    pub fn close_window(&self, now: Instant, policy: &AlertPolicy) -> Option<Alert> {
+        let requests = self.requests.swap(0, Ordering::Relaxed);
+        // a request finishing between the two swaps can have its error counted
+        // in one window and the request in the other
+        let errors = self.errors.swap(0, Ordering::Relaxed).min(requests);
+        let window = Window { requests, errors };
+
+        let mut windows = self.windows.lock().unwrap();
+
+        windows.recent.push_front(window);
+        windows.recent.truncate(RECENT_WINDOWS);
+
+        if !window.over(policy) {
+            windows.over = 0;
+            windows.alerting = false;
+
+            return None;
+        }
+
+        windows.over += 1;
+
+        let cooled_down = windows
+            .last_alert
+            .is_none_or(|at| now.saturating_duration_since(at) >= policy.cooldown);
+
+        if windows.alerting || windows.over < policy.windows || !cooled_down {
+            return None;
+        }
+
+        windows.alerting = true;
+        windows.last_alert = Some(now);
+        windows.alerts += 1;
+
+        Some(Alert {
+            window,
+            windows: windows.over,
+        })
+    }
+
+    pub fn stats(&self) -> ErrorStats {
+        let windows = self.windows.lock().unwrap();
+
+        ErrorStats {
+            current: Window {
+                requests: self.requests.load(Ordering::Relaxed),
+                errors: self.errors.load(Ordering::Relaxed),
+            },
+            recent: windows.recent.iter().copied().collect(),
+            over: windows.over,
+            alerting: windows.alerting,
+            alerts: windows.alerts,
+        }
+    }
+}
+
+/// Closes the open window and, when that fires an alert, logs it and records
+/// a `system.error_rate` event for the webhooks. Returns whether it fired.
+pub async fn check(state: &AppState, now: Instant) -> Result<bool> {
+    let policy = AlertPolicy::new(&state.config());
+
+    let Some(alert) = state.error_rate.close_window(now, &policy) else {
+        return Ok(false);
+    };
+
+    let detail = format!(
+        "{:.1}% of {} requests in the last minute were 5xx, {} windows in a row over {}%",
+        alert.window.percent(),
+        alert.window.requests,
+        alert.windows,
+        policy.percent
+    );
+
+    tracing::error!("error rate alert: {detail}");
+    state.metrics.record_error_rate_alert();
+
+    SystemEvent::record(&state.db, SystemEventKind::ErrorRate, &detail).await?;
+
+    Ok(true)
+}
+
+/// Closes a window every `WINDOW` until the process exits.
+pub async fn watch(state: AppState) {
+    let mut interval = tokio::time::interval(WINDOW);
+
+    // the first tick is straight away, the first window ends a `WINDOW` later
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = check(&state, Instant::now()).await {
+            tracing::error!("failed to record an error rate alert: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: AlertPolicy = AlertPolicy {
+        percent: 20,
+        windows: 2,
+        cooldown: Duration::from_secs(600),
+    };
+
+    fn window(rate: &ErrorRate, requests: u64, errors: u64) {
+        for i in 0..requests {
+            rate.record(if i < errors {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::OK
+            });
+        }
+    }
+
+    #[test]
+    fn test_alerts_once_per_episode() {
+        let rate = ErrorRate::default();
+        let start = Instant::now();
+        let minute = |n: u32| start + WINDOW * n;
+
+        window(&rate, 20, 10);
+        assert_eq!(rate.close_window(minute(1), &POLICY), None);
+
+        window(&rate, 20, 10);
+        let alert = rate.close_window(minute(2), &POLICY).unwrap();
+        assert_eq!(alert.windows, 2);
+        assert_eq!(alert.window.percent(), 50.0);
+
+        // still in the same episode
+        window(&rate, 20, 10);
+        assert_eq!(rate.close_window(minute(3), &POLICY), None);
+
+        // over again after a good window, but within the cool-down
+        window(&rate, 20, 0);
+        assert_eq!(rate.close_window(minute(4), &POLICY), None);
+        for n in 5..=6 {
+            window(&rate, 20, 10);
+            assert_eq!(rate.close_window(minute(n), &POLICY), None);
+        }
+
+        // the same episode once the cool-down is over
+        window(&rate, 20, 10);
+        assert!(rate.close_window(minute(12), &POLICY).is_some());
+
+        let stats = rate.stats();
+        assert_eq!(stats.alerts, 2);
+        assert!(stats.alerting);
+        assert_eq!(stats.recent.len(), 7);
+    }
+
+    #[test]
+    fn test_quiet_windows_are_never_over() {
+        let rate = ErrorRate::default();
+        let start = Instant::now();
+
+        for n in 1..=5 {
+            window(&rate, MIN_REQUESTS - 1, MIN_REQUESTS - 1);
+            assert_eq!(rate.close_window(start + WINDOW * n, &POLICY), None);
+        }
+
+        assert_eq!(rate.stats().over, 0);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{fmt::Display, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, Transaction, prelude::FromRow};
+
+use crate::{
+    clock,
+    db::{self, Db},
+    timing::Timed,
+    webhooks,
+};
+
+/// Something that happened to an order, kept as its history.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct OrderEvent {
+    pub id: Option<i64>,
+    pub order_id: i64,
+    pub kind: EventKind,
+    /// A human readable description, e.g. "merged into #12".
+    pub detail: String,
+    /// Who made the change, for changes made by a person rather than the api.
+    pub actor: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Another order was merged into this one.
+    Merged,
+    /// This order was merged into another one and canceled.
+    MergedInto,
+    /// The order sat in pending long enough to nudge someone about it.
+    PendingReminder,
+    /// The order's status was changed to canceled.
+    Canceled,
+    /// A canceled order was put back to pending.
+    Reopened,
+    /// A draft was confirmed into a pending order.
+    Confirmed,
+    /// Someone was mentioned with an `@username` in a note on the order.
+    NoteMention,
+    /// An item's quantity was corrected, the detail has the old and new one.
+    ItemAdjusted,
+    /// The order's derived fields were corrected, the detail has each one's
+    /// old and new value.
+    Recomputed,
+}
+
+impl EventKind {
+    pub const ALL: &[EventKind] = &[
+        EventKind::Merged,
+        EventKind::MergedInto,
+        EventKind::PendingReminder,
+        EventKind::Canceled,
+        EventKind::Reopened,
+        EventKind::Confirmed,
+        EventKind::NoteMention,
+        EventKind::ItemAdjusted,
+        EventKind::Recomputed,
+    ];
+
+    /// The kind of a webhook payload's `type`, like `order.canceled`.
+    pub fn from_type(event_type: &str) -> Option<Self> {
+        let kind = event_type.strip_prefix("order.")?;
+
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|known| known.to_string() == kind)
+    }
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            EventKind::Merged => "merged",
+            EventKind::MergedInto => "merged_into",
+            EventKind::PendingReminder => "pending_reminder",
+            EventKind::Canceled => "canceled",
+            EventKind::Reopened => "reopened",
+            EventKind::Confirmed => "confirmed",
+            EventKind::NoteMention => "note_mention",
+            EventKind::ItemAdjusted => "item_adjusted",
+            EventKind::Recomputed => "recomputed",
+        };
+
+        write!(f, "{kind}")
+    }
+}
+
+impl From<String> for EventKind {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "merged_into" => EventKind::MergedInto,
+            "pending_reminder" => EventKind::PendingReminder,
+            "canceled" => EventKind::Canceled,
+            "reopened" => EventKind::Reopened,
+            "confirmed" => EventKind::Confirmed,
+            "note_mention" => EventKind::NoteMention,
+            "item_adjusted" => EventKind::ItemAdjusted,
+            "recomputed" => EventKind::Recomputed,
+            _ => EventKind::Merged,
+        }
+    }
+}
+
+impl OrderEvent {
+    /// Events are written in the same transaction as the change they describe,
+    /// along with a delivery to each webhook.
+    pub async fn record(
+        tx: &mut Transaction<'_, Sqlite>,
+        order_id: i64,
+        kind: EventKind,
+        detail: &str,
+        actor: Option<&str>,
+    ) -> Result<()> {
+        let kind = kind.to_string();
+        let now = clock::now();
+
+        let id = sqlx::query!(
+            "INSERT INTO order_events (order_id, kind, detail, actor, created_at)
+            VALUES (?, ?, ?, ?, ?);",
+            order_id,
+            kind,
+            detail,
+            actor,
+            now
+        )
+        .execute(&mut **tx)
+        .timed()
+        .await?
+        .last_insert_rowid();
+
+        webhooks::enqueue(tx, id).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_by_id(db: &Db, id: i64) -> Result<Option<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(OrderEvent, "select * from order_events where id = ?", id)
+                .fetch_optional(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+
+    pub async fn get_by_order_id(db: &Db, order_id: i64) -> Result<Vec<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                OrderEvent,
+                "select * from order_events where order_id = ? order by id",
+                order_id
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?)
+    }
+
+    /// Up to `limit` events recorded after the event `after`, oldest first.
+    pub async fn get_after(db: &Db, after: i64, limit: i64) -> Result<Vec<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                OrderEvent,
+                "select * from order_events where id > ? order by id limit ?",
+                after,
+                limit
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?)
+    }
+
+    /// How long ago the order was canceled, or `None` when it wasn't canceled
+    /// by a status change or has been merged or reopened since.
+    pub async fn canceled_ago(db: &Db, order_id: i64) -> Result<Option<Duration>> {
+        // the request's clock, like the event's created_at
+        let now = clock::now();
+
+        let last = sqlx::query!(
+            r#"select kind, (julianday(?) - julianday(created_at)) * 86400.0 as "seconds!: f64"
+            from order_events
+            where order_id = ? and kind in ('canceled', 'merged_into', 'reopened')
+            order by id desc limit 1"#,
+            now,
+            order_id
+        )
+        .fetch_optional(db.reader())
+        .timed()
+        .await?;
+
+        Ok(last
+            .filter(|last| EventKind::from(last.kind.clone()) == EventKind::Canceled)
+            .map(|last| Duration::from_secs_f64(last.seconds.max(0.0))))
+    }
+}
+
+/// Something that happened to the service rather than to an order. Sent to
+/// the webhooks like order events, with the type `system.<kind>`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SystemEvent {
+    pub id: Option<i64>,
+    pub kind: SystemEventKind,
+    pub detail: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemEventKind {
+    /// Too many requests ended in a 5xx for too long, see `error_rate.rs`.
+    ErrorRate,
+    /// The database file failed an integrity check, see `integrity.rs`.
+    IntegrityFailure,
+}
+
+impl Display for SystemEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            SystemEventKind::ErrorRate => "error_rate",
+            SystemEventKind::IntegrityFailure => "integrity_failure",
+        };
+
+        write!(f, "{kind}")
+    }
+}
+
+impl From<String> for SystemEventKind {
+    fn from(kind: String) -> Self {
+        match kind.as_str() {
+            "integrity_failure" => SystemEventKind::IntegrityFailure,
+            _ => SystemEventKind::ErrorRate,
+        }
+    }
+}
+
+impl SystemEvent {
+    /// Records the event and a delivery to each webhook, in one transaction.
+    pub async fn record(db: &Db, kind: SystemEventKind, detail: &str) -> Result<i64> {
+        let kind = kind.to_string();
+        let now = clock::now();
+        let mut tx = db.writer().begin().await?;
+
+        let id = sqlx::query!(
+            "INSERT INTO system_events (kind, detail, created_at) VALUES (?, ?, ?);",
+            kind,
+            detail,
+            now
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?
+        .last_insert_rowid();
+
+        webhooks::enqueue_system(&mut tx, id).await?;
+
+        tx.commit().await?;
+
+        Ok(id)
+    }
+
+    pub async fn get_by_id(db: &Db, id: i64) -> Result<Option<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(SystemEvent, "select * from system_events where id = ?", id)
+                .fetch_optional(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+}
@@ -0,0 +1,1106 @@
+//! Examples for the OpenAPI document. Each one is a model or request type
+//! built here and turned into what the api sends with the same conversions and
+//! serializers the handlers use, so an example can't drift from the real
+//! thing. `EXAMPLES` has the request and response examples of every
+//! operation in `openapi`, and `CustomError::examples` those of the errors.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::{
+    address::ShippingAddress,
+    audit::{Break, Verification},
+    backup::Backup,
+    bus::BusStats,
+    clock,
+    config::{AppConfig, IntegrityCheck},
+    consistency::Violations,
+    api_keys::{ApiKey, Scope},
+    dto::{
+        AddItemRequest, ApiKeyResponse, ApiKeySecretResponse, CreateApiKeyRequest, AdjustItemRequest, AuditVerificationResponse, BackupResponse, CacheResponse, ConfigResponse,
+        ConsistencyCheckResponse, CountResponse, CreateNoteRequest, CreateOrderRequest, CreateProductRequest,
+        CreateWebhookRequest, DailyReportResponse, DiscountRequest, ErrorStatsResponse, EventResponse, GraphImportResponse, GrowthResponse,
+        ImportJobResponse, ItemResponse, LoggedRequestResponse, MaintenanceResponse, MergeOrderRequest, NoteResponse, OperationKind, OperationResponse, OrderAsOfResponse, OrderOperation,
+        OrderDurationsResponse, OrderStatsResponse, OrderView, PaginatedOrders, PreviewItem, PreviewTotalsRequest, PreviewTotalsResponse, ProductResponse, QuotaResponse, ReadinessResponse, RecomputeResponse, ReopenOrderRequest, ReplayEventsRequest, ReplayResponse, RetagRequest, RetagResponse,
+        RequestStatsResponse, ResetSequenceRequest, RuntimeResponse, SearchOrdersRequest, SequenceResponse, SetQuotaRequest, StreamEventResponse,
+        SlaReportResponse, UpdateOrderStatusRequest, UpdateProductRequest, UpdateWebhookRequest, UsageResponse, WebhookResponse,
+    },
+    error,
+    error_rate::{ErrorStats, Window},
+    events::{EventKind, OrderEvent},
+    exports::{AmountColumns, Export},
+    graphs::{self, GraphItem, GraphNote, OrderGraph, StatusChange},
+    growth::GrowthStats,
+    imports::{ImportJob, ImportStatus},
+    integrity::Report,
+    items::Item,
+    maintenance::{Checkpoint, Fragmentation, MaintenanceStats, Runs, Vacuum},
+    metadata::Metadata,
+    metrics::{Metrics, Outcome},
+    money::Currency,
+    negotiate::{JSON, NDJSON},
+    notes::{Mention, Note},
+    orders::{
+        Order, OrderStatus,
+        history::FieldAsOf,
+        pricing::{self, Adjustments, Discount, Line},
+        recompute::Correction,
+    },
+    products::Product,
+    quotas::{KeyQuota, Usage},
+    reports::DailyReport,
+    request_log::{KeyLatency, LoggedRequest},
+    routes,
+    schemas,
+    sla::{self, Period, SlaReport, StatusTimes},
+    stats::OrderStats,
+    webhooks::{Replay, Webhook},
+};
+
+/// Builds an example body.
+type Build = fn() -> Value;
+
+/// The examples of one operation, found by its method and path.
+pub struct Examples {
+    pub method: &'static str,
+    pub path: &'static str,
+    /// The request body with its media type, for the operations taking one.
+    pub request: Option<(&'static str, Build)>,
+    /// The success response's body, `None` for the ones without. Json unless
+    /// the route produces something else, then the text of it.
+    pub response: Option<Build>,
+}
+
+const fn ex(method: &'static str, path: &'static str, request: Option<Build>, response: Option<Build>) -> Examples {
+    Examples {
+        method,
+        path,
+        request: match request {
+            Some(request) => Some((JSON, request)),
+            None => None,
+        },
+        response,
+    }
+}
+
+/// A type an example of it can be made for.
+pub trait Example: Serialize + Sized {
+    fn example() -> Self;
+}
+
+impl<T: Example> Example for Vec<T> {
+    fn example() -> Self {
+        vec![T::example()]
+    }
+}
+
+fn of<T: Example>() -> Value {
+    serde_json::to_value(T::example()).expect("examples serialize to json")
+}
+
+const EXAMPLES: &[Examples] = &[
+    ex("get", routes::ORDERS, None, Some(of::<PaginatedOrders>)),
+    ex("post", routes::ORDERS, Some(of::<CreateOrderRequest>), Some(of::<OrderView>)),
+    ex("get", routes::ORDER_COUNT, None, Some(of::<CountResponse>)),
+    ex("get", routes::ORDER_STATS, None, Some(of::<OrderStatsResponse>)),
+    ex("post", routes::ORDER_SEARCH, Some(of::<SearchOrdersRequest>), Some(of::<Vec<OrderView>>)),
+    ex("get", routes::PICK_LIST, None, Some(of::<Vec<OrderView>>)),
+    ex("get", routes::ORDER_EXPORT, None, Some(export_csv)),
+    Examples {
+        method: "post",
+        path: routes::ORDER_IMPORT,
+        request: Some(("text/csv", import_csv)),
+        response: Some(of::<ImportJobResponse>),
+    },
+    ex("get", routes::ORDER_GRAPH_EXPORT, None, Some(export_graphs)),
+    Examples {
+        method: "post",
+        path: routes::ORDER_GRAPH_IMPORT,
+        request: Some((NDJSON, export_graphs)),
+        response: Some(of::<GraphImportResponse>),
+    },
+    ex("post", routes::ORDER_TRANSACTIONS, Some(transaction), Some(transaction_results)),
+    ex("get", routes::IMPORT_JOB, None, Some(of::<ImportJobResponse>)),
+    ex("get", routes::ORDER, None, Some(of::<OrderView>)),
+    ex("head", routes::ORDER, None, None),
+    ex("patch", routes::ORDER, Some(of::<UpdateOrderStatusRequest>), Some(of::<OrderView>)),
+    ex("delete", routes::ORDER, None, None),
+    ex("get", routes::ORDER_ITEMS, None, Some(of::<Vec<ItemResponse>>)),
+    ex("post", routes::ORDER_ITEMS, Some(of::<AddItemRequest>), Some(of::<ItemResponse>)),
+    ex("patch", routes::ORDER_ITEM, Some(of::<AdjustItemRequest>), Some(of::<ItemResponse>)),
+    ex("get", routes::ORDER_EVENTS, None, Some(of::<Vec<EventResponse>>)),
+    ex("get", routes::ORDER_AS_OF, None, Some(of::<OrderAsOfResponse>)),
+    ex("get", routes::ORDER_DURATIONS, None, Some(of::<OrderDurationsResponse>)),
+    ex("post", routes::ORDER_PREVIEW_TOTALS, Some(of::<PreviewTotalsRequest>), Some(of::<PreviewTotalsResponse>)),
+    ex("get", routes::ORDER_NOTES, None, Some(of::<Vec<NoteResponse>>)),
+    ex("post", routes::ORDER_NOTES, Some(of::<CreateNoteRequest>), Some(of::<NoteResponse>)),
+    ex("post", routes::ORDER_MERGE, Some(of::<MergeOrderRequest>), Some(of::<OrderView>)),
+    ex("post", routes::ORDER_REOPEN, Some(of::<ReopenOrderRequest>), Some(of::<OrderView>)),
+    ex("post", routes::ORDER_CONFIRM, None, Some(of::<OrderView>)),
+    ex("get", routes::EVENT_STREAM, None, Some(event_stream)),
+    ex("get", routes::PRODUCTS, None, Some(of::<Vec<ProductResponse>>)),
+    ex("post", routes::PRODUCTS, Some(of::<CreateProductRequest>), Some(of::<ProductResponse>)),
+    ex("get", routes::PRODUCT, None, Some(of::<ProductResponse>)),
+    ex("patch", routes::PRODUCT, Some(of::<UpdateProductRequest>), Some(of::<ProductResponse>)),
+    ex("delete", routes::PRODUCT, None, None),
+    ex("get", routes::WEBHOOKS, None, Some(of::<Vec<WebhookResponse>>)),
+    ex("post", routes::WEBHOOKS, Some(of::<CreateWebhookRequest>), Some(of::<WebhookResponse>)),
+    ex("patch", routes::WEBHOOK, Some(of::<UpdateWebhookRequest>), Some(of::<WebhookResponse>)),
+    ex("delete", routes::WEBHOOK, None, None),
+    ex("get", routes::USAGE, None, Some(of::<Vec<UsageResponse>>)),
+    ex("get", routes::REQUEST_LOG, None, Some(of::<Vec<LoggedRequestResponse>>)),
+    ex("get", routes::REQUEST_STATS, None, Some(of::<RequestStatsResponse>)),
+    ex("put", routes::QUOTA, Some(of::<SetQuotaRequest>), Some(of::<QuotaResponse>)),
+    ex("get", routes::API_KEYS, None, Some(of::<Vec<ApiKeyResponse>>)),
+    ex("post", routes::API_KEYS, Some(of::<CreateApiKeyRequest>), Some(of::<ApiKeySecretResponse>)),
+    ex("post", routes::API_KEY_REVOKE, None, Some(revoked_api_key)),
+    ex("post", routes::API_KEY_ROTATE, None, Some(rotated_api_key)),
+    ex("get", routes::SEQUENCE, None, Some(of::<SequenceResponse>)),
+    ex("put", routes::SEQUENCE, Some(of::<ResetSequenceRequest>), Some(of::<SequenceResponse>)),
+    ex("get", routes::GROWTH, None, Some(of::<GrowthResponse>)),
+    ex("get", routes::RUNTIME, None, Some(of::<RuntimeResponse>)),
+    ex("get", routes::ERROR_STATS, None, Some(of::<ErrorStatsResponse>)),
+    ex("get", routes::MAINTENANCE_STATS, None, Some(of::<MaintenanceResponse>)),
+    ex("get", routes::CACHES, None, Some(of::<Vec<CacheResponse>>)),
+    ex("get", routes::CONSISTENCY_CHECK, None, Some(of::<Vec<ConsistencyCheckResponse>>)),
+    ex("get", routes::AUDIT_VERIFY, None, Some(of::<AuditVerificationResponse>)),
+    ex("post", routes::RECOMPUTE, None, Some(of::<RecomputeResponse>)),
+    ex("post", routes::RETAG, Some(of::<RetagRequest>), Some(of::<RetagResponse>)),
+    ex("post", routes::BACKUP, None, Some(of::<BackupResponse>)),
+    ex("post", routes::CONFIG_RELOAD, None, Some(of::<ConfigResponse>)),
+    ex("post", routes::EVENT_REPLAY, Some(of::<ReplayEventsRequest>), Some(of::<ReplayResponse>)),
+    ex("get", routes::REPLAY, None, Some(of::<ReplayResponse>)),
+    ex("get", routes::DAILY_REPORT, None, Some(of::<DailyReportResponse>)),
+    ex("get", routes::SLA_REPORT, None, Some(of::<SlaReportResponse>)),
+    ex("get", routes::SCHEMA, None, Some(schema)),
+    ex("get", routes::CONFIG, None, Some(of::<ConfigResponse>)),
+    ex("get", routes::ERROR_CODES, None, Some(error_codes)),
+    ex("get", routes::OPENAPI, None, Some(openapi)),
+    ex("get", routes::METRICS, None, Some(metrics)),
+    ex("get", routes::HEALTHZ, None, Some(healthz)),
+    ex("get", routes::READYZ, None, Some(of::<ReadinessResponse>)),
+];
+
+/// The examples of the operation at `method` and `path`.
+pub fn get(method: &str, path: &str) -> Option<&'static Examples> {
+    EXAMPLES
+        .iter()
+        .find(|examples| examples.method == method && examples.path == path)
+}
+
+/// When the examples happen, 2025-10-01 12:00 UTC.
+fn created_at() -> String {
+    clock::timestamp(UNIX_EPOCH + Duration::from_secs(1_759_320_000))
+}
+
+fn metadata() -> Map<String, Value> {
+    let Value::Object(metadata) = json!({ "source": "web" }) else {
+        unreachable!("metadata is an object");
+    };
+
+    metadata
+}
+
+fn address() -> ShippingAddress {
+    ShippingAddress {
+        name: "Ada Lovelace".to_string(),
+        line1: "12 St James's Square".to_string(),
+        line2: None,
+        city: "London".to_string(),
+        region: None,
+        postal_code: "SW1Y 4JH".to_string(),
+        country: "GB".to_string(),
+    }
+}
+
+fn order() -> Order {
+    let mut order = Order {
+        id: Some(1),
+        amount: 500,
+        customer_id: Some("cus_42".to_string()),
+        metadata: Metadata::new(metadata()).expect("the example metadata is valid"),
+        created_at: Some(created_at()),
+        updated_at: Some(created_at()),
+        ..Default::default()
+    };
+    order.set_shipping_address(Some(&address()));
+
+    order
+}
+
+fn product() -> Product {
+    Product {
+        id: Some(1),
+        sku: "WIDGET-1".to_string(),
+        name: "Widget".to_string(),
+        unit_price: 250,
+    }
+}
+
+/// `OrderView::of` an hour after the example orders were created, rather than
+/// however long ago that is when the document's built.
+fn view(order: Order) -> OrderView {
+    OrderView {
+        age_seconds: Some(3600),
+        time_in_status_seconds: Some(3600),
+        ..OrderView::of(order)
+    }
+}
+
+impl Example for OrderView {
+    fn example() -> Self {
+        view(order())
+    }
+}
+
+impl Example for PaginatedOrders {
+    fn example() -> Self {
+        PaginatedOrders {
+            items: vec![view(order())],
+            total: 1,
+            page: 1,
+            per_page: 25,
+        }
+    }
+}
+
+impl Example for CreateOrderRequest {
+    fn example() -> Self {
+        CreateOrderRequest {
+            amount: 500,
+            status: Some(OrderStatus::Pending),
+            draft: false,
+            customer_id: Some("cus_42".to_string()),
+            currency: None,
+            metadata: metadata(),
+            shipping_address: Some(serde_json::to_value(address()).expect("addresses serialize to json")),
+        }
+    }
+}
+
+impl Example for UpdateOrderStatusRequest {
+    fn example() -> Self {
+        UpdateOrderStatusRequest {
+            status: Some(OrderStatus::InProgress),
+            metadata: Some(metadata()),
+            shipping_address: None,
+        }
+    }
+}
+
+impl Example for SearchOrdersRequest {
+    fn example() -> Self {
+        let Value::Object(clauses) = json!({ "source": { "eq": "web" } }) else {
+            unreachable!("clauses are an object");
+        };
+
+        SearchOrdersRequest {
+            status: Some(OrderStatus::Pending),
+            min_amount: Some(100),
+            created_from: Some("2025-10-01T00:00:00Z".to_string()),
+            metadata: clauses,
+            ..Default::default()
+        }
+    }
+}
+
+impl Example for CountResponse {
+    fn example() -> Self {
+        CountResponse {
+            status: Some(OrderStatus::Pending),
+            count: 12,
+        }
+    }
+}
+
+impl Example for OrderStatsResponse {
+    fn example() -> Self {
+        OrderStats {
+            orders: 40,
+            amount: 52_300,
+            orders_by_status: BTreeMap::from([(OrderStatus::Pending, 12), (OrderStatus::Complete, 28)]),
+            amount_by_status: BTreeMap::from([(OrderStatus::Pending, 9_800), (OrderStatus::Complete, 42_500)]),
+            buckets: [22, 15, 3, 0, 0, 0],
+            as_of: "2025-09-12T10:00:00.000Z".to_string(),
+        }
+        .into()
+    }
+}
+
+impl Example for ItemResponse {
+    fn example() -> Self {
+        Item {
+            id: Some(1),
+            ..Item::from_product(1, &product(), 2)
+        }
+        .into()
+    }
+}
+
+impl Example for AddItemRequest {
+    fn example() -> Self {
+        AddItemRequest::Sku {
+            sku: "WIDGET-1".to_string(),
+            quantity: 2,
+            currency: None,
+        }
+    }
+}
+
+impl Example for AdjustItemRequest {
+    fn example() -> Self {
+        AdjustItemRequest {
+            quantity: 3,
+            actor: Some("support@example.com".to_string()),
+        }
+    }
+}
+
+impl Example for EventResponse {
+    fn example() -> Self {
+        OrderEvent {
+            id: Some(1),
+            order_id: 1,
+            kind: EventKind::Canceled,
+            detail: "pending -> canceled".to_string(),
+            actor: Some("support@example.com".to_string()),
+            created_at: created_at(),
+        }
+        .into()
+    }
+}
+
+impl Example for OrderAsOfResponse {
+    fn example() -> Self {
+        let order = OrderView::example();
+
+        OrderAsOfResponse {
+            id: order.id,
+            at: created_at(),
+            fields: BTreeMap::from([
+                ("amount".to_string(), FieldAsOf { value: order.amount.into(), tracked: true }),
+                (
+                    "status".to_string(),
+                    FieldAsOf {
+                        value: serde_json::to_value(order.status).expect("statuses serialize to json"),
+                        tracked: true,
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+impl Example for OrderDurationsResponse {
+    fn example() -> Self {
+        let at = |seconds: u64| clock::timestamp(UNIX_EPOCH + Duration::from_secs(1_759_320_000 + seconds));
+        let periods = vec![
+            Period {
+                status: OrderStatus::Pending,
+                since: created_at(),
+                until: Some(at(600)),
+                millis: 600_000,
+            },
+            Period {
+                status: OrderStatus::InProgress,
+                since: at(600),
+                until: None,
+                millis: 7_200_000,
+            },
+        ];
+
+        OrderDurationsResponse {
+            id: 1,
+            status: OrderStatus::InProgress,
+            totals: sla::totals(&periods),
+            periods,
+        }
+    }
+}
+
+impl Example for PreviewTotalsRequest {
+    fn example() -> Self {
+        PreviewTotalsRequest {
+            discount: Some(DiscountRequest::Percent(10.into())),
+            tax_rate: Some(8.into()),
+            add: vec![PreviewItem {
+                sku: "MUG".to_string(),
+                quantity: 2,
+            }],
+            remove: vec!["TEE".to_string()],
+        }
+    }
+}
+
+impl Example for PreviewTotalsResponse {
+    fn example() -> Self {
+        let lines = [
+            Line {
+                sku: None,
+                description: "Gift wrap".to_string(),
+                quantity: 1,
+                unit_price: 150,
+            },
+            Line {
+                sku: Some("MUG".to_string()),
+                description: "Mug".to_string(),
+                quantity: 2,
+                unit_price: 250,
+            },
+        ];
+        let adjustments = Adjustments {
+            discount: Some(Discount::Percent(1_000)),
+            tax_rate: Some(800),
+        };
+        let totals = pricing::totals(&lines, &adjustments, 0).expect("the example fits");
+
+        PreviewTotalsResponse::new(1, Currency::default(), totals)
+    }
+}
+
+impl Example for NoteResponse {
+    fn example() -> Self {
+        Note {
+            id: 1,
+            order_id: 1,
+            author: Some("ada".to_string()),
+            body: "@grace can you check the address?".to_string(),
+            created_at: created_at(),
+            mentions: vec![Mention {
+                username: "grace".to_string(),
+                known: true,
+            }],
+        }
+        .into()
+    }
+}
+
+impl Example for CreateNoteRequest {
+    fn example() -> Self {
+        CreateNoteRequest {
+            body: "@grace can you check the address?".to_string(),
+            author: Some("ada".to_string()),
+        }
+    }
+}
+
+impl Example for MergeOrderRequest {
+    fn example() -> Self {
+        MergeOrderRequest { source_id: 2 }
+    }
+}
+
+impl Example for ReopenOrderRequest {
+    fn example() -> Self {
+        ReopenOrderRequest {
+            actor: "support@example.com".to_string(),
+        }
+    }
+}
+
+impl Example for ProductResponse {
+    fn example() -> Self {
+        product().into()
+    }
+}
+
+impl Example for CreateProductRequest {
+    fn example() -> Self {
+        let product = product();
+
+        CreateProductRequest {
+            sku: product.sku,
+            name: product.name,
+            unit_price: product.unit_price,
+        }
+    }
+}
+
+impl Example for UpdateProductRequest {
+    fn example() -> Self {
+        UpdateProductRequest {
+            name: None,
+            unit_price: Some(300),
+        }
+    }
+}
+
+fn webhook() -> Webhook {
+    Webhook {
+        id: 1,
+        url: "https://example.com/hooks/orders".to_string(),
+        payload_version: 3,
+        created_at: created_at(),
+        verify_tls: true,
+    }
+}
+
+impl Example for WebhookResponse {
+    fn example() -> Self {
+        webhook().into()
+    }
+}
+
+impl Example for CreateWebhookRequest {
+    fn example() -> Self {
+        let webhook = webhook();
+
+        CreateWebhookRequest {
+            url: webhook.url,
+            payload_version: Some(webhook.payload_version),
+            verify_tls: None,
+        }
+    }
+}
+
+impl Example for UpdateWebhookRequest {
+    fn example() -> Self {
+        UpdateWebhookRequest {
+            payload_version: None,
+            verify_tls: Some(false),
+        }
+    }
+}
+
+impl Example for ReplayResponse {
+    fn example() -> Self {
+        Replay {
+            id: 1,
+            webhook_id: 1,
+            after_id: Some(100),
+            before_id: None,
+            event_types: Some(r#"["canceled"]"#.to_string()),
+            total: 12,
+            created_at: created_at(),
+            delivered: 10,
+            failed: 0,
+            pending: 2,
+        }
+        .into()
+    }
+}
+
+impl Example for ReplayEventsRequest {
+    fn example() -> Self {
+        ReplayEventsRequest {
+            webhook_id: 1,
+            after_id: Some(100),
+            before_id: None,
+            event_types: Some(vec!["order.canceled".to_string()]),
+        }
+    }
+}
+
+impl Example for RetagRequest {
+    fn example() -> Self {
+        RetagRequest {
+            add_tags: vec!["promo-q1".to_string()],
+            remove_tags: vec!["promo-q4".to_string()],
+        }
+    }
+}
+
+impl Example for RetagResponse {
+    fn example() -> Self {
+        RetagResponse {
+            updated: 41,
+            skipped: vec![17],
+        }
+    }
+}
+
+impl Example for UsageResponse {
+    fn example() -> Self {
+        Usage {
+            api_key: "key_live_1".to_string(),
+            period: "2025-10".to_string(),
+            count: 120,
+            monthly_limit: Some(1000),
+        }
+        .into()
+    }
+}
+
+impl Example for LoggedRequestResponse {
+    fn example() -> Self {
+        LoggedRequest {
+            id: Some(5120),
+            method: "POST".to_string(),
+            path: routes::ORDERS.to_string(),
+            status: 200,
+            latency_ms: 14,
+            api_key: Some("key_live_1".to_string()),
+            request_id: "3f2a9c4e8b1d4f6a9e0c7b5d2a1f8e34".to_string(),
+            created_at: created_at(),
+        }
+        .into()
+    }
+}
+
+impl Example for RequestStatsResponse {
+    fn example() -> Self {
+        RequestStatsResponse {
+            from: "2025-10-01T00:00:00.000Z".to_string(),
+            to: "2025-10-02T00:00:00.000Z".to_string(),
+            keys: vec![KeyLatency {
+                key: Some("key_live_1".to_string()),
+                requests: 120,
+                p50: 12,
+                p95: 48,
+            }],
+        }
+    }
+}
+
+impl Example for QuotaResponse {
+    fn example() -> Self {
+        KeyQuota {
+            api_key: "key_live_1".to_string(),
+            monthly_limit: 1000,
+        }
+        .into()
+    }
+}
+
+fn api_key() -> ApiKey {
+    ApiKey {
+        id: 1,
+        name: "billing-service".to_string(),
+        prefix: "spk_3f9a1c0e".to_string(),
+        scopes: vec![Scope::Read, Scope::Write],
+        created_at: "2025-10-01T12:00:00.000Z".to_string(),
+        expires_at: None,
+        revoked_at: None,
+        previous_expires_at: None,
+        rotated_at: None,
+    }
+}
+
+const SECRET: &str = "spk_3f9a1c0e7d2b4a6f8e0c1d3b5a7f9e2c4d6b8a0f1e3c5d7b";
+
+impl Example for ApiKeyResponse {
+    fn example() -> Self {
+        api_key().into()
+    }
+}
+
+impl Example for ApiKeySecretResponse {
+    fn example() -> Self {
+        ApiKeySecretResponse::new(api_key(), SECRET.to_string())
+    }
+}
+
+impl Example for CreateApiKeyRequest {
+    fn example() -> Self {
+        CreateApiKeyRequest {
+            name: "billing-service".to_string(),
+            scopes: vec![Scope::Read, Scope::Write],
+            expires_at: None,
+        }
+    }
+}
+
+fn revoked_api_key() -> Value {
+    let key = ApiKey {
+        revoked_at: Some("2025-10-02T09:30:00.000Z".to_string()),
+        ..api_key()
+    };
+
+    serde_json::to_value(ApiKeyResponse::from(key)).expect("examples serialize to json")
+}
+
+fn rotated_api_key() -> Value {
+    let key = ApiKey {
+        prefix: "spk_8c2e5a1f".to_string(),
+        rotated_at: Some("2025-10-02T09:30:00.000Z".to_string()),
+        previous_expires_at: Some("2025-10-03T09:30:00.000Z".to_string()),
+        ..api_key()
+    };
+    let secret = "spk_8c2e5a1f0b3d6e9a2c4f7b1d3e5a8c0f2b4d6e8a1c3f5b7d";
+
+    serde_json::to_value(ApiKeySecretResponse::new(key, secret.to_string()))
+        .expect("examples serialize to json")
+}
+
+impl Example for SetQuotaRequest {
+    fn example() -> Self {
+        SetQuotaRequest { monthly_limit: 1000 }
+    }
+}
+
+impl Example for SequenceResponse {
+    fn example() -> Self {
+        SequenceResponse {
+            name: "invoice_number".to_string(),
+            value: Some(41),
+        }
+    }
+}
+
+impl Example for ResetSequenceRequest {
+    fn example() -> Self {
+        ResetSequenceRequest { value: 41 }
+    }
+}
+
+impl Example for GrowthResponse {
+    fn example() -> Self {
+        let stats = GrowthStats {
+            max_id: Some(1200),
+            orders_per_hour: Some(42.5),
+            alerting: false,
+            alerts: 0,
+        };
+
+        GrowthResponse::new(stats, AppConfig::default().growth_alert_per_hour)
+    }
+}
+
+impl Example for RuntimeResponse {
+    fn example() -> Self {
+        BusStats {
+            capacity: AppConfig::default().event_bus_capacity,
+            subscribers: 2,
+            missed_total: 3,
+            missed: BTreeMap::from([(1, 0), (2, 3)]),
+        }
+        .into()
+    }
+}
+
+impl Example for ErrorStatsResponse {
+    fn example() -> Self {
+        let stats = ErrorStats {
+            current: Window { requests: 40, errors: 1 },
+            recent: vec![Window { requests: 120, errors: 2 }],
+            ..Default::default()
+        };
+
+        ErrorStatsResponse::new(stats, &AppConfig::default())
+    }
+}
+
+impl Example for MaintenanceResponse {
+    fn example() -> Self {
+        let runs = Runs {
+            last_run_at: Some(UNIX_EPOCH + Duration::from_secs(1_759_320_000)),
+            last_duration: Some(Duration::from_millis(12)),
+            runs: 3,
+            ..Default::default()
+        };
+        let pages = Fragmentation {
+            page_count: 2048,
+            freelist_count: 12,
+        };
+
+        let stats = MaintenanceStats {
+            checkpoint: runs.clone(),
+            last_checkpoint: Some(Checkpoint {
+                busy: false,
+                wal_pages: Some(64),
+                checkpointed_pages: Some(64),
+            }),
+            vacuum: runs,
+            last_vacuum: Some(Vacuum {
+                before: pages,
+                after: pages,
+                vacuumed: false,
+            }),
+        };
+
+        MaintenanceResponse::new(stats, &AppConfig::default())
+    }
+}
+
+impl Example for CacheResponse {
+    fn example() -> Self {
+        CacheResponse {
+            name: "order_counts".to_string(),
+            entries: 5,
+            hits: 120,
+            misses: 2,
+            invalidations: 48,
+            last_invalidation: Some("order_changed".to_string()),
+            last_invalidated_at: Some(clock::timestamp(UNIX_EPOCH + Duration::from_secs(1_759_320_000))),
+        }
+    }
+}
+
+impl Example for ReadinessResponse {
+    fn example() -> Self {
+        let report = Report {
+            check: IntegrityCheck::Quick,
+            checked_at: UNIX_EPOCH + Duration::from_secs(1_759_320_000),
+            took: Duration::from_millis(840),
+            problems: vec![],
+        };
+
+        ReadinessResponse::new(Some(report), false)
+    }
+}
+
+impl Example for ConsistencyCheckResponse {
+    fn example() -> Self {
+        Violations {
+            name: "amount_matches_items",
+            description: "An order's amount is the total of its items.",
+            total: 1,
+            order_ids: vec![7],
+        }
+        .into()
+    }
+}
+
+impl Example for AuditVerificationResponse {
+    fn example() -> Self {
+        Verification {
+            checked: 120,
+            unchained: 0,
+            last_hash: None,
+            first_break: Some(Break {
+                id: 57,
+                reason: "row_hash doesn't match the row, it was changed".to_string(),
+            }),
+        }
+        .into()
+    }
+}
+
+impl Example for RecomputeResponse {
+    fn example() -> Self {
+        vec![
+            Correction {
+                field: "amount",
+                before: json!(1200),
+                after: json!(1500),
+            },
+            Correction {
+                field: "summary.amount",
+                before: json!(1200),
+                after: json!(1500),
+            },
+        ]
+        .into()
+    }
+}
+
+impl Example for BackupResponse {
+    fn example() -> Self {
+        Backup {
+            path: "backups/db-2025-10-01T120000Z.sqlite".into(),
+            size_bytes: 1_048_576,
+        }
+        .into()
+    }
+}
+
+impl Example for ConfigResponse {
+    fn example() -> Self {
+        let mut config = AppConfig::default();
+        // like the document, whether the build has the admin pages or not
+        config.features.admin_ui_enabled = false;
+
+        ConfigResponse::new(&config, 1)
+    }
+}
+
+impl Example for DailyReportResponse {
+    fn example() -> Self {
+        let report = DailyReport {
+            orders_created: 40,
+            orders_completed: 31,
+            gross_amount: 20_000,
+            refunded_amount: 500,
+            net_amount: 19_500,
+        };
+
+        DailyReportResponse::new("2025-10-01".to_string(), AppConfig::default().report_utc_offset, report)
+    }
+}
+
+impl Example for SlaReportResponse {
+    fn example() -> Self {
+        let report = SlaReport {
+            orders: 31,
+            missing_history: 2,
+            statuses: vec![
+                StatusTimes {
+                    status: OrderStatus::Pending,
+                    orders: 31,
+                    p50: 900_000,
+                    p95: 5_400_000,
+                },
+                StatusTimes {
+                    status: OrderStatus::InProgress,
+                    orders: 29,
+                    p50: 14_400_000,
+                    p95: 86_400_000,
+                },
+            ],
+        };
+
+        SlaReportResponse::new("2025-10-01T00:00:00.000Z".to_string(), "2025-10-02T00:00:00.000Z".to_string(), report)
+    }
+}
+
+impl Example for ImportJobResponse {
+    fn example() -> Self {
+        ImportJob {
+            id: 1,
+            status: ImportStatus::Done,
+            body: import_csv().as_str().unwrap_or_default().to_string(),
+            total_rows: 2,
+            processed_rows: 2,
+            imported_rows: 1,
+            failed_rows: 1,
+            row_errors: r#"[{"line":3,"message":"unknown status \"shipped\""}]"#.to_string(),
+            failure: None,
+            locale: None,
+            created_at: created_at(),
+        }
+        .into()
+    }
+}
+
+fn export_csv() -> Value {
+    let export = Export {
+        status: None,
+        amounts: AmountColumns::Both,
+        ids: false,
+        locale: None,
+        resume_after: None,
+    };
+
+    export.to_csv(&[order()]).into()
+}
+
+impl Example for GraphImportResponse {
+    fn example() -> Self {
+        graphs::Report {
+            imported: 1,
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// The example order once it's started, with an item and a note.
+fn export_graphs() -> Value {
+    let graph = OrderGraph {
+        order: Order {
+            status: OrderStatus::InProgress,
+            ..order()
+        },
+        items: vec![GraphItem {
+            sku: Some("WIDGET-1".to_string()),
+            description: "Widget".to_string(),
+            quantity: 2,
+            unit_price: 250,
+            currency: Currency::default(),
+        }],
+        notes: vec![GraphNote {
+            author: Some("ada".to_string()),
+            body: "@grace can you check the address?".to_string(),
+            created_at: created_at(),
+            mentions: vec![Mention {
+                username: "grace".to_string(),
+                known: true,
+            }],
+        }],
+        status_history: vec![StatusChange {
+            from: Some(OrderStatus::Pending),
+            to: OrderStatus::InProgress,
+            changed_at: created_at(),
+        }],
+    };
+    let line = serde_json::to_string(&graph).expect("examples serialize to json");
+
+    format!("{line}\n").into()
+}
+
+/// Replacing an order: canceling it and creating the new one.
+fn transaction() -> Value {
+    let operations = [
+        OrderOperation::Cancel {
+            id: 2,
+            reason: Some("replaced by the customer".to_string()),
+        },
+        OrderOperation::Create {
+            order: CreateOrderRequest::example(),
+            items: vec![AddItemRequest::example()],
+        },
+    ];
+
+    serde_json::to_value(operations).expect("examples serialize to json")
+}
+
+fn transaction_results() -> Value {
+    let canceled = Order {
+        id: Some(2),
+        status: OrderStatus::Canceled,
+        ..order()
+    };
+
+    let results = [
+        OperationResponse {
+            op: OperationKind::Cancel,
+            order: view(canceled),
+            items: Vec::new(),
+        },
+        OperationResponse {
+            op: OperationKind::Create,
+            order: OrderView::example(),
+            items: vec![ItemResponse::example()],
+        },
+    ];
+
+    serde_json::to_value(results).expect("examples serialize to json")
+}
+
+fn import_csv() -> Value {
+    "amount,status\n500,pending\n700,shipped\n".into()
+}
+
+/// An event as the stream frames it.
+fn event_stream() -> Value {
+    let event = StreamEventResponse {
+        event: EventResponse::example(),
+        order: Some(OrderView::example()),
+    };
+    let data = serde_json::to_string(&event).expect("events serialize to json");
+
+    format!("event: {}\nid: {}\ndata: {data}\n\n", event.event.kind, event.event.id).into()
+}
+
+fn schema() -> Value {
+    schemas::get("order-note").expect("order-note is a schema")
+}
+
+fn error_codes() -> Value {
+    serde_json::from_str(&error::codes_document()).expect("the codes are json")
+}
+
+/// Just the top of it, the whole document would hold itself.
+fn openapi() -> Value {
+    json!({
+        "openapi": "3.1.0",
+        "info": { "title": "sp-exercise orders api", "version": env!("CARGO_PKG_VERSION") },
+        "paths": {},
+    })
+}
+
+fn metrics() -> Value {
+    let metrics = Metrics::default();
+    metrics.record_request("GET", routes::ORDER, Outcome::Success);
+
+    metrics.render().into()
+}
+
+fn healthz() -> Value {
+    "ok".into()
+}
@@ -0,0 +1,177 @@
+//! Csv order exports, in the shape `/orders/import` reads back.
+//!
+//! An export is streamed by id, a page of orders read at a time, so a large
+//! one neither sits in memory nor holds a read open for its whole length. One
+//! that was cut off is picked up with `resume_after`, the id of the last row
+//! that arrived whole, which `ids` adds as a first column. What a resumed
+//! export has is the orders with a greater id when each page is read, so it
+//! carries on with orders created since, but rows already sent aren't sent
+//! again if they changed.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures_util::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    db::{Db, Interruptible},
+    money,
+    money_format::{self, Locale},
+    orders::{Order, OrderStatus},
+};
+
+/// Orders read by each statement of an export.
+const PAGE_SIZE: i64 = 500;
+
+/// Which amount columns an export has. Both is easiest to read, but a row
+/// with both filled in can't be imported, so re-importing takes one or the
+/// other.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountColumns {
+    #[default]
+    Both,
+    /// `amount` in minor units.
+    Minor,
+    /// `amount_decimal` like `5.00`, or `1.234,56` in `de-DE`.
+    Decimal,
+}
+
+/// What an export writes and where it starts, from its query.
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub status: Option<OrderStatus>,
+    pub amounts: AmountColumns,
+    /// Puts each order's id first, to resume after.
+    pub ids: bool,
+    /// Writes `amount_decimal` the way this locale does, in each order's
+    /// currency, plain decimals when `None`.
+    pub locale: Option<Locale>,
+    /// Starts after this id rather than with the first order, leaving out the
+    /// header too since the export it continues had it.
+    pub resume_after: Option<i64>,
+}
+
+impl Export {
+    /// The csv a page of orders at a time. The first page is read before this
+    /// returns, so a database that can't be read is an error response. A read
+    /// failing later ends the stream with the error, which cuts the response
+    /// off for the client to resume.
+    ///
+    /// The pages are read on one `Interruptible` connection, given back when
+    /// the stream ends. Dropped before that, because the client went away,
+    /// the page being read is stopped where it is.
+    pub async fn stream(self, db: Arc<Db>) -> Result<impl Stream<Item = Result<String>>> {
+        let after = self.resume_after.unwrap_or(0);
+        let mut read = Interruptible::acquire(&db).await?;
+
+        let first = match Order::export_page(read.conn(), self.status.as_ref(), after, PAGE_SIZE).await {
+            Ok(orders) => orders,
+            Err(err) => {
+                read.release().await;
+                return Err(err);
+            }
+        };
+
+        let mut csv = if self.resume_after.is_none() { self.header() } else { String::new() };
+        let next = self.page(&first, &mut csv);
+
+        let rest = stream::try_unfold((read, next), move |(mut read, next)| {
+            let export = self.clone();
+
+            async move {
+                let Some(after) = next else {
+                    read.release().await;
+                    return Ok(None);
+                };
+
+                let orders = match Order::export_page(read.conn(), export.status.as_ref(), after, PAGE_SIZE).await {
+                    Ok(orders) => orders,
+                    Err(err) => {
+                        read.release().await;
+                        return Err(err);
+                    }
+                };
+
+                let mut csv = String::new();
+                let next = export.page(&orders, &mut csv);
+
+                Ok(Some((csv, (read, next))))
+            }
+        });
+
+        Ok(stream::once(async { Ok(csv) }).chain(rest))
+    }
+
+    /// `orders` as a whole export, header and all.
+    pub fn to_csv(&self, orders: &[Order]) -> String {
+        let mut csv = self.header();
+        self.page(orders, &mut csv);
+
+        csv
+    }
+
+    /// Writes the rows of `orders` to `csv`, returning the id to read the next
+    /// page after unless this one was the last.
+    fn page(&self, orders: &[Order], csv: &mut String) -> Option<i64> {
+        for order in orders {
+            csv.push_str(&self.row(order));
+        }
+
+        match orders.last() {
+            Some(last) if orders.len() as i64 == PAGE_SIZE => last.id,
+            _ => None,
+        }
+    }
+
+    fn header(&self) -> String {
+        let columns = match self.amounts {
+            AmountColumns::Both => "amount,amount_decimal,status\n",
+            AmountColumns::Minor => "amount,status\n",
+            AmountColumns::Decimal => "amount_decimal,status\n",
+        };
+
+        if self.ids { format!("id,{columns}") } else { columns.to_string() }
+    }
+
+    fn row(&self, order: &Order) -> String {
+        let status = status(order);
+
+        let row = match self.amounts {
+            AmountColumns::Both => format!("{},{},{status}\n", order.amount, self.decimal(order)),
+            AmountColumns::Minor => format!("{},{status}\n", order.amount),
+            AmountColumns::Decimal => format!("{},{status}\n", self.decimal(order)),
+        };
+
+        match (self.ids, order.id) {
+            (true, Some(id)) => format!("{id},{row}"),
+            _ => row,
+        }
+    }
+
+    /// The amount for `amount_decimal`, quoted when the locale's decimal
+    /// separator is a comma.
+    fn decimal(&self, order: &Order) -> String {
+        let Some(locale) = self.locale else {
+            return money::format_decimal(order.amount);
+        };
+
+        let decimal = money_format::format_number(order.amount, &order.currency, locale);
+
+        match decimal.contains(',') {
+            true => format!("\"{decimal}\""),
+            false => decimal,
+        }
+    }
+}
+
+/// The status as imports read it, which is the json name, `inprogress` rather
+/// than `Display`'s `in-progress`.
+fn status(order: &Order) -> String {
+    match serde_json::to_value(order.status) {
+        Ok(Value::String(status)) => status,
+        _ => order.status.to_string(),
+    }
+}
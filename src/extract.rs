@@ -0,0 +1,315 @@
+use std::num::IntErrorKind;
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Path, Query, Request},
+    http::{HeaderValue, header::CONTENT_TYPE, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess,
+    Visitor,
+};
+
+use crate::error::CustomError;
+
+/// How deeply arrays and objects can nest in a json body, the top level
+/// counting as 1.
+pub const MAX_JSON_DEPTH: usize = 32;
+/// How many values, counting every nested one, a json body can hold.
+pub const MAX_JSON_ELEMENTS: usize = 10_000;
+
+/// Like `Json`, but also accepts bodies sent without a `Content-Type` as long as
+/// they parse. Bodies explicitly labelled as something other than json are
+/// still refused with a 415. Bodies nested too deeply or with too many values
+/// are a 422 with the code `payload_too_complex`, checked before deserializing.
+pub struct LenientJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for LenientJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = request.headers().get(CONTENT_TYPE).cloned();
+
+        if let Some(content_type) = &content_type
+            && !is_json(content_type)
+        {
+            return Err(CustomError::UnsupportedMediaType(format!(
+                "expected a Content-Type of application/json, got {content_type:?}"
+            ))
+            .into_response());
+        }
+
+        let method = request.method().clone();
+        let uri = request.uri().clone();
+
+        let bytes = Bytes::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        check_complexity(&bytes).map_err(IntoResponse::into_response)?;
+
+        let Json(value) = Json::<T>::from_bytes(&bytes).map_err(IntoResponse::into_response)?;
+
+        if content_type.is_none() {
+            // still accepted for now, but we'd like to require the header eventually
+            tracing::warn!("{method} {uri} sent a json body without a Content-Type header");
+        }
+
+        Ok(LenientJson(value))
+    }
+}
+
+/// Walks `bytes` without building anything, refusing documents nested deeper
+/// than `MAX_JSON_DEPTH` or holding more than `MAX_JSON_ELEMENTS` values before
+/// they're deserialized for real. Anything that isn't valid json is left for
+/// the real parse to report.
+fn check_complexity(bytes: &[u8]) -> Result<(), CustomError> {
+    let mut budget = Budget::default();
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+
+    match Walk(&mut budget, 1).deserialize(&mut deserializer) {
+        Err(_) if budget.exceeded => Err(CustomError::PayloadTooComplex(format!(
+            "json bodies can nest at most {MAX_JSON_DEPTH} deep and hold at most \
+            {MAX_JSON_ELEMENTS} values"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+struct Budget {
+    elements: usize,
+    exceeded: bool,
+}
+
+impl Budget {
+    /// Counts a value, `depth` is only checked for arrays and objects.
+    fn spend<E: de::Error>(&mut self, depth: Option<usize>) -> Result<(), E> {
+        self.elements += 1;
+
+        if depth.is_some_and(|depth| depth > MAX_JSON_DEPTH) || self.elements > MAX_JSON_ELEMENTS {
+            self.exceeded = true;
+            return Err(E::custom("payload too complex"));
+        }
+
+        Ok(())
+    }
+}
+
+/// A value at `depth`, arrays and objects put their contents one deeper.
+struct Walk<'a>(&'a mut Budget, usize);
+
+impl<'de> DeserializeSeed<'de> for Walk<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Walk<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("any json value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        self.0.spend(None)
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        self.0.spend(None)
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        self.0.spend(None)
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        self.0.spend(None)
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        self.0.spend(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        self.0.spend(None)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let Walk(budget, depth) = self;
+        budget.spend(Some(depth))?;
+
+        while seq.next_element_seed(Walk(budget, depth + 1))?.is_some() {}
+
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Walk(budget, depth) = self;
+        budget.spend(Some(depth))?;
+
+        while map.next_key::<IgnoredAny>()?.is_some() {
+            map.next_value_seed(Walk(budget, depth + 1))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The `{id}` of a route. Numbers no row can have, because they're below 1 or
+/// don't fit in an i64, are a 404 with the code `id_out_of_range`, anything
+/// that isn't a number is still a 400.
+pub struct PathId(pub i64);
+
+impl<S> FromRequestParts<S> for PathId
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(id) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        parse_id(&id).map(PathId).map_err(IntoResponse::into_response)
+    }
+}
+
+/// The `{id}` and `{item_id}` of an order's item route, each checked like
+/// `PathId`.
+pub struct PathIds(pub i64, pub i64);
+
+impl<S> FromRequestParts<S> for PathIds
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path((id, item_id)) = Path::<(String, String)>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let id = parse_id(&id).map_err(IntoResponse::into_response)?;
+        let item_id = parse_id(&item_id).map_err(IntoResponse::into_response)?;
+
+        Ok(PathIds(id, item_id))
+    }
+}
+
+/// Like `Query`, but a parameter that doesn't deserialize, such as an unknown
+/// status or a page that isn't a number, is a 422 naming it rather than
+/// axum's 400.
+pub struct ValidQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ValidQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = CustomError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match Query::<T>::try_from_uri(&parts.uri) {
+            Ok(Query(query)) => Ok(ValidQuery(query)),
+            Err(rejection) => {
+                let message = rejection.body_text();
+                let message = message
+                    .strip_prefix("Failed to deserialize query string: ")
+                    .unwrap_or(&message);
+
+                Err(CustomError::Validation(format!("invalid query parameter {message}")))
+            }
+        }
+    }
+}
+
+fn parse_id(id: &str) -> Result<i64, CustomError> {
+    match id.parse::<i64>() {
+        Ok(parsed) if parsed >= 1 => Ok(parsed),
+        Ok(_) => Err(CustomError::IdOutOfRange(id.to_string())),
+        Err(err) => match err.kind() {
+            IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+                Err(CustomError::IdOutOfRange(id.to_string()))
+            }
+            _ => Err(CustomError::BadRequest(format!("id {id:?} is not a number"))),
+        },
+    }
+}
+
+fn is_json(content_type: &HeaderValue) -> bool {
+    let Ok(content_type) = content_type.to_str() else {
+        return false;
+    };
+
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_json() {
+        assert!(is_json(&HeaderValue::from_static("application/json")));
+        assert!(is_json(&HeaderValue::from_static(
+            "application/json; charset=utf-8"
+        )));
+        assert!(is_json(&HeaderValue::from_static(
+            "application/merge-patch+json"
+        )));
+        assert!(!is_json(&HeaderValue::from_static("text/plain")));
+        assert!(!is_json(&HeaderValue::from_static("text/xml")));
+    }
+
+    #[test]
+    fn test_check_complexity() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+        assert!(check_complexity(nested(MAX_JSON_DEPTH).as_bytes()).is_ok());
+        assert!(check_complexity(br#"{"a": {"b": [1, "two", null, true, 2.5]}}"#).is_ok());
+
+        let err = check_complexity(nested(MAX_JSON_DEPTH + 1).as_bytes()).unwrap_err();
+        assert_eq!(err.code(), "payload_too_complex");
+
+        let wide = format!("[{}0]", "0,".repeat(MAX_JSON_ELEMENTS));
+        let err = check_complexity(wide.as_bytes()).unwrap_err();
+        assert_eq!(err.code(), "payload_too_complex");
+
+        // left for the real parse to reject
+        assert!(check_complexity(b"{not json").is_ok());
+    }
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id("1").unwrap(), 1);
+        assert_eq!(parse_id("9223372036854775807").unwrap(), i64::MAX);
+
+        for id in ["0", "-1", "9223372036854775808", "99999999999999999999", "-99999999999999999999"] {
+            let err = parse_id(id).unwrap_err();
+
+            assert_eq!(err.code(), "id_out_of_range", "{id}");
+        }
+
+        for id in ["abc", "1.5", ""] {
+            assert_eq!(parse_id(id).unwrap_err().code(), "bad_request", "{id}");
+        }
+    }
+}
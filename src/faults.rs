@@ -0,0 +1,200 @@
+//! Database failures on request, so partner QA teams can test their retries
+//! against the api's 5xx responses without the database actually breaking.
+//! With `CHAOS_HEADER=true` in the config, a request's `X-Chaos` header
+//! applies to every query the request runs:
+//!  - `db_unavailable` fails it as if the pool had no connection to give, a
+//!    503 with the code `database_unavailable`
+//!  - `slow:500ms` waits that long before running it, in `ms` or `s`
+//!  - `error_rate:0.5` fails it with that probability, a 500
+//!
+//! Several can be given comma separated, like `slow:200ms,error_rate:0.1`. In
+//! prod, or with the flag off, the header is ignored entirely. The policy is
+//! checked in `Timed::timed`, which every query goes through.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, config::Environment, error::CustomError};
+
+pub const CHAOS_HEADER: &str = "x-chaos";
+
+/// What the request's queries do before they run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosPolicy {
+    pub unavailable: bool,
+    pub delay: Option<Duration>,
+    /// The chance each query fails, from 0 to 1.
+    pub error_rate: f64,
+}
+
+impl ChaosPolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut policy = ChaosPolicy::default();
+
+        for directive in value.split(',').map(str::trim) {
+            let (name, argument) = match directive.split_once(':') {
+                Some((name, argument)) => (name, Some(argument)),
+                None => (directive, None),
+            };
+
+            match (name, argument) {
+                ("db_unavailable", None) => policy.unavailable = true,
+                ("slow", Some(delay)) => {
+                    policy.delay = Some(parse_delay(delay).ok_or_else(|| {
+                        format!("X-Chaos slow takes a delay like 500ms or 2s, not {delay:?}")
+                    })?);
+                }
+                ("error_rate", Some(rate)) => {
+                    policy.error_rate = rate
+                        .parse()
+                        .ok()
+                        .filter(|rate| (0.0..=1.0).contains(rate))
+                        .ok_or_else(|| {
+                            format!("X-Chaos error_rate takes a number from 0 to 1, not {rate:?}")
+                        })?;
+                }
+                _ => {
+                    return Err(format!(
+                        "X-Chaos {directive:?} isn't db_unavailable, slow:<delay> or error_rate:<rate>"
+                    ));
+                }
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// Waits and fails the way the policy says, an error is what the query
+    /// fails with instead of running.
+    pub async fn before_query(&self) -> sqlx::Result<()> {
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        if self.unavailable {
+            return Err(sqlx::Error::PoolTimedOut);
+        }
+
+        if self.error_rate > 0.0 && rand::random::<f64>() < self.error_rate {
+            return Err(sqlx::Error::Protocol("failed by X-Chaos error_rate".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_delay(value: &str) -> Option<Duration> {
+    if let Some(millis) = value.strip_suffix("ms") {
+        return millis.parse().ok().map(Duration::from_millis);
+    }
+
+    value.strip_suffix('s')?.parse().ok().map(Duration::from_secs)
+}
+
+tokio::task_local! {
+    static POLICY: Arc<ChaosPolicy>;
+}
+
+/// Makes the request's `X-Chaos` header the policy for its queries, when the
+/// config allows it. An invalid header is a 400 then, and ignored otherwise.
+pub(crate) async fn scope(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = state.config();
+    let header = request.headers().get(CHAOS_HEADER);
+
+    let (true, false, Some(header)) = (
+        config.chaos_header,
+        config.environment == Environment::Prod,
+        header,
+    ) else {
+        return next.run(request).await;
+    };
+
+    let policy = header
+        .to_str()
+        .map_err(|_| "X-Chaos should be ascii".to_string())
+        .and_then(ChaosPolicy::parse);
+
+    match policy {
+        Ok(policy) => POLICY.scope(Arc::new(policy), next.run(request)).await,
+        Err(message) => CustomError::BadRequest(message).into_response(),
+    }
+}
+
+/// Keeps the current policy for `future`, for work a request spawns onto a
+/// task of its own.
+pub fn keep<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let policy = POLICY.try_with(Arc::clone).ok();
+
+    async move {
+        match policy {
+            Some(policy) => POLICY.scope(policy, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// The current request's policy applied to a query about to run. Outside a
+/// request with one it does nothing.
+pub(crate) async fn before_query() -> sqlx::Result<()> {
+    match POLICY.try_with(Arc::clone) {
+        Ok(policy) => policy.before_query().await,
+        Err(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ChaosPolicy::parse("db_unavailable"),
+            Ok(ChaosPolicy { unavailable: true, ..Default::default() })
+        );
+        assert_eq!(
+            ChaosPolicy::parse("slow:500ms, error_rate:0.25"),
+            Ok(ChaosPolicy {
+                unavailable: false,
+                delay: Some(Duration::from_millis(500)),
+                error_rate: 0.25,
+            })
+        );
+        assert_eq!(
+            ChaosPolicy::parse("slow:2s").map(|policy| policy.delay),
+            Ok(Some(Duration::from_secs(2)))
+        );
+
+        for invalid in ["", "slow", "slow:fast", "slow:500", "error_rate:1.5", "error_rate:-0.1", "db_unavailable:1", "explode"] {
+            assert!(ChaosPolicy::parse(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_rate_fails_about_that_share_of_queries() {
+        let failed = async |error_rate: f64| {
+            let policy = ChaosPolicy { error_rate, ..Default::default() };
+            let mut failed = 0;
+
+            for _ in 0..2_000 {
+                if policy.before_query().await.is_err() {
+                    failed += 1;
+                }
+            }
+
+            failed
+        };
+
+        assert_eq!(failed(0.0).await, 0);
+        assert_eq!(failed(1.0).await, 2_000);
+
+        // 0.5 of 2000 is 1000, give or take 22
+        let half = failed(0.5).await;
+        assert!((850..=1150).contains(&half), "{half}");
+    }
+}
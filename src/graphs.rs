@@ -0,0 +1,659 @@
+//! Whole orders as JSON Lines, for moving them to another instance. Each line
+//! of `GET /orders/export/graph.ndjson` is an `OrderGraph`: the order with its
+//! tags, items, notes and status history, and `POST /orders/import/graph`
+//! reads the same lines back.
+//!
+//! An imported order keeps its id, the one the api and its clients know it
+//! by, and its timestamps. Its items and notes get new ids here. Its status
+//! history is appended to this instance's audit log at the times it was made,
+//! see `history::record_at`. There are no refunds or separate public ids in
+//! this api, so a graph has neither.
+//!
+//! Each line is checked the way the api checks an order, an item and a note
+//! it's sent, see `checked`, and imported in a transaction of its own, so a
+//! line that can't be imported is reported and leaves the others alone. An order whose id is
+//! already taken is skipped unless the import overwrites, which replaces the
+//! order and its children and records the difference as a change made now,
+//! leaving the audit log it already has as it is. An id that belonged to an
+//! order archived or deleted here is never reused.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{Context, Result};
+use futures_util::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use sqlx::{Sqlite, SqliteConnection, Transaction};
+
+use crate::{
+    address::{self, ShippingAddress},
+    clock,
+    constraints::{
+        AMOUNT_MIN, CUSTOMER_ID_MAX_LEN, DESCRIPTION_MAX_LEN, NOTE_MAX_LEN, QUANTITY_MIN, TAGS_MAX_PER_ORDER,
+        check_max_len, check_min,
+    },
+    db::{Db, Interruptible},
+    error::{self, CustomError},
+    imports::RowError,
+    items::{Added, Item},
+    metadata::Metadata,
+    money::Currency,
+    notes::{Mention, Note},
+    orders::{Order, OrderStatus, history},
+    projections, tags,
+    timing::Timed,
+};
+
+/// Orders read by each page of an export. Their children are read with them,
+/// one statement per table.
+const PAGE_SIZE: i64 = 500;
+
+/// An order and everything hanging off it, one line of an export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderGraph {
+    pub order: Order,
+    #[serde(default)]
+    pub items: Vec<GraphItem>,
+    #[serde(default)]
+    pub notes: Vec<GraphNote>,
+    /// Oldest first.
+    #[serde(default)]
+    pub status_history: Vec<StatusChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphItem {
+    pub sku: Option<String>,
+    pub description: String,
+    pub quantity: i64,
+    pub unit_price: i64,
+    /// Has to be the order's.
+    pub currency: Currency,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphNote {
+    pub author: Option<String>,
+    pub body: String,
+    pub created_at: String,
+    #[serde(default)]
+    pub mentions: Vec<Mention>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub from: Option<OrderStatus>,
+    pub to: OrderStatus,
+    pub changed_at: String,
+}
+
+/// The graphs, one json document per line, a page of orders at a time on one
+/// `Interruptible` connection like a csv export, see `exports.rs`. Drafts
+/// are included, a migration takes everything.
+pub async fn stream(db: Arc<Db>) -> Result<impl Stream<Item = Result<String>>> {
+    let mut read = Interruptible::acquire(&db).await?;
+
+    let first = match read_page(read.conn(), 0).await {
+        Ok(page) => page,
+        Err(err) => {
+            read.release().await;
+            return Err(err);
+        }
+    };
+
+    let rest = stream::try_unfold((read, first.1), |(mut read, next)| async move {
+        let Some(after) = next else {
+            read.release().await;
+            return Ok(None);
+        };
+
+        match read_page(read.conn(), after).await {
+            Ok((lines, next)) => Ok(Some((lines, (read, next)))),
+            Err(err) => {
+                read.release().await;
+                Err(err)
+            }
+        }
+    });
+
+    Ok(stream::once(async { Ok(first.0) }).chain(rest))
+}
+
+/// The lines of the orders after the id `after`, and the id to read the next
+/// page after unless this one was the last.
+async fn read_page(conn: &mut SqliteConnection, after: i64) -> Result<(String, Option<i64>)> {
+    let graphs = page(conn, after).await?;
+
+    let mut lines = String::new();
+
+    for graph in &graphs {
+        lines.push_str(&serde_json::to_string(graph)?);
+        lines.push('\n');
+    }
+
+    let next = match graphs.last() {
+        Some(last) if graphs.len() as i64 == PAGE_SIZE => last.order.id,
+        _ => None,
+    };
+
+    Ok((lines, next))
+}
+
+/// Up to `PAGE_SIZE` orders after the id `after` with their children. Each
+/// child table is read by the range of the orders' ids, which can have the
+/// changes of deleted and archived orders in it too, left out here.
+async fn page(conn: &mut SqliteConnection, after: i64) -> Result<Vec<OrderGraph>> {
+    let orders = sqlx::query_as!(
+        Order,
+        "select * from orders where id > ? order by id limit ?",
+        after,
+        PAGE_SIZE
+    )
+    .fetch_all(&mut *conn)
+    .timed()
+    .await?;
+
+    let (Some(first), Some(last)) = (
+        orders.first().and_then(|order| order.id),
+        orders.last().and_then(|order| order.id),
+    ) else {
+        return Ok(Vec::new());
+    };
+
+    let items = sqlx::query_as!(
+        Item,
+        "select * from order_items where order_id between ? and ? order by id",
+        first,
+        last
+    )
+    .fetch_all(&mut *conn)
+    .timed()
+    .await?;
+
+    let notes = sqlx::query!(
+        r#"select id as "id!: i64", order_id, author, body, created_at from order_notes
+        where order_id between ? and ? order by id"#,
+        first,
+        last
+    )
+    .fetch_all(&mut *conn)
+    .timed()
+    .await?;
+
+    let mentions = sqlx::query!(
+        r#"select m.note_id, m.username, m.known as "known: bool" from note_mentions m
+        join order_notes n on n.id = m.note_id
+        where n.order_id between ? and ? order by m.id"#,
+        first,
+        last
+    )
+    .fetch_all(&mut *conn)
+    .timed()
+    .await?;
+
+    let changes = sqlx::query!(
+        "select order_id, old_value, new_value, changed_at from order_changes
+        where order_id between ? and ? and field = 'status' order by id",
+        first,
+        last
+    )
+    .fetch_all(&mut *conn)
+    .timed()
+    .await?;
+
+    let mut graphs: Vec<OrderGraph> = orders
+        .into_iter()
+        .map(|order| OrderGraph {
+            order,
+            items: Vec::new(),
+            notes: Vec::new(),
+            status_history: Vec::new(),
+        })
+        .collect();
+    let index: HashMap<i64, usize> = graphs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, graph)| Some((graph.order.id?, index)))
+        .collect();
+
+    let mut note_mentions: HashMap<i64, Vec<Mention>> = HashMap::new();
+    for mention in mentions {
+        note_mentions.entry(mention.note_id).or_default().push(Mention {
+            username: mention.username,
+            known: mention.known,
+        });
+    }
+
+    for item in items {
+        graphs[index[&item.order_id]].items.push(GraphItem {
+            sku: item.sku,
+            description: item.description,
+            quantity: item.quantity,
+            unit_price: item.unit_price,
+            currency: item.currency,
+        });
+    }
+
+    for note in notes {
+        graphs[index[&note.order_id]].notes.push(GraphNote {
+            author: note.author,
+            body: note.body,
+            created_at: note.created_at,
+            mentions: note_mentions.remove(&note.id).unwrap_or_default(),
+        });
+    }
+
+    for change in changes {
+        let Some(&at) = index.get(&change.order_id) else {
+            continue;
+        };
+
+        let status = |value: Option<String>| -> Result<Option<OrderStatus>> {
+            value
+                .map(|value| serde_json::from_str(&value))
+                .transpose()
+                .with_context(|| format!("order {} has a status change that isn't a status", change.order_id))
+        };
+
+        let from = status(change.old_value)?;
+        let to = status(change.new_value)?
+            .with_context(|| format!("order {} has a status change to nothing", change.order_id))?;
+
+        graphs[at].status_history.push(StatusChange {
+            from,
+            to,
+            changed_at: change.changed_at,
+        });
+    }
+
+    Ok(graphs)
+}
+
+/// What an import did with each line, see `import`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub imported: i64,
+    pub overwritten: i64,
+    /// Orders whose id is already taken here, each with a line in `errors`.
+    pub skipped: i64,
+    pub failed: i64,
+    /// `line` counts from 1, blank lines included.
+    pub errors: Vec<RowError>,
+}
+
+/// What importing one graph did.
+#[derive(Debug, PartialEq, Eq)]
+enum Imported {
+    Created,
+    Overwritten,
+    /// Not imported, an order here already has the id.
+    Conflict,
+    /// Not imported, for this reason.
+    Refused(String),
+}
+
+/// Imports the graphs in `body`, one per line, each in its own transaction.
+/// Orders already here are replaced when `overwrite` is set and skipped when
+/// it isn't. An error writing to the database stops the import there, the
+/// lines before it stay imported.
+pub async fn import(db: &Db, body: &str, overwrite: bool) -> Result<Report> {
+    let mut report = Report::default();
+
+    for (index, text) in body.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let line = index + 1;
+
+        let graph: OrderGraph = match serde_json::from_str(text) {
+            Ok(graph) => graph,
+            Err(err) => {
+                // where in the line serde stopped, which reads like another line number
+                let message = err.to_string();
+                let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(message, _)| message);
+
+                report.failed += 1;
+                report.errors.push(RowError {
+                    line,
+                    message: message.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let graph = match checked(graph) {
+            Ok(graph) => graph,
+            Err(err) => {
+                report.failed += 1;
+                report.errors.push(RowError {
+                    line,
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let message = match import_one(db, &graph, overwrite).await? {
+            Imported::Created => {
+                report.imported += 1;
+                continue;
+            }
+            Imported::Overwritten => {
+                report.overwritten += 1;
+                continue;
+            }
+            Imported::Conflict => {
+                report.skipped += 1;
+                format!(
+                    "order {} already exists, import with overwrite=true to replace it",
+                    graph.order.id.unwrap_or_default()
+                )
+            }
+            Imported::Refused(message) => {
+                report.failed += 1;
+                message
+            }
+        };
+
+        report.errors.push(RowError { line, message });
+    }
+
+    Ok(report)
+}
+
+/// The graph as it's imported, checked like an order created with its status
+/// changed, its metadata and address set and its tags added, then its items
+/// added and its notes written through the api. Its address and metadata are
+/// normalized the same way too. Its timestamps have to be RFC 3339 times,
+/// `created_at` and `updated_at` are required.
+fn checked(mut graph: OrderGraph) -> error::Result<OrderGraph> {
+    let order = &mut graph.order;
+
+    // drafts are checked when they're confirmed, like one made through the api
+    if order.status != OrderStatus::Draft {
+        check_min("amount", order.amount, AMOUNT_MIN)?;
+    }
+
+    let ends_at = graph.status_history.last().map(|change| change.to);
+
+    if let Some(last) = ends_at.filter(|last| *last != order.status) {
+        return Err(CustomError::Validation(format!(
+            "the order is {} but its status history ends at {last}",
+            order.status
+        )));
+    }
+
+    if let Some(customer_id) = &order.customer_id {
+        if customer_id.is_empty() {
+            return Err(CustomError::Validation("customer_id can't be empty".to_string()));
+        }
+
+        check_max_len("customer_id", customer_id, CUSTOMER_ID_MAX_LEN)?;
+    }
+
+    let Value::Object(metadata) = serde_json::to_value(&order.metadata).map_err(anyhow::Error::from)? else {
+        unreachable!("metadata is a json object");
+    };
+    order.metadata = Metadata::new(metadata)?;
+
+    let tags = order.tags.to_vec();
+    if tags.len() > TAGS_MAX_PER_ORDER {
+        return Err(CustomError::Validation(format!(
+            "an order can have at most {TAGS_MAX_PER_ORDER} tags"
+        )));
+    }
+    for tag in &tags {
+        tags::check_tag(tag)?;
+    }
+
+    let columns = [
+        &order.shipping_name,
+        &order.shipping_line1,
+        &order.shipping_line2,
+        &order.shipping_city,
+        &order.shipping_region,
+        &order.shipping_postal_code,
+        &order.shipping_country,
+    ];
+    if columns.iter().any(|column| column.is_some()) {
+        let fields: Map<String, Value> = address::FIELDS
+            .iter()
+            .zip(columns)
+            .map(|(field, column)| (field.to_string(), json!(column)))
+            .collect();
+        let address = ShippingAddress::patch(None, &Value::Object(fields))?;
+
+        order.set_shipping_address(address.as_ref());
+    }
+
+    for (field, time) in [("created_at", &order.created_at), ("updated_at", &order.updated_at)] {
+        match time {
+            Some(time) => check_time(field, time)?,
+            None => return Err(CustomError::Validation(format!("{field} is required"))),
+        }
+    }
+    for (field, time) in [
+        ("completed_at", &order.completed_at),
+        ("assigned_at", &order.assigned_at),
+        ("reminder_sent_at", &order.reminder_sent_at),
+    ] {
+        if let Some(time) = time {
+            check_time(field, time)?;
+        }
+    }
+
+    for (index, item) in graph.items.iter().enumerate() {
+        check_min(&format!("items[{index}].quantity"), item.quantity, QUANTITY_MIN)?;
+        check_max_len(&format!("items[{index}].description"), &item.description, DESCRIPTION_MAX_LEN)?;
+    }
+
+    for (index, note) in graph.notes.iter().enumerate() {
+        if note.body.trim().is_empty() {
+            return Err(CustomError::Validation(format!("notes[{index}].body is required")));
+        }
+
+        check_max_len(&format!("notes[{index}].body"), &note.body, NOTE_MAX_LEN)?;
+        check_time(&format!("notes[{index}].created_at"), &note.created_at)?;
+    }
+
+    for (index, change) in graph.status_history.iter().enumerate() {
+        check_time(&format!("status_history[{index}].changed_at"), &change.changed_at)?;
+    }
+
+    Ok(graph)
+}
+
+fn check_time(field: &str, time: &str) -> error::Result<()> {
+    match clock::parse(time) {
+        Some(_) => Ok(()),
+        None => Err(CustomError::Validation(format!("{field} {time:?} isn't an RFC 3339 time"))),
+    }
+}
+
+async fn import_one(db: &Db, graph: &OrderGraph, overwrite: bool) -> Result<Imported> {
+    let Some(id) = graph.order.id else {
+        return Ok(Imported::Refused("the order has no id".to_string()));
+    };
+
+    let mut tx = db.writer().begin().await?;
+
+    let retired = sqlx::query_scalar!(
+        r#"select exists(select 1 from orders_archive where id = ?1)
+            or exists(select 1 from order_changes where order_id = ?1 and field = ?2)
+            as "retired!: bool""#,
+        id,
+        history::DELETED
+    )
+    .fetch_one(&mut *tx)
+    .timed()
+    .await?;
+
+    let existing = Order::get_by_id_in(&mut tx, id).await?;
+
+    let imported = match existing {
+        Some(_) if !overwrite => return Ok(Imported::Conflict),
+        Some(existing) => {
+            replace(&mut tx, &existing, &graph.order).await?;
+            Imported::Overwritten
+        }
+        None if retired => {
+            return Ok(Imported::Refused(format!(
+                "order {id} was archived or deleted here, its id can't be reused"
+            )));
+        }
+        None => {
+            insert(&mut tx, &graph.order).await?;
+
+            for change in &graph.status_history {
+                let from = change.from.map(|from| json!(from));
+                let to = json!(change.to);
+
+                history::record_at(&mut tx, id, "status", from.as_ref(), Some(&to), &change.changed_at)
+                    .await?;
+            }
+
+            Imported::Created
+        }
+    };
+
+    for item in &graph.items {
+        let mut item = Item {
+            order_id: id,
+            sku: item.sku.clone(),
+            description: item.description.clone(),
+            quantity: item.quantity,
+            unit_price: item.unit_price,
+            currency: item.currency.clone(),
+            ..Default::default()
+        };
+
+        if let Added::CurrencyMismatch { order } = item.add_in(&mut tx).await? {
+            return Ok(Imported::Refused(format!(
+                "an item is in {}, the order is in {order}",
+                item.currency
+            )));
+        }
+    }
+
+    for note in &graph.notes {
+        Note::import_in(
+            &mut tx,
+            id,
+            note.author.as_deref(),
+            &note.body,
+            &note.created_at,
+            &note.mentions,
+        )
+        .await?;
+    }
+
+    projections::update_summary(&mut tx, id).await?;
+
+    tx.commit().await?;
+
+    Ok(imported)
+}
+
+/// Inserts the order with its own id and timestamps. It has no api key here.
+async fn insert(tx: &mut Transaction<'_, Sqlite>, order: &Order) -> Result<()> {
+    let status = order.status.to_string();
+    let metadata = order.metadata.to_string();
+    let currency = order.currency.as_str();
+    let tags = order.tags.to_string();
+
+    sqlx::query!(
+        "INSERT INTO orders (id, status, amount, updated_at, metadata, reminder_sent_at,
+            customer_id, created_at, completed_at, currency, assigned_to, assigned_at,
+            shipping_name, shipping_line1, shipping_line2, shipping_city, shipping_region,
+            shipping_postal_code, shipping_country, tags)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);",
+        order.id,
+        status,
+        order.amount,
+        order.updated_at,
+        metadata,
+        order.reminder_sent_at,
+        order.customer_id,
+        order.created_at,
+        order.completed_at,
+        currency,
+        order.assigned_to,
+        order.assigned_at,
+        order.shipping_name,
+        order.shipping_line1,
+        order.shipping_line2,
+        order.shipping_city,
+        order.shipping_region,
+        order.shipping_postal_code,
+        order.shipping_country,
+        tags
+    )
+    .execute(&mut **tx)
+    .timed()
+    .await?;
+
+    Ok(())
+}
+
+/// Replaces `existing` with `order` and drops its items and notes, recording
+/// the tracked fields that changed as changes made now.
+async fn replace(tx: &mut Transaction<'_, Sqlite>, existing: &Order, order: &Order) -> Result<()> {
+    let status = order.status.to_string();
+    let metadata = order.metadata.to_string();
+    let currency = order.currency.as_str();
+    let tags = order.tags.to_string();
+
+    sqlx::query!(
+        "update orders set status = ?, amount = ?, updated_at = ?, metadata = ?,
+        reminder_sent_at = ?, customer_id = ?, created_at = ?, completed_at = ?, currency = ?,
+        assigned_to = ?, assigned_at = ?, shipping_name = ?, shipping_line1 = ?,
+        shipping_line2 = ?, shipping_city = ?, shipping_region = ?, shipping_postal_code = ?,
+        shipping_country = ?, tags = ?
+        where id = ?;",
+        status,
+        order.amount,
+        order.updated_at,
+        metadata,
+        order.reminder_sent_at,
+        order.customer_id,
+        order.created_at,
+        order.completed_at,
+        currency,
+        order.assigned_to,
+        order.assigned_at,
+        order.shipping_name,
+        order.shipping_line1,
+        order.shipping_line2,
+        order.shipping_city,
+        order.shipping_region,
+        order.shipping_postal_code,
+        order.shipping_country,
+        tags,
+        existing.id
+    )
+    .execute(&mut **tx)
+    .timed()
+    .await?;
+
+    sqlx::query!("DELETE FROM order_items WHERE order_id = ?", existing.id)
+        .execute(&mut **tx)
+        .timed()
+        .await?;
+    sqlx::query!("DELETE FROM order_notes WHERE order_id = ?", existing.id)
+        .execute(&mut **tx)
+        .timed()
+        .await?;
+
+    let before = history::snapshot(existing)?;
+    let after = history::snapshot(order)?;
+    let id = existing.id.expect("orders read from the database have ids");
+
+    for (field, new) in history::diff(&before, &after) {
+        if history::TRACKED.contains(&field.as_str()) {
+            history::record(tx, id, &field, before.get(&field), Some(&new)).await?;
+        }
+    }
+
+    Ok(())
+}
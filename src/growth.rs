@@ -0,0 +1,178 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    AppState,
+    db::{self, Db},
+    timing::Timed,
+};
+
+/// Watches how fast orders are being created so a runaway integration shows up
+/// in the logs and metrics instead of in tomorrow's disk usage.
+#[derive(Debug, Default)]
+pub struct Growth {
+    last: Mutex<Option<Sample>>,
+    stats: Mutex<GrowthStats>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    max_id: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GrowthStats {
+    /// Highest order id at the last sample, `None` before the first one.
+    pub max_id: Option<i64>,
+    /// Orders created per hour between the last two samples.
+    pub orders_per_hour: Option<f64>,
+    /// Whether the last rate was over the configured threshold.
+    pub alerting: bool,
+    /// Samples over the threshold since startup.
+    pub alerts: u64,
+}
+
+impl Growth {
+    /// Samples the highest order id and works out the rate since the previous
+    /// sample. Uses `max(id)` rather than `COUNT(*)` so it stays cheap on a big
+    /// table, deletes in between can only make the rate look lower.
+    pub async fn sample(
+        &self,
+        db: &Db,
+        at: Instant,
+        threshold_per_hour: u64,
+    ) -> Result<GrowthStats> {
+        let max_id = db::read(|| {
+            sqlx::query_scalar!(r#"select max(id) as "max_id: i64" from orders"#)
+                .fetch_one(db.reader())
+                .timed()
+        })
+        .await?
+        .unwrap_or(0);
+
+        Ok(self.observe(Sample { at, max_id }, threshold_per_hour))
+    }
+
+    fn observe(&self, sample: Sample, threshold_per_hour: u64) -> GrowthStats {
+        let previous = self.last.lock().unwrap().replace(sample);
+
+        let orders_per_hour = previous.and_then(|previous| {
+            let elapsed = sample.at.checked_duration_since(previous.at)?;
+
+            if elapsed.is_zero() {
+                return None;
+            }
+
+            let created = (sample.max_id - previous.max_id).max(0) as f64;
+
+            Some(created / elapsed.as_secs_f64() * 3600.0)
+        });
+
+        let alerting = orders_per_hour.is_some_and(|rate| rate > threshold_per_hour as f64);
+
+        let mut stats = self.stats.lock().unwrap();
+
+        stats.max_id = Some(sample.max_id);
+        stats.orders_per_hour = orders_per_hour;
+        stats.alerting = alerting;
+
+        if alerting {
+            stats.alerts += 1;
+        }
+
+        stats.clone()
+    }
+
+    pub fn stats(&self) -> GrowthStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// Samples order growth every `growth_sample_interval` until the process exits.
+pub async fn watch(state: AppState) {
+    let mut interval = tokio::time::interval(state.config().growth_sample_interval);
+
+    loop {
+        interval.tick().await;
+
+        // read every time, a reload can change it
+        let threshold = state.config().growth_alert_per_hour;
+
+        match state
+            .growth
+            .sample(&state.db, Instant::now(), threshold)
+            .await
+        {
+            Ok(stats) if stats.alerting => {
+                tracing::warn!(
+                    "orders are being created at {:.0}/hour, over the alert threshold of {threshold}/hour",
+                    stats.orders_per_hour.unwrap_or_default()
+                );
+
+                state.metrics.record_growth_alert();
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("failed to sample order growth: {err:#}"),
+        }
+    }
+}
+
+/// How often `watch` samples when `GROWTH_SAMPLE_MINUTES` isn't set.
+pub const DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, orders::Order};
+
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    async fn insert_orders(db: &Db, count: usize) {
+        for _ in 0..count {
+            Order::new(500).save(db.writer()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alerts_above_threshold() {
+        let db = test_db().await;
+        let growth = Growth::default();
+        let start = Instant::now();
+
+        insert_orders(&db, 5).await;
+        let first = growth.sample(&db, start, 50).await.unwrap();
+
+        assert_eq!(first.max_id, Some(5));
+        assert_eq!(first.orders_per_hour, None);
+        assert!(!first.alerting);
+
+        insert_orders(&db, 60).await;
+        let second = growth.sample(&db, start + HOUR, 50).await.unwrap();
+
+        assert_eq!(second.orders_per_hour, Some(60.0));
+        assert!(second.alerting);
+        assert_eq!(growth.stats().alerts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_quiet_below_threshold() {
+        let db = test_db().await;
+        let growth = Growth::default();
+        let start = Instant::now();
+
+        growth.sample(&db, start, 50).await.unwrap();
+
+        insert_orders(&db, 20).await;
+        let stats = growth.sample(&db, start + HOUR / 2, 50).await.unwrap();
+
+        assert_eq!(stats.orders_per_hour, Some(40.0));
+        assert!(!stats.alerting);
+        assert_eq!(growth.stats().alerts, 0);
+    }
+}
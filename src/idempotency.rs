@@ -0,0 +1,276 @@
+//! Replays of `POST /orders` with an `Idempotency-Key`. The first response to
+//! a key is stored with its status, the headers in `REPLAYED_HEADERS` and its
+//! body, and a retry with the same key and body gets exactly that back without
+//! the create running again, a 422 as much as a success. A retry with a
+//! different body is a 409. Responses are kept for `idempotency_ttl`, then
+//! they're pruned, see `retention/tables.rs`.
+//!
+//! 5xx responses aren't stored so a retry runs again, nor are 409s and 429s,
+//! which say something about the orders and quota at the time. A body over
+//! `BODY_MAX_BYTES` only has its hash kept, and a retry goes on to
+//! `create_order`, which answers with the order the key created.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use sqlx::prelude::FromRow;
+
+use crate::{
+    AppState, IDEMPOTENCY_KEY_HEADER, clock,
+    db::{self, Db},
+    error::CustomError,
+    journal,
+    timing::Timed,
+};
+
+/// Response bodies up to this size are stored to be replayed.
+pub const BODY_MAX_BYTES: usize = 64 * 1024;
+
+/// The response headers a replay sends again, the rest are the replay's own.
+pub const REPLAYED_HEADERS: &[&str] = &["content-type", "location", "etag", "last-modified"];
+
+/// A response as it was first sent for an idempotency key.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct StoredResponse {
+    pub key: String,
+    /// sha256 of the request body, hex encoded.
+    pub request_hash: String,
+    pub status: i64,
+    /// A json object of the `REPLAYED_HEADERS` the response had.
+    pub headers: String,
+    /// `None` when it was over `BODY_MAX_BYTES`.
+    pub body: Option<Vec<u8>>,
+    pub body_hash: String,
+    pub created_at: String,
+}
+
+impl StoredResponse {
+    pub async fn get(db: &Db, key: &str) -> Result<Option<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                StoredResponse,
+                "select * from idempotency_responses where key = ?",
+                key
+            )
+            .fetch_optional(db.reader())
+            .timed()
+        })
+        .await?)
+    }
+
+    /// Stores the response unless one is already stored for the key, the
+    /// first response to a key is the one replayed.
+    pub async fn save(&self, db: &Db) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO idempotency_responses
+                (key, request_hash, status, headers, body, body_hash, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (key) DO NOTHING;",
+            self.key,
+            self.request_hash,
+            self.status,
+            self.headers,
+            self.body,
+            self.body_hash,
+            self.created_at
+        )
+        .execute(db.writer())
+        .timed()
+        .await?;
+
+        Ok(())
+    }
+
+    /// Forgets the key's response, so the next request with it runs again.
+    pub async fn delete(db: &Db, key: &str) -> Result<()> {
+        sqlx::query!("delete from idempotency_responses where key = ?;", key)
+            .execute(db.writer())
+            .timed()
+            .await?;
+
+        Ok(())
+    }
+
+    /// The response as it was sent, `None` when its body wasn't kept.
+    pub fn replay(&self) -> Option<Response> {
+        let body = self.body.clone()?;
+        let status = StatusCode::from_u16(u16::try_from(self.status).ok()?).ok()?;
+        let headers: BTreeMap<String, String> = serde_json::from_str(&self.headers).ok()?;
+
+        let mut response = Response::builder().status(status);
+
+        for (name, value) in &headers {
+            response = response.header(name, value);
+        }
+
+        response.body(Body::from(body)).ok()
+    }
+}
+
+/// Whether a response with `status` is the same whenever the request's sent.
+fn is_stored(status: StatusCode) -> bool {
+    (status.is_success() || status.is_client_error())
+        && status != StatusCode::CONFLICT
+        && status != StatusCode::TOO_MANY_REQUESTS
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Replays the stored response for the request's `Idempotency-Key`, or runs
+/// the request and stores its response. Requests without a key go straight
+/// through.
+pub(crate) async fn replay(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let key = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let Some(key) = key else {
+        return next.run(request).await;
+    };
+
+    let (parts, body) = request.into_parts();
+
+    let Ok(bytes) = axum::body::to_bytes(body, journal::MAX_BODY_BYTES).await else {
+        return CustomError::PayloadTooLarge(format!(
+            "request bodies are limited to {} bytes",
+            journal::MAX_BODY_BYTES
+        ))
+        .into_response();
+    };
+    let request_hash = sha256(&bytes);
+
+    match StoredResponse::get(&state.db, &key).await {
+        Ok(Some(stored)) if stored.request_hash != request_hash => {
+            return CustomError::Conflict(format!(
+                "Idempotency-Key {key} was already used with a different body"
+            ))
+            .into_response();
+        }
+        Ok(Some(stored)) => {
+            if let Some(response) = stored.replay() {
+                return response;
+            }
+        }
+        Ok(None) => {}
+        Err(err) => return CustomError::from(err).into_response(),
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+
+    if !is_stored(response.status()) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::error!("failed to read the response to Idempotency-Key {key}: {err}");
+            return CustomError::Other(anyhow::anyhow!("unreadable response")).into_response();
+        }
+    };
+
+    let headers: BTreeMap<&str, &str> = REPLAYED_HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = parts.headers.get(HeaderName::from_static(name))?;
+
+            Some((*name, value.to_str().ok()?))
+        })
+        .collect();
+
+    let stored = StoredResponse {
+        key,
+        request_hash,
+        status: i64::from(parts.status.as_u16()),
+        headers: serde_json::to_string(&headers).expect("headers serialize to json"),
+        body: (body.len() <= BODY_MAX_BYTES).then(|| body.to_vec()),
+        body_hash: sha256(&body),
+        created_at: clock::now(),
+    };
+
+    // the create happened either way, so the client still gets its answer
+    if let Err(err) = stored.save(&state.db).await {
+        tracing::error!("failed to store the response to Idempotency-Key {}: {err:#}", stored.key);
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{config::AppConfig, db::test_db, retention::tables};
+
+    use super::*;
+
+    fn stored(key: &str, body: Option<&[u8]>) -> StoredResponse {
+        StoredResponse {
+            key: key.to_string(),
+            request_hash: sha256(b"{}"),
+            status: 422,
+            headers: r#"{"content-type":"application/json"}"#.to_string(),
+            body: body.map(<[u8]>::to_vec),
+            body_hash: sha256(body.unwrap_or_default()),
+            created_at: clock::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_response_is_kept() {
+        let db = test_db().await;
+
+        stored("abc", Some(b"first")).save(&db).await.unwrap();
+        stored("abc", Some(b"second")).save(&db).await.unwrap();
+
+        let found = StoredResponse::get(&db, "abc").await.unwrap().unwrap();
+        assert_eq!(found.body.as_deref(), Some(&b"first"[..]));
+
+        let response = found.replay().unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.headers()["content-type"], "application/json");
+
+        // a body over the cap can't be replayed
+        assert!(stored("def", None).replay().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_purge_after_ttl() {
+        let db = test_db().await;
+        let config = AppConfig::default();
+        let start = SystemTime::now();
+
+        stored("abc", Some(b"{}")).save(&db).await.unwrap();
+
+        let expired = start + config.idempotency_ttl + Duration::from_secs(1);
+
+        assert_eq!(tables::IDEMPOTENCY.prune(&db, &config, start).await.unwrap().rows(), 0);
+        assert_eq!(tables::IDEMPOTENCY.prune(&db, &config, expired).await.unwrap().rows(), 1);
+        assert!(StoredResponse::get(&db, "abc").await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_which_responses_are_stored() {
+        for status in [200, 201, 400, 404, 413, 422] {
+            assert!(is_stored(StatusCode::from_u16(status).unwrap()), "{status}");
+        }
+
+        for status in [409, 429, 500, 503, 504] {
+            assert!(!is_stored(StatusCode::from_u16(status).unwrap()), "{status}");
+        }
+    }
+}
@@ -0,0 +1,527 @@
+//! Csv order imports. Every upload becomes an `import_jobs` row that's worked
+//! through in batches, each batch committed with the job's progress so a
+//! restarted worker picks up where the last one stopped.
+
+use std::{fmt::Display, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::{
+    Deserialize, Serialize,
+    de::{IntoDeserializer, value},
+};
+use sqlx::prelude::FromRow;
+
+use crate::{
+    AppState,
+    caches::Invalidation,
+    constraints::{AMOUNT_MIN, check_min},
+    db::Db,
+    error::CustomError,
+    money::{self, Currency},
+    money_format::{self, Locale},
+    orders::{Order, OrderStatus},
+    timing::Timed,
+};
+
+/// The first line every upload has to start with. `amount_decimal` can take
+/// the place of `amount`, or sit alongside it, see `Columns`.
+pub const HEADER: &str = "amount,status";
+/// Rows inserted per transaction.
+pub const BATCH_SIZE: usize = 500;
+/// How long the worker sleeps when there's nothing queued.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    #[default]
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl Display for ImportStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            ImportStatus::Queued => "queued",
+            ImportStatus::Running => "running",
+            ImportStatus::Done => "done",
+            ImportStatus::Failed => "failed",
+        };
+
+        write!(f, "{status}")
+    }
+}
+
+impl From<String> for ImportStatus {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "running" => ImportStatus::Running,
+            "done" => ImportStatus::Done,
+            "failed" => ImportStatus::Failed,
+            _ => ImportStatus::Queued,
+        }
+    }
+}
+
+/// A csv row that wasn't imported, `line` counts from 1 including the header.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RowError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct ImportJob {
+    pub id: i64,
+    pub status: ImportStatus,
+    pub body: String,
+    pub total_rows: i64,
+    pub processed_rows: i64,
+    pub imported_rows: i64,
+    pub failed_rows: i64,
+    /// `RowError`s as a json array.
+    pub row_errors: String,
+    /// Why the job stopped, when it's `Failed`.
+    pub failure: Option<String>,
+    pub created_at: String,
+    /// The locale `amount_decimal` is written in, plain decimals when `None`.
+    pub locale: Option<String>,
+}
+
+impl ImportJob {
+    /// Stores the upload as a queued job.
+    pub async fn enqueue(db: &Db, body: String, locale: Option<Locale>) -> Result<Self> {
+        let total_rows = rows(&body).count() as i64;
+        let locale = locale.map(Locale::as_str);
+
+        let id = sqlx::query!(
+            "INSERT INTO import_jobs (body, total_rows, locale) VALUES (?, ?, ?);",
+            body,
+            total_rows,
+            locale
+        )
+        .execute(db.writer())
+        .timed()
+        .await?
+        .last_insert_rowid();
+
+        Ok(Self::get_by_id(db, id)
+            .await?
+            .expect("the job was just inserted"))
+    }
+
+    pub async fn get_by_id(db: &Db, id: i64) -> Result<Option<Self>> {
+        Ok(
+            sqlx::query_as!(ImportJob, "select * from import_jobs where id = ?", id)
+                .fetch_optional(db.reader())
+                .timed()
+                .await?,
+        )
+    }
+
+    /// Jobs not finished yet, oldest first. Running ones were interrupted by a
+    /// restart.
+    pub async fn get_unfinished(db: &Db) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            ImportJob,
+            "select * from import_jobs where status in ('queued', 'running') order by id"
+        )
+        .fetch_all(db.reader())
+        .timed()
+        .await?)
+    }
+
+    pub fn row_errors(&self) -> Vec<RowError> {
+        serde_json::from_str(&self.row_errors).unwrap_or_default()
+    }
+
+    /// Imports the next `batch_size` rows and records the progress in the same
+    /// transaction.
+    pub async fn process_batch(&mut self, db: &Db, batch_size: usize) -> Result<()> {
+        let Some(columns) = header(&self.body).and_then(Columns::parse) else {
+            anyhow::bail!("the upload's header can't be read");
+        };
+        let locale = match self.locale.as_deref() {
+            Some(tag) => Some(Locale::parse(tag).with_context(|| format!("the upload's locale {tag:?} is unknown"))?),
+            None => None,
+        };
+
+        let mut tx = db.writer().begin().await?;
+        let mut row_errors = self.row_errors();
+        let mut orders = Vec::new();
+
+        for (line, row) in rows(&self.body)
+            .skip(self.processed_rows as usize)
+            .take(batch_size)
+        {
+            match parse_row(&columns, row, locale) {
+                Ok(order) => orders.push(order),
+                Err(message) => {
+                    row_errors.push(RowError { line, message });
+                    self.failed_rows += 1;
+                }
+            }
+
+            self.processed_rows += 1;
+        }
+
+        Order::save_many(&mut tx, &mut orders).await?;
+        self.imported_rows += orders.len() as i64;
+
+        self.status = match self.processed_rows >= self.total_rows {
+            true => ImportStatus::Done,
+            false => ImportStatus::Running,
+        };
+        self.row_errors = serde_json::to_string(&row_errors)?;
+
+        let status = self.status.to_string();
+
+        sqlx::query!(
+            "update import_jobs set status = ?, processed_rows = ?, imported_rows = ?,
+            failed_rows = ?, row_errors = ? where id = ?;",
+            status,
+            self.processed_rows,
+            self.imported_rows,
+            self.failed_rows,
+            self.row_errors,
+            self.id
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Processes batches until every row has been handled.
+    pub async fn run(&mut self, db: &Db, batch_size: usize) -> Result<()> {
+        while self.status != ImportStatus::Done {
+            self.process_batch(db, batch_size).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `run`, but an error marks the job failed instead of leaving it for
+    /// the worker to retry.
+    pub async fn finish(&mut self, db: &Db, batch_size: usize) -> Result<()> {
+        if let Err(err) = self.run(db, batch_size).await {
+            tracing::error!("import job {} failed: {err:#}", self.id);
+
+            self.fail(db, format!("{err:#}")).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn fail(&mut self, db: &Db, failure: String) -> Result<()> {
+        self.status = ImportStatus::Failed;
+
+        let status = self.status.to_string();
+
+        sqlx::query!(
+            "update import_jobs set status = ?, failure = ? where id = ?;",
+            status,
+            failure,
+            self.id
+        )
+        .execute(db.writer())
+        .timed()
+        .await?;
+
+        self.failure = Some(failure);
+
+        Ok(())
+    }
+}
+
+/// Where each field is in a row, read from the header. Amounts come as
+/// `amount` in minor units or as `amount_decimal` like `5.00`, or `1.234,56`
+/// for a job in `de-DE`, and a header can have both as long as each row only
+/// fills in one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Columns {
+    amount: Option<usize>,
+    amount_decimal: Option<usize>,
+    status: usize,
+    len: usize,
+}
+
+impl Columns {
+    pub fn parse(header: &str) -> Option<Self> {
+        let names: Vec<&str> = header.split(',').map(str::trim).collect();
+        let position = |name| names.iter().position(|column| *column == name);
+
+        let known = ["amount", "amount_decimal", "status"];
+        let duplicated = names
+            .iter()
+            .any(|name| names.iter().filter(|other| *other == name).count() > 1);
+
+        if duplicated || !names.iter().all(|name| known.contains(name)) {
+            return None;
+        }
+
+        let columns = Self {
+            amount: position("amount"),
+            amount_decimal: position("amount_decimal"),
+            status: position("status")?,
+            len: names.len(),
+        };
+
+        columns.amount.or(columns.amount_decimal).map(|_| columns)
+    }
+}
+
+/// Refuses uploads that don't start with a header `Columns` can read before a
+/// job is created.
+pub fn check_header(body: &str) -> crate::error::Result<()> {
+    match header(body).and_then(Columns::parse) {
+        Some(_) => Ok(()),
+        None => Err(CustomError::Validation(format!(
+            "csv must start with the header {HEADER}, amount_decimal,status or amount,amount_decimal,status"
+        ))),
+    }
+}
+
+fn header(body: &str) -> Option<&str> {
+    body.lines().map(str::trim).find(|line| !line.is_empty())
+}
+
+/// Data rows with their line numbers, skipping the header and blank lines.
+fn rows(body: &str) -> impl Iterator<Item = (usize, &str)> {
+    body.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .skip(1)
+        .map(|(index, line)| (index + 1, line))
+}
+
+/// The fields of a row. A field in double quotes can have commas, which
+/// localized decimals like `"1.234,56"` do, and `""` for a quote.
+fn fields(row: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("there's always a field");
+
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted || field.trim().is_empty() => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+
+    fields.into_iter().map(|field| field.trim().to_string()).collect()
+}
+
+fn parse_row(columns: &Columns, row: &str, locale: Option<Locale>) -> std::result::Result<Order, String> {
+    let fields = fields(row);
+
+    if fields.len() != columns.len {
+        return Err(format!("expected {} fields like the header", columns.len));
+    }
+
+    let field = |index: Option<usize>| {
+        index
+            .map(|index| fields[index].as_str())
+            .filter(|field| !field.is_empty())
+    };
+
+    let amount = match (field(columns.amount), field(columns.amount_decimal)) {
+        (Some(_), Some(_)) => return Err("set amount or amount_decimal, not both".to_string()),
+        (None, None) => return Err("amount or amount_decimal is required".to_string()),
+        (Some(amount), None) => amount
+            .parse()
+            .map_err(|_| format!("amount {amount:?} is not a number"))?,
+        (None, Some(decimal)) => match locale {
+            // imported orders are in the default currency
+            Some(locale) => money_format::parse(decimal, &Currency::default(), locale),
+            None => money::parse_decimal(decimal),
+        }
+        .map_err(|err| format!("amount_decimal {err}"))?,
+    };
+
+    check_min("amount", amount, AMOUNT_MIN).map_err(|err| err.to_string())?;
+
+    let status = fields[columns.status].as_str();
+    let status = OrderStatus::deserialize(status.into_deserializer())
+        .map_err(|_: value::Error| format!("unknown status {status:?}"))?;
+
+    Ok(Order {
+        amount,
+        status,
+        ..Default::default()
+    })
+}
+
+/// Runs every unfinished job to completion, marking jobs that hit an error as
+/// failed so one bad job doesn't block the queue. Returns how many it ran.
+pub async fn run_unfinished(db: &Db, batch_size: usize) -> Result<usize> {
+    let mut jobs = ImportJob::get_unfinished(db).await?;
+
+    for job in &mut jobs {
+        job.finish(db, batch_size).await?;
+    }
+
+    Ok(jobs.len())
+}
+
+/// Works through queued imports until the process exits.
+pub async fn work(state: AppState) {
+    loop {
+        if state.integrity.writes_allowed() {
+            match run_unfinished(&state.db, BATCH_SIZE).await {
+                Ok(0) => {}
+                Ok(_) => state.invalidations.publish(Invalidation::Orders),
+                Err(err) => tracing::error!("failed to run import jobs: {err:#}"),
+            }
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::test_db;
+
+    use super::*;
+
+    const UPLOAD: &str = "amount,status
+500,pending
+abc,pending
+
+700,complete
+0,pending
+900,shipped
+300,inprogress
+";
+
+    #[test]
+    fn test_check_header() {
+        assert!(check_header(UPLOAD).is_ok());
+        assert!(check_header("\namount,status\n").is_ok());
+        assert!(check_header("500,pending\n").is_err());
+
+        assert!(check_header("amount_decimal,status\n").is_ok());
+        assert!(check_header("status,amount,amount_decimal\n").is_ok());
+        assert!(check_header("amount,amount,status\n").is_err());
+        assert!(check_header("amount,currency,status\n").is_err());
+        assert!(check_header("status\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_decimal_rows() {
+        let columns = Columns::parse("amount,amount_decimal,status").unwrap();
+        let amount = |row| parse_row(&columns, row, None).map(|order| order.amount);
+
+        assert_eq!(amount("500,,pending"), Ok(500));
+        assert_eq!(amount(",5.00,pending"), Ok(500));
+        assert_eq!(amount(",12.3,complete"), Ok(1230));
+
+        assert_eq!(
+            amount("500,5.00,pending"),
+            Err("set amount or amount_decimal, not both".to_string())
+        );
+        assert_eq!(
+            amount(",,pending"),
+            Err("amount or amount_decimal is required".to_string())
+        );
+        assert_eq!(
+            amount(",5.001,pending"),
+            Err(r#"amount_decimal "5.001" has more than 2 decimal places"#.to_string())
+        );
+        assert!(amount(",0.00,pending").is_err());
+        assert!(amount("500,pending").is_err());
+    }
+
+    #[test]
+    fn test_parse_localized_rows() {
+        let columns = Columns::parse("amount,amount_decimal,status").unwrap();
+        let amount = |row, locale| parse_row(&columns, row, locale).map(|order| order.amount);
+
+        assert_eq!(amount(r#","1.234,56",pending"#, Some(Locale::DeDe)), Ok(123_456));
+        assert_eq!(amount(",1234.5,pending", Some(Locale::JaJp)), Ok(123_450));
+        assert_eq!(amount(r#","1,234.50",pending"#, Some(Locale::EnUs)), Ok(123_450));
+        // the minor units stay plain whatever the locale
+        assert_eq!(amount("1234,,pending", Some(Locale::DeDe)), Ok(1_234));
+
+        assert_eq!(
+            amount(r#","5,00",pending"#, None),
+            Err(r#"amount_decimal "5,00" is not a decimal amount"#.to_string())
+        );
+        assert_eq!(
+            amount(",1234,56,pending", Some(Locale::DeDe)),
+            Err("expected 3 fields like the header".to_string())
+        );
+        assert!(amount(r#","1.5",pending"#, Some(Locale::DeDe)).is_err());
+    }
+
+    #[test]
+    fn test_quoted_fields() {
+        assert_eq!(fields(r#"a, "b,c" ,"say ""hi""""#), ["a", "b,c", r#"say "hi""#]);
+        assert_eq!(fields(",,"), ["", "", ""]);
+        assert_eq!(fields(r#"5"0,x"#), [r#"5"0"#, "x"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_failed_rows() {
+        let db = test_db().await;
+
+        let job = ImportJob::enqueue(&db, UPLOAD.to_string(), None).await.unwrap();
+
+        assert_eq!(job.status, ImportStatus::Queued);
+        assert_eq!(job.total_rows, 6);
+
+        run_unfinished(&db, 2).await.unwrap();
+
+        let job = ImportJob::get_by_id(&db, job.id).await.unwrap().unwrap();
+
+        assert_eq!(job.status, ImportStatus::Done);
+        assert_eq!(job.processed_rows, 6);
+        assert_eq!(job.imported_rows, 3);
+        assert_eq!(job.failed_rows, 3);
+        assert_eq!(
+            job.row_errors()
+                .iter()
+                .map(|error| error.line)
+                .collect::<Vec<_>>(),
+            vec![3, 6, 7]
+        );
+        assert_eq!(Order::get_all(&db).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_resumes_after_last_batch() {
+        let db = test_db().await;
+
+        let mut job = ImportJob::enqueue(&db, UPLOAD.to_string(), None).await.unwrap();
+
+        // a worker that stopped after one batch
+        job.process_batch(&db, 2).await.unwrap();
+
+        let job = ImportJob::get_by_id(&db, job.id).await.unwrap().unwrap();
+
+        assert_eq!(job.status, ImportStatus::Running);
+        assert_eq!(job.processed_rows, 2);
+
+        run_unfinished(&db, 2).await.unwrap();
+
+        let job = ImportJob::get_by_id(&db, job.id).await.unwrap().unwrap();
+
+        assert_eq!(job.status, ImportStatus::Done);
+        assert_eq!(job.imported_rows, 3);
+        assert_eq!(Order::get_all(&db).await.unwrap().len(), 3);
+    }
+}
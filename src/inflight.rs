@@ -0,0 +1,147 @@
+//! Creates that are still running, so a client that gave up waiting and sent
+//! the same create again gets the order the first one makes instead of a
+//! second one. Only creates running at the same time are joined, an entry is
+//! gone as soon as its create finishes. Idempotency keys are still the way to
+//! make retries safe after that.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::watch;
+
+/// What makes two creates the same: the api key, the idempotency key and the
+/// body, serialized.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CreateKey {
+    pub api_key: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub body: String,
+}
+
+/// `None` while the create runs, then the id of the order it made, or `None`
+/// inside when it failed.
+type Outcome = Option<Option<i64>>;
+
+#[derive(Debug, Default)]
+pub struct InFlight {
+    creates: Mutex<HashMap<CreateKey, watch::Receiver<Outcome>>>,
+}
+
+pub enum Joined {
+    /// Nothing identical is running, this create runs and reports through
+    /// the `Flight`.
+    Leader(Flight),
+    /// An identical create is running, wait for it.
+    Follower(watch::Receiver<Outcome>),
+}
+
+impl InFlight {
+    pub fn join(self: &Arc<Self>, key: CreateKey) -> Joined {
+        let mut creates = self.creates.lock().unwrap();
+
+        if let Some(running) = creates.get(&key) {
+            return Joined::Follower(running.clone());
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        creates.insert(key.clone(), receiver);
+
+        Joined::Leader(Flight {
+            key,
+            sender,
+            in_flight: self.clone(),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.creates.lock().unwrap().len()
+    }
+}
+
+/// The running create's entry, removed when it's finished or dropped.
+pub struct Flight {
+    key: CreateKey,
+    sender: watch::Sender<Outcome>,
+    in_flight: Arc<InFlight>,
+}
+
+impl Flight {
+    /// Hands the created order's id, or `None` when the create failed, to
+    /// whoever joined.
+    pub fn finish(self, order_id: Option<i64>) {
+        // removed first so a create arriving from now on runs by itself
+        self.in_flight.creates.lock().unwrap().remove(&self.key);
+
+        let _ = self.sender.send(Some(order_id));
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        let mut creates = self.in_flight.creates.lock().unwrap();
+
+        // `finish` already removed it, and an identical create may have
+        // taken its place since
+        if creates
+            .get(&self.key)
+            .is_some_and(|running| running.same_channel(&self.sender.subscribe()))
+        {
+            creates.remove(&self.key);
+        }
+    }
+}
+
+/// Waits for the create being followed, returning the id of the order it
+/// made, or `None` when it failed or went away without saying.
+pub async fn wait(mut running: watch::Receiver<Outcome>) -> Option<i64> {
+    match running.wait_for(Option::is_some).await {
+        Ok(outcome) => outcome.flatten(),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(body: &str) -> CreateKey {
+        CreateKey {
+            api_key: Some("key-a".to_string()),
+            idempotency_key: None,
+            body: body.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_creates_join() {
+        let in_flight = Arc::new(InFlight::default());
+
+        let Joined::Leader(flight) = in_flight.join(key("a")) else {
+            panic!("the first create should lead");
+        };
+        let Joined::Follower(follower) = in_flight.join(key("a")) else {
+            panic!("an identical create should follow");
+        };
+        let Joined::Leader(other) = in_flight.join(key("b")) else {
+            panic!("a different create should run by itself");
+        };
+
+        flight.finish(Some(7));
+
+        assert_eq!(wait(follower).await, Some(7));
+        assert_eq!(in_flight.len(), 1);
+
+        // a dropped create lets its followers go, and runs the next one fresh
+        let Joined::Follower(follower) = in_flight.join(key("b")) else {
+            panic!("an identical create should follow");
+        };
+        drop(other);
+
+        assert_eq!(wait(follower).await, None);
+        assert_eq!(in_flight.len(), 0);
+        assert!(matches!(in_flight.join(key("a")), Joined::Leader(_)));
+    }
+}
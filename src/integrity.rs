@@ -0,0 +1,263 @@
+//! Corruption checks of the database file. A power cut in the middle of a
+//! write can leave a file sqlite still opens but with pages of garbage, and
+//! nothing notices until a query trips over them. `check` runs `PRAGMA
+//! quick_check`, or the much slower `integrity_check`, at startup and then
+//! every `INTEGRITY_CHECK_HOURS`, see `every`.
+//!
+//! A check that finds a problem turns on read only mode for good: writes get
+//! a 503 and the background tasks that write stop, except webhook delivery,
+//! which takes the `system.integrity_failure` event out. `/readyz` has the
+//! last result so orchestration can take the node out of rotation.
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Result;
+
+use crate::{
+    AppState, clock,
+    config::IntegrityCheck,
+    db::Db,
+    events::{SystemEvent, SystemEventKind},
+};
+
+/// How often the quick check runs, unless `INTEGRITY_CHECK_HOURS` says
+/// otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Problems a check lists before it stops looking.
+const MAX_PROBLEMS: usize = 100;
+
+/// What a check found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub check: IntegrityCheck,
+    pub checked_at: SystemTime,
+    pub took: Duration,
+    /// What sqlite found wrong, empty when the file is intact.
+    pub problems: Vec<String>,
+}
+
+impl Report {
+    pub fn is_intact(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// `checked_at` the way the timestamp columns hold it.
+    pub fn checked_at_timestamp(&self) -> String {
+        clock::timestamp(self.checked_at)
+    }
+}
+
+/// Checks the database file. A file too damaged for the check to get through
+/// is a problem like any other, an error is only a check that couldn't run,
+/// like one that timed out on a lock.
+pub async fn check(db: &Db, check: IntegrityCheck) -> Result<Report> {
+    let checked_at = SystemTime::now();
+    let timer = Instant::now();
+
+    let pragma = match check {
+        IntegrityCheck::Quick => "quick_check",
+        IntegrityCheck::Full => "integrity_check",
+    };
+
+    let problems = match sqlx::query_scalar::<_, String>(&format!("PRAGMA {pragma}({MAX_PROBLEMS})"))
+        .fetch_all(db.reader())
+        .await
+    {
+        // a single "ok" when there's nothing wrong
+        Ok(rows) => rows.into_iter().filter(|row| row != "ok").collect(),
+        Err(err) if is_corruption(&err) => vec![err.to_string()],
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(Report {
+        check,
+        checked_at,
+        took: timer.elapsed(),
+        problems,
+    })
+}
+
+/// Whether sqlite gave up on the file: `SQLITE_CORRUPT`, `SQLITE_NOTADB` or
+/// one of their extended codes.
+fn is_corruption(err: &sqlx::Error) -> bool {
+    let code = err
+        .as_database_error()
+        .and_then(|err| err.code())
+        .and_then(|code| code.parse::<i32>().ok());
+
+    matches!(code.map(|code| code & 0xff), Some(11 | 26))
+}
+
+/// The last check's result and whether any check since startup failed.
+#[derive(Debug, Default)]
+pub struct Integrity {
+    last: Mutex<Option<Report>>,
+    failed: AtomicBool,
+}
+
+impl Integrity {
+    /// `None` until the first check.
+    pub fn last(&self) -> Option<Report> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Whether a check found a problem. A later one that passes doesn't turn
+    /// read only mode back off, the file needs looking at first.
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Whether the background workers may write. None do once a check has
+    /// failed, since a damaged file is only made worse by writing to it, and
+    /// each skips its turn while that lasts.
+    pub fn writes_allowed(&self) -> bool {
+        !self.failed()
+    }
+
+    /// Keeps `report` as the last result. The first one with a problem is
+    /// logged and recorded as a `system.integrity_failure` event, which can
+    /// fail on a file that damaged, so that's only logged.
+    pub async fn record(&self, db: &Db, report: Report) {
+        let newly_failed = !report.is_intact() && !self.failed.swap(true, Ordering::SeqCst);
+        *self.last.lock().unwrap() = Some(report.clone());
+
+        if !newly_failed {
+            return;
+        }
+
+        let detail = format!(
+            "the database failed its {} check, serving reads only: {}",
+            report.check.as_str(),
+            report.problems.join("; ")
+        );
+
+        tracing::error!("{detail}");
+
+        if let Err(err) = SystemEvent::record(db, SystemEventKind::IntegrityFailure, &detail).await {
+            tracing::error!("failed to record the integrity failure event: {err:#}");
+        }
+    }
+
+    /// Runs `check` and records what it found.
+    pub async fn run(&self, db: &Db, check: IntegrityCheck) -> Result<Report> {
+        let report = self::check(db, check).await?;
+        self.record(db, report.clone()).await;
+
+        Ok(report)
+    }
+}
+
+/// Runs a quick check every `interval`, the first one an interval after
+/// startup, until the process exits.
+pub async fn every(state: AppState, interval: Duration) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = state.integrity.run(&state.db, IntegrityCheck::Quick).await {
+            tracing::warn!("database integrity check failed to run: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::{app_and_state, config::AppConfig, db::setup_db};
+
+    use super::*;
+
+    /// A migrated file database with a few hundred pages of rows in it, all
+    /// written back from the wal so the file holds them.
+    async fn filled(name: &str) -> (Db, PathBuf) {
+        let path = std::env::temp_dir().join(format!("{name}-{}.sqlite", std::process::id()));
+        remove(&path);
+
+        let db = setup_db(&format!("sqlite://{}", path.display()), None).await.unwrap();
+
+        sqlx::raw_sql(
+            "with recursive n(i) as (select 1 union all select i + 1 from n where i < 2000)
+            insert into request_journal (method, path, body)
+            select 'POST', '/orders', hex(randomblob(500)) from n;
+            PRAGMA wal_checkpoint(TRUNCATE);",
+        )
+        .execute(db.writer())
+        .await
+        .unwrap();
+
+        (db, path)
+    }
+
+    fn remove(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_healthy_database_passes() {
+        let (db, path) = filled("integrity-healthy").await;
+        let integrity = Integrity::default();
+
+        for kind in [IntegrityCheck::Quick, IntegrityCheck::Full] {
+            let report = integrity.run(&db, kind).await.unwrap();
+
+            assert!(report.is_intact(), "{:?}", report.problems);
+            assert_eq!(report.check, kind);
+        }
+
+        assert!(!integrity.failed());
+        assert_eq!(integrity.last().unwrap().check, IntegrityCheck::Full);
+
+        db.close().await;
+        remove(&path);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_database_fails() {
+        let (db, path) = filled("integrity-truncated").await;
+        db.close().await;
+
+        // a copy that lost the back half of the file
+        let copy = path.with_extension("truncated.sqlite");
+        std::fs::copy(&path, &copy).unwrap();
+        let file = std::fs::OpenOptions::new().write(true).open(&copy).unwrap();
+        file.set_len(file.metadata().unwrap().len() / 2).unwrap();
+
+        let db: Db = SqlitePoolOptions::new()
+            .connect(&format!("sqlite://{}", copy.display()))
+            .await
+            .unwrap()
+            .into();
+        let (_, state) = app_and_state(db, AppConfig::default());
+        assert!(!state.read_only());
+
+        let report = state.integrity.run(&state.db, IntegrityCheck::Quick).await.unwrap();
+        assert!(!report.is_intact());
+        assert!(state.integrity.failed());
+        assert!(state.read_only());
+        assert_eq!(state.integrity.last(), Some(report.clone()));
+
+        // a check that passes afterwards doesn't clear it
+        state
+            .integrity
+            .record(&state.db, Report { problems: vec![], ..report })
+            .await;
+        assert!(state.read_only());
+
+        state.db.close().await;
+        remove(&path);
+        remove(&copy);
+    }
+}
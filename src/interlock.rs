@@ -0,0 +1,117 @@
+//! Keeps destructive admin operations from being run against prod by
+//! mistake, since the same build serves every environment. In prod they need
+//! `ALLOW_DESTRUCTIVE_OPS=true` in the config and an `X-Confirm-Environment:
+//! prod` header on the request, anything less is a 403 naming what's missing.
+//! Other environments run them as they are.
+//!
+//! A handler is marked destructive by taking `Destructive`, before its body.
+//! A destructive CLI command calls `check_command` instead, with
+//! `--confirm-environment prod` standing in for the header.
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use crate::{
+    AppState,
+    config::{AppConfig, Environment},
+    error::CustomError,
+};
+
+/// Names the environment the caller means to change.
+pub const CONFIRM_ENVIRONMENT_HEADER: &str = "x-confirm-environment";
+
+/// Extracting it is the check.
+pub struct Destructive;
+
+impl FromRequestParts<AppState> for Destructive {
+    type Rejection = CustomError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let config = state.config();
+
+        if config.environment != Environment::Prod {
+            return Ok(Destructive);
+        }
+
+        let confirmed = parts
+            .headers
+            .get(CONFIRM_ENVIRONMENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Environment::parse)
+            == Some(Environment::Prod);
+
+        let missing = missing(config.allow_destructive_ops, confirmed);
+
+        match missing.is_empty() {
+            true => Ok(Destructive),
+            false => Err(CustomError::DestructiveOpLocked { missing }),
+        }
+    }
+}
+
+/// What a request in prod still needs before a destructive operation runs.
+pub fn missing(allowed: bool, confirmed: bool) -> Vec<&'static str> {
+    let mut missing = Vec::new();
+
+    if !allowed {
+        missing.push("ALLOW_DESTRUCTIVE_OPS=true in the config, then a reload");
+    }
+    if !confirmed {
+        missing.push("an X-Confirm-Environment: prod header");
+    }
+
+    missing
+}
+
+/// The interlock for a destructive CLI command, `confirmation` being the
+/// value of its `--confirm-environment` flag. The error names what's missing.
+pub fn check_command(config: &AppConfig, confirmation: Option<&str>) -> anyhow::Result<()> {
+    if config.environment != Environment::Prod {
+        return Ok(());
+    }
+
+    let mut missing = Vec::new();
+
+    if !config.allow_destructive_ops {
+        missing.push("ALLOW_DESTRUCTIVE_OPS=true");
+    }
+    if confirmation.and_then(Environment::parse) != Some(Environment::Prod) {
+        missing.push("--confirm-environment prod");
+    }
+
+    match missing.is_empty() {
+        true => Ok(()),
+        false => anyhow::bail!("this is destructive in prod, it needs {}", missing.join(" and ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_are_locked_in_prod() {
+        for (environment, allowed, confirmation, passes) in [
+            (Environment::Dev, false, None, true),
+            (Environment::Prod, false, None, false),
+            (Environment::Prod, true, None, false),
+            (Environment::Prod, false, Some("prod"), false),
+            (Environment::Prod, true, Some("staging"), false),
+            (Environment::Prod, true, Some("prod"), true),
+        ] {
+            let config = AppConfig {
+                environment,
+                allow_destructive_ops: allowed,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                check_command(&config, confirmation).is_ok(),
+                passes,
+                "{environment:?} {allowed} {confirmation:?}"
+            );
+        }
+    }
+}
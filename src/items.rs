@@ -0,0 +1,373 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{Sqlite, Transaction, prelude::FromRow};
+
+use crate::{
+    clock,
+    db::{self, Db},
+    events::{EventKind, OrderEvent},
+    money::Currency,
+    orders::{
+        OrderStatus, history,
+        pricing::{self, Adjustments, Line},
+    },
+    products::Product,
+    projections,
+    timing::Timed,
+};
+
+/// A line on an order. Prices are copied onto the item when it's added so
+/// later catalog changes don't rewrite existing orders.
+#[derive(Debug, Serialize, Deserialize, FromRow, Default)]
+pub struct Item {
+    pub id: Option<i64>,
+    pub order_id: i64,
+    pub sku: Option<String>,
+    pub description: String,
+    pub quantity: i64,
+    pub unit_price: i64,
+    /// Has to be the order's, `add` checks.
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub currency: Currency,
+}
+
+/// What `Item::add` did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Added {
+    Saved,
+    /// Not saved, the order has no such id.
+    NoOrder,
+    /// Not saved, the order is in another currency.
+    CurrencyMismatch {
+        order: Currency,
+    },
+}
+
+/// What `Item::adjust_quantity` did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Adjusted {
+    Saved,
+    /// Not saved, the order has no item with that id.
+    NoItem,
+    /// Not saved, the order is complete.
+    Locked,
+}
+
+impl Item {
+    pub fn from_product(order_id: i64, product: &Product, quantity: i64) -> Self {
+        Self {
+            order_id,
+            sku: Some(product.sku.clone()),
+            description: product.name.clone(),
+            quantity,
+            unit_price: product.unit_price,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the item to its order, checking in the same transaction that the
+    /// order is there and in the item's currency. Nothing is written otherwise.
+    pub async fn add(&mut self, db: &Db) -> Result<Added> {
+        let mut tx = db.writer().begin().await?;
+
+        let added = self.add_in(&mut tx).await?;
+
+        if added == Added::Saved {
+            tx.commit().await?;
+        }
+
+        Ok(added)
+    }
+
+    /// `add` in the caller's transaction, which it leaves to commit.
+    pub async fn add_in(&mut self, tx: &mut Transaction<'_, Sqlite>) -> Result<Added> {
+        let order_currency =
+            sqlx::query_scalar!("select currency from orders where id = ?", self.order_id)
+                .fetch_optional(&mut **tx)
+                .timed()
+                .await?;
+
+        match order_currency.map(Currency::from) {
+            None => return Ok(Added::NoOrder),
+            Some(order) if order != self.currency => return Ok(Added::CurrencyMismatch { order }),
+            Some(_) => {}
+        }
+
+        let currency = self.currency.as_str();
+
+        let result = sqlx::query!(
+            "INSERT INTO order_items (order_id, sku, description, quantity, unit_price, currency)
+            VALUES (?, ?, ?, ?, ?, ?);",
+            self.order_id,
+            self.sku,
+            self.description,
+            self.quantity,
+            self.unit_price,
+            currency
+        )
+        .execute(&mut **tx)
+        .timed()
+        .await?;
+
+        projections::update_summary(tx, self.order_id).await?;
+
+        self.id = Some(result.last_insert_rowid());
+
+        Ok(Added::Saved)
+    }
+
+    pub async fn save(&mut self, db: &Db) -> Result<()> {
+        match self.id {
+            None => match self.add(db).await? {
+                Added::Saved => {}
+                refused => anyhow::bail!("item not added to order #{}: {refused:?}", self.order_id),
+            },
+            Some(id) => {
+                sqlx::query!(
+                    "update order_items set sku = ?, description = ?, quantity = ?, unit_price = ?
+                    where id = ?;",
+                    self.sku,
+                    self.description,
+                    self.quantity,
+                    self.unit_price,
+                    id
+                )
+                .execute(db.writer())
+                .timed()
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Changes the quantity and works the order's amount out again from its
+    /// items, with an `item_adjusted` event saying what it was. The order is
+    /// checked to still not be complete in the same transaction.
+    pub async fn adjust_quantity(
+        &mut self,
+        db: &Db,
+        quantity: i64,
+        actor: Option<&str>,
+    ) -> Result<Adjusted> {
+        let id = self.id.expect("adjusted items have been saved");
+        let mut tx = db.writer().begin().await?;
+
+        let status = sqlx::query_scalar!("select status from orders where id = ?", self.order_id)
+            .fetch_optional(&mut *tx)
+            .timed()
+            .await?;
+
+        if status.map(OrderStatus::from) == Some(OrderStatus::Complete) {
+            return Ok(Adjusted::Locked);
+        }
+
+        let previous = sqlx::query_scalar!(
+            "select quantity from order_items where id = ? and order_id = ?",
+            id,
+            self.order_id
+        )
+        .fetch_optional(&mut *tx)
+        .timed()
+        .await?;
+
+        let Some(previous) = previous else {
+            return Ok(Adjusted::NoItem);
+        };
+
+        sqlx::query!(
+            "update order_items set quantity = ? where id = ?;",
+            quantity,
+            id
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        let now = clock::now();
+
+        let amount = sqlx::query_scalar!("select amount from orders where id = ?", self.order_id)
+            .fetch_one(&mut *tx)
+            .timed()
+            .await?;
+
+        let items = sqlx::query_as!(
+            Item,
+            "select * from order_items where order_id = ? order by id",
+            self.order_id
+        )
+        .fetch_all(&mut *tx)
+        .timed()
+        .await?;
+        let lines: Vec<Line> = items.iter().map(Line::from).collect();
+
+        let adjusted = pricing::totals(&lines, &Adjustments::default(), amount)
+            .map_err(anyhow::Error::msg)?
+            .total;
+
+        sqlx::query!(
+            "update orders set updated_at = ?, amount = ? where id = ?;",
+            now,
+            adjusted,
+            self.order_id
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        if adjusted != amount {
+            let (old, new) = (json!(amount), json!(adjusted));
+            history::record(&mut tx, self.order_id, "amount", Some(&old), Some(&new)).await?;
+        }
+
+        projections::update_summary(&mut tx, self.order_id).await?;
+
+        let detail = format!("item #{id} quantity {previous} -> {quantity}");
+        OrderEvent::record(
+            &mut tx,
+            self.order_id,
+            EventKind::ItemAdjusted,
+            &detail,
+            actor,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        self.quantity = quantity;
+
+        Ok(Adjusted::Saved)
+    }
+
+    pub async fn get_by_id(db: &Db, order_id: i64, id: i64) -> Result<Option<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                Item,
+                "select * from order_items where id = ? and order_id = ?",
+                id,
+                order_id
+            )
+            .fetch_optional(db.reader())
+            .timed()
+        })
+        .await?)
+    }
+
+    pub async fn get_by_order_id(db: &Db, order_id: i64) -> Result<Vec<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                Item,
+                "select * from order_items where order_id = ? order by id",
+                order_id
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, orders::Order};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_add_checks_the_orders_currency() {
+        let db = test_db().await;
+
+        let mut order = Order {
+            currency: Currency::parse("USD").unwrap(),
+            ..Order::new(500)
+        };
+        order.save(db.writer()).await.unwrap();
+
+        let mut item = Item {
+            order_id: order.id.unwrap(),
+            description: "Gift wrap".to_string(),
+            quantity: 1,
+            unit_price: 150,
+            currency: Currency::parse("EUR").unwrap(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            item.add(&db).await.unwrap(),
+            Added::CurrencyMismatch {
+                order: Currency::parse("USD").unwrap()
+            }
+        );
+        assert!(item.save(&db).await.is_err());
+        assert!(item.id.is_none());
+
+        item.order_id = 999;
+        assert_eq!(item.add(&db).await.unwrap(), Added::NoOrder);
+
+        item.order_id = order.id.unwrap();
+        item.currency = Currency::parse("USD").unwrap();
+        assert_eq!(item.add(&db).await.unwrap(), Added::Saved);
+
+        let items = Item::get_by_order_id(&db, order.id.unwrap()).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].currency.as_str(), "USD");
+    }
+
+    #[tokio::test]
+    async fn test_item_snapshots_product_price() {
+        let db = test_db().await;
+
+        let mut order = Order::new(500);
+        order.save(db.writer()).await.unwrap();
+
+        let mut product = Product {
+            sku: "WIDGET".to_string(),
+            name: "Widget".to_string(),
+            unit_price: 250,
+            ..Default::default()
+        };
+        product.save(&db).await.unwrap();
+
+        let mut item = Item::from_product(order.id.unwrap(), &product, 2);
+        item.save(&db)
+            .await
+            .expect("item should save without error");
+
+        product.unit_price = 999;
+        product.save(&db).await.unwrap();
+
+        let items = Item::get_by_order_id(&db, order.id.unwrap())
+            .await
+            .expect("query should run without error");
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].sku.as_deref(), Some("WIDGET"));
+        assert_eq!(items[0].unit_price, 250);
+        assert_eq!(items[0].quantity, 2);
+    }
+
+    #[tokio::test]
+    async fn test_items_deleted_with_order() {
+        let db = test_db().await;
+
+        let mut order = Order::new(500);
+        order.save(db.writer()).await.unwrap();
+
+        let mut item = Item {
+            order_id: order.id.unwrap(),
+            description: "Gift wrap".to_string(),
+            quantity: 1,
+            unit_price: 100,
+            ..Default::default()
+        };
+        item.save(&db).await.unwrap();
+
+        Order::delete_by_id(&db, order.id.unwrap()).await.unwrap();
+
+        let items = Item::get_by_order_id(&db, order.id.unwrap()).await.unwrap();
+
+        assert!(items.is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::Result;
+use axum::{
+    Router,
+    body::Body,
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::prelude::FromRow;
+use tower::ServiceExt;
+
+use crate::{
+    AppState, IDEMPOTENCY_KEY_HEADER,
+    api_keys::Caller,
+    db::Db,
+    error::CustomError,
+    idempotency::StoredResponse,
+    orders::Order,
+    redact::{self, RedactionRules},
+    routes,
+    timing::Timed,
+};
+
+/// Bodies larger than this aren't journaled or handled.
+pub(crate) const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// A mutating request as it arrived, written before the request is handled.
+#[derive(Debug, Serialize, Deserialize, FromRow, Default)]
+pub struct JournalEntry {
+    pub id: Option<i64>,
+    pub method: String,
+    pub path: String,
+    pub body: String,
+    /// The `Caller`, which a replay is made as.
+    pub api_key: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub created_at: Option<String>,
+}
+
+impl JournalEntry {
+    pub async fn append(&mut self, db: &Db) -> Result<()> {
+        let result = sqlx::query!(
+            "INSERT INTO request_journal (method, path, body, api_key, idempotency_key)
+            VALUES (?, ?, ?, ?, ?);",
+            self.method,
+            self.path,
+            self.body,
+            self.api_key,
+            self.idempotency_key
+        )
+        .execute(db.writer())
+        .timed()
+        .await?;
+
+        self.id = Some(result.last_insert_rowid());
+
+        Ok(())
+    }
+
+    /// Entries at or after `since`, an RFC 3339 timestamp or any prefix of one like `2025-08-11`.
+    pub async fn since(db: &Db, since: &str) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            JournalEntry,
+            "select * from request_journal where created_at >= ? order by id",
+            since
+        )
+        .fetch_all(db.reader())
+        .timed()
+        .await?)
+    }
+}
+
+/// Journals mutating requests when `journal_enabled` is set, and logs their
+/// bodies at debug level. Both get the body with `REDACTED_FIELDS` redacted,
+/// the handler gets it as it was sent.
+pub async fn record(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = state.config();
+    let debug = tracing::enabled!(tracing::Level::DEBUG);
+
+    if !(config.journal_enabled || debug) || is_read_only(request.method()) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_BODY_BYTES).await else {
+        return CustomError::PayloadTooLarge(format!(
+            "request bodies are limited to {MAX_BODY_BYTES} bytes"
+        ))
+        .into_response();
+    };
+
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+
+    let body = redacted_body(&bytes, &config.redaction);
+    tracing::debug!("{} {} {body}", parts.method, parts.uri);
+
+    if !config.journal_enabled {
+        return next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    }
+
+    let mut entry = JournalEntry {
+        method: parts.method.to_string(),
+        path: parts.uri.to_string(),
+        body,
+        api_key: Caller::name(&parts.extensions),
+        idempotency_key: header(IDEMPOTENCY_KEY_HEADER),
+        ..Default::default()
+    };
+
+    if let Err(err) = entry.append(&state.db).await {
+        tracing::error!("failed to journal {} {}: {err:#}", entry.method, entry.path);
+        return crate::error::CustomError::Other(err).into_response();
+    }
+
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+/// The body with the rules' paths redacted when it's json. Other bodies, like
+/// csv imports, are kept as they were sent.
+fn redacted_body(bytes: &[u8], rules: &RedactionRules) -> String {
+    if !rules.is_empty()
+        && let Ok(body) = serde_json::from_slice::<Value>(bytes)
+    {
+        return redact::redact(body, rules).to_string();
+    }
+
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+pub(crate) fn is_read_only(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Creates whose order was missing and were sent again.
+    pub replayed: usize,
+    /// Creates whose order still exists.
+    pub present: usize,
+    /// Entries without an idempotency key, or that aren't creates, so there's no
+    /// way to tell whether they took effect.
+    pub skipped: usize,
+}
+
+/// Re-sends journaled creates whose orders are missing through `app`. Only
+/// `POST /orders` entries with an idempotency key can be checked, so those are
+/// the only ones replayed, without the response stored for their key.
+pub async fn replay(db: &Db, app: Router, since: &str) -> Result<ReplayReport> {
+    let mut report = ReplayReport::default();
+
+    for entry in JournalEntry::since(db, since).await? {
+        let Some(idempotency_key) = entry.idempotency_key.as_deref() else {
+            report.skipped += 1;
+            continue;
+        };
+
+        if entry.method != "POST" || entry.path != routes::ORDERS {
+            report.skipped += 1;
+            continue;
+        }
+
+        if Order::get_by_idempotency_key(db, idempotency_key)
+            .await?
+            .is_some()
+        {
+            report.present += 1;
+            continue;
+        }
+
+        // the stored response is of the create that was lost
+        StoredResponse::delete(db, idempotency_key).await?;
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(&entry.path)
+            .header("Content-Type", "application/json")
+            .header(IDEMPOTENCY_KEY_HEADER, idempotency_key);
+
+        if let Some(api_key) = entry.api_key {
+            request = request.extension(Caller(api_key));
+        }
+
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::from(entry.body))?)
+            .await?;
+
+        if !response.status().is_success() {
+            tracing::warn!(
+                "journal entry {:?} replayed with status {}",
+                entry.id,
+                response.status()
+            );
+        }
+
+        report.replayed += 1;
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,401 @@
+//! Where the server listens. `LISTEN` takes a comma separated list of
+//! `tcp://0.0.0.0:3000`, `unix:///run/orders.sock` or `fd://0`, and the same
+//! router is served on each. `fd://N` is the Nth socket systemd passed with
+//! socket activation, so a restart doesn't refuse connections while the new
+//! process starts.
+//!
+//! Each listener stops accepting on the shutdown signal and finishes its own
+//! requests, without waiting on the others. Socket files this process created
+//! are removed once their listener has stopped.
+
+use std::{
+    fmt::Display,
+    fs::{self, Permissions},
+    io,
+    net::SocketAddr,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::fs::{FileTypeExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use axum::Router;
+use tokio::{
+    net::{TcpListener, UnixListener},
+    sync::watch,
+    task::JoinSet,
+};
+
+/// The first file descriptor systemd passes, `fd://0`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Permissions of the socket files `unix://` listeners create, unless
+/// `UNIX_SOCKET_MODE` says otherwise: the owner and group can connect.
+pub const DEFAULT_SOCKET_MODE: u32 = 0o660;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerSpec {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// The index among the sockets passed by systemd.
+    Fd(usize),
+}
+
+impl ListenerSpec {
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+
+        if let Some(addr) = value.strip_prefix("tcp://") {
+            let addr = addr
+                .parse()
+                .with_context(|| format!("{value:?} is not a socket address"))?;
+
+            return Ok(ListenerSpec::Tcp(addr));
+        }
+
+        if let Some(path) = value.strip_prefix("unix://") {
+            if !path.starts_with('/') {
+                bail!("{value:?} should be an absolute path, like unix:///run/orders.sock");
+            }
+
+            return Ok(ListenerSpec::Unix(PathBuf::from(path)));
+        }
+
+        if let Some(index) = value.strip_prefix("fd://") {
+            let index = index
+                .parse()
+                .with_context(|| format!("{value:?} should be fd:// and a number"))?;
+
+            return Ok(ListenerSpec::Fd(index));
+        }
+
+        bail!("{value:?} should start with tcp://, unix:// or fd://")
+    }
+
+    /// A comma separated list, with no listener given twice.
+    pub fn parse_list(value: &str) -> Result<Vec<Self>> {
+        let mut specs: Vec<Self> = Vec::new();
+
+        for spec in value.split(',').filter(|spec| !spec.trim().is_empty()) {
+            let spec = Self::parse(spec)?;
+
+            if specs.contains(&spec) {
+                bail!("{spec} is listed twice");
+            }
+
+            specs.push(spec);
+        }
+
+        if specs.is_empty() {
+            bail!("there should be at least one listener");
+        }
+
+        Ok(specs)
+    }
+}
+
+impl Display for ListenerSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenerSpec::Tcp(addr) => write!(f, "tcp://{addr}"),
+            ListenerSpec::Unix(path) => write!(f, "unix://{}", path.display()),
+            ListenerSpec::Fd(index) => write!(f, "fd://{index}"),
+        }
+    }
+}
+
+/// A bound listener.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    /// With the socket file to remove when it stops, `None` for a socket
+    /// passed by systemd, which owns its file.
+    Unix(UnixListener, Option<SocketFile>),
+}
+
+impl Listener {
+    /// Binds `spec`. A socket file left by a process that didn't stop cleanly
+    /// is replaced, anything else at the path is an error.
+    pub async fn bind(spec: &ListenerSpec, socket_mode: u32) -> io::Result<Self> {
+        match spec {
+            ListenerSpec::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            ListenerSpec::Unix(path) => {
+                remove_stale_socket(path)?;
+
+                let listener = UnixListener::bind(path)?;
+                let file = SocketFile(path.clone());
+
+                fs::set_permissions(path, Permissions::from_mode(socket_mode))?;
+
+                Ok(Listener::Unix(listener, Some(file)))
+            }
+            ListenerSpec::Fd(index) => activated(*index),
+        }
+    }
+
+    /// What it's listening on, e.g. to log.
+    pub fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => format!("tcp://{addr}"),
+                Err(_) => "tcp".to_string(),
+            },
+            Listener::Unix(listener, _) => match listener.local_addr() {
+                Ok(addr) => match addr.as_pathname() {
+                    Some(path) => format!("unix://{}", path.display()),
+                    None => "unix".to_string(),
+                },
+                Err(_) => "unix".to_string(),
+            },
+        }
+    }
+}
+
+/// A socket file created by `bind`, removed when dropped.
+#[derive(Debug)]
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.0)
+            && err.kind() != io::ErrorKind::NotFound
+        {
+            tracing::warn!("failed to remove {}: {err}", self.0.display());
+        }
+    }
+}
+
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// The `index`th socket passed by systemd, checked against `LISTEN_PID` and
+/// `LISTEN_FDS` so a variable inherited from a parent isn't trusted.
+fn activated(index: usize) -> io::Result<Listener> {
+    let not_passed = |reason: String| io::Error::new(io::ErrorKind::NotFound, reason);
+
+    let pid = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+
+    if pid != Some(std::process::id()) {
+        return Err(not_passed(format!(
+            "fd://{index} needs socket activation, LISTEN_PID isn't this process"
+        )));
+    }
+
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    if index >= count {
+        return Err(not_passed(format!(
+            "fd://{index} wasn't passed, LISTEN_FDS is {count}"
+        )));
+    }
+
+    let fd = LISTEN_FDS_START + index as RawFd;
+
+    // SAFETY: systemd passed the descriptor for this process to take over,
+    // and `parse_list` refuses the same index twice so it's only owned once
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // a unix socket has no socket address, that tells the two apart
+    let tcp = std::net::TcpListener::from(fd);
+
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true)?;
+
+        return Ok(Listener::Tcp(TcpListener::from_std(tcp)?));
+    }
+
+    let unix = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp));
+    unix.set_nonblocking(true)?;
+
+    Ok(Listener::Unix(UnixListener::from_std(unix)?, None))
+}
+
+/// Serves `app` on every listener until `shutdown` changes. Each listener
+/// then stops accepting and finishes its requests on its own, and this
+/// returns when all of them have.
+pub async fn serve(listeners: Vec<Listener>, app: Router, shutdown: watch::Receiver<bool>) {
+    let mut servers = JoinSet::new();
+
+    for listener in listeners {
+        let app = app.clone();
+        let mut shutdown = shutdown.clone();
+        let stopped = async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        };
+
+        servers.spawn(async move {
+            let described = listener.describe();
+
+            let served = match listener {
+                Listener::Tcp(listener) => {
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(stopped)
+                        .await
+                }
+                Listener::Unix(listener, file) => {
+                    let served = axum::serve(listener, app)
+                        .with_graceful_shutdown(stopped)
+                        .await;
+
+                    drop(file);
+
+                    served
+                }
+            };
+
+            match served {
+                Ok(()) => tracing::info!("stopped listening on {described}"),
+                Err(err) => tracing::error!("serving on {described} failed: {err}"),
+            }
+        });
+    }
+
+    while servers.join_next().await.is_some() {}
+}
+
+/// Resolves on ctrl-c or `SIGTERM`, what systemd and orchestrators send.
+pub async fn signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::warn!("can't listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    use crate::{app, db::test_db};
+
+    use super::*;
+
+    async fn healthz<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> String {
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            ListenerSpec::parse_list("tcp://0.0.0.0:3000, unix:///run/orders.sock,fd://0").unwrap(),
+            [
+                ListenerSpec::Tcp("0.0.0.0:3000".parse().unwrap()),
+                ListenerSpec::Unix(PathBuf::from("/run/orders.sock")),
+                ListenerSpec::Fd(0),
+            ]
+        );
+
+        for invalid in [
+            "",
+            "0.0.0.0:3000",
+            "tcp://localhost",
+            "unix://orders.sock",
+            "fd://first",
+            "fd://0,fd://0",
+        ] {
+            assert!(ListenerSpec::parse_list(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serves_on_two_ports_and_a_unix_socket() {
+        let dir = std::env::temp_dir().join(format!("listeners-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("orders.sock");
+
+        // left by a process that didn't stop cleanly
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let tcp = ListenerSpec::Tcp("127.0.0.1:0".parse().unwrap());
+        let mut listeners = Vec::new();
+        for spec in [&tcp, &tcp, &ListenerSpec::Unix(path.clone())] {
+            listeners.push(Listener::bind(spec, 0o600).await.unwrap());
+        }
+
+        let addrs: Vec<SocketAddr> = listeners[..2]
+            .iter()
+            .map(|listener| match listener {
+                Listener::Tcp(listener) => listener.local_addr().unwrap(),
+                Listener::Unix(..) => unreachable!(),
+            })
+            .collect();
+        assert_ne!(addrs[0], addrs[1]);
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let (stop, stopped) = watch::channel(false);
+        let server = tokio::spawn(serve(listeners, app(test_db().await), stopped));
+
+        for addr in addrs {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            assert!(healthz(stream).await.starts_with("HTTP/1.1 200"));
+        }
+
+        let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        assert!(healthz(stream).await.starts_with("HTTP/1.1 200"));
+
+        stop.send(true).unwrap();
+        server.await.unwrap();
+
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_to_replace_a_file() {
+        let path = std::env::temp_dir().join(format!("listeners-file-{}", std::process::id()));
+        fs::write(&path, "not a socket").unwrap();
+
+        let spec = ListenerSpec::Unix(path.clone());
+        assert!(Listener::bind(&spec, DEFAULT_SOCKET_MODE).await.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fd_needs_socket_activation() {
+        let err = Listener::bind(&ListenerSpec::Fd(0), DEFAULT_SOCKET_MODE)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("LISTEN_PID"), "{err}");
+    }
+}
@@ -0,0 +1,406 @@
+//! Housekeeping nobody would remember to run by hand. `every` runs a task on
+//! an interval in the background: a wal checkpoint that truncates the wal file
+//! back to nothing, and much less often a vacuum that gives the pages freed by
+//! deletes and archiving back to the file system, but only when enough of the
+//! file is free. Neither starts while the api is busy, see `every`.
+
+use std::{
+    fmt::Display,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Result;
+
+use crate::{AppState, clock, db::Db};
+
+/// How often the wal is checkpointed, unless `WAL_CHECKPOINT_MINUTES` says
+/// otherwise.
+pub const DEFAULT_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often fragmentation is checked, unless `VACUUM_HOURS` says otherwise.
+pub const DEFAULT_VACUUM_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Percent of the file's pages on the freelist that makes a check vacuum,
+/// unless `VACUUM_FREELIST_PERCENT` says otherwise.
+pub const DEFAULT_VACUUM_FREELIST_PERCENT: u64 = 20;
+/// Requests in flight above which maintenance waits, unless
+/// `MAINTENANCE_MAX_IN_FLIGHT` says otherwise.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 8;
+/// How long a task put off by load waits before looking again.
+const BUSY_RETRY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Checkpoint,
+    Vacuum,
+}
+
+impl Task {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Task::Checkpoint => "checkpoint",
+            Task::Vacuum => "vacuum",
+        }
+    }
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What a checkpoint found and did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Whether a reader or writer kept the wal from being truncated, it's
+    /// tried again on the next run.
+    pub busy: bool,
+    /// Pages that were in the wal, `None` when the database isn't in wal mode
+    /// and there's nothing to checkpoint.
+    pub wal_pages: Option<i64>,
+    /// Of those, the pages written back to the database.
+    pub checkpointed_pages: Option<i64>,
+}
+
+/// How much of the database file is free pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragmentation {
+    pub page_count: i64,
+    pub freelist_count: i64,
+}
+
+impl Fragmentation {
+    pub fn percent(&self) -> f64 {
+        if self.page_count == 0 {
+            return 0.0;
+        }
+
+        self.freelist_count as f64 / self.page_count as f64 * 100.0
+    }
+}
+
+/// What a vacuum check found and did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vacuum {
+    pub before: Fragmentation,
+    pub after: Fragmentation,
+    /// False when the freelist was under the threshold and nothing was done.
+    pub vacuumed: bool,
+}
+
+/// Writes the wal back to the database and truncates it. A truncating
+/// checkpoint that works reports an empty wal, so a passive one goes first to
+/// count the pages, and leaves the truncate little to do.
+pub async fn checkpoint(db: &Db) -> Result<Checkpoint> {
+    let (_, wal_pages, checkpointed_pages): (i64, i64, i64) =
+        sqlx::query_as("PRAGMA wal_checkpoint(PASSIVE)")
+            .fetch_one(db.writer())
+            .await?;
+    let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+        .fetch_one(db.writer())
+        .await?;
+
+    // both are -1 outside of wal mode
+    let pages = |pages: i64| (pages >= 0).then_some(pages);
+
+    Ok(Checkpoint {
+        busy: busy != 0,
+        wal_pages: pages(wal_pages),
+        checkpointed_pages: pages(checkpointed_pages),
+    })
+}
+
+pub async fn fragmentation(db: &Db) -> Result<Fragmentation> {
+    let (page_count, freelist_count): (i64, i64) = sqlx::query_as(
+        "select page_count, freelist_count from pragma_page_count(), pragma_freelist_count()",
+    )
+    .fetch_one(db.reader())
+    .await?;
+
+    Ok(Fragmentation {
+        page_count,
+        freelist_count,
+    })
+}
+
+/// Vacuums when at least `threshold_percent` of the pages are free. A
+/// database with `auto_vacuum = INCREMENTAL` frees them with
+/// `incremental_vacuum`, which is quick. Any other rebuilds the file with
+/// `VACUUM`, which holds the write lock for as long as that takes.
+pub async fn vacuum(db: &Db, threshold_percent: u64) -> Result<Vacuum> {
+    let before = fragmentation(db).await?;
+
+    if before.freelist_count == 0 || before.percent() < threshold_percent as f64 {
+        return Ok(Vacuum {
+            before,
+            after: before,
+            vacuumed: false,
+        });
+    }
+
+    let auto_vacuum: i64 = sqlx::query_scalar("PRAGMA auto_vacuum").fetch_one(db.reader()).await?;
+
+    // 2 is incremental
+    if auto_vacuum == 2 {
+        sqlx::query("PRAGMA incremental_vacuum").execute(db.writer()).await?;
+    } else {
+        sqlx::query("VACUUM").execute(db.writer()).await?;
+    }
+
+    Ok(Vacuum {
+        before,
+        after: fragmentation(db).await?,
+        vacuumed: true,
+    })
+}
+
+/// A task's runs since startup.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Runs {
+    pub last_run_at: Option<SystemTime>,
+    pub last_duration: Option<Duration>,
+    pub runs: u64,
+    pub failures: u64,
+    /// Times the task was put off because the api was busy.
+    pub skipped: u64,
+}
+
+impl Runs {
+    /// `last_run_at` the way the timestamp columns hold it.
+    pub fn last_run_timestamp(&self) -> Option<String> {
+        self.last_run_at.map(clock::timestamp)
+    }
+
+    fn record<T>(&mut self, started: SystemTime, took: Duration, result: &Result<T>) {
+        self.last_run_at = Some(started);
+        self.last_duration = Some(took);
+        self.runs += 1;
+
+        if result.is_err() {
+            self.failures += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaintenanceStats {
+    pub checkpoint: Runs,
+    /// What the last checkpoint that worked found.
+    pub last_checkpoint: Option<Checkpoint>,
+    pub vacuum: Runs,
+    /// What the last vacuum check that worked found and did.
+    pub last_vacuum: Option<Vacuum>,
+}
+
+impl MaintenanceStats {
+    pub fn runs(&self) -> [(Task, &Runs); 2] {
+        [(Task::Checkpoint, &self.checkpoint), (Task::Vacuum, &self.vacuum)]
+    }
+}
+
+/// The maintenance tasks' runs, for `/admin/stats/maintenance` and `/metrics`.
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    stats: Mutex<MaintenanceStats>,
+}
+
+impl Maintenance {
+    /// Runs `task` now, whatever the load, and keeps how it went.
+    pub async fn run(&self, db: &Db, task: Task, threshold_percent: u64) -> Result<()> {
+        let started = SystemTime::now();
+        let timer = Instant::now();
+
+        match task {
+            Task::Checkpoint => {
+                let result = checkpoint(db).await;
+                let mut stats = self.stats.lock().unwrap();
+
+                stats.checkpoint.record(started, timer.elapsed(), &result);
+                stats.last_checkpoint = Some(result?);
+            }
+            Task::Vacuum => {
+                let result = vacuum(db, threshold_percent).await;
+                let mut stats = self.stats.lock().unwrap();
+
+                stats.vacuum.record(started, timer.elapsed(), &result);
+                let vacuum = result?;
+
+                if vacuum.vacuumed {
+                    tracing::info!(
+                        "vacuumed the database, {} free pages of {} down to {}",
+                        vacuum.before.freelist_count,
+                        vacuum.before.page_count,
+                        vacuum.after.freelist_count
+                    );
+                }
+
+                stats.last_vacuum = Some(vacuum);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_skip(&self, task: Task) {
+        let mut stats = self.stats.lock().unwrap();
+
+        match task {
+            Task::Checkpoint => stats.checkpoint.skipped += 1,
+            Task::Vacuum => stats.vacuum.skipped += 1,
+        }
+    }
+
+    pub fn stats(&self) -> MaintenanceStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// Runs `task` every `interval`, the first time one interval after startup,
+/// until the process exits. With more than `maintenance_max_in_flight`
+/// requests in flight a run is put off, and tried again a minute later rather
+/// than an interval later.
+pub async fn every(state: AppState, task: Task, interval: Duration) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+
+    loop {
+        interval.tick().await;
+
+        if !state.integrity.writes_allowed() {
+            continue;
+        }
+
+        loop {
+            // read every time, a reload can change them
+            let config = state.config();
+            let in_flight = state.metrics.requests_in_flight();
+
+            if in_flight <= config.maintenance_max_in_flight {
+                if let Err(err) = state
+                    .maintenance
+                    .run(&state.db, task, config.vacuum_freelist_percent)
+                    .await
+                {
+                    tracing::warn!("database {task} failed: {err:#}");
+                }
+
+                break;
+            }
+
+            tracing::info!("putting off the database {task}, {in_flight} requests are in flight");
+            state.maintenance.record_skip(task);
+
+            tokio::time::sleep(BUSY_RETRY).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+
+    use crate::db::test_db;
+
+    use super::*;
+
+    /// A file database in wal mode, with a table that had a few hundred pages
+    /// of rows written and then deleted.
+    async fn churned(name: &str) -> (Db, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("{name}-{}.sqlite", std::process::id()));
+        let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.display()))
+            .unwrap()
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            // or the churn is checkpointed as soon as it's written
+            .pragma("wal_autocheckpoint", "0");
+        let db: Db = SqlitePoolOptions::new().connect_with(options).await.unwrap().into();
+
+        sqlx::raw_sql(
+            "create table churn (id integer primary key, body text not null);
+            with recursive n(i) as (select 1 union all select i + 1 from n where i < 2000)
+            insert into churn (body) select hex(randomblob(500)) from n;
+            delete from churn where id % 10 != 0;",
+        )
+        .execute(db.writer())
+        .await
+        .unwrap();
+
+        (db, path)
+    }
+
+    async fn remove(db: Db, path: std::path::PathBuf) {
+        db.close().await;
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_truncates_the_wal() {
+        let (db, path) = churned("checkpoint").await;
+        let wal = format!("{}-wal", path.display());
+        assert!(std::fs::metadata(&wal).unwrap().len() > 0);
+
+        let checkpoint = checkpoint(&db).await.unwrap();
+
+        assert!(!checkpoint.busy);
+        assert!(checkpoint.wal_pages.unwrap() > 0);
+        assert_eq!(checkpoint.wal_pages, checkpoint.checkpointed_pages);
+        assert_eq!(std::fs::metadata(&wal).unwrap().len(), 0);
+
+        remove(db, path).await;
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_outside_of_wal_mode() {
+        let db = test_db().await;
+
+        let checkpoint = checkpoint(&db).await.unwrap();
+
+        assert_eq!(checkpoint.wal_pages, None);
+        assert_eq!(checkpoint.checkpointed_pages, None);
+    }
+
+    #[tokio::test]
+    async fn test_vacuum_over_the_threshold() {
+        let (db, path) = churned("vacuum").await;
+
+        let fragmented = fragmentation(&db).await.unwrap();
+        assert!(fragmented.percent() > 50.0, "{fragmented:?}");
+
+        // under the threshold, nothing is done
+        let skipped = vacuum(&db, 100).await.unwrap();
+        assert!(!skipped.vacuumed);
+        assert_eq!(skipped.after, fragmented);
+
+        let vacuumed = vacuum(&db, 20).await.unwrap();
+        assert!(vacuumed.vacuumed);
+        assert_eq!(vacuumed.after.freelist_count, 0);
+        assert!(vacuumed.after.page_count < fragmented.page_count);
+
+        remove(db, path).await;
+    }
+
+    #[tokio::test]
+    async fn test_runs_are_kept() {
+        let (db, path) = churned("maintenance-runs").await;
+        let maintenance = Maintenance::default();
+
+        maintenance.run(&db, Task::Checkpoint, 20).await.unwrap();
+        maintenance.run(&db, Task::Vacuum, 20).await.unwrap();
+        maintenance.record_skip(Task::Vacuum);
+
+        let stats = maintenance.stats();
+        assert_eq!((stats.checkpoint.runs, stats.checkpoint.failures), (1, 0));
+        assert!(stats.checkpoint.last_run_timestamp().is_some());
+        assert!(stats.last_checkpoint.unwrap().wal_pages.unwrap() > 0);
+
+        assert_eq!((stats.vacuum.runs, stats.vacuum.skipped), (1, 1));
+        assert!(stats.last_vacuum.unwrap().vacuumed);
+        assert!(stats.vacuum.last_duration.is_some());
+
+        remove(db, path).await;
+    }
+}
@@ -0,0 +1,152 @@
+//! References integrators keep on their orders: a flat json object of string
+//! keys to scalar values, stored as text in `orders.metadata`.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    constraints::{METADATA_KEY_MAX_LEN, METADATA_MAX_KEYS, METADATA_VALUE_MAX_LEN, check_max_len},
+    error::{CustomError, Result},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Metadata(Map<String, Value>);
+
+impl Metadata {
+    /// Checks metadata sent with a create, null values are dropped.
+    pub fn new(values: Map<String, Value>) -> Result<Self> {
+        let mut metadata = Self::default();
+        metadata.merge(values)?;
+
+        Ok(metadata)
+    }
+
+    /// Applies a json merge patch (RFC 7396): a null value removes the key,
+    /// anything else sets it. Nothing changes if the patch is invalid.
+    pub fn merge(&mut self, patch: Map<String, Value>) -> Result<()> {
+        for (key, value) in &patch {
+            check_entry(key, value)?;
+        }
+
+        let mut merged = self.0.clone();
+
+        for (key, value) in patch {
+            match value {
+                Value::Null => merged.remove(&key),
+                value => merged.insert(key, value),
+            };
+        }
+
+        if merged.len() > METADATA_MAX_KEYS {
+            return Err(CustomError::Validation(format!(
+                "metadata must have at most {METADATA_MAX_KEYS} keys"
+            )));
+        }
+
+        self.0 = merged;
+
+        Ok(())
+    }
+}
+
+impl Display for Metadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Value::Object(self.0.clone()))
+    }
+}
+
+impl From<String> for Metadata {
+    fn from(value: String) -> Self {
+        serde_json::from_str(&value).unwrap_or_default()
+    }
+}
+
+pub fn check_key(key: &str) -> Result<()> {
+    let valid = key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if key.is_empty() || !valid {
+        return Err(CustomError::Validation(format!(
+            "metadata key {key:?} may only contain letters, digits, _ and -"
+        )));
+    }
+
+    check_max_len("metadata key", key, METADATA_KEY_MAX_LEN)
+}
+
+fn check_entry(key: &str, value: &Value) -> Result<()> {
+    check_key(key)?;
+
+    match value {
+        Value::String(value) => {
+            check_max_len(&format!("metadata.{key}"), value, METADATA_VALUE_MAX_LEN)
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => Ok(()),
+        Value::Array(_) | Value::Object(_) => Err(CustomError::Validation(format!(
+            "metadata.{key} must be a string, number or boolean"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => panic!("expected an object"),
+        }
+    }
+
+    #[test]
+    fn test_merge_sets_and_removes() {
+        let mut metadata =
+            Metadata::new(object(json!({ "source": "shopify", "ref": 12 }))).unwrap();
+
+        metadata
+            .merge(object(json!({ "ref": null, "region": "eu" })))
+            .unwrap();
+
+        assert_eq!(
+            metadata,
+            Metadata(object(json!({ "source": "shopify", "region": "eu" })))
+        );
+    }
+
+    #[test]
+    fn test_rejects_nested_values() {
+        assert!(Metadata::new(object(json!({ "tags": ["a"] }))).is_err());
+        assert!(Metadata::new(object(json!({ "shop": { "id": 1 } }))).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let too_many = (0..=METADATA_MAX_KEYS)
+            .map(|i| (format!("key{i}"), json!(i)))
+            .collect();
+
+        assert!(Metadata::new(too_many).is_err());
+        assert!(Metadata::new(object(json!({ "note": "x".repeat(257) }))).is_err());
+        assert!(Metadata::new(object(json!({ "bad key": 1 }))).is_err());
+        assert!(Metadata::new(object(json!({ "$.x": 1 }))).is_err());
+    }
+
+    #[test]
+    fn test_invalid_patch_changes_nothing() {
+        let mut metadata = Metadata::new(object(json!({ "source": "shopify" }))).unwrap();
+
+        assert!(
+            metadata
+                .merge(object(json!({ "source": null, "nested": {} })))
+                .is_err()
+        );
+        assert_eq!(metadata, Metadata(object(json!({ "source": "shopify" }))));
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{AppState, db, error::ErrorCode};
+
+/// How a request ended, coarse enough to alert on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Success,
+    ClientError,
+    Conflict,
+    ServerError,
+}
+
+impl Outcome {
+    pub fn from_status(status: StatusCode) -> Self {
+        if status == StatusCode::CONFLICT {
+            Outcome::Conflict
+        } else if status.is_server_error() {
+            Outcome::ServerError
+        } else if status.is_client_error() {
+            Outcome::ClientError
+        } else {
+            Outcome::Success
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::ClientError => "client_error",
+            Outcome::Conflict => "conflict",
+            Outcome::ServerError => "server_error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestLabels {
+    method: String,
+    route: String,
+    outcome: Outcome,
+}
+
+/// In-process counters rendered in the Prometheus text format at `/metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<RequestLabels, u64>>,
+    errors: Mutex<BTreeMap<&'static str, u64>>,
+    deprecations: Mutex<BTreeMap<&'static str, u64>>,
+    /// Requests being handled right now.
+    requests_in_flight: AtomicUsize,
+    growth_alerts: AtomicU64,
+    error_rate_alerts: AtomicU64,
+    /// Attempts and the seconds they took, by webhook id.
+    webhook_deliveries: Mutex<BTreeMap<i64, (u64, f64)>>,
+    /// Pending deliveries by webhook id, as of the dispatcher's last pass.
+    webhook_backlog: Mutex<BTreeMap<i64, i64>>,
+    /// Rows pruned, passes and the seconds they took, by table.
+    pruned: Mutex<BTreeMap<&'static str, (u64, u64, f64)>>,
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, route: &str, outcome: Outcome) {
+        let labels = RequestLabels {
+            method: method.to_string(),
+            route: route.to_string(),
+            outcome,
+        };
+
+        *self.requests.lock().unwrap().entry(labels).or_default() += 1;
+    }
+
+    pub fn requests_in_flight(&self) -> usize {
+        self.requests_in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn record_error(&self, code: &'static str) {
+        *self.errors.lock().unwrap().entry(code).or_default() += 1;
+    }
+
+    pub fn record_deprecation(&self, behavior: &'static str) {
+        *self.deprecations.lock().unwrap().entry(behavior).or_default() += 1;
+    }
+
+    pub fn record_growth_alert(&self) {
+        self.growth_alerts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_error_rate_alert(&self) {
+        self.error_rate_alerts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a delivery attempt to `webhook_id`, whether it worked or not.
+    pub fn record_webhook_delivery(&self, webhook_id: i64, took: Duration) {
+        let mut deliveries = self.webhook_deliveries.lock().unwrap();
+        let (count, seconds) = deliveries.entry(webhook_id).or_default();
+
+        *count += 1;
+        *seconds += took.as_secs_f64();
+    }
+
+    pub fn set_webhook_backlog(&self, backlog: BTreeMap<i64, i64>) {
+        *self.webhook_backlog.lock().unwrap() = backlog;
+    }
+
+    /// Counts a retention pass over `table`, see `retention/tables.rs`.
+    pub fn record_prune(&self, table: &'static str, rows: u64, took: Duration) {
+        let mut pruned = self.pruned.lock().unwrap();
+        let (total, passes, seconds) = pruned.entry(table).or_default();
+
+        *total += rows;
+        *passes += 1;
+        *seconds += took.as_secs_f64();
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# TYPE http_requests_total counter\n");
+        for (labels, count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",outcome=\"{}\"}} {count}",
+                labels.method,
+                labels.route,
+                labels.outcome.as_str()
+            );
+        }
+
+        out.push_str("# TYPE http_requests_in_flight gauge\n");
+        let _ = writeln!(out, "http_requests_in_flight {}", self.requests_in_flight());
+
+        out.push_str("# TYPE api_errors_total counter\n");
+        for (code, count) in self.errors.lock().unwrap().iter() {
+            let _ = writeln!(out, "api_errors_total{{code=\"{code}\"}} {count}");
+        }
+
+        out.push_str("# TYPE deprecated_behavior_requests_total counter\n");
+        for (behavior, count) in self.deprecations.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "deprecated_behavior_requests_total{{behavior=\"{behavior}\"}} {count}"
+            );
+        }
+
+        out.push_str("# TYPE order_growth_alerts_total counter\n");
+        let _ = writeln!(
+            out,
+            "order_growth_alerts_total {}",
+            self.growth_alerts.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# TYPE error_rate_alerts_total counter\n");
+        let _ = writeln!(
+            out,
+            "error_rate_alerts_total {}",
+            self.error_rate_alerts.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# TYPE webhook_delivery_seconds summary\n");
+        for (webhook_id, (count, seconds)) in self.webhook_deliveries.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "webhook_delivery_seconds_sum{{webhook=\"{webhook_id}\"}} {seconds}"
+            );
+            let _ = writeln!(
+                out,
+                "webhook_delivery_seconds_count{{webhook=\"{webhook_id}\"}} {count}"
+            );
+        }
+
+        out.push_str("# TYPE webhook_backlog gauge\n");
+        for (webhook_id, pending) in self.webhook_backlog.lock().unwrap().iter() {
+            let _ = writeln!(out, "webhook_backlog{{webhook=\"{webhook_id}\"}} {pending}");
+        }
+
+        let pruned = self.pruned.lock().unwrap();
+
+        out.push_str("# TYPE retention_pruned_rows_total counter\n");
+        for (table, (rows, _, _)) in pruned.iter() {
+            let _ = writeln!(out, "retention_pruned_rows_total{{table=\"{table}\"}} {rows}");
+        }
+
+        out.push_str("# TYPE retention_prune_seconds summary\n");
+        for (table, (_, passes, seconds)) in pruned.iter() {
+            let _ = writeln!(out, "retention_prune_seconds_sum{{table=\"{table}\"}} {seconds}");
+            let _ = writeln!(out, "retention_prune_seconds_count{{table=\"{table}\"}} {passes}");
+        }
+
+        drop(pruned);
+
+        out.push_str("# TYPE db_read_retries_total counter\n");
+        let _ = writeln!(out, "db_read_retries_total {}", db::read_retries());
+
+        out.push_str("# TYPE cancelled_queries_total counter\n");
+        let _ = writeln!(out, "cancelled_queries_total {}", db::cancelled_queries());
+
+        out
+    }
+}
+
+/// Counts every request by route and outcome, and every `CustomError` by code,
+/// and feeds the error rate alert.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+
+    let response = {
+        let _in_flight = InFlightRequest::start(&state.metrics);
+
+        next.run(request).await
+    };
+
+    state
+        .metrics
+        .record_request(&method, &route, Outcome::from_status(response.status()));
+    state.error_rate.record(response.status());
+
+    if let Some(ErrorCode(code)) = response.extensions().get::<ErrorCode>() {
+        state.metrics.record_error(code);
+    }
+
+    response
+}
+
+/// Counts a request in flight until it's dropped, so one whose client went
+/// away mid-request isn't counted forever.
+struct InFlightRequest<'a>(&'a Metrics);
+
+impl<'a> InFlightRequest<'a> {
+    fn start(metrics: &'a Metrics) -> Self {
+        metrics.requests_in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(metrics)
+    }
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.0.requests_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub async fn render(State(state): State<AppState>) -> String {
+    let mut out = state.metrics.render();
+    let bus = state.events.stats();
+
+    out.push_str("# TYPE event_bus_subscribers gauge\n");
+    let _ = writeln!(out, "event_bus_subscribers {}", bus.subscribers);
+
+    out.push_str("# TYPE event_bus_missed_events_total counter\n");
+    let _ = writeln!(out, "event_bus_missed_events_total {}", bus.missed_total);
+
+    out.push_str("# TYPE event_bus_subscriber_missed_events gauge\n");
+    for (subscriber, missed) in &bus.missed {
+        let _ = writeln!(
+            out,
+            "event_bus_subscriber_missed_events{{subscriber=\"{subscriber}\"}} {missed}"
+        );
+    }
+
+    out.push_str("# TYPE db_write_queue_depth gauge\n");
+    let _ = writeln!(out, "db_write_queue_depth {}", state.db.writer().depth());
+
+    out.push_str("# TYPE request_log_dropped_total counter\n");
+    let _ = writeln!(out, "request_log_dropped_total {}", state.request_log.dropped());
+
+    let maintenance = state.maintenance.stats();
+
+    out.push_str("# TYPE db_maintenance_runs_total counter\n");
+    for (task, runs) in maintenance.runs() {
+        let _ = writeln!(out, "db_maintenance_runs_total{{task=\"{task}\"}} {}", runs.runs);
+    }
+
+    out.push_str("# TYPE db_maintenance_skipped_total counter\n");
+    for (task, runs) in maintenance.runs() {
+        let _ = writeln!(out, "db_maintenance_skipped_total{{task=\"{task}\"}} {}", runs.skipped);
+    }
+
+    out.push_str("# TYPE db_maintenance_last_run_timestamp_seconds gauge\n");
+    for (task, runs) in maintenance.runs() {
+        if let Some(at) = runs.last_run_at.and_then(|at| at.duration_since(UNIX_EPOCH).ok()) {
+            let _ = writeln!(
+                out,
+                "db_maintenance_last_run_timestamp_seconds{{task=\"{task}\"}} {}",
+                at.as_secs_f64()
+            );
+        }
+    }
+
+    out.push_str("# TYPE db_maintenance_last_duration_seconds gauge\n");
+    for (task, runs) in maintenance.runs() {
+        if let Some(took) = runs.last_duration {
+            let _ = writeln!(
+                out,
+                "db_maintenance_last_duration_seconds{{task=\"{task}\"}} {}",
+                took.as_secs_f64()
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes;
+
+    use super::*;
+
+    #[test]
+    fn test_outcome_from_status() {
+        assert_eq!(Outcome::from_status(StatusCode::OK), Outcome::Success);
+        assert_eq!(
+            Outcome::from_status(StatusCode::NOT_FOUND),
+            Outcome::ClientError
+        );
+        assert_eq!(
+            Outcome::from_status(StatusCode::CONFLICT),
+            Outcome::Conflict
+        );
+        assert_eq!(
+            Outcome::from_status(StatusCode::INTERNAL_SERVER_ERROR),
+            Outcome::ServerError
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+
+        metrics.record_request("GET", routes::ORDERS, Outcome::Success);
+        metrics.record_request("GET", routes::ORDERS, Outcome::Success);
+        metrics.record_error("record_not_found");
+        metrics.record_deprecation("status_casing");
+        metrics.record_growth_alert();
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains(
+            "http_requests_total{method=\"GET\",route=\"/orders\",outcome=\"success\"} 2"
+        ));
+        assert!(rendered.contains("api_errors_total{code=\"record_not_found\"} 1"));
+        assert!(rendered.contains(
+            "deprecated_behavior_requests_total{behavior=\"status_casing\"} 1"
+        ));
+        assert!(rendered.contains("order_growth_alerts_total 1"));
+    }
+}
@@ -0,0 +1,393 @@
+//! Checks every migration upgrades a database that has data in it, not only a
+//! fresh one. A table rebuilt with a new `NOT NULL` column, or a unique index
+//! over values that repeat, works on empty tables and fails on a real
+//! database. `test_upgrade_path` migrates a scratch database to each version
+//! in turn, seeds it, runs the rest of the migrations on it, then checks the
+//! schema for drift and reads the data back with the app's own queries.
+//! `tests/migrations.rs` runs it with `seed`, behind the `migration-tests`
+//! feature.
+
+use anyhow::{Context, Result, ensure};
+use sqlx::{
+    Acquire,
+    migrate::{Migrate, Migration},
+    sqlite::SqlitePoolOptions,
+};
+
+pub use crate::db::Db;
+use crate::{
+    audit, consistency,
+    db::{self, MIGRATOR},
+    drift::{self, Drift},
+    events::OrderEvent,
+    imports::ImportJob,
+    items::Item,
+    notes::Note,
+    orders::{Order, history},
+    products::Product,
+    quotas::Usage,
+    webhooks::Webhook,
+};
+
+/// Rows `seed` puts in each table.
+pub const ROWS: usize = 3;
+
+/// Statuses were only stored lowercase and trimmed from here on, earlier rows
+/// can have any case, see `canonical_status_case.sql`.
+const CANONICAL_STATUSES: i64 = 20250924102718;
+
+/// Upgrades a database seeded by `seed` from every migration this build
+/// carries, returning how many were checked. Every failing version is listed
+/// in the error, not only the first.
+pub async fn test_upgrade_path<F>(seed: F) -> Result<usize>
+where
+    F: AsyncFn(&Db, i64) -> Result<()>,
+{
+    let migrations: Vec<Migration> = MIGRATOR.iter().cloned().collect();
+
+    upgrade_path(&migrations, seed).await
+}
+
+async fn upgrade_path<F>(migrations: &[Migration], seed: F) -> Result<usize>
+where
+    F: AsyncFn(&Db, i64) -> Result<()>,
+{
+    let mut failures = Vec::new();
+
+    for migration in migrations {
+        if let Err(err) = upgrade_from(migrations, migration.version, &seed).await {
+            failures.push(format!("from {}: {err:#}", migration.version));
+        }
+    }
+
+    ensure!(
+        failures.is_empty(),
+        "{} of {} versions don't upgrade with data in them:\n{}",
+        failures.len(),
+        migrations.len(),
+        failures.join("\n")
+    );
+
+    Ok(migrations.len())
+}
+
+async fn upgrade_from<F>(migrations: &[Migration], version: i64, seed: &F) -> Result<()>
+where
+    F: AsyncFn(&Db, i64) -> Result<()>,
+{
+    let db: Db = SqlitePoolOptions::new().connect(":memory:").await?.into();
+
+    let (before, after): (Vec<_>, Vec<_>) = migrations
+        .iter()
+        .partition(|migration| migration.version <= version);
+
+    apply(&db, &before).await.context("migrating to it")?;
+    seed(&db, version).await.context("seeding")?;
+    apply(&db, &after).await?;
+
+    let drift = drift::check(&db).await?;
+    ensure!(drift == Drift::default(), "the schema drifted: {drift}");
+
+    read_back(&db).await.context("reading the data back")
+}
+
+/// Applies `migrations` in order, recording them the way `Migrator::run` does
+/// so the versions read as applied.
+async fn apply(db: &Db, migrations: &[&Migration]) -> Result<()> {
+    let mut conn = db.writer().acquire().await?;
+    let conn = conn.acquire().await?;
+
+    conn.ensure_migrations_table().await?;
+
+    for migration in migrations {
+        conn.apply(migration)
+            .await
+            .with_context(|| format!("{}_{}", migration.version, migration.description))?;
+    }
+
+    Ok(())
+}
+
+/// Reads every table the app reads, through the app's queries, so rows the
+/// migrations left in a shape the models can't take show up.
+async fn read_back(db: &Db) -> Result<()> {
+    db::warm_up(db).await?;
+
+    let orders = Order::get_all(db).await?;
+    ensure!(!orders.is_empty(), "the seeded orders are gone");
+
+    for order in &orders {
+        let id = order.id.expect("stored orders have an id");
+
+        Item::get_by_order_id(db, id).await?;
+        OrderEvent::get_by_order_id(db, id).await?;
+        Note::get_by_order_id(db, id).await?;
+        history::get_by_order_id(db, id).await?;
+    }
+
+    let archived: Vec<i64> = sqlx::query_scalar("select id from orders_archive")
+        .fetch_all(db.reader())
+        .await?;
+
+    for id in archived {
+        Order::get_archived_by_id(db, id).await?;
+    }
+
+    Product::get_all(db).await?;
+    Webhook::get_all(db).await?;
+    Usage::get_all(db).await?;
+    ImportJob::get_unfinished(db).await?;
+    consistency::run_all(db).await?;
+    audit::verify(db).await?;
+
+    Ok(())
+}
+
+/// A value `seed` writes.
+enum Seed {
+    Int(i64),
+    Text(String),
+    Null,
+}
+
+/// A foreign key of the table being seeded.
+struct ForeignKey {
+    column: String,
+    parent: String,
+    parent_column: String,
+}
+
+/// Puts `ROWS` rows in every table a database at `version` has, parents before
+/// the tables referencing them. It goes by the columns sqlite reports rather
+/// than a list per version, so new tables and columns are seeded without
+/// changing it: columns with a default get it, foreign keys point at the
+/// parent's rows and the rest get a value fitting their name and type.
+pub async fn seed(db: &Db, version: i64) -> Result<()> {
+    let mut pending: Vec<String> = sqlx::query_scalar(
+        "select name from sqlite_master where type = 'table' order by name",
+    )
+    .fetch_all(db.reader())
+    .await?;
+    pending.retain(|table| !table.starts_with("sqlite_") && !table.starts_with("_sqlx"));
+
+    let mut seeded: Vec<String> = Vec::new();
+
+    while !pending.is_empty() {
+        let mut ready = None;
+
+        for table in &pending {
+            let keys = foreign_keys(db, table).await?;
+
+            if keys
+                .iter()
+                .all(|key| key.parent == *table || seeded.contains(&key.parent))
+            {
+                ready = Some((table.clone(), keys));
+                break;
+            }
+        }
+
+        let Some((table, keys)) = ready else {
+            anyhow::bail!("the foreign keys between {pending:?} go round in a circle");
+        };
+
+        seed_table(db, &table, &keys, version)
+            .await
+            .with_context(|| format!("seeding {table}"))?;
+
+        pending.retain(|pending| *pending != table);
+        seeded.push(table);
+    }
+
+    Ok(())
+}
+
+async fn foreign_keys(db: &Db, table: &str) -> Result<Vec<ForeignKey>> {
+    let keys: Vec<(String, String, Option<String>)> =
+        sqlx::query_as(r#"select "from", "table", "to" from pragma_foreign_key_list(?)"#)
+            .bind(table)
+            .fetch_all(db.reader())
+            .await?;
+
+    Ok(keys
+        .into_iter()
+        .map(|(column, parent, parent_column)| ForeignKey {
+            column,
+            parent,
+            parent_column: parent_column.unwrap_or_else(|| "rowid".to_string()),
+        })
+        .collect())
+}
+
+async fn seed_table(db: &Db, table: &str, keys: &[ForeignKey], version: i64) -> Result<()> {
+    let columns: Vec<(String, String, bool, bool)> = sqlx::query_as(
+        "select name, upper(type), dflt_value is not null, pk > 0 from pragma_table_info(?)",
+    )
+    .bind(table)
+    .fetch_all(db.reader())
+    .await?;
+
+    for n in 0..ROWS {
+        let mut names = Vec::new();
+        let mut values = Vec::new();
+
+        for (column, kind, has_default, primary_key) in &columns {
+            let key = keys.iter().find(|key| key.column == *column);
+
+            let value = match key {
+                _ if left_null(table, column) => Seed::Null,
+                Some(key) => parent_id(db, key, n).await?,
+                // handed out by sqlite
+                None if *primary_key && kind.starts_with("INT") => continue,
+                None if *has_default => continue,
+                None => value(table, column, kind, n, version),
+            };
+
+            names.push(format!(r#""{column}""#));
+            values.push(value);
+        }
+
+        let sql = match names.is_empty() {
+            true => format!(r#"INSERT INTO "{table}" DEFAULT VALUES"#),
+            false => format!(
+                r#"INSERT INTO "{table}" ({}) VALUES ({})"#,
+                names.join(", "),
+                vec!["?"; names.len()].join(", ")
+            ),
+        };
+
+        let mut query = sqlx::query(&sql);
+
+        for value in values {
+            query = match value {
+                Seed::Int(value) => query.bind(value),
+                Seed::Text(value) => query.bind(value),
+                Seed::Null => query.bind(None::<String>),
+            };
+        }
+
+        query.execute(db.writer()).await?;
+    }
+
+    Ok(())
+}
+
+/// The parent's `n`th row, or the row itself for a table referencing itself
+/// before it has any.
+async fn parent_id(db: &Db, key: &ForeignKey, n: usize) -> Result<Seed> {
+    let ids: Vec<i64> = sqlx::query_scalar(&format!(
+        r#"select "{}" from "{}" order by rowid"#,
+        key.parent_column, key.parent
+    ))
+    .fetch_all(db.reader())
+    .await?;
+
+    Ok(match ids.is_empty() {
+        true => Seed::Null,
+        false => Seed::Int(ids[n % ids.len()]),
+    })
+}
+
+/// Columns another column, or the app once the rows are read, fills in.
+fn left_null(table: &str, column: &str) -> bool {
+    // a delivery is of an order event or a system event, not both
+    matches!(
+        (table, column),
+        ("webhook_deliveries", "system_event_id") | (_, "prev_hash" | "row_hash")
+    )
+}
+
+fn value(table: &str, column: &str, kind: &str, n: usize, version: i64) -> Seed {
+    let n = n as i64 + 1;
+
+    match (table, column) {
+        ("import_jobs", "body") => Seed::Text(format!("amount,status\n{},pending\n", n * 100)),
+        (_, "status") => Seed::Text(status(n, version).to_string()),
+        (_, "metadata") => Seed::Text(format!(r#"{{"source":"seed-{n}"}}"#)),
+        (_, "field") => Seed::Text("amount".to_string()),
+        (_, "old_value" | "new_value") => Seed::Text((n * 100).to_string()),
+        (_, "tags") => Seed::Text(format!(r#"["seed-{n}"]"#)),
+        (_, "scopes") => Seed::Text(r#"["read"]"#.to_string()),
+        (_, "event_types") => Seed::Text(r#"["order.canceled"]"#.to_string()),
+        (_, "kind") => Seed::Text("canceled".to_string()),
+        (_, "url") => Seed::Text(format!("https://hooks.example.com/{n}")),
+        (_, "method") => Seed::Text("POST".to_string()),
+        (_, "path") => Seed::Text("/orders".to_string()),
+        (_, "period") => Seed::Text("2025-09".to_string()),
+        (_, "shipping_country") => Seed::Text("GB".to_string()),
+        (_, "known") => Seed::Int(n % 2),
+        (_, column) if column.ends_with("_at") => {
+            Seed::Text(format!("2025-08-{n:02}T10:00:00.000Z"))
+        }
+        _ if kind.starts_with("INT") || kind == "BOOLEAN" => Seed::Int(n * 100),
+        _ => Seed::Text(format!("{column}-{n}")),
+    }
+}
+
+/// An order status the way rows were stored at `version`.
+fn status(n: i64, version: i64) -> &'static str {
+    let statuses = match version < CANONICAL_STATUSES {
+        true => ["Pending", " complete", "CANCELED"],
+        false => ["pending", "complete", "canceled"],
+    };
+
+    statuses[(n as usize - 1) % statuses.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use sqlx::migrate::MigrationType;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_seed_fills_every_table() {
+        let db: Db = SqlitePoolOptions::new().connect(":memory:").await.unwrap().into();
+        let migrations: Vec<Migration> = MIGRATOR.iter().cloned().collect();
+        let latest = migrations.last().unwrap().version;
+
+        apply(&db, &migrations.iter().collect::<Vec<_>>()).await.unwrap();
+        seed(&db, latest).await.unwrap();
+
+        for (table, _) in drift::TABLES {
+            let rows: i64 = sqlx::query_scalar(&format!("select count(*) from {table}"))
+                .fetch_one(db.reader())
+                .await
+                .unwrap();
+
+            assert_eq!(rows, ROWS as i64, "{table}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_catches_a_migration_that_only_works_when_empty() {
+        // the column has no default and the copy doesn't fill it in
+        let sql = "CREATE TABLE products_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                sku TEXT NOT NULL,
+                name TEXT NOT NULL,
+                unit_price INTEGER NOT NULL,
+                weight INTEGER NOT NULL
+            );
+            INSERT INTO products_new (id, sku, name, unit_price)
+            SELECT id, sku, name, unit_price FROM products;
+            DROP TABLE products;
+            ALTER TABLE products_new RENAME TO products;";
+
+        let mut migrations: Vec<Migration> = MIGRATOR.iter().cloned().collect();
+        migrations.push(Migration::new(
+            29991231000000,
+            Cow::Borrowed("product weights"),
+            MigrationType::Simple,
+            Cow::Borrowed(sql),
+            false,
+        ));
+
+        let err = upgrade_path(&migrations, seed).await.unwrap_err().to_string();
+
+        assert!(err.contains("NOT NULL constraint failed: products_new.weight"), "{err}");
+        assert!(err.starts_with(&format!("{} of {}", migrations.len(), migrations.len())), "{err}");
+    }
+}
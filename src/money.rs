@@ -0,0 +1,181 @@
+//! Conversions between amounts in minor units, which is how they're stored and
+//! sent as json, and decimal strings like `5.00` for people reading them, and
+//! the currency they're in.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Digits after the decimal point of the plain decimal strings, which treat
+/// every currency as a two decimal one. Localized amounts use the currency's
+/// own `Currency::exponent`, see `money_format.rs`.
+pub const CURRENCY_EXPONENT: u32 = 2;
+
+/// The ISO 4217 currencies without minor units, whose minor unit is the
+/// whole one.
+const ZERO_EXPONENT: [&str; 16] = [
+    "BIF", "CLP", "DJF", "GNF", "ISK", "JPY", "KMF", "KRW", "PYG", "RWF", "UGX", "VND", "VUV", "XAF", "XOF", "XPF",
+];
+/// The ISO 4217 currencies with three digit minor units.
+const THREE_EXPONENT: [&str; 7] = ["BHD", "IQD", "JOD", "KWD", "LYD", "OMR", "TND"];
+
+/// Orders created without a currency, and every order from before they had
+/// one, are in this.
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// An ISO 4217 code like `USD`. Items are in their order's currency.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Currency(String);
+
+impl Currency {
+    /// Checks `code` is three uppercase letters. Whether it's a currency
+    /// anyone uses isn't checked.
+    pub fn parse(code: &str) -> Result<Self, String> {
+        match code.len() == 3 && code.bytes().all(|byte| byte.is_ascii_uppercase()) {
+            true => Ok(Self(code.to_string())),
+            false => Err(format!("currency {code:?} should be a code like USD")),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Digits after the decimal point, 2 unless ISO 4217 says otherwise.
+    pub fn exponent(&self) -> u32 {
+        let code = self.as_str();
+
+        if ZERO_EXPONENT.contains(&code) {
+            0
+        } else if THREE_EXPONENT.contains(&code) {
+            3
+        } else {
+            2
+        }
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self(DEFAULT_CURRENCY.to_string())
+    }
+}
+
+/// Read from the database, where only checked codes are written.
+impl From<String> for Currency {
+    fn from(code: String) -> Self {
+        Self(code)
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Formats minor units as a decimal string, `500` as `5.00`.
+pub fn format_decimal(minor: i64) -> String {
+    let scale = 10_u64.pow(CURRENCY_EXPONENT);
+    let sign = if minor < 0 { "-" } else { "" };
+    let minor = minor.unsigned_abs();
+    let width = CURRENCY_EXPONENT as usize;
+
+    format!("{sign}{}.{:0width$}", minor / scale, minor % scale)
+}
+
+/// Parses a decimal string into minor units, `5`, `5.5` and `5.50` all as
+/// `550`. More decimal places than the currency has is an error rather than
+/// rounding.
+pub fn parse_decimal(decimal: &str) -> Result<i64, String> {
+    let invalid = || format!("{decimal:?} is not a decimal amount");
+
+    let (negative, digits) = match decimal.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, decimal),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+    let all_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+
+    if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) {
+        return Err(invalid());
+    }
+    if digits.ends_with('.') {
+        return Err(invalid());
+    }
+    if fraction.len() > CURRENCY_EXPONENT as usize {
+        return Err(format!(
+            "{decimal:?} has more than {CURRENCY_EXPONENT} decimal places"
+        ));
+    }
+
+    let too_large = || format!("{decimal:?} is too large");
+    let width = CURRENCY_EXPONENT as usize;
+
+    let whole: i64 = whole.parse().map_err(|_| too_large())?;
+    let fraction: i64 = format!("{fraction:0<width$}")
+        .parse()
+        .map_err(|_| invalid())?;
+
+    let minor = whole
+        .checked_mul(10_i64.pow(CURRENCY_EXPONENT))
+        .and_then(|minor| minor.checked_add(fraction))
+        .ok_or_else(too_large)?;
+
+    Ok(if negative { -minor } else { minor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimal_round_trip() {
+        assert_eq!(format_decimal(500), "5.00");
+        assert_eq!(format_decimal(1), "0.01");
+        assert_eq!(format_decimal(-250), "-2.50");
+
+        assert_eq!(parse_decimal("5"), Ok(500));
+        assert_eq!(parse_decimal("5.5"), Ok(550));
+        assert_eq!(parse_decimal("0.01"), Ok(1));
+        assert_eq!(parse_decimal("-2.50"), Ok(-250));
+
+        for minor in [1, 99, 100, 12_345, i64::MAX, i64::MIN + 1] {
+            assert_eq!(parse_decimal(&format_decimal(minor)), Ok(minor));
+        }
+
+        assert!(
+            parse_decimal("5.001")
+                .unwrap_err()
+                .contains("more than 2 decimal places")
+        );
+        assert!(
+            parse_decimal("92233720368547758.08")
+                .unwrap_err()
+                .contains("too large")
+        );
+
+        for invalid in ["", ".50", "5.", "5,00", "$5", "1e3", "5.0a"] {
+            assert!(
+                parse_decimal(invalid).is_err(),
+                "{invalid:?} should be refused"
+            );
+        }
+    }
+
+    #[test]
+    fn test_currency_codes() {
+        assert_eq!(Currency::parse("EUR").unwrap().as_str(), "EUR");
+        assert_eq!(Currency::default().as_str(), DEFAULT_CURRENCY);
+
+        for invalid in ["", "eur", "EURO", "E1R", "€"] {
+            assert!(Currency::parse(invalid).is_err(), "{invalid:?}");
+        }
+
+        assert_eq!(Currency::default().exponent(), 2);
+        assert_eq!(Currency::parse("JPY").unwrap().exponent(), 0);
+        assert_eq!(Currency::parse("KWD").unwrap().exponent(), 3);
+    }
+}
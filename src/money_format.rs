@@ -0,0 +1,316 @@
+//! Amounts formatted for people in their locale, `1.234,56 €` in Germany
+//! where `money::format_decimal` has `1234.56` for everyone. A starter set of
+//! locales, each with its decimal separator, digit grouping and where the
+//! currency symbol goes, and the currency's own exponent, so yen have no
+//! decimals.
+//!
+//! The admin pages format amounts in the locale `Accept-Language` asks for,
+//! or `DEFAULT_LOCALE`. Csv exports keep plain decimals unless `?locale=`
+//! asks, and imports read localized decimals when the upload says its locale.
+
+use std::fmt::Display;
+
+use crate::money::Currency;
+
+/// No-break space, between an amount and a symbol after it.
+const NBSP: char = '\u{a0}';
+/// Narrow no-break space, which groups digits in French.
+const NNBSP: char = '\u{202f}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    EnUs,
+    EnGb,
+    DeDe,
+    FrFr,
+    JaJp,
+}
+
+/// How a locale writes an amount.
+struct Rules {
+    group: char,
+    decimal: char,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 5] = [Locale::EnUs, Locale::EnGb, Locale::DeDe, Locale::FrFr, Locale::JaJp];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Locale::EnUs => "en-US",
+            Locale::EnGb => "en-GB",
+            Locale::DeDe => "de-DE",
+            Locale::FrFr => "fr-FR",
+            Locale::JaJp => "ja-JP",
+        }
+    }
+
+    /// A tag like `de-DE`, in any case and with `_` for `-`. A tag this has
+    /// no country for, like `de-AT` or plain `de`, gets its language's.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.trim().replace('_', "-");
+
+        if let Some(locale) = Self::ALL.into_iter().find(|locale| locale.as_str().eq_ignore_ascii_case(&tag)) {
+            return Some(locale);
+        }
+
+        let language = tag.split('-').next()?.to_ascii_lowercase();
+
+        match language.as_str() {
+            "en" => Some(Locale::EnUs),
+            "de" => Some(Locale::DeDe),
+            "fr" => Some(Locale::FrFr),
+            "ja" => Some(Locale::JaJp),
+            _ => None,
+        }
+    }
+
+    /// The locale an `Accept-Language` header likes best of the ones this
+    /// knows, going by the `q` weights.
+    pub fn negotiate(accept_language: &str) -> Option<Self> {
+        let mut ranges: Vec<(f32, &str)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next().filter(|tag| !tag.is_empty())?;
+                let weight = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+
+                (weight > 0.0).then_some((weight, tag))
+            })
+            .collect();
+
+        // stable, so equal weights keep the header's order
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        ranges.into_iter().find_map(|(_, tag)| Self::parse(tag))
+    }
+
+    fn rules(self) -> Rules {
+        match self {
+            Locale::EnUs | Locale::EnGb | Locale::JaJp => Rules { group: ',', decimal: '.' },
+            Locale::DeDe => Rules { group: '.', decimal: ',' },
+            Locale::FrFr => Rules { group: NNBSP, decimal: ',' },
+        }
+    }
+
+    /// Whether the currency symbol goes after the number.
+    #[cfg(any(test, feature = "admin-ui"))]
+    fn symbol_after(self) -> bool {
+        matches!(self, Locale::DeDe | Locale::FrFr)
+    }
+}
+
+impl Display for Locale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The symbols of the common currencies, the others are written as their
+/// code.
+fn symbol(currency: &Currency) -> Option<&'static str> {
+    match currency.as_str() {
+        "USD" => Some("$"),
+        "EUR" => Some("€"),
+        "GBP" => Some("£"),
+        "JPY" => Some("¥"),
+        _ => None,
+    }
+}
+
+/// Formats minor units as a grouped decimal without a symbol, `123456` euro
+/// cents as `1.234,56` in `de-DE` and `123456` yen as `123,456` in `ja-JP`.
+pub fn format_number(minor: i64, currency: &Currency, locale: Locale) -> String {
+    let rules = locale.rules();
+    let exponent = currency.exponent();
+    let scale = 10_u64.pow(exponent);
+    let sign = if minor < 0 { "-" } else { "" };
+    let minor = minor.unsigned_abs();
+
+    let digits = (minor / scale).to_string();
+    let mut number = String::from(sign);
+
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            number.push(rules.group);
+        }
+        number.push(digit);
+    }
+
+    if exponent > 0 {
+        let width = exponent as usize;
+        number.push(rules.decimal);
+        number.push_str(&format!("{:0width$}", minor % scale));
+    }
+
+    number
+}
+
+/// `format_number` with the currency's symbol where the locale puts it,
+/// `$1,234.56` in `en-US` and `1.234,56 €` in `de-DE`. Only the admin pages
+/// show symbols, csv has the plain number.
+#[cfg(any(test, feature = "admin-ui"))]
+pub fn format_money(minor: i64, currency: &Currency, locale: Locale) -> String {
+    let sign = if minor < 0 { "-" } else { "" };
+    let number = format_number(minor.saturating_abs(), currency, locale);
+
+    let (symbol, spaced) = match symbol(currency) {
+        Some(symbol) => (symbol, false),
+        None => (currency.as_str(), true),
+    };
+
+    match locale.symbol_after() {
+        true => format!("{sign}{number}{NBSP}{symbol}"),
+        false if spaced => format!("{sign}{symbol}{NBSP}{number}"),
+        false => format!("{sign}{symbol}{number}"),
+    }
+}
+
+/// Parses what `format_number` or `format_money` write back into minor units.
+/// The symbol or code and the grouping can be left out, but groups have to be
+/// three digits, so `1.5` in `de-DE` is an error rather than fifteen hundred.
+/// More decimal places than the currency has is an error too.
+pub fn parse(amount: &str, currency: &Currency, locale: Locale) -> Result<i64, String> {
+    let invalid = || format!("{amount:?} is not an amount in {locale}");
+    let rules = locale.rules();
+    let is_space = |c: char| c.is_whitespace() || c == NBSP || c == NNBSP;
+
+    let (negative, rest) = match amount.trim_matches(is_space).strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, amount.trim_matches(is_space)),
+    };
+
+    let mut number = rest;
+    for mark in symbol(currency).into_iter().chain([currency.as_str()]) {
+        number = number.strip_prefix(mark).or_else(|| number.strip_suffix(mark)).unwrap_or(number);
+    }
+    let number = number.trim_matches(is_space);
+
+    let (whole, fraction) = match number.split_once(rules.decimal) {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (number, None),
+    };
+
+    let all_digits = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+    let is_group = |c: char| c == rules.group || (rules.group == NNBSP && is_space(c));
+
+    let groups: Vec<&str> = whole.split(is_group).collect();
+    let grouped = groups.len() == 1
+        || (groups[0].len() <= 3 && groups[1..].iter().all(|group| group.len() == 3));
+
+    if !grouped || !groups.iter().all(|group| all_digits(group)) {
+        return Err(invalid());
+    }
+
+    let exponent = currency.exponent();
+    let fraction = match fraction {
+        None => "",
+        Some(fraction) if !all_digits(fraction) => return Err(invalid()),
+        Some(_) if exponent == 0 => return Err(format!("{amount:?} has decimals, {currency} has none")),
+        Some(fraction) if fraction.len() > exponent as usize => {
+            return Err(format!("{amount:?} has more than {exponent} decimal places"));
+        }
+        Some(fraction) => fraction,
+    };
+
+    let too_large = || format!("{amount:?} is too large");
+    let width = exponent as usize;
+
+    let whole: i64 = groups.concat().parse().map_err(|_| too_large())?;
+    let fraction: i64 = match width {
+        0 => 0,
+        _ => format!("{fraction:0<width$}").parse().map_err(|_| invalid())?,
+    };
+
+    let minor = whole
+        .checked_mul(10_i64.pow(exponent))
+        .and_then(|minor| minor.checked_add(fraction))
+        .ok_or_else(too_large)?;
+
+    Ok(if negative { -minor } else { minor })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn currency(code: &str) -> Currency {
+        Currency::parse(code).unwrap()
+    }
+
+    #[test]
+    fn test_format_by_locale() {
+        let (usd, eur, jpy) = (currency("USD"), currency("EUR"), currency("JPY"));
+
+        assert_eq!(format_money(123_456, &usd, Locale::EnUs), "$1,234.56");
+        assert_eq!(format_money(123_456, &eur, Locale::DeDe), "1.234,56\u{a0}€");
+        assert_eq!(format_money(123_456, &jpy, Locale::JaJp), "¥123,456");
+        assert_eq!(format_money(123_456, &eur, Locale::FrFr), "1\u{202f}234,56\u{a0}€");
+        assert_eq!(format_money(-123_456, &usd, Locale::DeDe), "-1.234,56\u{a0}$");
+        assert_eq!(format_money(500, &currency("CHF"), Locale::EnGb), "CHF\u{a0}5.00");
+
+        assert_eq!(format_number(123_456, &eur, Locale::DeDe), "1.234,56");
+        assert_eq!(format_number(123_456, &jpy, Locale::DeDe), "123.456");
+        assert_eq!(format_number(0, &jpy, Locale::JaJp), "0");
+        assert_eq!(format_number(0, &usd, Locale::EnUs), "0.00");
+        assert_eq!(format_number(5, &usd, Locale::EnUs), "0.05");
+        assert_eq!(format_number(100_000_000, &usd, Locale::EnUs), "1,000,000.00");
+    }
+
+    #[test]
+    fn test_parse_round_trips() {
+        for locale in [Locale::EnUs, Locale::DeDe, Locale::JaJp] {
+            for code in ["USD", "EUR", "JPY", "CHF"] {
+                let currency = currency(code);
+
+                for minor in [0, 1, 99, 100, 123_456, -123_456, 1_000_000_000, i64::MAX, i64::MIN + 1] {
+                    let number = format_number(minor, &currency, locale);
+                    let money = format_money(minor, &currency, locale);
+
+                    assert_eq!(parse(&number, &currency, locale), Ok(minor), "{number:?} in {locale}");
+                    assert_eq!(parse(&money, &currency, locale), Ok(minor), "{money:?} in {locale}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_localized_forms() {
+        let (usd, eur, jpy) = (currency("USD"), currency("EUR"), currency("JPY"));
+
+        assert_eq!(parse("1234,56", &eur, Locale::DeDe), Ok(123_456));
+        assert_eq!(parse("1.234,5", &eur, Locale::DeDe), Ok(123_450));
+        assert_eq!(parse("1.234 €", &eur, Locale::DeDe), Ok(123_400));
+        assert_eq!(parse("1 234,56", &eur, Locale::FrFr), Ok(123_456));
+        assert_eq!(parse("1,234.56 USD", &usd, Locale::EnUs), Ok(123_456));
+        assert_eq!(parse("¥1,234", &jpy, Locale::JaJp), Ok(1_234));
+
+        assert!(parse("1.5", &eur, Locale::DeDe).is_err());
+        assert!(parse("1,234.56", &eur, Locale::DeDe).is_err());
+        assert!(parse("1,23", &usd, Locale::EnUs).is_err());
+        assert!(parse("5.001", &usd, Locale::EnUs).unwrap_err().contains("more than 2 decimal places"));
+        assert!(parse("1.5", &jpy, Locale::JaJp).unwrap_err().contains("JPY has none"));
+
+        for invalid in ["", "-", "€", ",50", "5,", "5,0a", "1..234,00", "1e3"] {
+            assert!(parse(invalid, &eur, Locale::DeDe).is_err(), "{invalid:?} should be refused");
+        }
+    }
+
+    #[test]
+    fn test_locale_tags() {
+        assert_eq!(Locale::parse("de-DE"), Some(Locale::DeDe));
+        assert_eq!(Locale::parse("ja_jp"), Some(Locale::JaJp));
+        assert_eq!(Locale::parse("de-AT"), Some(Locale::DeDe));
+        assert_eq!(Locale::parse("en"), Some(Locale::EnUs));
+        assert_eq!(Locale::parse("pt-BR"), None);
+
+        assert_eq!(Locale::negotiate("de-DE,de;q=0.9,en;q=0.8"), Some(Locale::DeDe));
+        assert_eq!(Locale::negotiate("pt-BR, en-GB;q=0.5, ja;q=0.7"), Some(Locale::JaJp));
+        assert_eq!(Locale::negotiate("fr;q=0, en-GB"), Some(Locale::EnGb));
+        assert_eq!(Locale::negotiate("*"), None);
+        assert_eq!(Locale::negotiate(""), None);
+    }
+}
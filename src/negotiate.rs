@@ -0,0 +1,141 @@
+//! Optional strict content negotiation. With `STRICT_ACCEPT` on, a request
+//! whose `Accept` header rules out everything its route can produce gets a 406
+//! instead of a body the client will choke on.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{HeaderMap, header::ACCEPT},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{AppState, error::CustomError};
+
+pub const PREFER_HEADER: &str = "prefer";
+pub const PREFERENCE_APPLIED_HEADER: &str = "preference-applied";
+
+pub const JSON: &str = "application/json";
+pub const PLAIN_TEXT: &str = "text/plain";
+pub const EVENT_STREAM: &str = "text/event-stream";
+pub const CSV: &str = "text/csv";
+pub const NDJSON: &str = "application/x-ndjson";
+#[cfg(feature = "admin-ui")]
+pub const HTML: &str = "text/html";
+
+/// Asks a `PATCH` to respond with only the fields it changed, see
+/// `orders::history::diff`.
+pub const MINIMAL_DIFF: &str = "return=minimal-diff";
+
+/// Whether a `Prefer` header asks for `preference`, like `return=minimal-diff`.
+/// Preferences are comma separated, and any parameters after a `;` are
+/// ignored.
+pub fn prefers(headers: &HeaderMap, preference: &str) -> bool {
+    headers
+        .get_all(PREFER_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|value| value.split(';').next())
+        .any(|value| value.trim().eq_ignore_ascii_case(preference))
+}
+
+/// The media types each route can respond with, by the path it's declared
+/// with. Routes that aren't listed produce json.
+#[derive(Debug, Default)]
+pub struct MediaTypes(BTreeMap<&'static str, &'static [&'static str]>);
+
+impl MediaTypes {
+    pub fn route(mut self, path: &'static str, produces: &'static [&'static str]) -> Self {
+        self.0.insert(path, produces);
+        self
+    }
+
+    pub fn produced_by(&self, path: &str) -> &'static [&'static str] {
+        self.0.get(path).copied().unwrap_or(&[JSON])
+    }
+}
+
+/// Whether an `Accept` header value allows any of `produces`. Ranges with
+/// `q=0` are refusals and don't match.
+pub fn accepts(accept: &str, produces: &[&str]) -> bool {
+    accept.split(',').any(|range| {
+        let mut parts = range.split(';').map(str::trim);
+        let range = parts.next().unwrap_or_default().to_ascii_lowercase();
+
+        let refused = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+
+        !refused && produces.iter().any(|media_type| matches(&range, media_type))
+    })
+}
+
+fn matches(range: &str, media_type: &str) -> bool {
+    match range.strip_suffix("/*") {
+        Some("*") => true,
+        Some(kind) => media_type.split('/').next() == Some(kind),
+        None => range == media_type,
+    }
+}
+
+/// Refuses requests that can't accept anything the matched route produces,
+/// when `strict_accept` is on. A missing `Accept` header accepts anything.
+pub async fn strict_accept(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.config().strict_accept {
+        return next.run(request).await;
+    }
+
+    let (Some(path), Some(accept)) = (
+        request.extensions().get::<MatchedPath>(),
+        request.headers().get(ACCEPT),
+    ) else {
+        return next.run(request).await;
+    };
+
+    let produces = state.media_types.produced_by(path.as_str());
+    let accept = accept.to_str().unwrap_or_default();
+
+    if !accepts(accept, produces) {
+        return CustomError::NotAcceptable(produces).into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        assert!(accepts("application/json", &[JSON]));
+        assert!(accepts("*/*", &[JSON]));
+        assert!(accepts("application/*", &[JSON]));
+        assert!(accepts("text/html, application/json;q=0.9", &[JSON]));
+        assert!(accepts("text/plain; version=0.0.4", &[PLAIN_TEXT]));
+
+        assert!(!accepts("text/html", &[JSON]));
+        assert!(!accepts("text/*", &[JSON]));
+        assert!(!accepts("application/json;q=0", &[JSON]));
+        assert!(!accepts("application/json", &[PLAIN_TEXT]));
+    }
+
+    #[test]
+    fn test_prefers() {
+        let prefers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(PREFER_HEADER, value.parse().unwrap());
+            prefers(&headers, MINIMAL_DIFF)
+        };
+
+        assert!(prefers("return=minimal-diff"));
+        assert!(prefers("respond-async, return=minimal-diff; strict"));
+        assert!(!prefers("return=minimal"));
+        assert!(!prefers("return=representation"));
+    }
+}
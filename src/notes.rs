@@ -0,0 +1,292 @@
+//! Notes staff leave on an order. `@username` mentions are parsed out when a
+//! note is created and kept in `note_mentions`, and each mention of a known
+//! username records a `note_mention` event, which goes out to webhooks like
+//! any other so something downstream can notify the person.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, Transaction};
+
+use crate::{
+    clock,
+    db::{self, Db},
+    events::{EventKind, OrderEvent},
+    timing::Timed,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub id: i64,
+    pub order_id: i64,
+    /// Who wrote it, when they said.
+    pub author: Option<String>,
+    pub body: String,
+    pub created_at: String,
+    pub mentions: Vec<Mention>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mention {
+    /// Lowercase, without the `@`.
+    pub username: String,
+    /// `false` when `mentionable_users` is set and doesn't have the username.
+    /// Stored anyway, but nobody is notified.
+    pub known: bool,
+}
+
+/// The usernames `@mentioned` in `body`, lowercase and each once, in the order
+/// they first appear. An `@` inside a word, like in an email address, isn't a
+/// mention, and trailing `.` or `-` is taken as punctuation.
+pub fn parse_mentions(body: &str) -> Vec<String> {
+    let is_username_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-');
+
+    let mut mentions = Vec::new();
+    let mut previous = None;
+
+    for (at, c) in body.char_indices() {
+        let starts_word = !previous.is_some_and(|previous: char| {
+            previous.is_alphanumeric() || matches!(previous, '_' | '.' | '-' | '@')
+        });
+        previous = Some(c);
+
+        if c != '@' || !starts_word {
+            continue;
+        }
+
+        let rest = &body[at + 1..];
+        let end = rest.find(|c| !is_username_char(c)).unwrap_or(rest.len());
+        let username = rest[..end].trim_end_matches(['.', '-']).to_lowercase();
+
+        if !username.is_empty() && !mentions.contains(&username) {
+            mentions.push(username);
+        }
+    }
+
+    mentions
+}
+
+impl Note {
+    /// Saves a note on `order_id` with its mentions, checked against
+    /// `mentionable` unless it's empty, and a `note_mention` event for each
+    /// known one, all in one transaction. The caller checks the order exists.
+    pub async fn create(
+        db: &Db,
+        order_id: i64,
+        author: Option<&str>,
+        body: &str,
+        mentionable: &[String],
+    ) -> Result<Self> {
+        let mut tx = db.writer().begin().await?;
+        let now = clock::now();
+
+        let note = sqlx::query!(
+            "INSERT INTO order_notes (order_id, author, body, created_at) VALUES (?, ?, ?, ?)
+            RETURNING id as \"id!: i64\", created_at;",
+            order_id,
+            author,
+            body,
+            now
+        )
+        .fetch_one(&mut *tx)
+        .timed()
+        .await?;
+
+        let mut mentions = Vec::new();
+
+        for username in parse_mentions(body) {
+            let known = mentionable.is_empty() || mentionable.contains(&username);
+
+            sqlx::query!(
+                "INSERT INTO note_mentions (note_id, username, known) VALUES (?, ?, ?);",
+                note.id,
+                username,
+                known
+            )
+            .execute(&mut *tx)
+            .timed()
+            .await?;
+
+            if known {
+                let detail = format!("@{username} was mentioned in note #{}: {body}", note.id);
+
+                OrderEvent::record(&mut tx, order_id, EventKind::NoteMention, &detail, author)
+                    .await?;
+            }
+
+            mentions.push(Mention { username, known });
+        }
+
+        tx.commit().await?;
+
+        Ok(Self {
+            id: note.id,
+            order_id,
+            author: author.map(str::to_string),
+            body: body.to_string(),
+            created_at: note.created_at,
+            mentions,
+        })
+    }
+
+    /// Saves a note brought over from another instance with the time it was
+    /// written and the mentions found then, see `graphs.rs`. Nobody is
+    /// notified again.
+    pub async fn import_in(
+        tx: &mut Transaction<'_, Sqlite>,
+        order_id: i64,
+        author: Option<&str>,
+        body: &str,
+        created_at: &str,
+        mentions: &[Mention],
+    ) -> Result<()> {
+        let note_id = sqlx::query_scalar!(
+            r#"INSERT INTO order_notes (order_id, author, body, created_at) VALUES (?, ?, ?, ?)
+            RETURNING id as "id!: i64";"#,
+            order_id,
+            author,
+            body,
+            created_at
+        )
+        .fetch_one(&mut **tx)
+        .timed()
+        .await?;
+
+        for mention in mentions {
+            sqlx::query!(
+                "INSERT INTO note_mentions (note_id, username, known) VALUES (?, ?, ?);",
+                note_id,
+                mention.username,
+                mention.known
+            )
+            .execute(&mut **tx)
+            .timed()
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// The order's notes with their mentions, oldest first.
+    pub async fn get_by_order_id(db: &Db, order_id: i64) -> Result<Vec<Self>> {
+        let notes = db::read(|| {
+            sqlx::query!(
+                r#"select id as "id!: i64", author, body, created_at from order_notes
+                where order_id = ? order by id"#,
+                order_id
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?;
+
+        let mentions = db::read(|| {
+            sqlx::query!(
+                r#"select m.note_id, m.username, m.known as "known: bool" from note_mentions m
+                join order_notes n on n.id = m.note_id
+                where n.order_id = ? order by m.id"#,
+                order_id
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?;
+
+        Ok(notes
+            .into_iter()
+            .map(|note| Self {
+                id: note.id,
+                order_id,
+                author: note.author,
+                body: note.body,
+                created_at: note.created_at,
+                mentions: mentions
+                    .iter()
+                    .filter(|mention| mention.note_id == note.id)
+                    .map(|mention| Mention {
+                        username: mention.username.clone(),
+                        known: mention.known,
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, orders::Order};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_mentions() {
+        assert_eq!(
+            parse_mentions("@alice please check payment, cc @Bob.Smith."),
+            vec!["alice", "bob.smith"]
+        );
+        assert_eq!(parse_mentions("@alice and @ALICE again"), vec!["alice"]);
+        assert_eq!(parse_mentions("(@carol) @dan-"), vec!["carol", "dan"]);
+
+        for none in ["billing@example.com", "@ alone", "@@", "no mentions"] {
+            assert!(parse_mentions(none).is_empty(), "{none:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_stores_mentions_and_records_events() {
+        let db = test_db().await;
+        let mut order = Order::new(500);
+        order.save(db.writer()).await.unwrap();
+        let order_id = order.id.unwrap();
+
+        let mentionable = ["alice".to_string(), "bob".to_string()];
+        let note = Note::create(
+            &db,
+            order_id,
+            Some("support"),
+            "@alice please check payment with @bob, @mallory FYI",
+            &mentionable,
+        )
+        .await
+        .unwrap();
+
+        let known = |username: &str, known| Mention {
+            username: username.to_string(),
+            known,
+        };
+        assert_eq!(
+            note.mentions,
+            vec![
+                known("alice", true),
+                known("bob", true),
+                known("mallory", false)
+            ]
+        );
+        assert_eq!(
+            Note::get_by_order_id(&db, order_id).await.unwrap(),
+            vec![note]
+        );
+
+        let events = OrderEvent::get_by_order_id(&db, order_id).await.unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert!(
+            events
+                .iter()
+                .all(|event| event.kind == EventKind::NoteMention)
+        );
+        assert!(
+            events[0]
+                .detail
+                .starts_with("@alice was mentioned in note #1: ")
+        );
+        assert!(events[1].detail.starts_with("@bob was mentioned"));
+        assert_eq!(events[0].actor.as_deref(), Some("support"));
+
+        // with no list to check against every mention is known
+        let note = Note::create(&db, order_id, None, "@mallory", &[])
+            .await
+            .unwrap();
+        assert_eq!(note.mentions, vec![known("mallory", true)]);
+    }
+}
@@ -0,0 +1,296 @@
+//! The OpenAPI document served at `/openapi.json` and written by
+//! `export-openapi`. It's built from `OPERATIONS`, the request body schemas
+//! in `schemas`, the media types in `media_types` and the examples in
+//! `examples`, none of which need a running app, so clients can be generated
+//! without starting the server.
+
+use serde_json::{Map, Value, json};
+
+use crate::{
+    error::{CODES, CustomError},
+    examples, media_types,
+    negotiate::JSON,
+    routes,
+    schemas,
+};
+
+/// The request body schemas, by the name `/meta/schemas/{name}` uses.
+const SCHEMAS: &[&str] = &["order", "order-status", "order-item", "order-note"];
+
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    /// The name of the request body's schema in `SCHEMAS`.
+    body: Option<&'static str>,
+}
+
+const fn op(method: &'static str, path: &'static str, summary: &'static str) -> Operation {
+    Operation {
+        method,
+        path,
+        summary,
+        body: None,
+    }
+}
+
+const fn with_body(
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    body: &'static str,
+) -> Operation {
+    Operation {
+        method,
+        path,
+        summary,
+        body: Some(body),
+    }
+}
+
+/// Every route the api serves with every optional feature on, leaving out
+/// the html admin pages.
+const OPERATIONS: &[Operation] = &[
+    op("get", routes::ORDERS, "List orders"),
+    with_body("post", routes::ORDERS, "Create an order", "order"),
+    op("get", routes::ORDER_COUNT, "Count orders"),
+    op("get", routes::ORDER_STATS, "Get order totals for dashboards"),
+    op("post", routes::ORDER_SEARCH, "Search orders"),
+    op("get", routes::PICK_LIST, "List the next orders to pick"),
+    op("get", routes::ORDER_EXPORT, "Export orders as csv"),
+    op("post", routes::ORDER_IMPORT, "Import orders from csv"),
+    op("get", routes::ORDER_GRAPH_EXPORT, "Export orders with their children as JSON Lines"),
+    op("post", routes::ORDER_GRAPH_IMPORT, "Import orders exported with their children"),
+    op("post", routes::ORDER_TRANSACTIONS, "Create and cancel orders in one transaction"),
+    op("get", routes::IMPORT_JOB, "Get an import job"),
+    op("get", routes::ORDER, "Get an order"),
+    op("head", routes::ORDER, "Check an order exists"),
+    with_body("patch", routes::ORDER, "Update an order", "order-status"),
+    op("delete", routes::ORDER, "Delete an order"),
+    op("get", routes::ORDER_ITEMS, "List an order's items"),
+    with_body(
+        "post",
+        routes::ORDER_ITEMS,
+        "Add an item to an order",
+        "order-item",
+    ),
+    op("patch", routes::ORDER_ITEM, "Change an item's quantity"),
+    op("get", routes::ORDER_EVENTS, "List an order's events"),
+    op("get", routes::ORDER_AS_OF, "Read an order as it was at a time"),
+    op("get", routes::ORDER_DURATIONS, "Get the time an order has spent in each status"),
+    op("post", routes::ORDER_PREVIEW_TOTALS, "Price an order with hypothetical changes, writing nothing"),
+    op("get", routes::ORDER_NOTES, "List an order's notes"),
+    with_body("post", routes::ORDER_NOTES, "Add a note to an order", "order-note"),
+    op(
+        "post",
+        routes::ORDER_MERGE,
+        "Merge another order into an order",
+    ),
+    op("post", routes::ORDER_REOPEN, "Reopen a canceled order"),
+    op("post", routes::ORDER_CONFIRM, "Confirm a draft"),
+    op("get", routes::EVENT_STREAM, "Stream order events"),
+    op("get", routes::PRODUCTS, "List products"),
+    op("post", routes::PRODUCTS, "Create a product"),
+    op("get", routes::PRODUCT, "Get a product"),
+    op("patch", routes::PRODUCT, "Update a product"),
+    op("delete", routes::PRODUCT, "Delete a product"),
+    op("get", routes::WEBHOOKS, "List webhooks"),
+    op("post", routes::WEBHOOKS, "Register a webhook"),
+    op("patch", routes::WEBHOOK, "Update a webhook"),
+    op("delete", routes::WEBHOOK, "Delete a webhook"),
+    op("get", routes::USAGE, "Get usage per api key"),
+    op("get", routes::REQUEST_LOG, "Search the request log"),
+    op("get", routes::REQUEST_STATS, "Get latency percentiles per api key"),
+    op("put", routes::QUOTA, "Set an api key's quota"),
+    op("get", routes::API_KEYS, "List api keys"),
+    op("post", routes::API_KEYS, "Create an api key"),
+    op("post", routes::API_KEY_REVOKE, "Revoke an api key"),
+    op("post", routes::API_KEY_ROTATE, "Rotate an api key's secret"),
+    op("get", routes::SEQUENCE, "Get a sequence's last value"),
+    op("put", routes::SEQUENCE, "Reset a sequence"),
+    op("get", routes::GROWTH, "Get order growth"),
+    op("get", routes::RUNTIME, "Get runtime stats"),
+    op("get", routes::ERROR_STATS, "Get the error rate windows"),
+    op("get", routes::MAINTENANCE_STATS, "Get the wal checkpoint and vacuum runs"),
+    op("get", routes::CACHES, "List the caches with their hits, misses and last invalidation"),
+    op(
+        "get",
+        routes::CONSISTENCY_CHECK,
+        "Run the consistency checks",
+    ),
+    op("get", routes::AUDIT_VERIFY, "Verify the audit log's hash chain"),
+    op("post", routes::RECOMPUTE, "Correct an order's derived fields"),
+    op("post", routes::RETAG, "Add and remove tags on the orders matching a filter"),
+    op("post", routes::BACKUP, "Back up the database"),
+    op("post", routes::CONFIG_RELOAD, "Reload the configuration"),
+    op("post", routes::EVENT_REPLAY, "Replay order events to a webhook"),
+    op("get", routes::REPLAY, "Get a replay's progress"),
+    op("get", routes::DAILY_REPORT, "Get a day's totals"),
+    op("get", routes::SLA_REPORT, "Get percentiles of the time orders spent in each status"),
+    op("get", routes::SCHEMA, "Get a request body schema"),
+    op("get", routes::CONFIG, "Get the effective configuration"),
+    op("get", routes::ERROR_CODES, "List the error codes"),
+    op("get", routes::OPENAPI, "Get this document"),
+    op("get", routes::METRICS, "Get metrics"),
+    op("get", routes::HEALTHZ, "Check the process is up"),
+    op("get", routes::READYZ, "Check the node should get traffic"),
+];
+
+/// The OpenAPI 3.1 document.
+pub fn spec() -> Value {
+    let media_types = media_types();
+    let errors = error_content();
+    let mut paths = Map::new();
+
+    for operation in OPERATIONS {
+        let examples = examples::get(operation.method, operation.path);
+
+        let mut spec = json!({
+            "summary": operation.summary,
+            "responses": {
+                "2XX": { "description": "Success" },
+                "default": {
+                    "description": "An error, see `/meta/error-codes`",
+                    "content": errors,
+                },
+            },
+        });
+
+        if operation.method != "head" {
+            let example = examples.and_then(|examples| examples.response).map(|build| build());
+
+            let content: Map<String, Value> = media_types
+                .produced_by(operation.path)
+                .iter()
+                .map(|media_type| {
+                    let content = match &example {
+                        Some(example) => json!({ "example": example }),
+                        None => json!({}),
+                    };
+
+                    (media_type.to_string(), content)
+                })
+                .collect();
+
+            spec["responses"]["2XX"]["content"] = content.into();
+        }
+
+        let parameters: Vec<Value> = path_parameters(operation.path)
+            .map(|name| {
+                json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } })
+            })
+            .collect();
+
+        if !parameters.is_empty() {
+            spec["parameters"] = parameters.into();
+        }
+
+        let request = examples.and_then(|examples| examples.request);
+
+        if operation.body.is_some() || request.is_some() {
+            let mut content = json!({});
+
+            if let Some(body) = operation.body {
+                content["schema"] = json!({ "$ref": format!("#/components/schemas/{body}") });
+            }
+
+            if let Some((_, build)) = request {
+                content["example"] = build();
+            }
+
+            let media_type = request.map_or(JSON, |(media_type, _)| media_type);
+
+            spec["requestBody"] = json!({
+                "required": true,
+                "content": { media_type: content },
+            });
+        }
+
+        let path = paths
+            .entry(operation.path)
+            .or_insert_with(|| Value::Object(Map::new()));
+        path[operation.method] = spec;
+    }
+
+    let mut components: Map<String, Value> = SCHEMAS
+        .iter()
+        .filter_map(|name| {
+            let mut schema = schemas::get(name)?;
+            // the document's dialect applies
+            schema.as_object_mut()?.remove("$schema");
+
+            Some((name.to_string(), schema))
+        })
+        .collect();
+    components.insert("error".to_string(), error_schema());
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "sp-exercise orders api",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": components },
+    })
+}
+
+/// The json error bodies. Each has its code and message, some say more.
+fn error_schema() -> Value {
+    let codes: Vec<&str> = CODES.iter().map(|(code, ..)| *code).collect();
+
+    json!({
+        "type": "object",
+        "required": ["error"],
+        "properties": {
+            "error": {
+                "type": "object",
+                "required": ["code", "message"],
+                "properties": {
+                    "code": { "type": "string", "enum": codes },
+                    "message": { "type": "string" },
+                },
+            },
+        },
+    })
+}
+
+/// The error response's content, an example of every code.
+fn error_content() -> Value {
+    let mut examples = Map::new();
+
+    for error in CustomError::examples() {
+        let code = error.code();
+        let summary = CODES
+            .iter()
+            .find(|(listed, ..)| *listed == code)
+            .map(|(_, status, description)| format!("{status}, {description}"));
+
+        examples.insert(code.to_string(), json!({ "summary": summary, "value": error.body() }));
+    }
+
+    json!({
+        JSON: { "schema": { "$ref": "#/components/schemas/error" }, "examples": examples },
+    })
+}
+
+/// `spec` as it's served and exported.
+pub fn document() -> String {
+    serde_json::to_string_pretty(&spec()).expect("the spec is plain json")
+}
+
+/// The `{name}` segments of a route's path.
+fn path_parameters(path: &'static str) -> impl Iterator<Item = &'static str> {
+    path.split('/').filter_map(|segment| {
+        segment
+            .strip_prefix('{')
+            .and_then(|segment| segment.strip_suffix('}'))
+    })
+}
+
+#[cfg(test)]
+pub fn operations() -> impl Iterator<Item = (&'static str, &'static str)> {
+    OPERATIONS
+        .iter()
+        .map(|operation| (operation.method, operation.path))
+}
@@ -1,19 +1,179 @@
-use std::{fmt::Display, result, str::FromStr};
+#[cfg(test)]
+use std::cell::RefCell;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, de};
+use serde_json::{Map, Value, json};
+use sqlx::{Acquire, Encode, QueryBuilder, Sqlite, SqliteConnection, Transaction, prelude::FromRow};
 
-use anyhow::{Result, bail};
-use serde::{Deserialize, Serialize};
-use sqlx::{Encode, prelude::FromRow};
+use crate::{
+    address::ShippingAddress,
+    clock,
+    config::OpenOrderLimits,
+    db::{self, Db},
+    deadline,
+    deprecations::{self, Behavior},
+    events::{EventKind, OrderEvent},
+    metadata::{self, Metadata},
+    money::Currency,
+    projections,
+    quotas::{self, Metered},
+    search::OrderSearch,
+    sla,
+    tags::Tags,
+    timing::Timed,
+};
 
-use crate::db::Db;
+pub mod bulk;
+pub mod history;
+pub mod pricing;
+pub mod recompute;
+pub mod retag;
 
-#[derive(Debug, Serialize, Deserialize, FromRow, Default)]
+/// Orders moved per transaction by `Order::archive_before`.
+pub const ARCHIVE_BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, Default)]
 pub struct Order {
     pub id: Option<i64>,
     pub amount: i64,
+    #[sqlx(try_from = "String")]
     pub status: OrderStatus,
+    pub updated_at: Option<String>,
+    /// When the sweeper sent a pending reminder, at most once per order.
+    pub reminder_sent_at: Option<String>,
+    #[serde(default)]
+    #[sqlx(json)]
+    pub metadata: Metadata,
+    /// Who the order is for, open orders are limited per customer.
+    pub customer_id: Option<String>,
+    /// The api key that created the order, set by `create`.
+    #[serde(skip)]
+    pub api_key: Option<String>,
+    pub created_at: Option<String>,
+    /// When the order became complete, set by `save`.
+    pub completed_at: Option<String>,
+    /// Fixed when the order's created, its items are in the same one.
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub currency: Currency,
+    /// The worker picking the order, see `Order::pick_list`.
+    pub assigned_to: Option<String>,
+    pub assigned_at: Option<String>,
+    /// Where the order ships to, read and set through `shipping_address`.
+    pub shipping_name: Option<String>,
+    pub shipping_line1: Option<String>,
+    pub shipping_line2: Option<String>,
+    pub shipping_city: Option<String>,
+    pub shipping_region: Option<String>,
+    pub shipping_postal_code: Option<String>,
+    pub shipping_country: Option<String>,
+    /// Set in bulk, see `orders/retag.rs`.
+    #[serde(default)]
+    #[sqlx(json)]
+    pub tags: Tags,
+}
+
+/// `find`'s conditions, after a `where` the query already has. Metadata keys
+/// go into the sql as they are, so they're checked with `check_key` first.
+fn push_find_filters<'a>(
+    query: &mut QueryBuilder<'a, Sqlite>,
+    status: Option<&OrderStatus>,
+    country: Option<&'a str>,
+    metadata: &'a BTreeMap<String, String>,
+    include_drafts: bool,
+) {
+    if let Some(status) = status {
+        query.push(" and status = ").push_bind(status.to_string());
+    }
+
+    if let Some(country) = country {
+        query.push(" and shipping_country = ").push_bind(country);
+    }
+
+    if !include_drafts {
+        query.push(" and status != 'draft'");
+    }
+
+    for (key, value) in metadata {
+        query
+            .push(format_args!(" and json_extract(metadata, '$.{key}') = "))
+            .push_bind(value);
+    }
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    /// Names of the order lookups run in the current scope, so tests can tell
+    /// which path a handler took.
+    pub static QUERY_LOG: RefCell<Vec<&'static str>>;
+}
+
+fn log_query(_name: &'static str) {
+    #[cfg(test)]
+    let _ = QUERY_LOG.try_with(|log| log.borrow_mut().push(_name));
+}
+
+/// A slice of a list, by id, for `Order::find_page`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// How `Order::find` orders what it returns, `?sort=` on `GET /orders`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSort {
+    #[default]
+    Id,
+    /// Longest in their current status first, then by id, see
+    /// `sla::push_seconds_in_status`.
+    TimeInStatus,
+}
+
+/// An order with when it entered the status it's in, read in the same
+/// statement, see `sla::STATUS_SINCE`.
+#[derive(Debug, Clone, FromRow)]
+pub struct InStatus {
+    #[sqlx(flatten)]
+    pub order: Order,
+    /// `None` for archived orders.
+    pub status_since: Option<String>,
+}
+
+/// The order `find_in` lists in and the slice of it.
+#[derive(Debug, Clone, Copy, Default)]
+struct Listing {
+    sort: OrderSort,
+    page: Option<Page>,
+}
+
+/// Enough to answer a `HEAD` for an order without reading the rest of the row.
+#[derive(Debug)]
+pub struct Existence {
+    pub id: i64,
+    /// Milliseconds since the unix epoch.
+    pub modified_ms: i64,
+}
+
+impl Existence {
+    pub fn etag(&self) -> String {
+        format!("\"{}-{}\"", self.id, self.modified_ms)
+    }
+
+    pub fn last_modified(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(self.modified_ms.max(0) as u64)
+    }
 }
 
 impl Order {
+    #[cfg(test)]
     pub fn new(amount: i64) -> Self {
         Self {
             amount,
@@ -21,103 +181,1203 @@ impl Order {
         }
     }
 
-    pub async fn save(&mut self, db: &Db) -> Result<()> {
+    /// The order's `ETag`, the one `exists` reads from the table, worked out
+    /// from the row in hand.
+    pub fn etag(&self) -> String {
+        let modified_ms = self
+            .updated_at
+            .as_deref()
+            .and_then(deadline::parse_rfc3339)
+            .and_then(|at| at.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_millis() as i64);
+
+        Existence {
+            id: self.id.unwrap_or_default(),
+            modified_ms,
+        }
+        .etag()
+    }
+
+    pub fn shipping_address(&self) -> Option<ShippingAddress> {
+        ShippingAddress::from_columns([
+            self.shipping_name.clone(),
+            self.shipping_line1.clone(),
+            self.shipping_line2.clone(),
+            self.shipping_city.clone(),
+            self.shipping_region.clone(),
+            self.shipping_postal_code.clone(),
+            self.shipping_country.clone(),
+        ])
+    }
+
+    pub fn set_shipping_address(&mut self, address: Option<&ShippingAddress>) {
+        [
+            self.shipping_name,
+            self.shipping_line1,
+            self.shipping_line2,
+            self.shipping_city,
+            self.shipping_region,
+            self.shipping_postal_code,
+            self.shipping_country,
+        ] = ShippingAddress::columns(address);
+    }
+
+    /// Inserts or updates the order and its summary, in a transaction of its
+    /// own or nested in the one `db` is in.
+    pub async fn save<'a, A>(&mut self, db: A) -> Result<()>
+    where
+        A: Acquire<'a, Database = Sqlite>,
+    {
         let status = &self.status.to_string();
+        let metadata = &self.metadata.to_string();
+        let currency = self.currency.as_str();
+        let now = clock::now();
+        let mut tx = db.begin().await?;
 
         match self.id {
             None => {
                 let result = sqlx::query!(
-                    "INSERT INTO orders (status, amount) VALUES (?, ?);",
+                    "INSERT INTO orders (status, amount, metadata, customer_id, api_key, currency,
+                        updated_at, created_at, completed_at, shipping_name, shipping_line1,
+                        shipping_line2, shipping_city, shipping_region, shipping_postal_code,
+                        shipping_country)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, case when ?1 = 'complete' then ?7 end,
+                        ?8, ?9, ?10, ?11, ?12, ?13, ?14);",
                     status,
-                    self.amount
+                    self.amount,
+                    metadata,
+                    self.customer_id,
+                    self.api_key,
+                    currency,
+                    now,
+                    self.shipping_name,
+                    self.shipping_line1,
+                    self.shipping_line2,
+                    self.shipping_city,
+                    self.shipping_region,
+                    self.shipping_postal_code,
+                    self.shipping_country
                 )
-                .execute(db)
+                .execute(&mut *tx)
+                .timed()
                 .await?;
 
                 self.id = Some(result.last_insert_rowid());
+                self.created_at = Some(now.clone());
             }
             Some(id) => {
+                let previous = sqlx::query!(
+                    "select status, amount, metadata from orders where id = ?",
+                    id
+                )
+                .fetch_optional(&mut *tx)
+                .timed()
+                .await?;
+
                 sqlx::query!(
-                    "update orders set status = ?, amount = ? where id = ?;",
+                    "update orders set status = ?1, amount = ?2, metadata = ?3,
+                    updated_at = ?5,
+                    completed_at = case when ?1 = 'complete' then coalesce(completed_at, ?5) end,
+                    shipping_name = ?6, shipping_line1 = ?7, shipping_line2 = ?8,
+                    shipping_city = ?9, shipping_region = ?10, shipping_postal_code = ?11,
+                    shipping_country = ?12
+                    where id = ?4;",
                     status,
                     self.amount,
-                    id
-                ).execute(db).await?;
+                    metadata,
+                    id,
+                    now,
+                    self.shipping_name,
+                    self.shipping_line1,
+                    self.shipping_line2,
+                    self.shipping_city,
+                    self.shipping_region,
+                    self.shipping_postal_code,
+                    self.shipping_country
+                ).execute(&mut *tx).timed().await?;
+
+                if let Some(previous) = previous {
+                    let before = Map::from_iter([
+                        ("status".to_string(), json!(OrderStatus::from(previous.status))),
+                        ("amount".to_string(), json!(previous.amount)),
+                        ("metadata".to_string(), serde_json::from_str(&previous.metadata)?),
+                    ]);
+                    let after = Map::from_iter([
+                        ("status".to_string(), json!(self.status)),
+                        ("amount".to_string(), json!(self.amount)),
+                        ("metadata".to_string(), serde_json::to_value(&self.metadata)?),
+                    ]);
+
+                    for (field, new) in history::diff(&before, &after) {
+                        history::record(&mut tx, id, &field, before.get(&field), Some(&new)).await?;
+                    }
+                }
             }
         }
 
+        // what the statements set, so the order in hand matches the row
+        self.completed_at = match self.status {
+            OrderStatus::Complete => Some(self.completed_at.take().unwrap_or_else(|| now.clone())),
+            _ => None,
+        };
+        self.updated_at = Some(now);
+
+        let id = self.id.expect("the order was just saved");
+        projections::update_summary(&mut tx, id).await?;
+
+        tx.commit().await?;
+
         Ok(())
     }
 
+    /// Saves a new order in one transaction with its side records: the create is
+    /// counted against the api key's monthly quota and the idempotency key is
+    /// remembered. Nothing is written when the customer already has too many
+    /// open orders or the quota is used up.
+    pub async fn create(&mut self, db: &Db, creation: Creation<'_>) -> Result<Created> {
+        let mut tx = db.writer().begin().await?;
+
+        let created = self.create_in(&mut tx, creation).await?;
+
+        if let Created::Saved(_) = created {
+            tx.commit().await?;
+        }
+
+        Ok(created)
+    }
+
+    /// `create` in the caller's transaction, which mustn't commit unless this
+    /// returns `Created::Saved`: a refused create may have counted against
+    /// the quota.
+    pub async fn create_in(&mut self, tx: &mut Transaction<'_, Sqlite>, creation: Creation<'_>) -> Result<Created> {
+        self.api_key = creation.api_key.map(str::to_string);
+
+        // counted in the transaction so concurrent creates can't both squeeze in
+        if let Some(limit) = self.open_order_limit(creation.limits) {
+            let open = self.count_open(tx).await?;
+
+            if open >= limit {
+                return Ok(Created::OpenOrderLimit { open, limit });
+            }
+        }
+
+        let metered = match creation.api_key {
+            Some(api_key) => {
+                let metered = quotas::record_create(tx, api_key).await?;
+
+                if metered.exceeded() {
+                    return Ok(Created::QuotaExceeded(metered));
+                }
+
+                Some(metered)
+            }
+            None => None,
+        };
+
+        self.save(&mut **tx).await?;
+
+        if let Some(key) = creation.idempotency_key {
+            sqlx::query!(
+                "INSERT INTO idempotency_keys (key, order_id) VALUES (?, ?);",
+                key,
+                self.id
+            )
+            .execute(&mut **tx)
+            .timed()
+            .await?;
+        }
+
+        Ok(Created::Saved(metered))
+    }
+
+    /// The limit on open orders that applies to this one: its customer's, or
+    /// its api key's when it has no customer. `None` when it's exempt.
+    fn open_order_limit(&self, limits: OpenOrderLimits) -> Option<i64> {
+        match (&self.customer_id, &self.api_key) {
+            (Some(_), _) => limits.per_customer,
+            (None, Some(_)) => limits.per_anonymous_key,
+            (None, None) => None,
+        }
+    }
+
+    /// Open orders sharing this order's customer, or its api key when it has
+    /// no customer.
+    async fn count_open(&self, tx: &mut Transaction<'_, Sqlite>) -> Result<i64> {
+        let count = match &self.customer_id {
+            Some(customer_id) => sqlx::query_scalar!(
+                r#"select count(*) as "count!: i64" from orders
+                where customer_id = ? and status not in ('complete', 'canceled')"#,
+                customer_id
+            )
+            .fetch_one(&mut **tx)
+            .timed()
+            .await?,
+            None => sqlx::query_scalar!(
+                r#"select count(*) as "count!: i64" from orders
+                where api_key = ? and customer_id is null
+                and status not in ('complete', 'canceled')"#,
+                self.api_key
+            )
+            .fetch_one(&mut **tx)
+            .timed()
+            .await?,
+        };
+
+        Ok(count)
+    }
+
+    /// Same as `create`, metered against `api_key`.
+    #[cfg(test)]
+    pub async fn save_metered(&mut self, db: &Db, api_key: &str) -> Result<Metered> {
+        let creation = Creation {
+            api_key: Some(api_key),
+            ..Default::default()
+        };
+
+        match self.create(db, creation).await? {
+            Created::Saved(Some(metered)) | Created::QuotaExceeded(metered) => Ok(metered),
+            created => panic!("metered when an api key is given, got {created:?}"),
+        }
+    }
+
+    pub async fn get_by_idempotency_key(db: &Db, key: &str) -> Result<Option<Self>> {
+        Ok(sqlx::query_as!(
+            Order,
+            "select o.* from orders o
+            join idempotency_keys k on k.order_id = o.id
+            where k.key = ?",
+            key
+        )
+        .fetch_optional(db.reader())
+        .timed()
+        .await?)
+    }
+
+    /// `get_by_id` in a transaction, seeing what it has written.
+    pub async fn get_by_id_in(tx: &mut Transaction<'_, Sqlite>, id: i64) -> Result<Option<Self>> {
+        Ok(sqlx::query_as!(Order, "select * from orders where id = ?", id)
+            .fetch_optional(&mut **tx)
+            .timed()
+            .await?)
+    }
+
     pub async fn get_by_id(db: &Db, id: i64) -> Result<Option<Self>> {
-        Ok(
+        log_query("get_by_id");
+
+        Ok(db::read(|| {
             sqlx::query_as!(Order, "select * from orders where id = ?", id)
-                .fetch_optional(db)
-                .await?,
-        )
+                .fetch_optional(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+
+    /// `get_by_id` with when the order entered its status, for the detail.
+    pub async fn get_in_status(db: &Db, id: i64) -> Result<Option<InStatus>> {
+        log_query("get_by_id");
+
+        let query = format!(
+            "select *, {} as status_since from orders where id = ?",
+            sla::STATUS_SINCE
+        );
+
+        Ok(db::read(|| {
+            sqlx::query_as(&query)
+                .bind(id)
+                .fetch_optional(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+
+    /// Checks an order exists without fetching it, for `HEAD` requests.
+    pub async fn exists(db: &Db, id: i64) -> Result<Option<Existence>> {
+        log_query("exists");
+
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                Existence,
+                r#"select id as "id!: i64",
+                cast(round((julianday(coalesce(updated_at, '1970-01-01')) - 2440587.5) * 86400000) as integer)
+                    as "modified_ms!: i64"
+                from orders where id = ?"#,
+                id
+            )
+            .fetch_optional(db.reader())
+            .timed()
+        })
+        .await?)
     }
 
     pub async fn get_all(db: &Db) -> Result<Vec<Self>> {
-        Ok(sqlx::query_as!(Order, "select * from orders")
-            .fetch_all(db)
+        Ok(db::read(|| {
+            sqlx::query_as!(Order, "select * from orders")
+                .fetch_all(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+
+    /// Orders with the given status, shipping country and string metadata
+    /// values, any of them can be left empty, with when they entered their
+    /// status. Metadata keys are written into the json path rather than bound
+    /// so the expression indexes on common keys apply. Drafts are left out
+    /// unless `include_drafts` is set.
+    pub async fn find(
+        db: &Db,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+        include_drafts: bool,
+        sort: OrderSort,
+    ) -> Result<Vec<InStatus>> {
+        let listing = Listing { sort, page: None };
+
+        Self::find_in(db, "orders", status, country, metadata, include_drafts, listing).await
+    }
+
+    /// `page` of what `find` returns, in the order `sort` asks for.
+    pub async fn find_page(
+        db: &Db,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+        include_drafts: bool,
+        sort: OrderSort,
+        page: Page,
+    ) -> Result<Vec<InStatus>> {
+        let listing = Listing { sort, page: Some(page) };
+
+        Self::find_in(db, "orders", status, country, metadata, include_drafts, listing).await
+    }
+
+    /// Same as `find`, over the archived orders.
+    pub async fn find_archived(
+        db: &Db,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<Vec<Self>> {
+        let archived =
+            Self::find_in(db, "orders_archive", status, country, metadata, true, Listing::default()).await?;
+
+        Ok(archived.into_iter().map(|archived| archived.order).collect())
+    }
+
+    /// `page` of what `find_archived` returns, by id.
+    pub async fn find_archived_page(
+        db: &Db,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+        page: Page,
+    ) -> Result<Vec<Self>> {
+        let listing = Listing {
+            page: Some(page),
+            ..Default::default()
+        };
+
+        let archived = Self::find_in(db, "orders_archive", status, country, metadata, true, listing).await?;
+
+        Ok(archived.into_iter().map(|archived| archived.order).collect())
+    }
+
+    /// How many orders `find` returns, for the total of a page.
+    pub async fn count_found(
+        db: &Db,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+        include_drafts: bool,
+    ) -> Result<i64> {
+        Self::count_in(db, "orders", status, country, metadata, include_drafts).await
+    }
+
+    /// How many orders `find_archived` returns.
+    pub async fn count_found_archived(
+        db: &Db,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+    ) -> Result<i64> {
+        Self::count_in(db, "orders_archive", status, country, metadata, true).await
+    }
+
+    async fn find_in(
+        db: &Db,
+        table: &'static str,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+        include_drafts: bool,
+        listing: Listing,
+    ) -> Result<Vec<InStatus>> {
+        for key in metadata.keys() {
+            metadata::check_key(key)?;
+        }
+
+        // `STATUS_SINCE` is over `orders`, archived orders are only listed by id
+        let live = table == "orders";
+        let status_since = if live { sla::STATUS_SINCE } else { "null" };
+        // the same time for every row and every retry, like the responses use
+        let now = clock::now();
+
+        Ok(db::read(|| async {
+            let mut query = QueryBuilder::<Sqlite>::new(format!(
+                "select *, {status_since} as status_since from {table} where 1 = 1"
+            ));
+
+            push_find_filters(&mut query, status, country, metadata, include_drafts);
+
+            if listing.sort == OrderSort::TimeInStatus && live {
+                query.push(" order by ");
+                sla::push_seconds_in_status(&mut query, now.clone());
+                query.push(" desc, id");
+            } else if listing.page.is_some() {
+                query.push(" order by id");
+            }
+
+            if let Some(page) = listing.page {
+                query
+                    .push(" limit ")
+                    .push_bind(page.limit)
+                    .push(" offset ")
+                    .push_bind(page.offset);
+            }
+
+            query
+                .build_query_as::<InStatus>()
+                .fetch_all(db.reader())
+                .timed()
+                .await
+        })
+        .await?)
+    }
+
+    async fn count_in(
+        db: &Db,
+        table: &'static str,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+        include_drafts: bool,
+    ) -> Result<i64> {
+        for key in metadata.keys() {
+            metadata::check_key(key)?;
+        }
+
+        Ok(db::read(|| async {
+            let mut query =
+                QueryBuilder::<Sqlite>::new(format!("select count(*) from {table} where 1 = 1"));
+
+            push_find_filters(&mut query, status, country, metadata, include_drafts);
+
+            query
+                .build_query_scalar::<i64>()
+                .fetch_one(db.reader())
+                .timed()
+                .await
+        })
+        .await?)
+    }
+
+    /// The ids of the orders `find` returns, by id, for changes made to all
+    /// of them like a retag.
+    pub async fn find_ids(
+        db: &Db,
+        status: Option<&OrderStatus>,
+        country: Option<&str>,
+        metadata: &BTreeMap<String, String>,
+        include_drafts: bool,
+    ) -> Result<Vec<i64>> {
+        for key in metadata.keys() {
+            metadata::check_key(key)?;
+        }
+
+        Ok(db::read(|| async {
+            let mut query = QueryBuilder::<Sqlite>::new("select id from orders where 1 = 1");
+
+            push_find_filters(&mut query, status, country, metadata, include_drafts);
+            query.push(" order by id");
+
+            query
+                .build_query_scalar::<i64>()
+                .fetch_all(db.reader())
+                .timed()
+                .await
+        })
+        .await?)
+    }
+
+    /// Orders matching a search, oldest first.
+    pub async fn search(db: &Db, search: &OrderSearch) -> Result<Vec<Self>> {
+        Ok(db::read(|| {
+            db::interruptible(db, async |conn| {
+                let mut query = QueryBuilder::<Sqlite>::new("select * from orders where 1 = 1");
+
+                search.push_filters(&mut query);
+                query.push(" order by id");
+
+                query
+                    .build_query_as::<Order>()
+                    .fetch_all(conn)
+                    .timed()
+                    .await
+            })
+        })
+        .await?)
+    }
+
+    /// Up to `limit` orders after the id `after`, by id, for an export to read
+    /// a page at a time on its `Interruptible` connection. Drafts are left out
+    /// like in `find`.
+    pub async fn export_page(
+        conn: &mut SqliteConnection,
+        status: Option<&OrderStatus>,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let mut query = QueryBuilder::<Sqlite>::new("select * from orders where id > ");
+        query.push_bind(after).push(" and status != 'draft'");
+
+        if let Some(status) = status {
+            query.push(" and status = ").push_bind(status.to_string());
+        }
+
+        query.push(" order by id limit ").push_bind(limit);
+
+        Ok(query
+            .build_query_as::<Order>()
+            .fetch_all(conn)
+            .timed()
             .await?)
     }
 
+    /// Moves `source`'s items and amount onto `self` and cancels `source`, with
+    /// a history event on each, all in one transaction. Callers check both
+    /// orders can be merged first, and read them again when this returns false:
+    /// one of them changed since, and nothing was merged.
+    pub async fn merge_into(&mut self, db: &Db, source: &mut Order) -> Result<bool> {
+        let (Some(target_id), Some(source_id)) = (self.id, source.id) else {
+            anyhow::bail!("only saved orders can be merged");
+        };
+
+        // an order's currency never changes, so there's nothing to recheck in
+        // the transaction
+        if self.currency != source.currency {
+            anyhow::bail!(
+                "order #{source_id} is in {} and can't be merged into #{target_id} in {}",
+                source.currency,
+                self.currency
+            );
+        }
+
+        let mut tx = db.writer().begin().await?;
+
+        if !Self::claim(&mut tx, self).await? || !Self::claim(&mut tx, source).await? {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            "update order_items set order_id = ? where order_id = ?;",
+            target_id,
+            source_id
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        self.amount += source.amount;
+        self.save(&mut *tx).await?;
+
+        source.status = OrderStatus::Canceled;
+        source.save(&mut *tx).await?;
+
+        OrderEvent::record(
+            &mut tx,
+            target_id,
+            EventKind::Merged,
+            &format!("merged #{source_id} into this order"),
+            None,
+        )
+        .await?;
+        OrderEvent::record(
+            &mut tx,
+            source_id,
+            EventKind::MergedInto,
+            &format!("merged into #{target_id}"),
+            None,
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Saves the order after an update, recording a `canceled` event in the
+    /// same transaction when the update canceled it.
+    pub async fn save_with_history(&mut self, db: &Db, previous: OrderStatus) -> Result<()> {
+        let mut tx = db.writer().begin().await?;
+
+        self.record_update(&mut tx, previous, None).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// `save_with_history` for an update of `read`, the order as the caller
+    /// read it. Returns false without saving when another request changed the
+    /// order since, so the caller can read it again and redo its change instead
+    /// of writing back a stale amount or status.
+    pub async fn save_with_history_if_unchanged(&mut self, db: &Db, read: &Order) -> Result<bool> {
+        let mut tx = db.writer().begin().await?;
+
+        if !self.update_in(&mut tx, read, None).await? {
+            return Ok(false);
+        }
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// `save_with_history_if_unchanged` in the caller's transaction, with the
+    /// `reason` for a cancellation added to its event.
+    pub async fn update_in(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        read: &Order,
+        reason: Option<&str>,
+    ) -> Result<bool> {
+        if !Self::claim(tx, read).await? {
+            return Ok(false);
+        }
+
+        self.record_update(tx, read.status, reason).await?;
+
+        Ok(true)
+    }
+
+    /// Checks the order's status, amount, metadata and shipping address are
+    /// still `read`'s, with a write that changes nothing so the transaction
+    /// holds sqlite's write lock from here on and nothing can change them
+    /// before it commits.
+    async fn claim(tx: &mut Transaction<'_, Sqlite>, read: &Order) -> Result<bool> {
+        let status = read.status.to_string();
+        let metadata = read.metadata.to_string();
+        let address = serde_json::to_string(&[
+            &read.shipping_name,
+            &read.shipping_line1,
+            &read.shipping_line2,
+            &read.shipping_city,
+            &read.shipping_region,
+            &read.shipping_postal_code,
+            &read.shipping_country,
+        ])?;
+
+        let claimed = sqlx::query!(
+            "update orders set id = id
+            where id = ? and status = ? and amount = ? and json(metadata) = json(?)
+            and json_array(shipping_name, shipping_line1, shipping_line2, shipping_city,
+                shipping_region, shipping_postal_code, shipping_country) = json(?);",
+            read.id,
+            status,
+            read.amount,
+            metadata,
+            address
+        )
+        .execute(&mut **tx)
+        .timed()
+        .await?;
+
+        Ok(claimed.rows_affected() == 1)
+    }
+
+    async fn record_update(
+        &mut self,
+        tx: &mut Transaction<'_, Sqlite>,
+        previous: OrderStatus,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        self.save(&mut **tx).await?;
+
+        if self.status == OrderStatus::Canceled && previous != OrderStatus::Canceled {
+            let id = self.id.expect("saved orders have an id");
+
+            let detail = match reason {
+                Some(reason) => format!("canceled from {previous}: {reason}"),
+                None => format!("canceled from {previous}"),
+            };
+
+            OrderEvent::record(tx, id, EventKind::Canceled, &detail, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Turns a draft into a pending order, recording a `confirmed` event. The
+    /// caller checks it's a draft and valid to place.
+    pub async fn confirm(&mut self, db: &Db) -> Result<()> {
+        let id = self.id.expect("saved orders have an id");
+        let mut tx = db.writer().begin().await?;
+
+        self.status = OrderStatus::Pending;
+        self.save(&mut *tx).await?;
+
+        OrderEvent::record(&mut tx, id, EventKind::Confirmed, "draft confirmed", None).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Makes a status change outside the normal lifecycle, recording who made
+    /// it. The caller checks the transition is allowed.
+    pub async fn apply_admin(
+        &mut self,
+        db: &Db,
+        transition: AdminTransition,
+        actor: &str,
+    ) -> Result<()> {
+        let id = self.id.expect("saved orders have an id");
+        let mut tx = db.writer().begin().await?;
+
+        self.status = transition.to();
+        self.save(&mut *tx).await?;
+
+        OrderEvent::record(
+            &mut tx,
+            id,
+            transition.event(),
+            &format!("{} -> {}", transition.from(), transition.to()),
+            Some(actor),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Up to `limit` pending or in progress orders nobody is picking, oldest
+    /// first, the work queue. The status condition is written exactly like
+    /// the `orders_open` index's so the planner walks that instead of every
+    /// order by `created_at`, keep them the same.
+    pub async fn get_open(db: &Db, limit: i64) -> Result<Vec<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                Order,
+                "select * from orders
+                where status in ('pending', 'in-progress') and assigned_to is null
+                order by created_at, id limit ?",
+                limit
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?)
+    }
+
+    /// `get_open`, with a `worker` assigning the orders to them by the same
+    /// statement that picks them, so two workers never get the same order.
+    pub async fn pick_list(db: &Db, limit: i64, worker: Option<&str>) -> Result<Vec<Self>> {
+        let mut orders = match worker {
+            None => return Self::get_open(db, limit).await,
+            Some(worker) => {
+                let now = clock::now();
+
+                sqlx::query_as!(
+                    Order,
+                    "update orders set assigned_to = ?, assigned_at = ?
+                    where id in (
+                        select id from orders
+                        where status in ('pending', 'in-progress') and assigned_to is null
+                        order by created_at, id limit ?
+                    )
+                    returning *",
+                    worker,
+                    now,
+                    limit
+                )
+                .fetch_all(db.writer())
+                .timed()
+                .await?
+            }
+        };
+
+        // returning doesn't keep the subquery's order
+        orders.sort_by(|a, b| (&a.created_at, a.id).cmp(&(&b.created_at, b.id)));
+
+        Ok(orders)
+    }
+
+    /// The newest orders first, for the admin pages.
+    #[cfg(feature = "admin-ui")]
+    pub async fn recent(db: &Db, limit: i64) -> Result<Vec<Self>> {
+        Ok(
+            sqlx::query_as!(Order, "select * from orders order by id desc limit ?", limit)
+                .fetch_all(db.reader())
+                .timed()
+                .await?,
+        )
+    }
+
+    pub async fn get_archived_by_id(db: &Db, id: i64) -> Result<Option<Self>> {
+        Ok(
+            sqlx::query_as!(Order, "select * from orders_archive where id = ?", id)
+                .fetch_optional(db.reader())
+                .timed()
+                .await?,
+        )
+    }
+
+    /// Moves orders completed before `cutoff`, a date or timestamp compared
+    /// against `updated_at`, into the archive tables along with their items
+    /// and events. Works in batches of `ARCHIVE_BATCH_SIZE`, each in its own
+    /// transaction, and returns how many orders were moved.
+    pub async fn archive_before(db: &Db, cutoff: &str) -> Result<u64> {
+        let valid: bool = sqlx::query_scalar("select date(?) is not null")
+            .bind(cutoff)
+            .fetch_one(db.reader())
+            .timed()
+            .await?;
+
+        if !valid {
+            anyhow::bail!("{cutoff:?} is not a date");
+        }
+
+        let mut archived = 0;
+
+        loop {
+            match Self::archive_batch(db, cutoff, ARCHIVE_BATCH_SIZE).await? {
+                0 => return Ok(archived),
+                moved => archived += moved,
+            }
+        }
+    }
+
+    async fn archive_batch(db: &Db, cutoff: &str, batch_size: i64) -> Result<u64> {
+        let mut tx = db.writer().begin().await?;
+
+        let ids = sqlx::query_scalar!(
+            r#"select id as "id!: i64" from orders
+            where status = 'complete' and updated_at < ?
+            order by id limit ?"#,
+            cutoff,
+            batch_size
+        )
+        .fetch_all(&mut *tx)
+        .timed()
+        .await?;
+
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let ids = serde_json::to_string(&ids)?;
+
+        sqlx::query!(
+            "insert into orders_archive (id, status, amount, updated_at, metadata, reminder_sent_at,
+                customer_id, api_key, created_at, completed_at, currency, assigned_to, assigned_at,
+                shipping_name, shipping_line1, shipping_line2, shipping_city, shipping_region,
+                shipping_postal_code, shipping_country, tags)
+            select id, status, amount, updated_at, metadata, reminder_sent_at, customer_id, api_key,
+                created_at, completed_at, currency, assigned_to, assigned_at, shipping_name,
+                shipping_line1, shipping_line2, shipping_city, shipping_region, shipping_postal_code,
+                shipping_country, tags
+            from orders
+            where id in (select value from json_each(?));",
+            ids
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "insert into order_items_archive (id, order_id, sku, description, quantity, unit_price,
+                currency)
+            select id, order_id, sku, description, quantity, unit_price, currency from order_items
+            where order_id in (select value from json_each(?));",
+            ids
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "insert into order_events_archive (id, order_id, kind, detail, created_at, actor)
+            select id, order_id, kind, detail, created_at, actor from order_events
+            where order_id in (select value from json_each(?));",
+            ids
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "insert into order_notes_archive (id, order_id, author, body, created_at)
+            select id, order_id, author, body, created_at from order_notes
+            where order_id in (select value from json_each(?));",
+            ids
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        sqlx::query!(
+            "insert into note_mentions_archive (id, note_id, username, known)
+            select m.id, m.note_id, m.username, m.known from note_mentions m
+            join order_notes n on n.id = m.note_id
+            where n.order_id in (select value from json_each(?));",
+            ids
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        // items, events, notes and idempotency keys go with the order
+        let moved = sqlx::query!(
+            "delete from orders where id in (select value from json_each(?));",
+            ids
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+
+        Ok(moved)
+    }
+
+    /// Deletes the order, keeping its last state in its history so it can
+    /// still be read as it was before.
     pub async fn delete_by_id(db: &Db, id: i64) -> Result<bool> {
-        let result = sqlx::query!("DELETE FROM orders WHERE id = ?", id)
-            .execute(db)
+        let mut tx = db.writer().begin().await?;
+
+        let order = sqlx::query_as!(Order, "select * from orders where id = ?", id)
+            .fetch_optional(&mut *tx)
+            .timed()
             .await?;
-        
-        Ok(result.rows_affected() > 0)
+
+        let Some(order) = order else {
+            return Ok(false);
+        };
+
+        sqlx::query!("DELETE FROM orders WHERE id = ?", id)
+            .execute(&mut *tx)
+            .timed()
+            .await?;
+
+        let last = Value::Object(history::snapshot(&order)?);
+        history::record(&mut tx, id, history::DELETED, Some(&last), None).await?;
+
+        tx.commit().await?;
+
+        Ok(true)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Encode, PartialEq, Eq)]
+/// Who a new order is being created for, see `Order::create`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Creation<'a> {
+    pub api_key: Option<&'a str>,
+    pub idempotency_key: Option<&'a str>,
+    pub limits: OpenOrderLimits,
+}
+
+/// What `Order::create` did.
+#[derive(Debug)]
+pub enum Created {
+    /// Saved, with the quota usage when an api key was given.
+    Saved(Option<Metered>),
+    /// Not saved, the api key's quota is used up.
+    QuotaExceeded(Metered),
+    /// Not saved, the customer, or the api key for an order without one,
+    /// already has `limit` or more open orders.
+    OpenOrderLimit { open: i64, limit: i64 },
+}
+
+#[derive(Debug, Serialize, Encode, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum OrderStatus {
+    #[default]
     Pending,
     InProgress,
     Complete,
     Canceled,
+    /// Reserved but not placed yet, see `POST /orders/{id}/confirm`. Left out
+    /// of lists by default and purged by the sweeper once stale.
+    Draft,
+}
+
+impl OrderStatus {
+    /// The statuses requests can set. Drafts are only created with the
+    /// `draft` flag and left by confirming them.
+    pub const ALL: [OrderStatus; 4] = [
+        OrderStatus::Pending,
+        OrderStatus::InProgress,
+        OrderStatus::Complete,
+        OrderStatus::Canceled,
+    ];
+
+    /// Nothing more happens to an order once it's complete or canceled.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, OrderStatus::Complete | OrderStatus::Canceled)
+    }
+
+    /// The statuses an order in this one can move on to. A draft is left by
+    /// confirming it, and `AdminTransition`s are outside these rules.
+    pub fn next(&self) -> &'static [OrderStatus] {
+        match self {
+            OrderStatus::Pending => &[OrderStatus::InProgress, OrderStatus::Canceled],
+            OrderStatus::InProgress => &[OrderStatus::Complete, OrderStatus::Canceled],
+            OrderStatus::Draft => &[OrderStatus::Pending],
+            OrderStatus::Complete | OrderStatus::Canceled => &[],
+        }
+    }
+
+    /// Whether an order in this status can be set to `next`. Setting the
+    /// status it already has changes nothing and is allowed.
+    pub fn can_transition_to(&self, next: &OrderStatus) -> bool {
+        self == next || self.next().contains(next)
+    }
+
+    /// The status with this json name.
+    fn from_json_name(name: &str) -> Option<Self> {
+        match name {
+            "pending" => Some(OrderStatus::Pending),
+            "inprogress" => Some(OrderStatus::InProgress),
+            "complete" => Some(OrderStatus::Complete),
+            "canceled" => Some(OrderStatus::Canceled),
+            "draft" => Some(OrderStatus::Draft),
+            _ => None,
+        }
+    }
 }
 
-impl Default for OrderStatus {
-    fn default() -> Self {
-        OrderStatus::Pending
+/// Reads the json names, and for now any casing of them with `-`, `_` or
+/// spaces between words, like `In-Progress`, which is recorded as a
+/// deprecated `Behavior::StatusCasing`.
+impl<'de> Deserialize<'de> for OrderStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        const NAMES: &[&str] = &["pending", "inprogress", "complete", "canceled", "draft"];
+
+        let name = String::deserialize(deserializer)?;
+
+        if let Some(status) = Self::from_json_name(&name) {
+            return Ok(status);
+        }
+
+        let loose: String = name
+            .chars()
+            .filter(|char| !matches!(char, '-' | '_' | ' '))
+            .map(|char| char.to_ascii_lowercase())
+            .collect();
+
+        match Self::from_json_name(&loose) {
+            Some(status) => {
+                deprecations::record(Behavior::StatusCasing);
+                Ok(status)
+            }
+            None => Err(de::Error::unknown_variant(&name, NAMES)),
+        }
     }
 }
 
-impl ToString for OrderStatus {
-    fn to_string(&self) -> String {
+/// Status changes that break the normal lifecycle, like leaving a terminal
+/// status. They're only made through their own endpoints, never a `PATCH`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminTransition {
+    /// Canceled back to pending, for a cancellation made by mistake.
+    Reopen,
+}
+
+impl AdminTransition {
+    pub fn from(self) -> OrderStatus {
+        match self {
+            AdminTransition::Reopen => OrderStatus::Canceled,
+        }
+    }
+
+    pub fn to(self) -> OrderStatus {
+        match self {
+            AdminTransition::Reopen => OrderStatus::Pending,
+        }
+    }
+
+    fn event(self) -> EventKind {
         match self {
+            AdminTransition::Reopen => EventKind::Reopened,
+        }
+    }
+}
+
+impl Display for OrderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
             OrderStatus::Pending => "pending",
             OrderStatus::InProgress => "in-progress",
             OrderStatus::Complete => "complete",
             OrderStatus::Canceled => "canceled",
-        }
-        .to_string()
+            OrderStatus::Draft => "draft",
+        };
+
+        write!(f, "{status}")
     }
 }
 
+impl OrderStatus {
+    /// Every status, drafts too, as stored.
+    pub const STORED: [OrderStatus; 5] = [
+        OrderStatus::Pending,
+        OrderStatus::InProgress,
+        OrderStatus::Complete,
+        OrderStatus::Canceled,
+        OrderStatus::Draft,
+    ];
+
+    /// The status stored as `name`, which is its `Display`.
+    pub fn from_stored(name: &str) -> Option<Self> {
+        Self::STORED
+            .into_iter()
+            .find(|status| status.to_string() == name)
+    }
+}
+
+/// Anything that isn't a stored status reads as pending, `fix-statuses`
+/// finds and rewrites those rows.
 impl From<String> for OrderStatus {
     fn from(value: String) -> Self {
-        match value.as_str() {
-            "pending" => OrderStatus::Pending,
-            "in-progress" => OrderStatus::InProgress,
-            "complete" => OrderStatus::Complete,
-            "canceled" => OrderStatus::Canceled,
-            _ => OrderStatus::default(),
-        }
+        Self::from_stored(&value).unwrap_or_default()
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::db::test_db;
+    use sqlx::Row;
+
+    use crate::{db::test_db, items::Item, notes::Note};
 
     use super::*;
 
+    #[tokio::test]
+    async fn test_etag_matches_the_table() {
+        let db = test_db().await;
+
+        let mut order = Order::new(500);
+        order.save(db.writer()).await.unwrap();
+        let id = order.id.unwrap();
+
+        // the saved order in hand, and the one read back
+        let existence = Order::exists(&db, id).await.unwrap().unwrap();
+        assert_eq!(order.etag(), existence.etag());
+        assert_eq!(Order::get_by_id(&db, id).await.unwrap().unwrap().etag(), existence.etag());
+    }
+
     #[tokio::test]
     async fn test_save_and_get_order() {
         let db = test_db().await;
@@ -125,7 +1385,7 @@ mod tests {
         let mut order = Order::new(500);
 
         order
-            .save(&db)
+            .save(db.writer())
             .await
             .expect("order should save without error");
 
@@ -141,7 +1401,7 @@ mod tests {
         order.amount = 900;
 
         order
-            .save(&db)
+            .save(db.writer())
             .await
             .expect("order should save without error");
 
@@ -170,7 +1430,7 @@ mod tests {
         for _ in 0..5 {
             let mut order = Order::new(500);
             order
-                .save(&db)
+                .save(db.writer())
                 .await
                 .expect("order should save without error");
         }
@@ -180,13 +1440,167 @@ mod tests {
         assert_eq!(results.len(), 5);
     }
 
+    #[tokio::test]
+    async fn test_find_by_status() {
+        let db = test_db().await;
+
+        for status in [OrderStatus::Pending, OrderStatus::Complete, OrderStatus::Complete] {
+            let mut order = Order {
+                amount: 500,
+                status,
+                ..Default::default()
+            };
+            order
+                .save(db.writer())
+                .await
+                .expect("order should save without error");
+        }
+
+        let results = Order::find(&db, Some(&OrderStatus::Complete), None, &BTreeMap::new(), false, OrderSort::Id)
+            .await
+            .expect("should not error");
+
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|found| found.order.status == OrderStatus::Complete));
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        use OrderStatus::*;
+
+        let allowed = [
+            (Pending, InProgress),
+            (Pending, Canceled),
+            (InProgress, Complete),
+            (InProgress, Canceled),
+            (Draft, Pending),
+        ];
+
+        for from in OrderStatus::STORED {
+            for to in OrderStatus::STORED {
+                let expected = from == to || allowed.contains(&(from, to));
+
+                assert_eq!(from.can_transition_to(&to), expected, "{from} to {to}");
+            }
+
+            assert_eq!(from.next().is_empty(), from.is_terminal(), "{from}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_open() {
+        let db = test_db().await;
+
+        let mut ids = Vec::new();
+        for status in [
+            OrderStatus::Complete,
+            OrderStatus::InProgress,
+            OrderStatus::Canceled,
+            OrderStatus::Pending,
+            OrderStatus::Draft,
+            OrderStatus::Pending,
+        ] {
+            let mut order = Order {
+                amount: 500,
+                status,
+                ..Default::default()
+            };
+            order.save(db.writer()).await.unwrap();
+            ids.push(order.id.unwrap());
+        }
+
+        Order::pick_list(&db, 1, Some("sam")).await.unwrap();
+
+        let open: Vec<i64> = Order::get_open(&db, 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|order| order.id.unwrap())
+            .collect();
+        assert_eq!(open, [ids[3], ids[5]]);
+    }
+
+    #[tokio::test]
+    async fn test_open_orders_use_their_index() {
+        let db = test_db().await;
+
+        // the work queue's queries, as `get_open` and `pick_list` send them
+        let queries = [
+            "select * from orders
+            where status in ('pending', 'in-progress') and assigned_to is null
+            order by created_at, id limit ?",
+            "select id from orders
+            where status in ('pending', 'in-progress') and assigned_to is null
+            order by created_at, id limit ?",
+        ];
+
+        // planned with and without statistics, with mostly finished orders
+        for analyzed in [false, true] {
+            if analyzed {
+                for status in [OrderStatus::Complete, OrderStatus::Canceled, OrderStatus::Pending] {
+                    for _ in 0..20 {
+                        Order { amount: 500, status, ..Default::default() }
+                            .save(db.writer())
+                            .await
+                            .unwrap();
+                    }
+                }
+
+                sqlx::query("analyze").execute(db.writer()).await.unwrap();
+            }
+
+            for query in queries {
+                assert_eq!(plan(&db, query).await, ["SCAN orders USING INDEX orders_open"], "{query}");
+            }
+        }
+    }
+
+    /// The `detail` of each step of the query's plan.
+    async fn plan(db: &Db, query: &str) -> Vec<String> {
+        sqlx::query(&format!("explain query plan {query}"))
+            .bind(10)
+            .fetch_all(db.reader())
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("detail"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_create_with_idempotency_key() {
+        let db = test_db().await;
+
+        let mut order = Order::new(500);
+        let creation = Creation {
+            idempotency_key: Some("abc"),
+            ..Default::default()
+        };
+
+        order
+            .create(&db, creation)
+            .await
+            .expect("order should save without error");
+
+        let found = Order::get_by_idempotency_key(&db, "abc")
+            .await
+            .expect("query should not error")
+            .expect("order should be found by its key");
+
+        assert_eq!(found.id, order.id);
+
+        // a second create with the same key is refused by the database
+        assert!(Order::new(500).create(&db, creation).await.is_err());
+        assert_eq!(Order::get_all(&db).await.unwrap().len(), 1);
+    }
+
     #[tokio::test]
     async fn test_delete_order() {
         let db = test_db().await;
 
         let mut order = Order::new(500);
         order
-            .save(&db)
+            .save(db.writer())
             .await
             .expect("order should save without error");
 
@@ -204,4 +1618,91 @@ mod tests {
 
         assert!(result.is_none());
     }
+
+    async fn completed_in(db: &Db, status: OrderStatus, updated_at: &str) -> i64 {
+        let mut order = Order {
+            amount: 500,
+            status,
+            ..Default::default()
+        };
+        order.save(db.writer()).await.unwrap();
+
+        let id = order.id.unwrap();
+
+        let mut item = Item {
+            order_id: id,
+            description: "Widget".to_string(),
+            quantity: 1,
+            unit_price: 500,
+            ..Default::default()
+        };
+        item.save(db).await.unwrap();
+
+        Note::create(db, id, None, "@alice shipped", &[]).await.unwrap();
+
+        sqlx::query("update orders set updated_at = ? where id = ?")
+            .bind(updated_at)
+            .bind(id)
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        id
+    }
+
+    #[tokio::test]
+    async fn test_archive_before() {
+        let db = test_db().await;
+
+        let old = completed_in(&db, OrderStatus::Complete, "2022-06-01T00:00:00.000Z").await;
+        let recent = completed_in(&db, OrderStatus::Complete, "2023-06-01T00:00:00.000Z").await;
+        let pending = completed_in(&db, OrderStatus::Pending, "2022-06-01T00:00:00.000Z").await;
+
+        let archived = Order::archive_before(&db, "2023-01-01").await.unwrap();
+
+        assert_eq!(archived, 1);
+        assert!(Order::get_by_id(&db, old).await.unwrap().is_none());
+        assert!(Order::get_by_id(&db, recent).await.unwrap().is_some());
+        assert!(Order::get_by_id(&db, pending).await.unwrap().is_some());
+
+        let order = Order::get_archived_by_id(&db, old).await.unwrap().unwrap();
+        assert_eq!(order.amount, 500);
+        assert!(Item::get_by_order_id(&db, old).await.unwrap().is_empty());
+
+        let items: i64 = sqlx::query_scalar("select count(*) from order_items_archive where order_id = ?")
+            .bind(old)
+            .fetch_one(db.reader())
+            .await
+            .unwrap();
+        assert_eq!(items, 1);
+
+        let mentions: i64 = sqlx::query_scalar(
+            "select count(*) from note_mentions_archive m
+            join order_notes_archive n on n.id = m.note_id where n.order_id = ?",
+        )
+        .bind(old)
+        .fetch_one(db.reader())
+        .await
+        .unwrap();
+        assert_eq!(mentions, 1);
+
+        // running it again has nothing left to move
+        assert_eq!(Order::archive_before(&db, "2023-01-01").await.unwrap(), 0);
+        assert!(Order::archive_before(&db, "last year").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_archive_in_batches() {
+        let db = test_db().await;
+
+        for _ in 0..5 {
+            completed_in(&db, OrderStatus::Complete, "2022-06-01T00:00:00.000Z").await;
+        }
+
+        assert_eq!(Order::archive_batch(&db, "2023-01-01", 2).await.unwrap(), 2);
+        assert_eq!(Order::get_all(&db).await.unwrap().len(), 3);
+
+        let archived = Order::find_archived(&db, None, None, &BTreeMap::new()).await.unwrap();
+        assert_eq!(archived.len(), 2);
+    }
 }
@@ -0,0 +1,300 @@
+//! Inserts many new orders at once, for imports. They go in as multi-row
+//! `INSERT`s sized to the bound parameter limit of the sqlite the app is
+//! running on, instead of a statement and a savepoint each, which is what a
+//! large import spent most of its time on.
+
+use std::time::Instant;
+
+use anyhow::Result;
+use sqlx::{QueryBuilder, Sqlite, Transaction};
+
+use crate::{
+    clock,
+    orders::{Order, OrderStatus},
+    projections,
+    timing::Timed,
+};
+
+/// Parameters bound per order in a multi-row insert.
+const BINDS_PER_ROW: usize = 16;
+
+/// What the sqlite the app is running on lets a bulk insert do, from its
+/// version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Bound parameters per statement, `SQLITE_MAX_VARIABLE_NUMBER`'s default:
+    /// 999 before 3.32.0, 32766 since.
+    pub max_params: usize,
+    /// `RETURNING` came in 3.35.0. Without it a multi-row insert's ids can't be
+    /// read back, so the orders are inserted one at a time.
+    pub returning: bool,
+}
+
+impl Limits {
+    pub async fn detect(tx: &mut Transaction<'_, Sqlite>) -> Result<Self> {
+        let version: String = sqlx::query_scalar("select sqlite_version()")
+            .fetch_one(&mut **tx)
+            .timed()
+            .await?;
+
+        Ok(Self::of_version(&version))
+    }
+
+    fn of_version(version: &str) -> Self {
+        let mut parts = version.split('.').map(|part| part.parse::<u32>().unwrap_or(0));
+        let (major, minor) = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+        let since = |since: u32| major > 3 || (major == 3 && minor >= since);
+
+        Self {
+            max_params: if since(32) { 32766 } else { 999 },
+            returning: since(35),
+        }
+    }
+
+    /// Orders per multi-row insert.
+    fn rows(&self) -> usize {
+        (self.max_params / BINDS_PER_ROW).max(1)
+    }
+}
+
+/// How `save_many` inserted the orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inserted {
+    Chunked { statements: usize },
+    PerRow,
+}
+
+impl Order {
+    /// Inserts new orders and their summaries in `tx`, giving each its id, in
+    /// as few statements as the running sqlite allows. The rate is logged.
+    pub async fn save_many(tx: &mut Transaction<'_, Sqlite>, orders: &mut [Order]) -> Result<Inserted> {
+        let limits = Limits::detect(tx).await?;
+
+        Self::save_many_within(tx, orders, limits).await
+    }
+
+    /// `save_many` with the limits given rather than detected.
+    pub async fn save_many_within(
+        tx: &mut Transaction<'_, Sqlite>,
+        orders: &mut [Order],
+        limits: Limits,
+    ) -> Result<Inserted> {
+        anyhow::ensure!(
+            orders.iter().all(|order| order.id.is_none()),
+            "save_many only inserts new orders"
+        );
+
+        let started = Instant::now();
+
+        let inserted = match limits.returning {
+            true => {
+                let mut statements = 0;
+
+                for chunk in orders.chunks_mut(limits.rows()) {
+                    insert_chunk(tx, chunk).await?;
+                    statements += 1;
+                }
+
+                Inserted::Chunked { statements }
+            }
+            false => {
+                for order in orders.iter_mut() {
+                    order.save(&mut **tx).await?;
+                }
+
+                Inserted::PerRow
+            }
+        };
+
+        if !orders.is_empty() {
+            let elapsed = started.elapsed();
+            let rate = orders.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+
+            tracing::info!(
+                "inserted {} orders in {elapsed:?} ({inserted:?}), {rate:.0} rows/s",
+                orders.len()
+            );
+        }
+
+        Ok(inserted)
+    }
+}
+
+/// One multi-row insert, what `Order::save` does for a new order.
+async fn insert_chunk(tx: &mut Transaction<'_, Sqlite>, orders: &mut [Order]) -> Result<()> {
+    let now = clock::now();
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "INSERT INTO orders (status, amount, metadata, customer_id, api_key, currency,
+            updated_at, created_at, completed_at, shipping_name, shipping_line1,
+            shipping_line2, shipping_city, shipping_region, shipping_postal_code,
+            shipping_country) ",
+    );
+
+    query.push_values(orders.iter(), |mut row, order| {
+        let completed_at = (order.status == OrderStatus::Complete).then(|| now.clone());
+
+        row.push_bind(order.status.to_string())
+            .push_bind(order.amount)
+            .push_bind(order.metadata.to_string())
+            .push_bind(order.customer_id.clone())
+            .push_bind(order.api_key.clone())
+            .push_bind(order.currency.as_str().to_string())
+            .push_bind(now.clone())
+            .push_bind(now.clone())
+            .push_bind(completed_at)
+            .push_bind(order.shipping_name.clone())
+            .push_bind(order.shipping_line1.clone())
+            .push_bind(order.shipping_line2.clone())
+            .push_bind(order.shipping_city.clone())
+            .push_bind(order.shipping_region.clone())
+            .push_bind(order.shipping_postal_code.clone())
+            .push_bind(order.shipping_country.clone());
+    });
+    query.push(" RETURNING id");
+
+    let mut ids: Vec<i64> = query
+        .build_query_scalar()
+        .fetch_all(&mut **tx)
+        .timed()
+        .await?;
+
+    anyhow::ensure!(
+        ids.len() == orders.len(),
+        "inserted {} orders but got {} ids back",
+        orders.len(),
+        ids.len()
+    );
+
+    // RETURNING's rows come in no set order, but the ids are AUTOINCREMENT so
+    // they were handed out in the order the rows were inserted
+    ids.sort_unstable();
+
+    for (order, id) in orders.iter_mut().zip(&ids) {
+        order.id = Some(*id);
+    }
+
+    projections::update_summaries(tx, &ids).await
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Map, json};
+
+    use crate::{
+        db::{Db, test_db},
+        metadata::Metadata,
+    };
+
+    use super::*;
+
+    fn orders(count: usize) -> Vec<Order> {
+        (0..count)
+            .map(|n| Order {
+                amount: 100 + n as i64,
+                status: match n % 3 {
+                    0 => OrderStatus::Pending,
+                    1 => OrderStatus::Complete,
+                    _ => OrderStatus::Canceled,
+                },
+                metadata: Metadata::new(Map::from_iter([("row".to_string(), json!(n))])).unwrap(),
+                customer_id: (n % 2 == 0).then(|| format!("customer-{}", n % 7)),
+                shipping_country: (n % 5 == 0).then(|| "GB".to_string()),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    async fn save_many(db: &Db, orders: &mut [Order], limits: Limits) -> Inserted {
+        let mut tx = db.writer().begin().await.unwrap();
+        let inserted = Order::save_many_within(&mut tx, orders, limits).await.unwrap();
+        tx.commit().await.unwrap();
+
+        inserted
+    }
+
+    /// Every order and summary, leaving out the times the inserts were made at.
+    async fn rows(db: &Db) -> Vec<(i64, String, i64, String, Option<String>, bool, Option<String>, i64)> {
+        sqlx::query_as(
+            "select o.id, o.status, o.amount, o.metadata, o.customer_id,
+                o.completed_at is not null, o.shipping_country, s.item_count
+            from orders o join order_summaries s on s.order_id = o.id
+            where o.created_at = o.updated_at and s.updated_at = o.updated_at
+                and s.status = o.status and s.amount = o.amount
+                and s.customer_id is o.customer_id
+            order by o.id",
+        )
+        .fetch_all(db.reader())
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn test_limits_of_version() {
+        assert_eq!(
+            Limits::of_version("3.31.1"),
+            Limits { max_params: 999, returning: false }
+        );
+        assert_eq!(
+            Limits::of_version("3.34.0"),
+            Limits { max_params: 32766, returning: false }
+        );
+        assert_eq!(
+            Limits::of_version("3.51.2"),
+            Limits { max_params: 32766, returning: true }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunked_inserts_match_per_row_ones() {
+        const COUNT: usize = 20_000;
+
+        let chunked_db = test_db().await;
+        let mut tx = chunked_db.writer().begin().await.unwrap();
+        let limits = Limits::detect(&mut tx).await.unwrap();
+        drop(tx);
+
+        let mut chunked = orders(COUNT);
+        let started = Instant::now();
+        let inserted = save_many(&chunked_db, &mut chunked, limits).await;
+        let chunked_took = started.elapsed();
+
+        assert_eq!(
+            inserted,
+            Inserted::Chunked { statements: COUNT.div_ceil(limits.rows()) }
+        );
+
+        let per_row_db = test_db().await;
+        let mut per_row = orders(COUNT);
+        let started = Instant::now();
+        let inserted = save_many(
+            &per_row_db,
+            &mut per_row,
+            Limits { returning: false, ..limits },
+        )
+        .await;
+        let per_row_took = started.elapsed();
+
+        assert_eq!(inserted, Inserted::PerRow);
+        assert!(chunked_took < per_row_took, "{chunked_took:?} vs {per_row_took:?}");
+
+        let ids = |orders: &[Order]| orders.iter().map(|order| order.id.unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids(&chunked), (1..=COUNT as i64).collect::<Vec<_>>());
+        assert_eq!(ids(&chunked), ids(&per_row));
+
+        let rows_chunked = rows(&chunked_db).await;
+        assert_eq!(rows_chunked.len(), COUNT);
+        assert_eq!(rows_chunked, rows(&per_row_db).await);
+    }
+
+    #[tokio::test]
+    async fn test_small_parameter_limits_take_more_statements() {
+        let db = test_db().await;
+
+        let mut orders = orders(200);
+        let limits = Limits { max_params: 999, returning: true };
+
+        assert_eq!(save_many(&db, &mut orders, limits).await, Inserted::Chunked { statements: 4 });
+        assert_eq!(rows(&db).await.len(), 200);
+    }
+}
@@ -0,0 +1,368 @@
+//! Reads an order as it was at an earlier time, for disputes. `Order::save`,
+//! item adjustments and deletes write the old and new value of each tracked
+//! field to `order_changes`, and `replay` undoes the changes made after the
+//! time asked about, starting from the order as it is now or as it was when it
+//! was deleted. Fields nothing tracks are marked as such, not guessed.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{Sqlite, Transaction, prelude::FromRow};
+
+use crate::{
+    audit, clock,
+    db::{self, Db},
+    orders::Order,
+    redact,
+    timing::Timed,
+};
+
+/// Fields every change to is written to `order_changes`.
+pub const TRACKED: &[&str] = &["status", "amount", "metadata", "tags"];
+/// Fields set when the order's created that never change after.
+const FIXED: &[&str] = &["id", "created_at", "currency", "customer_id"];
+
+/// The change deleting the order, its old value is the order as it was.
+pub const DELETED: &str = "deleted";
+/// When tracking started for an order created before `order_changes` existed,
+/// its tracked fields before then aren't known.
+pub const TRACKING_STARTED: &str = "tracking_started";
+
+/// A row of `order_changes`, the values are json.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct Change {
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: String,
+}
+
+/// A field's value at the time asked about.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldAsOf {
+    /// `null` when the field isn't tracked.
+    pub value: Value,
+    pub tracked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsOf {
+    /// The order hadn't been created yet.
+    NotCreated,
+    /// The order had been deleted, at this time.
+    Deleted(String),
+    Found(BTreeMap<String, FieldAsOf>),
+}
+
+/// The order's fields as json, what `replay` starts from.
+pub fn snapshot(order: &Order) -> Result<Map<String, Value>> {
+    match serde_json::to_value(order)? {
+        Value::Object(fields) => Ok(fields),
+        _ => anyhow::bail!("an order is a json object"),
+    }
+}
+
+/// The fields of `new` whose value isn't the one in `old`, and `null` for
+/// the ones `new` doesn't have. What's recorded as the order's changes, and
+/// what a `Prefer: return=minimal-diff` response sends.
+pub fn diff(old: &Map<String, Value>, new: &Map<String, Value>) -> Map<String, Value> {
+    let mut changed: Map<String, Value> = new
+        .iter()
+        .filter(|(field, value)| old.get(*field) != Some(*value))
+        .map(|(field, value)| (field.clone(), value.clone()))
+        .collect();
+
+    for field in old.keys().filter(|field| !new.contains_key(*field)) {
+        changed.insert(field.clone(), Value::Null);
+    }
+
+    changed
+}
+
+/// Works out the order as it was at `at`, a timestamp like `clock::now`
+/// gives, from `current`, the order now or when it was deleted, and its
+/// changes oldest first. A change made at `at` exactly counts as made.
+pub fn replay(current: &Map<String, Value>, changes: &[Change], at: &str) -> Result<AsOf> {
+    let deleted = changes
+        .iter()
+        .find(|change| change.field == DELETED && change.changed_at.as_str() <= at);
+
+    if let Some(deleted) = deleted {
+        return Ok(AsOf::Deleted(deleted.changed_at.clone()));
+    }
+
+    if let Some(Value::String(created_at)) = current.get("created_at")
+        && created_at.as_str() > at
+    {
+        return Ok(AsOf::NotCreated);
+    }
+
+    let mut order = current.clone();
+    let mut known = true;
+
+    for change in changes.iter().rev() {
+        if change.changed_at.as_str() <= at {
+            continue;
+        }
+
+        match change.field.as_str() {
+            TRACKING_STARTED => known = false,
+            field if TRACKED.contains(&field) => {
+                let old = match &change.old_value {
+                    Some(old) => serde_json::from_str(old)
+                        .with_context(|| format!("{field} change has invalid json"))?,
+                    None => Value::Null,
+                };
+
+                order.insert(field.to_string(), old);
+            }
+            _ => {}
+        }
+    }
+
+    let fields = order
+        .into_iter()
+        .map(|(field, value)| {
+            let tracked =
+                FIXED.contains(&field.as_str()) || (known && TRACKED.contains(&field.as_str()));
+            let value = if tracked { value } else { Value::Null };
+
+            (field, FieldAsOf { value, tracked })
+        })
+        .collect();
+
+    Ok(AsOf::Found(fields))
+}
+
+/// Writes a change to the order in the transaction it's made in, chained
+/// onto the changes before it, see `audit.rs`. The values are redacted first,
+/// see `redact.rs`, a deleted order's as the whole order.
+pub async fn record(
+    tx: &mut Transaction<'_, Sqlite>,
+    order_id: i64,
+    field: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+) -> Result<()> {
+    record_at(tx, order_id, field, old, new, &clock::now()).await
+}
+
+/// `record` with the time the change was made rather than now, for an order
+/// imported along with its history, see `graphs.rs`.
+pub async fn record_at(
+    tx: &mut Transaction<'_, Sqlite>,
+    order_id: i64,
+    field: &str,
+    old: Option<&Value>,
+    new: Option<&Value>,
+    changed_at: &str,
+) -> Result<()> {
+    let redacted = |value: &Value| {
+        let value = match field {
+            DELETED => redact::current(value.clone()),
+            field => redact::current_field(field, value.clone()),
+        };
+
+        value.to_string()
+    };
+    let old = old.map(redacted);
+    let new = new.map(redacted);
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO order_changes (order_id, field, old_value, new_value, changed_at)
+        VALUES (?, ?, ?, ?, ?) RETURNING id;",
+        order_id,
+        field,
+        old,
+        new,
+        changed_at
+    )
+    .fetch_one(&mut **tx)
+    .timed()
+    .await?;
+
+    audit::link(tx, id).await?;
+
+    Ok(())
+}
+
+/// The order's changes, oldest first.
+pub async fn get_by_order_id(db: &Db, order_id: i64) -> Result<Vec<Change>> {
+    Ok(db::read(|| {
+        sqlx::query_as!(
+            Change,
+            "select field, old_value, new_value, changed_at from order_changes
+            where order_id = ? order by id",
+            order_id
+        )
+        .fetch_all(db.reader())
+        .timed()
+    })
+    .await?)
+}
+
+/// The order as it was when it was deleted, `None` when it wasn't.
+pub async fn deleted(db: &Db, order_id: i64) -> Result<Option<Map<String, Value>>> {
+    let last = sqlx::query_scalar!(
+        "select old_value from order_changes where order_id = ? and field = ?
+        order by id desc limit 1",
+        order_id,
+        DELETED
+    )
+    .fetch_optional(db.reader())
+    .timed()
+    .await?
+    .flatten();
+
+    Ok(last.map(|last| serde_json::from_str(&last)).transpose()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn change(field: &str, old: Value, new: Value, changed_at: &str) -> Change {
+        Change {
+            field: field.to_string(),
+            old_value: Some(old.to_string()),
+            new_value: Some(new.to_string()),
+            changed_at: changed_at.to_string(),
+        }
+    }
+
+    fn current() -> Map<String, Value> {
+        snapshot(&Order {
+            id: Some(1),
+            amount: 900,
+            status: crate::orders::OrderStatus::Complete,
+            created_at: Some("2025-09-01T10:00:00.000Z".to_string()),
+            updated_at: Some("2025-09-03T10:00:00.000Z".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn found(as_of: AsOf) -> BTreeMap<String, FieldAsOf> {
+        match as_of {
+            AsOf::Found(fields) => fields,
+            other => panic!("expected the order, got {other:?}"),
+        }
+    }
+
+    fn value(fields: &BTreeMap<String, FieldAsOf>, field: &str) -> Value {
+        fields[field].value.clone()
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = json!({ "amount": 500, "status": "pending", "note": "x" });
+        let new = json!({ "amount": 700, "status": "pending", "gift": true });
+
+        assert_eq!(
+            Value::Object(diff(old.as_object().unwrap(), new.as_object().unwrap())),
+            json!({ "amount": 700, "gift": true, "note": null })
+        );
+    }
+
+    #[test]
+    fn test_replays_changes_backward() {
+        let changes = [
+            change("amount", json!(500), json!(700), "2025-09-02T10:00:00.000Z"),
+            change(
+                "status",
+                json!("pending"),
+                json!("complete"),
+                "2025-09-03T10:00:00.000Z",
+            ),
+            change("amount", json!(700), json!(900), "2025-09-03T10:00:00.000Z"),
+        ];
+
+        let before = found(replay(&current(), &changes, "2025-09-01T12:00:00.000Z").unwrap());
+        assert_eq!(value(&before, "amount"), json!(500));
+        assert_eq!(value(&before, "status"), json!("pending"));
+
+        let between = found(replay(&current(), &changes, "2025-09-02T10:00:00.000Z").unwrap());
+        assert_eq!(value(&between, "amount"), json!(700));
+        assert_eq!(value(&between, "status"), json!("pending"));
+
+        let now = found(replay(&current(), &changes, "2025-09-04T00:00:00.000Z").unwrap());
+        assert_eq!(value(&now, "amount"), json!(900));
+        assert_eq!(value(&now, "status"), json!("complete"));
+    }
+
+    #[test]
+    fn test_untracked_fields_are_not_guessed() {
+        let fields = found(replay(&current(), &[], "2025-09-02T00:00:00.000Z").unwrap());
+
+        assert!(fields["id"].tracked);
+        assert_eq!(
+            value(&fields, "created_at"),
+            json!("2025-09-01T10:00:00.000Z")
+        );
+        assert!(fields["amount"].tracked);
+
+        for field in [
+            "updated_at",
+            "completed_at",
+            "assigned_to",
+            "reminder_sent_at",
+        ] {
+            assert_eq!(
+                fields[field],
+                FieldAsOf {
+                    value: Value::Null,
+                    tracked: false
+                },
+                "{field}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_before_tracking_started_nothing_is_known() {
+        let changes = [Change {
+            field: TRACKING_STARTED.to_string(),
+            old_value: None,
+            new_value: None,
+            changed_at: "2025-09-02T00:00:00.000Z".to_string(),
+        }];
+
+        let before = found(replay(&current(), &changes, "2025-09-01T12:00:00.000Z").unwrap());
+        assert!(!before["amount"].tracked);
+        assert_eq!(value(&before, "status"), Value::Null);
+        assert!(before["currency"].tracked);
+
+        let after = found(replay(&current(), &changes, "2025-09-02T12:00:00.000Z").unwrap());
+        assert_eq!(value(&after, "amount"), json!(900));
+    }
+
+    #[test]
+    fn test_not_created_and_deleted() {
+        let snapshot = current();
+        let changes = [
+            change("amount", json!(500), json!(900), "2025-09-02T10:00:00.000Z"),
+            Change {
+                field: DELETED.to_string(),
+                old_value: Some(Value::Object(snapshot.clone()).to_string()),
+                new_value: None,
+                changed_at: "2025-09-05T10:00:00.000Z".to_string(),
+            },
+        ];
+
+        assert_eq!(
+            replay(&snapshot, &changes, "2025-08-31T00:00:00.000Z").unwrap(),
+            AsOf::NotCreated
+        );
+        assert_eq!(
+            replay(&snapshot, &changes, "2025-09-05T10:00:00.000Z").unwrap(),
+            AsOf::Deleted("2025-09-05T10:00:00.000Z".to_string())
+        );
+
+        let before = found(replay(&snapshot, &changes, "2025-09-04T00:00:00.000Z").unwrap());
+        assert_eq!(value(&before, "amount"), json!(900));
+    }
+}
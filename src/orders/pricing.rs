@@ -0,0 +1,210 @@
+//! An order's totals from its items, the one place they're worked out. The
+//! writes that set an order's amount from its items, `Item::adjust_quantity`
+//! and `recompute`, price them with no adjustments, and
+//! `POST /orders/{id}/preview-totals` prices them with hypothetical ones
+//! without writing anything. Stored orders have no discount or tax, so their
+//! amount is the subtotal.
+//!
+//! Rates are in hundredths of a percent, `825` for 8.25%, and the discount and
+//! tax are each rounded half up to a minor unit.
+
+use crate::items::Item;
+
+/// Hundredths of a percent in a whole.
+const WHOLE: i128 = 10_000;
+
+/// A line to price, an item on the order or one being added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub sku: Option<String>,
+    pub description: String,
+    pub quantity: i64,
+    pub unit_price: i64,
+}
+
+impl From<&Item> for Line {
+    fn from(item: &Item) -> Self {
+        Self {
+            sku: item.sku.clone(),
+            description: item.description.clone(),
+            quantity: item.quantity,
+            unit_price: item.unit_price,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discount {
+    /// Hundredths of a percent of the subtotal, at most 100%.
+    Percent(i64),
+    /// Minor units, more than the subtotal takes it to 0.
+    Amount(i64),
+}
+
+/// What's applied on top of the lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adjustments {
+    pub discount: Option<Discount>,
+    /// Hundredths of a percent of the discounted subtotal.
+    pub tax_rate: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineTotal {
+    pub line: Line,
+    /// `quantity * unit_price`.
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Totals {
+    pub lines: Vec<LineTotal>,
+    pub subtotal: i64,
+    pub discount: i64,
+    pub tax: i64,
+    /// `subtotal - discount + tax`, what the order's amount is set to.
+    pub total: i64,
+}
+
+/// Prices `lines` with `adjustments`. An order without items is priced at
+/// `amount`, the one it was given. An error when a number doesn't fit.
+pub fn totals(lines: &[Line], adjustments: &Adjustments, amount: i64) -> Result<Totals, String> {
+    let too_large = || "the order's total is too large".to_string();
+
+    let lines = lines
+        .iter()
+        .map(|line| {
+            let total = line.quantity.checked_mul(line.unit_price).ok_or_else(too_large)?;
+
+            Ok(LineTotal { line: line.clone(), total })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let subtotal = match lines.is_empty() {
+        true => amount,
+        false => lines
+            .iter()
+            .try_fold(0_i64, |sum, line| sum.checked_add(line.total))
+            .ok_or_else(too_large)?,
+    };
+
+    let discount = match adjustments.discount {
+        None => 0,
+        Some(Discount::Percent(rate)) => share(subtotal, rate).ok_or_else(too_large)?,
+        Some(Discount::Amount(discount)) => discount.min(subtotal).max(0),
+    };
+    let discounted = subtotal - discount;
+
+    let tax = match adjustments.tax_rate {
+        None => 0,
+        Some(rate) => share(discounted, rate).ok_or_else(too_large)?,
+    };
+
+    Ok(Totals {
+        lines,
+        subtotal,
+        discount,
+        tax,
+        total: discounted.checked_add(tax).ok_or_else(too_large)?,
+    })
+}
+
+/// A percent like `8` or `8.25` as hundredths of one, from 0 to 100%.
+pub fn parse_rate(percent: &str) -> Option<i64> {
+    let digits = |part: &str, most: usize| {
+        !part.is_empty() && part.len() <= most && part.bytes().all(|byte| byte.is_ascii_digit())
+    };
+
+    let (whole, fraction) = match percent.split_once('.') {
+        Some((whole, fraction)) if digits(fraction, 2) => (whole, fraction),
+        Some(_) => return None,
+        None => (percent, ""),
+    };
+
+    if !digits(whole, 3) {
+        return None;
+    }
+
+    let whole: i64 = whole.parse().ok()?;
+    let fraction: i64 = format!("{fraction:0<2}").parse().ok()?;
+
+    Some(whole * 100 + fraction).filter(|rate| *rate <= WHOLE as i64)
+}
+
+/// `rate` hundredths of a percent of `amount`, rounded half up.
+fn share(amount: i64, rate: i64) -> Option<i64> {
+    let hundredths = i128::from(amount) * i128::from(rate);
+
+    (hundredths + WHOLE / 2).div_euclid(WHOLE).try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(sku: &str, quantity: i64, unit_price: i64) -> Line {
+        Line {
+            sku: Some(sku.to_string()),
+            description: sku.to_string(),
+            quantity,
+            unit_price,
+        }
+    }
+
+    #[test]
+    fn test_subtotal_is_the_lines_or_the_amount() {
+        let lines = [line("MUG", 2, 250), line("TEE", 1, 1200)];
+        let totals = totals(&lines, &Adjustments::default(), 999).unwrap();
+
+        assert_eq!(totals.lines.iter().map(|line| line.total).collect::<Vec<_>>(), [500, 1200]);
+        assert_eq!((totals.subtotal, totals.discount, totals.tax, totals.total), (1700, 0, 0, 1700));
+
+        let itemless = super::totals(&[], &Adjustments::default(), 999).unwrap();
+        assert_eq!((itemless.subtotal, itemless.total), (999, 999));
+    }
+
+    #[test]
+    fn test_discount_then_tax() {
+        let lines = [line("MUG", 3, 333)];
+        let price = |discount, tax_rate| {
+            let totals = totals(&lines, &Adjustments { discount, tax_rate }, 0).unwrap();
+
+            (totals.subtotal, totals.discount, totals.tax, totals.total)
+        };
+
+        // 10% of 999 is 99.9, 8% of 899 is 71.92
+        assert_eq!(price(Some(Discount::Percent(1_000)), Some(800)), (999, 100, 72, 971));
+        // 8.25% of 999 is 82.4175
+        assert_eq!(price(None, Some(825)), (999, 0, 82, 1081));
+        // half a minor unit rounds up, 0.5% of 999 is 4.995
+        assert_eq!(price(None, Some(50)), (999, 0, 5, 1004));
+        assert_eq!(price(Some(Discount::Amount(199)), None), (999, 199, 0, 800));
+        // never below zero
+        assert_eq!(price(Some(Discount::Amount(5_000)), Some(800)), (999, 999, 0, 0));
+        assert_eq!(price(Some(Discount::Percent(10_000)), None), (999, 999, 0, 0));
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("8"), Some(800));
+        assert_eq!(parse_rate("8.25"), Some(825));
+        assert_eq!(parse_rate("12.5"), Some(1_250));
+        assert_eq!(parse_rate("0"), Some(0));
+        assert_eq!(parse_rate("100.0"), Some(10_000));
+
+        for invalid in ["", "-1", "100.01", "8.125", "8.", ".5", "1e2", "eight", "1000"] {
+            assert_eq!(parse_rate(invalid), None, "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_too_large_is_an_error() {
+        let adjustments = Adjustments::default();
+
+        assert!(totals(&[line("MUG", i64::MAX, 2)], &adjustments, 0).is_err());
+        assert!(totals(&[line("MUG", 1, i64::MAX), line("TEE", 1, 1)], &adjustments, 0).is_err());
+
+        let taxed = Adjustments { tax_rate: Some(10_000), ..Default::default() };
+        assert!(totals(&[line("MUG", 1, i64::MAX)], &taxed, 0).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Works an order's derived fields out again from what they're derived from,
+//! for when a bug or a hand edit left them wrong: the amount from the items,
+//! priced by `pricing.rs` as `order_amount_matches_items` in `consistency.rs`
+//! checks it, and the order's row in `order_summaries` from the order. Run for
+//! one order at a time by `POST /admin/orders/{id}/recompute`, so fixing one
+//! doesn't take sql.
+
+use anyhow::Result;
+use serde_json::{Value, json};
+
+use crate::{
+    clock,
+    db::Db,
+    events::{EventKind, OrderEvent},
+    items::Item,
+    orders::{
+        history,
+        pricing::{self, Adjustments, Line},
+    },
+    projections,
+    timing::Timed,
+};
+
+/// A derived field that was wrong, `summary.` ones are `order_summaries`'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Correction {
+    pub field: &'static str,
+    /// `null` for a summary row that was missing.
+    pub before: Value,
+    pub after: Value,
+}
+
+/// Corrects the order's derived fields in one transaction, bumping its
+/// `updated_at`, recording the amount's change in its history and a
+/// `recomputed` event listing every correction. Nothing's written when
+/// they're all right. `None` when there's no such order, archived orders
+/// included, their rows are kept as they were archived.
+pub async fn recompute(db: &Db, order_id: i64) -> Result<Option<Vec<Correction>>> {
+    let mut tx = db.writer().begin().await?;
+
+    let order = sqlx::query!(
+        "select status, amount, customer_id from orders where id = ?",
+        order_id
+    )
+    .fetch_optional(&mut *tx)
+    .timed()
+    .await?;
+
+    let Some(order) = order else {
+        return Ok(None);
+    };
+
+    let items = sqlx::query_as!(
+        Item,
+        "select * from order_items where order_id = ? order by id",
+        order_id
+    )
+    .fetch_all(&mut *tx)
+    .timed()
+    .await?;
+    let lines: Vec<Line> = items.iter().map(Line::from).collect();
+
+    // an order without items keeps the amount it was given
+    let amount = pricing::totals(&lines, &Adjustments::default(), order.amount)
+        .map_err(anyhow::Error::msg)?
+        .total;
+
+    let summary = sqlx::query!(
+        "select status, amount, item_count, customer_id from order_summaries where order_id = ?",
+        order_id
+    )
+    .fetch_optional(&mut *tx)
+    .timed()
+    .await?;
+
+    let mut corrections = Vec::new();
+
+    if amount != order.amount {
+        corrections.push(Correction {
+            field: "amount",
+            before: json!(order.amount),
+            after: json!(amount),
+        });
+    }
+
+    let summarized = match summary {
+        Some(summary) => [
+            json!(summary.status),
+            json!(summary.amount),
+            json!(summary.item_count),
+            json!(summary.customer_id),
+        ],
+        None => [Value::Null, Value::Null, Value::Null, Value::Null],
+    };
+    let expected = [
+        ("summary.status", json!(order.status)),
+        ("summary.amount", json!(amount)),
+        ("summary.item_count", json!(items.len())),
+        ("summary.customer_id", json!(order.customer_id)),
+    ];
+
+    for ((field, after), before) in expected.into_iter().zip(summarized) {
+        if before != after {
+            corrections.push(Correction { field, before, after });
+        }
+    }
+
+    if corrections.is_empty() {
+        return Ok(Some(corrections));
+    }
+
+    let now = clock::now();
+
+    sqlx::query!(
+        "update orders set amount = ?, updated_at = ? where id = ?;",
+        amount,
+        now,
+        order_id
+    )
+    .execute(&mut *tx)
+    .timed()
+    .await?;
+
+    if amount != order.amount {
+        let (old, new) = (json!(order.amount), json!(amount));
+        history::record(&mut tx, order_id, "amount", Some(&old), Some(&new)).await?;
+    }
+
+    projections::update_summary(&mut tx, order_id).await?;
+
+    let detail = corrections
+        .iter()
+        .map(|correction| format!("{} {} -> {}", correction.field, correction.before, correction.after))
+        .collect::<Vec<_>>()
+        .join(", ");
+    OrderEvent::record(&mut tx, order_id, EventKind::Recomputed, &detail, None).await?;
+
+    tx.commit().await?;
+
+    Ok(Some(corrections))
+}
@@ -0,0 +1,86 @@
+//! Adds and removes tags on many orders at once, for `POST
+//! /admin/orders/retag`. The orders are changed `RETAG_BATCH_SIZE` at a time,
+//! each batch in its own transaction, so a large retag doesn't hold the write
+//! lock for its whole length. Every change is recorded in the order's history
+//! like any other, see `history.rs`.
+
+use anyhow::Result;
+use serde_json::json;
+
+use crate::{
+    clock,
+    db::Db,
+    orders::history,
+    tags::Tags,
+    timing::Timed,
+};
+
+/// Orders changed per transaction.
+pub const RETAG_BATCH_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Retagged {
+    /// Orders whose tags changed. One that already had the tags added and
+    /// none of those removed isn't written.
+    pub updated: i64,
+    /// Orders left as they were because they'd have had more than
+    /// `TAGS_MAX_PER_ORDER` tags, by id.
+    pub skipped: Vec<i64>,
+}
+
+/// Adds `add` to the tags of the orders `ids` and then removes `remove`,
+/// bumping `updated_at` on the ones that changed. An id no longer in `orders`
+/// by the time its batch runs is passed over.
+pub async fn retag(db: &Db, ids: &[i64], add: &[String], remove: &[String]) -> Result<Retagged> {
+    let mut retagged = Retagged::default();
+
+    for batch in ids.chunks(RETAG_BATCH_SIZE) {
+        let batch = serde_json::to_string(batch)?;
+        let mut tx = db.writer().begin().await?;
+
+        let orders = sqlx::query!(
+            r#"select id as "id!", tags from orders
+            where id in (select value from json_each(?)) order by id"#,
+            batch
+        )
+        .fetch_all(&mut *tx)
+        .timed()
+        .await?;
+
+        let now = clock::now();
+
+        for order in orders {
+            let tags = Tags::from(order.tags);
+
+            let Some(new) = tags.retagged(add, remove) else {
+                retagged.skipped.push(order.id);
+                continue;
+            };
+
+            if new == tags {
+                continue;
+            }
+
+            let stored = new.to_string();
+
+            sqlx::query!(
+                "update orders set tags = ?, updated_at = ? where id = ?",
+                stored,
+                now,
+                order.id
+            )
+            .execute(&mut *tx)
+            .timed()
+            .await?;
+
+            let (old, new) = (json!(tags.to_vec()), json!(new.to_vec()));
+            history::record(&mut tx, order.id, "tags", Some(&old), Some(&new)).await?;
+
+            retagged.updated += 1;
+        }
+
+        tx.commit().await?;
+    }
+
+    Ok(retagged)
+}
@@ -0,0 +1,195 @@
+//! Webhook payload shapes, one module per version. A version's shape never
+//! changes once receivers rely on it, new fields go in a new version.
+
+use serde_json::Value;
+
+use crate::{
+    events::{OrderEvent, SystemEvent},
+    items::Item,
+    orders::Order,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadVersion {
+    V1,
+    V2,
+    V3,
+}
+
+impl PayloadVersion {
+    pub const LATEST: PayloadVersion = PayloadVersion::V3;
+
+    pub fn parse(version: i64) -> Option<Self> {
+        match version {
+            1 => Some(PayloadVersion::V1),
+            2 => Some(PayloadVersion::V2),
+            3 => Some(PayloadVersion::V3),
+            _ => None,
+        }
+    }
+
+    pub fn number(self) -> i64 {
+        match self {
+            PayloadVersion::V1 => 1,
+            PayloadVersion::V2 => 2,
+            PayloadVersion::V3 => 3,
+        }
+    }
+
+    /// Whether the payload has the order's items, which the caller loads.
+    pub fn embeds_items(self) -> bool {
+        self == PayloadVersion::V3
+    }
+
+    /// The body sent for `event`. `order` is the order's current state, `None`
+    /// when it's been deleted since, and `items` its items when the version
+    /// `embeds_items`.
+    pub fn payload(self, event: &OrderEvent, order: Option<&Order>, items: Vec<Item>) -> Value {
+        let payload = match self {
+            PayloadVersion::V1 => serde_json::to_value(v1::Payload::new(event)),
+            PayloadVersion::V2 => serde_json::to_value(v2::Payload::new(event, order)),
+            PayloadVersion::V3 => serde_json::to_value(v3::Payload::new(event, order, items)),
+        };
+
+        payload.expect("payloads serialize to json")
+    }
+
+    /// The body sent for a system event. It's the same in every version, the
+    /// version 1 fields without an order.
+    pub fn system_payload(event: &SystemEvent) -> Value {
+        serde_json::to_value(system::Payload::new(event)).expect("payloads serialize to json")
+    }
+}
+
+/// Something that happened to the service rather than to an order.
+mod system {
+    use serde::Serialize;
+
+    use crate::events::SystemEvent;
+
+    #[derive(Serialize)]
+    pub struct Payload<'a> {
+        pub id: i64,
+        #[serde(rename = "type")]
+        pub kind: String,
+        pub detail: &'a str,
+        pub created_at: &'a str,
+    }
+
+    impl<'a> Payload<'a> {
+        pub fn new(event: &'a SystemEvent) -> Self {
+            Self {
+                id: event.id.unwrap_or_default(),
+                kind: format!("system.{}", event.kind),
+                detail: &event.detail,
+                created_at: &event.created_at,
+            }
+        }
+    }
+}
+
+/// The first shape: what happened to which order.
+mod v1 {
+    use serde::Serialize;
+
+    use crate::events::OrderEvent;
+
+    #[derive(Serialize)]
+    pub struct Payload<'a> {
+        pub id: i64,
+        #[serde(rename = "type")]
+        pub kind: String,
+        pub order_id: i64,
+        pub detail: &'a str,
+        pub created_at: &'a str,
+    }
+
+    impl<'a> Payload<'a> {
+        pub fn new(event: &'a OrderEvent) -> Self {
+            Self {
+                id: event.id.unwrap_or_default(),
+                kind: format!("order.{}", event.kind),
+                order_id: event.order_id,
+                detail: &event.detail,
+                created_at: &event.created_at,
+            }
+        }
+    }
+}
+
+/// Adds who made the change and the order itself, so receivers don't have to
+/// fetch it.
+mod v2 {
+    use serde::Serialize;
+
+    use crate::{events::OrderEvent, metadata::Metadata, orders::Order, orders::OrderStatus};
+
+    #[derive(Serialize)]
+    pub struct Payload<'a> {
+        #[serde(flatten)]
+        pub v1: super::v1::Payload<'a>,
+        pub actor: Option<&'a str>,
+        pub order: Option<PayloadOrder<'a>>,
+    }
+
+    #[derive(Serialize)]
+    pub struct PayloadOrder<'a> {
+        pub id: i64,
+        pub amount: i64,
+        pub status: &'a OrderStatus,
+        pub metadata: &'a Metadata,
+    }
+
+    impl<'a> Payload<'a> {
+        pub fn new(event: &'a OrderEvent, order: Option<&'a Order>) -> Self {
+            Self {
+                v1: super::v1::Payload::new(event),
+                actor: event.actor.as_deref(),
+                order: order.map(|order| PayloadOrder {
+                    id: order.id.unwrap_or_default(),
+                    amount: order.amount,
+                    status: &order.status,
+                    metadata: &order.metadata,
+                }),
+            }
+        }
+    }
+}
+
+/// The order the way the api sends it, every field of `GET /orders/{id}`, with
+/// its items embedded.
+mod v3 {
+    use serde::Serialize;
+
+    use crate::{
+        dto::{OrderChildren, OrderView, ViewOptions},
+        events::OrderEvent,
+        items::Item,
+        orders::Order,
+    };
+
+    #[derive(Serialize)]
+    pub struct Payload<'a> {
+        #[serde(flatten)]
+        pub v1: super::v1::Payload<'a>,
+        pub actor: Option<&'a str>,
+        pub order: Option<OrderView>,
+    }
+
+    impl<'a> Payload<'a> {
+        pub fn new(event: &'a OrderEvent, order: Option<&Order>, items: Vec<Item>) -> Self {
+            let options = ViewOptions {
+                embed_items: true,
+                ..Default::default()
+            };
+
+            Self {
+                v1: super::v1::Payload::new(event),
+                actor: event.actor.as_deref(),
+                order: order.map(|order| {
+                    OrderView::from(order.clone(), OrderChildren { items, ..Default::default() }, options)
+                }),
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+use crate::{
+    db::{self, Db},
+    timing::Timed,
+};
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Default)]
+pub struct Product {
+    pub id: Option<i64>,
+    pub sku: String,
+    pub name: String,
+    pub unit_price: i64,
+}
+
+impl Product {
+    pub async fn save(&mut self, db: &Db) -> Result<()> {
+        match self.id {
+            None => {
+                let result = sqlx::query!(
+                    "INSERT INTO products (sku, name, unit_price) VALUES (?, ?, ?);",
+                    self.sku,
+                    self.name,
+                    self.unit_price
+                )
+                .execute(db.writer())
+                .timed()
+                .await?;
+
+                self.id = Some(result.last_insert_rowid());
+            }
+            Some(id) => {
+                sqlx::query!(
+                    "update products set sku = ?, name = ?, unit_price = ? where id = ?;",
+                    self.sku,
+                    self.name,
+                    self.unit_price,
+                    id
+                )
+                .execute(db.writer())
+                .timed()
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_by_id(db: &Db, id: i64) -> Result<Option<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(Product, "select * from products where id = ?", id)
+                .fetch_optional(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+
+    pub async fn get_by_sku(db: &Db, sku: &str) -> Result<Option<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(Product, "select * from products where sku = ?", sku)
+                .fetch_optional(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+
+    pub async fn get_all(db: &Db) -> Result<Vec<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(Product, "select * from products order by sku")
+                .fetch_all(db.reader())
+                .timed()
+        })
+        .await?)
+    }
+
+    pub async fn delete_by_id(db: &Db, id: i64) -> Result<bool> {
+        let result = sqlx::query!("DELETE FROM products WHERE id = ?", id)
+            .execute(db.writer())
+            .timed()
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::test_db;
+
+    use super::*;
+
+    fn widget() -> Product {
+        Product {
+            sku: "WIDGET".to_string(),
+            name: "Widget".to_string(),
+            unit_price: 250,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_and_get_by_sku() {
+        let db = test_db().await;
+
+        let mut product = widget();
+
+        product
+            .save(&db)
+            .await
+            .expect("product should save without error");
+
+        product.unit_price = 300;
+        product
+            .save(&db)
+            .await
+            .expect("product should save without error");
+
+        let fresh_product = Product::get_by_sku(&db, "WIDGET")
+            .await
+            .expect("query should run without error")
+            .expect("product should have been found");
+
+        assert_eq!(fresh_product.id, product.id);
+        assert_eq!(fresh_product.unit_price, 300);
+    }
+
+    #[tokio::test]
+    async fn test_sku_is_unique() {
+        let db = test_db().await;
+
+        widget().save(&db).await.unwrap();
+
+        assert!(widget().save(&db).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delete_product() {
+        let db = test_db().await;
+
+        let mut product = widget();
+        product.save(&db).await.unwrap();
+
+        let deleted = Product::delete_by_id(&db, product.id.unwrap())
+            .await
+            .expect("delete should not error");
+
+        assert!(deleted);
+        assert!(Product::get_by_sku(&db, "WIDGET").await.unwrap().is_none());
+    }
+}
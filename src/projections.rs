@@ -0,0 +1,218 @@
+//! Read-side copies of order data, written in the same transaction as the
+//! order or item write they come from so they're never behind. An order's row
+//! in `order_summaries` goes with it when it's deleted or archived.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+
+use crate::{
+    db::{self, Db},
+    timing::Timed,
+};
+
+/// Writes the order's summary from its current row and items. Called by every
+/// write that changes what's in it: `Order::save`, `Item::add` and
+/// `recompute::recompute`. `Order::save_many` uses `update_summaries`.
+pub async fn update_summary(tx: &mut Transaction<'_, Sqlite>, order_id: i64) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO order_summaries (order_id, status, amount, item_count, customer_id, updated_at)
+        SELECT o.id, o.status, o.amount,
+            (SELECT count(*) FROM order_items i WHERE i.order_id = o.id),
+            o.customer_id, o.updated_at
+        FROM orders o WHERE o.id = ?
+        ON CONFLICT (order_id) DO UPDATE SET status = excluded.status, amount = excluded.amount,
+            item_count = excluded.item_count, customer_id = excluded.customer_id,
+            updated_at = excluded.updated_at;",
+        order_id
+    )
+    .execute(&mut **tx)
+    .timed()
+    .await?;
+
+    Ok(())
+}
+
+/// `update_summary` for many orders at once, for `Order::save_many`.
+pub async fn update_summaries(tx: &mut Transaction<'_, Sqlite>, order_ids: &[i64]) -> Result<()> {
+    let order_ids = serde_json::to_string(order_ids)?;
+
+    sqlx::query!(
+        "INSERT INTO order_summaries (order_id, status, amount, item_count, customer_id, updated_at)
+        SELECT o.id, o.status, o.amount,
+            (SELECT count(*) FROM order_items i WHERE i.order_id = o.id),
+            o.customer_id, o.updated_at
+        FROM orders o WHERE o.id IN (SELECT value FROM json_each(?))
+        ON CONFLICT (order_id) DO UPDATE SET status = excluded.status, amount = excluded.amount,
+            item_count = excluded.item_count, customer_id = excluded.customer_id,
+            updated_at = excluded.updated_at;",
+        order_ids
+    )
+    .execute(&mut **tx)
+    .timed()
+    .await?;
+
+    Ok(())
+}
+
+/// The item counts of `order_ids` that have a summary.
+pub async fn item_counts(db: &Db, order_ids: &[i64]) -> Result<HashMap<i64, i64>> {
+    let order_ids = serde_json::to_string(order_ids)?;
+
+    let rows = db::read(|| {
+        sqlx::query!(
+            r#"select order_id as "order_id!: i64", item_count from order_summaries
+            where order_id in (select value from json_each(?))"#,
+            order_ids
+        )
+        .fetch_all(db.reader())
+        .timed()
+    })
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.order_id, row.item_count))
+        .collect())
+}
+
+/// Throws the summaries away and writes them again from the orders, for when
+/// they've been changed by hand or a bug. Returns how many were written.
+pub async fn rebuild(db: &Db) -> Result<u64> {
+    let mut tx = db.writer().begin().await?;
+
+    sqlx::query!("delete from order_summaries;")
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+    let written = sqlx::query!(
+        "INSERT INTO order_summaries (order_id, status, amount, item_count, customer_id, updated_at)
+        SELECT o.id, o.status, o.amount,
+            (SELECT count(*) FROM order_items i WHERE i.order_id = o.id),
+            o.customer_id, o.updated_at
+        FROM orders o;"
+    )
+    .execute(&mut *tx)
+    .timed()
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::test_db,
+        items::Item,
+        orders::{Order, OrderStatus},
+    };
+
+    use super::*;
+
+    /// Summaries that don't match what they'd be built from now, plus orders
+    /// missing one.
+    async fn out_of_sync(db: &Db) -> i64 {
+        sqlx::query_scalar(
+            "select count(*) from (
+                select o.id, o.status, o.amount,
+                    (select count(*) from order_items i where i.order_id = o.id),
+                    o.customer_id, o.updated_at
+                from orders o
+                except
+                select order_id, status, amount, item_count, customer_id, updated_at
+                from order_summaries
+            )",
+        )
+        .fetch_one(db.reader())
+        .await
+        .unwrap()
+    }
+
+    async fn summaries(db: &Db) -> i64 {
+        sqlx::query_scalar("select count(*) from order_summaries")
+            .fetch_one(db.reader())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_every_write_keeps_the_summary_in_sync() {
+        let db = test_db().await;
+
+        let mut order = Order::new(500);
+        order.customer_id = Some("c-1".to_string());
+        order.save(db.writer()).await.unwrap();
+        let mut source = Order::new(300);
+        source.save(db.writer()).await.unwrap();
+        assert_eq!((out_of_sync(&db).await, summaries(&db).await), (0, 2));
+
+        for order_id in [order.id.unwrap(), order.id.unwrap(), source.id.unwrap()] {
+            let mut item = Item {
+                order_id,
+                description: "Mug".to_string(),
+                quantity: 1,
+                unit_price: 250,
+                ..Default::default()
+            };
+            item.save(&db).await.unwrap();
+        }
+        assert_eq!(item_counts(&db, &[order.id.unwrap()]).await.unwrap()[&1], 2);
+        assert_eq!(out_of_sync(&db).await, 0);
+
+        order.amount = 700;
+        order.save(db.writer()).await.unwrap();
+        assert_eq!(out_of_sync(&db).await, 0);
+
+        order.status = OrderStatus::Canceled;
+        order
+            .save_with_history(&db, OrderStatus::Pending)
+            .await
+            .unwrap();
+        assert_eq!(out_of_sync(&db).await, 0);
+
+        order.status = OrderStatus::Pending;
+        order.save(db.writer()).await.unwrap();
+        order.merge_into(&db, &mut source).await.unwrap();
+        assert_eq!(item_counts(&db, &[1, 2]).await.unwrap()[&1], 3);
+        assert_eq!(out_of_sync(&db).await, 0);
+
+        Order::delete_by_id(&db, source.id.unwrap()).await.unwrap();
+        assert_eq!((out_of_sync(&db).await, summaries(&db).await), (0, 1));
+
+        order.status = OrderStatus::Complete;
+        order.save(db.writer()).await.unwrap();
+        Order::archive_before(&db, "9999-01-01").await.unwrap();
+        assert_eq!(summaries(&db).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_after_corruption() {
+        let db = test_db().await;
+
+        for amount in [500, 700, 900] {
+            Order::new(amount).save(db.writer()).await.unwrap();
+        }
+
+        sqlx::query("update order_summaries set amount = 0, item_count = 9 where order_id = 1")
+            .execute(db.writer())
+            .await
+            .unwrap();
+        sqlx::query("delete from order_summaries where order_id = 2")
+            .execute(db.writer())
+            .await
+            .unwrap();
+        sqlx::query("update order_summaries set status = 'complete' where order_id = 3")
+            .execute(db.writer())
+            .await
+            .unwrap();
+        assert_eq!(out_of_sync(&db).await, 3);
+
+        assert_eq!(rebuild(&db).await.unwrap(), 3);
+        assert_eq!((out_of_sync(&db).await, summaries(&db).await), (0, 3));
+    }
+}
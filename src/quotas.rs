@@ -0,0 +1,184 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, Transaction, prelude::FromRow};
+
+use crate::{
+    clock,
+    db::{self, Db},
+    timing::Timed,
+};
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct KeyQuota {
+    pub api_key: String,
+    pub monthly_limit: i64,
+}
+
+impl KeyQuota {
+    pub async fn set(db: &Db, api_key: &str, monthly_limit: i64) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO key_quotas (api_key, monthly_limit) VALUES (?, ?)
+            ON CONFLICT (api_key) DO UPDATE SET monthly_limit = excluded.monthly_limit;",
+            api_key,
+            monthly_limit
+        )
+        .execute(db.writer())
+        .timed()
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Order creations counted against a key for one calendar month (`YYYY-MM`).
+#[derive(Debug, Serialize, Deserialize, FromRow, PartialEq, Eq)]
+pub struct Usage {
+    pub api_key: String,
+    pub period: String,
+    pub count: i64,
+    pub monthly_limit: Option<i64>,
+}
+
+impl Usage {
+    pub async fn get_all(db: &Db) -> Result<Vec<Self>> {
+        Ok(db::read(|| {
+            sqlx::query_as!(
+                Usage,
+                r#"select substr(s.name, 15) as "api_key!: String",
+                    substr(s.name, 7, 7) as "period!: String",
+                    s.value as count, q.monthly_limit
+                from sequences s
+                left join key_quotas q on q.api_key = substr(s.name, 15)
+                where s.name like 'usage:%'
+                order by 2 desc, 1"#
+            )
+            .fetch_all(db.reader())
+            .timed()
+        })
+        .await?)
+    }
+}
+
+/// The outcome of counting a create against a key's quota.
+#[derive(Debug)]
+pub struct Metered {
+    /// Creations this month before the current one.
+    pub usage: i64,
+    pub limit: Option<i64>,
+    /// First day of next month, when the counter starts over.
+    pub resets_on: String,
+}
+
+impl Metered {
+    pub fn exceeded(&self) -> bool {
+        self.limit.is_some_and(|limit| self.usage >= limit)
+    }
+}
+
+/// The sequence counting `api_key`'s creates in `period`.
+fn usage_sequence(period: &str, api_key: &str) -> String {
+    format!("usage:{period}:{api_key}")
+}
+
+/// Bumps the current month's counter for `api_key`. Callers roll back the
+/// transaction when the result is `exceeded()` so rejected creates aren't counted.
+pub async fn record_create(tx: &mut Transaction<'_, Sqlite>, api_key: &str) -> Result<Metered> {
+    let month_start = format!("{}-01", &clock::now()[..7]);
+    let count = db::next_value(tx, &usage_sequence(&month_start[..7], api_key)).await?;
+
+    let quota = sqlx::query!(
+        r#"select
+            (select monthly_limit from key_quotas where api_key = ?) as "monthly_limit: i64",
+            date(?, '+1 month') as "resets_on!: String""#,
+        api_key,
+        month_start
+    )
+    .fetch_one(&mut **tx)
+    .timed()
+    .await?;
+
+    Ok(Metered {
+        usage: count - 1,
+        limit: quota.monthly_limit,
+        resets_on: quota.resets_on,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, orders::Order};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_counts_creates_per_key() {
+        let db = test_db().await;
+
+        for _ in 0..3 {
+            Order::new(500)
+                .save_metered(&db, "key-a")
+                .await
+                .expect("order should save without error");
+        }
+
+        Order::new(500)
+            .save_metered(&db, "key-b")
+            .await
+            .expect("order should save without error");
+
+        let usage = Usage::get_all(&db).await.expect("should not error");
+
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].api_key, "key-a");
+        assert_eq!(usage[0].count, 3);
+        assert_eq!(usage[1].count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_over_quota_without_counting() {
+        let db = test_db().await;
+
+        KeyQuota::set(&db, "key-a", 2).await.unwrap();
+
+        for _ in 0..2 {
+            let metered = Order::new(500).save_metered(&db, "key-a").await.unwrap();
+            assert!(!metered.exceeded());
+        }
+
+        let mut order = Order::new(500);
+        let metered = order.save_metered(&db, "key-a").await.unwrap();
+
+        assert!(metered.exceeded());
+        assert_eq!(metered.usage, 2);
+        assert!(order.id.is_none());
+
+        let orders = Order::get_all(&db).await.unwrap();
+        assert_eq!(orders.len(), 2);
+
+        let usage = Usage::get_all(&db).await.unwrap();
+        assert_eq!(usage[0].count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_counter_scoped_by_month() {
+        let db = test_db().await;
+
+        KeyQuota::set(&db, "key-a", 1).await.unwrap();
+
+        // last year's usage is at the limit but shouldn't count towards this month
+        db::reset(&db, &usage_sequence("2000-01", "key-a"), 1)
+            .await
+            .unwrap();
+
+        let metered = Order::new(500).save_metered(&db, "key-a").await.unwrap();
+
+        assert!(!metered.exceeded());
+        assert_eq!(metered.usage, 0);
+
+        let usage = Usage::get_all(&db).await.unwrap();
+
+        assert_eq!(usage.len(), 2);
+        assert_ne!(usage[0].period, "2000-01");
+        assert_eq!(usage[1].period, "2000-01");
+    }
+}
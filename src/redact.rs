@@ -0,0 +1,216 @@
+//! Keeps personal data like names, addresses and metadata out of what's
+//! written down about requests: the request journal, the debug log of request
+//! bodies and the values in `order_changes`. `REDACTED_FIELDS` lists json
+//! paths, like `shipping_address.name` or `metadata.*`, whose values are
+//! replaced with `"[REDACTED]"` there. Responses are never redacted.
+//!
+//! A path is its keys joined by dots. `*` stands for any one key and `**` for
+//! any number of them, so `**.email` is an `email` key at any depth. Arrays
+//! don't take a key, a path through one applies to each of its elements.
+
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+
+use crate::AppState;
+
+/// What a redacted value is replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// The paths to redact, from `REDACTED_FIELDS`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedactionRules {
+    paths: Vec<Vec<String>>,
+}
+
+impl RedactionRules {
+    /// Reads a comma separated list of paths like `shipping_address.name,metadata.*`.
+    pub fn parse(paths: &str) -> Result<Self> {
+        let mut rules = Self::default();
+
+        for path in paths.split(',').map(str::trim).filter(|path| !path.is_empty()) {
+            let keys: Vec<String> = path.split('.').map(str::to_string).collect();
+
+            if keys.iter().any(String::is_empty) {
+                bail!("REDACTED_FIELDS has a path {path:?} with an empty key");
+            }
+
+            rules.paths.push(keys);
+        }
+
+        Ok(rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// The paths as they were configured.
+    pub fn paths(&self) -> Vec<String> {
+        self.paths.iter().map(|keys| keys.join(".")).collect()
+    }
+
+    fn matches(&self, path: &[&str]) -> bool {
+        self.paths.iter().any(|pattern| matches(pattern, path))
+    }
+}
+
+fn matches(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skipped| matches(rest, &path[skipped..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((key, path)) => (first == "*" || first == key) && matches(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// `value` with every value at a path `rules` lists replaced with `"[REDACTED]"`.
+pub fn redact(value: Value, rules: &RedactionRules) -> Value {
+    walk(&[], value, rules)
+}
+
+/// `redact` for a value found at `key` of a larger document, like one field
+/// of an order.
+pub fn redact_field(key: &str, value: Value, rules: &RedactionRules) -> Value {
+    walk(&[key], value, rules)
+}
+
+fn walk(path: &[&str], value: Value, rules: &RedactionRules) -> Value {
+    if rules.is_empty() {
+        return value;
+    }
+
+    if !path.is_empty() && rules.matches(path) {
+        return Value::String(REDACTED.to_string());
+    }
+
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = {
+                        let mut path = path.to_vec();
+                        path.push(&key);
+                        walk(&path, value, rules)
+                    };
+
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| walk(path, value, rules))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+tokio::task_local! {
+    static RULES: Arc<RedactionRules>;
+}
+
+/// Makes the config's rules the current ones while the request is handled,
+/// for what's written down deep in the models, like `order_changes`.
+pub(crate) async fn scope(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let rules = Arc::new(state.config().redaction.clone());
+
+    RULES.scope(rules, next.run(request)).await
+}
+
+/// Keeps the current rules for `future`, for work a request spawns onto a
+/// task of its own.
+pub fn keep<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let rules = RULES.try_with(Arc::clone).ok();
+
+    async move {
+        match rules {
+            Some(rules) => RULES.scope(rules, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// `redact_field` with the current request's rules. Outside a request, e.g.
+/// in the sweeper or an import job, nothing is redacted.
+pub fn current_field(key: &str, value: Value) -> Value {
+    match RULES.try_with(Arc::clone) {
+        Ok(rules) => redact_field(key, value, &rules),
+        Err(_) => value,
+    }
+}
+
+/// `redact` with the current request's rules, see `current_field`.
+pub fn current(value: Value) -> Value {
+    match RULES.try_with(Arc::clone) {
+        Ok(rules) => redact(value, &rules),
+        Err(_) => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let rules = RedactionRules::parse("shipping_address.name, metadata.*,**.email").unwrap();
+
+        let order = json!({
+            "amount": 500,
+            "shipping_address": { "name": "Ada Lovelace", "country": "GB" },
+            "metadata": { "source": "web", "phone": "555-0100" },
+            "items": [{ "sku": "A-1", "gift": { "email": "ada@example.com" } }],
+            "email": "top@example.com"
+        });
+
+        assert_eq!(
+            redact(order, &rules),
+            json!({
+                "amount": 500,
+                "shipping_address": { "name": REDACTED, "country": "GB" },
+                "metadata": { "source": REDACTED, "phone": REDACTED },
+                "items": [{ "sku": "A-1", "gift": { "email": REDACTED } }],
+                "email": REDACTED
+            })
+        );
+
+        // a field of a larger document has its key in front of its paths
+        assert_eq!(
+            redact_field("metadata", json!({ "source": "web" }), &rules),
+            json!({ "source": REDACTED })
+        );
+        assert_eq!(redact_field("amount", json!(500), &rules), json!(500));
+
+        let whole = RedactionRules::parse("shipping_address").unwrap();
+        assert_eq!(
+            redact(json!({ "shipping_address": { "name": "Ada" } }), &whole),
+            json!({ "shipping_address": REDACTED })
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(RedactionRules::parse("").unwrap().is_empty());
+        assert_eq!(
+            RedactionRules::parse(" metadata.* ,notes").unwrap().paths(),
+            ["metadata.*", "notes"]
+        );
+        assert!(RedactionRules::parse("metadata..source").is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Daily totals for accounting, `GET /reports/daily`. A day is a calendar day
+//! at the configured utc offset, so it starts at that offset's midnight
+//! rather than utc's, and the sums are done in sql over live and archived
+//! orders alike.
+
+use anyhow::Result;
+
+use crate::{
+    db::{self, Db},
+    timing::Timed,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DailyReport {
+    /// Placed that day, drafts aren't counted until they're confirmed.
+    pub orders_created: i64,
+    pub orders_completed: i64,
+    /// The amounts of the orders completed that day.
+    pub gross_amount: i64,
+    pub refunded_amount: i64,
+    pub net_amount: i64,
+}
+
+/// Checks `date` is a real `YYYY-MM-DD` date.
+pub fn check_date(date: &str) -> Result<(), String> {
+    let invalid = || format!("date {date:?} should be a date like 2024-05-01");
+
+    let bytes = date.as_bytes();
+
+    if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return Err(invalid());
+    }
+
+    let number = |range: std::ops::Range<usize>| -> Option<u32> {
+        let digits = &date[range];
+
+        match digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            true => digits.parse().ok(),
+            false => None,
+        }
+    };
+
+    let (Some(year), Some(month), Some(day)) = (number(0..4), number(5..7), number(8..10)) else {
+        return Err(invalid());
+    };
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return Err(invalid()),
+    };
+
+    match (1..=days).contains(&day) {
+        true => Ok(()),
+        false => Err(invalid()),
+    }
+}
+
+/// Reads a utc offset like `+02:00` or `-05:30`, or `UTC`, as minutes east
+/// of utc.
+pub fn parse_offset(value: &str) -> Option<i32> {
+    if matches!(value, "UTC" | "utc" | "Z") {
+        return Some(0);
+    }
+
+    let sign = match value.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+
+    let (hours, minutes) = value[1..].split_once(':')?;
+
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+
+    if hours > 14 || minutes > 59 {
+        return None;
+    }
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Minutes east of utc as `+02:00`.
+pub fn format_offset(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let offset = offset.unsigned_abs();
+
+    format!("{sign}{:02}:{:02}", offset / 60, offset % 60)
+}
+
+/// Totals for `date`, which `check_date` has accepted, with days starting at
+/// midnight `offset` minutes east of utc. A day with no orders, one in the
+/// future say, is all zeros.
+pub async fn daily(db: &Db, date: &str, offset: i32) -> Result<DailyReport> {
+    // moves local midnight back to utc, e.g. `-120 minutes` for +02:00
+    let to_utc = format!("{} minutes", -offset);
+
+    let row = db::read(|| {
+        sqlx::query!(
+            r#"with day as (
+                select strftime('%Y-%m-%dT%H:%M:%fZ', ?1, ?2) as start,
+                strftime('%Y-%m-%dT%H:%M:%fZ', ?1, ?2, '+1 day') as end
+            ),
+            placed as (
+                select status, amount, created_at, completed_at from orders
+                where status != 'draft'
+                union all
+                select status, amount, created_at, completed_at from orders_archive
+            )
+            select
+                (select count(*) from placed, day
+                    where created_at >= day.start and created_at < day.end)
+                    as "orders_created!: i64",
+                (select count(*) from placed, day
+                    where completed_at >= day.start and completed_at < day.end)
+                    as "orders_completed!: i64",
+                (select coalesce(sum(amount), 0) from placed, day
+                    where completed_at >= day.start and completed_at < day.end)
+                    as "gross_amount!: i64""#,
+            date,
+            to_utc
+        )
+        .fetch_one(db.reader())
+        .timed()
+    })
+    .await?;
+
+    // there are no refunds yet, so nothing comes off the gross
+    let refunded_amount = 0;
+
+    Ok(DailyReport {
+        orders_created: row.orders_created,
+        orders_completed: row.orders_completed,
+        gross_amount: row.gross_amount,
+        refunded_amount,
+        net_amount: row.gross_amount - refunded_amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::test_db,
+        orders::{Order, OrderStatus},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_check_date() {
+        for date in ["2024-05-01", "2024-02-29", "2000-02-29", "2023-12-31"] {
+            assert_eq!(check_date(date), Ok(()), "{date}");
+        }
+
+        for date in [
+            "",
+            "2024-5-1",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-13-01",
+            "2024-04-31",
+            "tomorrow",
+        ] {
+            assert!(check_date(date).is_err(), "{date}");
+        }
+    }
+
+    #[test]
+    fn test_offsets() {
+        assert_eq!(parse_offset("UTC"), Some(0));
+        assert_eq!(parse_offset("+02:00"), Some(120));
+        assert_eq!(parse_offset("-05:30"), Some(-330));
+        assert_eq!(parse_offset("Europe/Paris"), None);
+        assert_eq!(parse_offset("+2"), None);
+
+        assert_eq!(format_offset(-330), "-05:30");
+        assert_eq!(format_offset(0), "+00:00");
+    }
+
+    /// Saves an order as if it was created, and completed when `completed_at`
+    /// is given, at those utc times.
+    async fn seed(db: &Db, amount: i64, created_at: &str, completed_at: Option<&str>) {
+        let mut order = Order {
+            amount,
+            status: match completed_at {
+                Some(_) => OrderStatus::Complete,
+                None => OrderStatus::Pending,
+            },
+            ..Default::default()
+        };
+        order.save(db.writer()).await.unwrap();
+
+        sqlx::query("update orders set created_at = ?, completed_at = ? where id = ?")
+            .bind(created_at)
+            .bind(completed_at)
+            .bind(order.id)
+            .execute(db.writer())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_days_start_at_the_offsets_midnight() {
+        let db = test_db().await;
+        let offset = parse_offset("+02:00").unwrap();
+
+        // 2024-05-01 at +02:00 runs from 2024-04-30T22:00Z to 2024-05-01T22:00Z
+        seed(&db, 100, "2024-04-30T21:59:59.999Z", None).await;
+        seed(
+            &db,
+            200,
+            "2024-04-30T22:00:00.000Z",
+            Some("2024-05-01T21:30:00.000Z"),
+        )
+        .await;
+        seed(
+            &db,
+            400,
+            "2024-05-01T12:00:00.000Z",
+            Some("2024-05-01T22:00:00.000Z"),
+        )
+        .await;
+
+        let report = daily(&db, "2024-05-01", offset).await.unwrap();
+
+        assert_eq!(
+            report,
+            DailyReport {
+                orders_created: 2,
+                orders_completed: 1,
+                gross_amount: 200,
+                refunded_amount: 0,
+                net_amount: 200,
+            }
+        );
+
+        // in utc the first two are created on the 30th and both completions are on the 1st
+        let utc = daily(&db, "2024-05-01", 0).await.unwrap();
+        assert_eq!(utc.orders_created, 1);
+        assert_eq!(utc.orders_completed, 2);
+        assert_eq!(utc.gross_amount, 600);
+
+        let before = daily(&db, "2024-04-30", offset).await.unwrap();
+        assert_eq!(before.orders_created, 1);
+
+        let after = daily(&db, "2024-05-02", offset).await.unwrap();
+        assert_eq!(after.orders_completed, 1);
+        assert_eq!(after.gross_amount, 400);
+
+        let future = daily(&db, "2999-01-01", offset).await.unwrap();
+        assert_eq!(future, DailyReport::default());
+    }
+}
@@ -0,0 +1,411 @@
+//! Every request's method, route, status and latency with the api key and a
+//! request id, kept in `request_log` so support can look up what a key did at
+//! a given time, see `GET /admin/requests`. With `REQUEST_LOG_ENABLED` the
+//! middleware hands each request to a bounded channel without waiting, and
+//! `work` writes what's queued every `FLUSH_INTERVAL` in one transaction. When
+//! the channel is full the request isn't logged and
+//! `request_log_dropped_total` counts it, handling a request never waits on
+//! the log. Rows older than `request_log_retention` are pruned, see
+//! `retention/tables.rs`.
+//!
+//! The request id is the `X-Request-Id` the request came with, or a random
+//! one, and is sent back in the response's `X-Request-Id`.
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::{
+    AppState,
+    api_keys::Caller,
+    clock,
+    db::{self, Db},
+    sla,
+    timing::Timed,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// How many requests can wait to be written before more are dropped.
+pub const CHANNEL_CAPACITY: usize = 10_000;
+
+/// How often `work` writes the queued requests.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Request ids sent longer than this are replaced with one of ours.
+const REQUEST_ID_MAX_LEN: usize = 128;
+
+/// A row of `request_log`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, Default)]
+pub struct LoggedRequest {
+    pub id: Option<i64>,
+    pub method: String,
+    /// The route's template, like `/orders/{id}`, or `unmatched`.
+    pub path: String,
+    pub status: i64,
+    pub latency_ms: i64,
+    /// The `Caller`, never the key that was sent.
+    pub api_key: Option<String>,
+    pub request_id: String,
+    pub created_at: String,
+}
+
+/// What `GET /admin/requests` narrows the log to, newest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub api_key: Option<String>,
+    /// A timestamp like the column holds.
+    pub since: Option<String>,
+    pub status: Option<i64>,
+    /// Only rows with a lower id, the last id of the page before.
+    pub before: Option<i64>,
+    pub limit: i64,
+}
+
+/// One key's latencies over a window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyLatency {
+    /// `null` for requests without an api key.
+    pub key: Option<String>,
+    pub requests: usize,
+    /// Milliseconds, nearest rank percentiles.
+    pub p50: i64,
+    pub p95: i64,
+}
+
+/// The channel between the requests and the writer.
+#[derive(Debug)]
+pub struct RequestLog {
+    sender: mpsc::Sender<LoggedRequest>,
+    receiver: Mutex<mpsc::Receiver<LoggedRequest>>,
+    dropped: AtomicU64,
+}
+
+impl Default for RequestLog {
+    fn default() -> Self {
+        Self::new(CHANNEL_CAPACITY)
+    }
+}
+
+impl RequestLog {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+
+        Self {
+            sender,
+            receiver: Mutex::new(receiver),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues the request to be written, or drops it when the queue's full.
+    pub fn push(&self, request: LoggedRequest) {
+        if self.sender.try_send(request).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Requests dropped since startup, because the queue was full or writing
+    /// them failed.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Writes every queued request in one transaction. Returns how many were
+    /// written, the ones a failed write took are counted as dropped.
+    pub async fn flush(&self, db: &Db) -> Result<usize> {
+        let mut queued = Vec::new();
+
+        {
+            let mut receiver = self.receiver.lock().await;
+
+            while let Ok(request) = receiver.try_recv() {
+                queued.push(request);
+            }
+        }
+
+        if queued.is_empty() {
+            return Ok(0);
+        }
+
+        let written = queued.len();
+
+        if let Err(err) = insert(db, &queued).await {
+            self.dropped.fetch_add(written as u64, Ordering::Relaxed);
+
+            return Err(err);
+        }
+
+        Ok(written)
+    }
+}
+
+async fn insert(db: &Db, requests: &[LoggedRequest]) -> Result<()> {
+    let mut tx = db.writer().begin().await?;
+
+    for request in requests {
+        sqlx::query!(
+            "INSERT INTO request_log
+                (method, path, status, latency_ms, api_key, request_id, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?);",
+            request.method,
+            request.path,
+            request.status,
+            request.latency_ms,
+            request.api_key,
+            request.request_id,
+            request.created_at
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// The request's own id when it sent a usable one, a random one otherwise.
+fn request_id(request: &Request) -> String {
+    let sent = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= REQUEST_ID_MAX_LEN);
+
+    match sent {
+        Some(id) => id.to_string(),
+        None => format!("{:032x}", rand::random::<u128>()),
+    }
+}
+
+/// Logs the request once it's answered, when `request_log_enabled` is set.
+pub(crate) async fn record(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.config().request_log_enabled {
+        return next.run(request).await;
+    }
+
+    let started = Instant::now();
+    let created_at = clock::timestamp(state.clock.now());
+    let method = request.method().to_string();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let api_key = Caller::name(request.extensions());
+    let request_id = request_id(&request);
+
+    let mut response = next.run(request).await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    state.request_log.push(LoggedRequest {
+        id: None,
+        method,
+        path,
+        status: i64::from(response.status().as_u16()),
+        latency_ms: started.elapsed().as_millis().try_into().unwrap_or(i64::MAX),
+        api_key,
+        request_id,
+        created_at,
+    });
+
+    response
+}
+
+/// The logged requests `filter` matches, newest first.
+pub async fn find(db: &Db, filter: &Filter) -> Result<Vec<LoggedRequest>> {
+    Ok(db::read(|| {
+        sqlx::query_as!(
+            LoggedRequest,
+            "select * from request_log
+            where (?1 is null or api_key = ?1)
+            and (?2 is null or created_at >= ?2)
+            and (?3 is null or status = ?3)
+            and (?4 is null or id < ?4)
+            order by id desc limit ?5",
+            filter.api_key,
+            filter.since,
+            filter.status,
+            filter.before,
+            filter.limit
+        )
+        .fetch_all(db.reader())
+        .timed()
+    })
+    .await?)
+}
+
+/// The p50 and p95 latency of each key's requests from `from` up to `to`,
+/// both timestamps like the column holds, by key.
+pub async fn stats(db: &Db, from: &str, to: &str) -> Result<Vec<KeyLatency>> {
+    let rows = db::read(|| {
+        sqlx::query!(
+            "select api_key, latency_ms from request_log
+            where created_at >= ? and created_at < ?
+            order by latency_ms",
+            from,
+            to
+        )
+        .fetch_all(db.reader())
+        .timed()
+    })
+    .await?;
+
+    let mut latencies: BTreeMap<Option<String>, Vec<i64>> = BTreeMap::new();
+
+    for row in rows {
+        latencies.entry(row.api_key).or_default().push(row.latency_ms);
+    }
+
+    Ok(latencies
+        .into_iter()
+        .map(|(key, millis)| KeyLatency {
+            key,
+            requests: millis.len(),
+            p50: sla::percentile(&millis, 50),
+            p95: sla::percentile(&millis, 95),
+        })
+        .collect())
+}
+
+/// Writes the queued requests every `FLUSH_INTERVAL` until the process exits.
+pub async fn work(state: AppState) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if !state.integrity.writes_allowed() {
+            continue;
+        }
+
+        if let Err(err) = state.request_log.flush(&state.db).await {
+            tracing::error!("failed to write the request log: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use crate::{config::AppConfig, db::test_db, retention::tables, routes};
+
+    use super::*;
+
+    fn logged(api_key: &str, status: i64, latency_ms: i64, created_at: &str) -> LoggedRequest {
+        LoggedRequest {
+            id: None,
+            method: "GET".to_string(),
+            path: routes::ORDER.to_string(),
+            status,
+            latency_ms,
+            api_key: Some(api_key.to_string()),
+            request_id: format!("req-{latency_ms}"),
+            created_at: created_at.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drops_when_full() {
+        let db = test_db().await;
+        let log = RequestLog::new(2);
+
+        for latency in 1..=3 {
+            log.push(logged("a", 200, latency, "2025-10-01T10:00:00.000Z"));
+        }
+
+        assert_eq!(log.dropped(), 1);
+        assert_eq!(log.flush(&db).await.unwrap(), 2);
+        assert_eq!(log.flush(&db).await.unwrap(), 0);
+
+        // written, there's room again
+        log.push(logged("a", 200, 4, "2025-10-01T10:00:00.000Z"));
+        assert_eq!(log.flush(&db).await.unwrap(), 1);
+        assert_eq!(log.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_find_and_stats() {
+        let db = test_db().await;
+        let log = RequestLog::default();
+
+        for latency in 1..=20 {
+            log.push(logged("a", 200, latency * 10, "2025-10-01T10:00:00.000Z"));
+        }
+        log.push(logged("b", 404, 7, "2025-10-01T11:00:00.000Z"));
+        log.push(logged("b", 200, 9, "2025-10-02T11:00:00.000Z"));
+        log.flush(&db).await.unwrap();
+
+        let filter = Filter {
+            api_key: Some("b".to_string()),
+            limit: 10,
+            ..Default::default()
+        };
+        let found = find(&db, &filter).await.unwrap();
+        assert_eq!(found.iter().map(|r| r.latency_ms).collect::<Vec<_>>(), [9, 7]);
+
+        let filter = Filter { status: Some(404), limit: 10, ..Default::default() };
+        assert_eq!(find(&db, &filter).await.unwrap().len(), 1);
+
+        let filter = Filter {
+            since: Some("2025-10-02T00:00:00.000Z".to_string()),
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(find(&db, &filter).await.unwrap().len(), 1);
+
+        // pages of 5, newest first
+        let page = find(&db, &Filter { limit: 5, ..Default::default() }).await.unwrap();
+        let next = Filter { before: page[4].id, limit: 5, ..Default::default() };
+        let next = find(&db, &next).await.unwrap();
+        assert!(next[0].id < page[4].id);
+        assert_eq!(next.len(), 5);
+
+        let stats = stats(&db, "2025-10-01T00:00:00.000Z", "2025-10-02T00:00:00.000Z")
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            [
+                KeyLatency { key: Some("a".to_string()), requests: 20, p50: 100, p95: 190 },
+                KeyLatency { key: Some("b".to_string()), requests: 1, p50: 7, p95: 7 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_purge_after_retention() {
+        let db = test_db().await;
+        let config = AppConfig::default();
+        let log = RequestLog::default();
+        let start = SystemTime::now();
+
+        log.push(logged("a", 200, 5, &clock::timestamp(start)));
+        log.flush(&db).await.unwrap();
+
+        let expired = start + config.request_log_retention + Duration::from_secs(1);
+
+        assert_eq!(tables::REQUEST_LOG.prune(&db, &config, start).await.unwrap().rows(), 0);
+        assert_eq!(tables::REQUEST_LOG.prune(&db, &config, expired).await.unwrap().rows(), 1);
+    }
+}
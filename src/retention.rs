@@ -0,0 +1,368 @@
+//! The data retention policy: orders completed or canceled more than
+//! `RETENTION_YEARS` ago have the customer's personal data scrubbed, live and
+//! archived alike. Their `customer_id` is nulled, their shipping address
+//! blanked, the metadata keys in `PII_METADATA_KEYS` removed and their notes'
+//! bodies replaced with `TOMBSTONE`. Amounts, statuses and items are left as
+//! they are, the order still counts in reports.
+//!
+//! Each scrubbed order gets one `retention_scrubbed` row in `order_changes`
+//! listing what was scrubbed, never the values. Scrubbed orders have nothing
+//! left to scrub, so the sweeper can run this on every pass and
+//! `enforce-retention --dry-run` only counts what's due.
+//!
+//! A canceled order doesn't record when it was canceled, so it goes by when it
+//! was last updated, and a completed order from before `completed_at` existed
+//! does too. Scrubbing leaves `updated_at` alone so a scrubbed order stays
+//! past the cutoff and is picked up again when `PII_METADATA_KEYS` grows.
+//!
+//! The tables that only grow, like the request journal, have limits of their
+//! own on how old and how many their rows can be, see `tables`.
+
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value, json};
+use sqlx::{Sqlite, Transaction, prelude::FromRow};
+
+use crate::{clock, config::AppConfig, db::Db, orders::history, timing::Timed};
+
+pub mod tables;
+
+/// The `order_changes` field of a scrub, its new value lists what was
+/// scrubbed.
+pub const SCRUBBED: &str = "retention_scrubbed";
+
+/// What a scrubbed note's body becomes.
+pub const TOMBSTONE: &str = "[removed under the data retention policy]";
+
+/// What a pass scrubbed, or with `--dry-run` would have.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub orders: u64,
+    pub customer_id: u64,
+    pub shipping_address: u64,
+    /// Keys removed, an order can have several.
+    pub metadata_keys: u64,
+    pub notes: u64,
+}
+
+/// An order past the cutoff with something left to scrub.
+#[derive(Debug, FromRow)]
+struct Due {
+    id: i64,
+    customer_id: Option<String>,
+    has_address: bool,
+    metadata: String,
+    notes: i64,
+}
+
+/// The live orders and their notes, and the archived ones and theirs.
+const TABLES: [(&str, &str); 2] = [("orders", "order_notes"), ("orders_archive", "order_notes_archive")];
+
+/// The time `years` before `now`, a timestamp like the columns hold. The same
+/// day and time of the year, a 29th of February compares right after the 28th
+/// in a year without one.
+pub fn cutoff(now: SystemTime, years: u32) -> Result<String> {
+    let now = clock::timestamp(now);
+    let year: i64 = now[..4].parse().context("a timestamp starts with its year")?;
+
+    Ok(format!("{:04}{}", year - i64::from(years), &now[4..]))
+}
+
+/// Scrubs the orders `config.retention_years` says are due at `now`, in one
+/// transaction that's rolled back unless `apply`. `None` when the policy is
+/// off.
+pub async fn enforce(
+    db: &Db,
+    config: &AppConfig,
+    now: SystemTime,
+    apply: bool,
+) -> Result<Option<RetentionReport>> {
+    let Some(years) = config.retention_years else {
+        return Ok(None);
+    };
+
+    let cutoff = cutoff(now, years)?;
+    let keys = serde_json::to_string(&config.pii_metadata_keys)?;
+
+    let mut tx = db.writer().begin().await?;
+    let mut report = RetentionReport::default();
+
+    for (orders, notes) in TABLES {
+        let due: Vec<Due> = sqlx::query_as(&format!(
+            "select id, customer_id,
+                coalesce(shipping_name, shipping_line1, shipping_line2, shipping_city,
+                    shipping_region, shipping_postal_code, shipping_country) is not null
+                    as has_address,
+                metadata,
+                (select count(*) from {notes} n where n.order_id = o.id and n.body != ?3) as notes
+            from {orders} o
+            where ((status = 'complete' and coalesce(completed_at, updated_at) < ?1)
+                or (status = 'canceled' and updated_at < ?1))
+            and (customer_id is not null
+                or coalesce(shipping_name, shipping_line1, shipping_line2, shipping_city,
+                    shipping_region, shipping_postal_code, shipping_country) is not null
+                or exists(select 1 from json_each(o.metadata)
+                    where key in (select value from json_each(?2)))
+                or exists(select 1 from {notes} n where n.order_id = o.id and n.body != ?3))
+            order by id"
+        ))
+        .bind(&cutoff)
+        .bind(&keys)
+        .bind(TOMBSTONE)
+        .fetch_all(&mut *tx)
+        .timed()
+        .await?;
+
+        for order in due {
+            scrub(&mut tx, orders, notes, &order, config, &mut report).await?;
+        }
+    }
+
+    match apply {
+        true => tx.commit().await?,
+        false => tx.rollback().await?,
+    }
+
+    Ok(Some(report))
+}
+
+async fn scrub(
+    tx: &mut Transaction<'_, Sqlite>,
+    orders: &str,
+    notes: &str,
+    order: &Due,
+    config: &AppConfig,
+    report: &mut RetentionReport,
+) -> Result<()> {
+    let mut scrubbed = Vec::new();
+
+    if order.customer_id.is_some() {
+        scrubbed.push("customer_id".to_string());
+        report.customer_id += 1;
+    }
+
+    if order.has_address {
+        scrubbed.push("shipping_address".to_string());
+        report.shipping_address += 1;
+    }
+
+    let mut metadata: Map<String, Value> = serde_json::from_str(&order.metadata)
+        .with_context(|| format!("order #{} has invalid metadata", order.id))?;
+
+    for key in &config.pii_metadata_keys {
+        if metadata.remove(key).is_some() {
+            scrubbed.push(format!("metadata.{key}"));
+            report.metadata_keys += 1;
+        }
+    }
+
+    if order.notes > 0 {
+        scrubbed.push("notes".to_string());
+        report.notes += order.notes as u64;
+    }
+
+    report.orders += 1;
+
+    sqlx::query(&format!(
+        "update {orders} set customer_id = null, shipping_name = null, shipping_line1 = null,
+            shipping_line2 = null, shipping_city = null, shipping_region = null,
+            shipping_postal_code = null, shipping_country = null, metadata = ?
+        where id = ?;"
+    ))
+    .bind(Value::Object(metadata).to_string())
+    .bind(order.id)
+    .execute(&mut **tx)
+    .timed()
+    .await?;
+
+    sqlx::query(&format!("update {notes} set body = ? where order_id = ? and body != ?;"))
+        .bind(TOMBSTONE)
+        .bind(order.id)
+        .bind(TOMBSTONE)
+        .execute(&mut **tx)
+        .timed()
+        .await?;
+
+    // archived orders have no summary
+    sqlx::query!(
+        "update order_summaries set customer_id = null where order_id = ?;",
+        order.id
+    )
+    .execute(&mut **tx)
+    .timed()
+    .await?;
+
+    history::record(tx, order.id, SCRUBBED, None, Some(&json!(scrubbed))).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::{
+        address::ShippingAddress,
+        db::test_db,
+        notes::Note,
+        orders::{Order, OrderStatus},
+    };
+
+    use super::*;
+
+    const YEAR: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+    fn config() -> AppConfig {
+        AppConfig {
+            retention_years: Some(7),
+            pii_metadata_keys: vec!["phone".to_string(), "email".to_string()],
+            ..Default::default()
+        }
+    }
+
+    async fn order(db: &Db, status: OrderStatus) -> i64 {
+        let mut order = Order {
+            amount: 1200,
+            status,
+            customer_id: Some("cust-1".to_string()),
+            metadata: serde_json::from_value(json!({ "phone": "555-0100", "source": "web" }))
+                .unwrap(),
+            ..Default::default()
+        };
+        order.set_shipping_address(Some(&ShippingAddress {
+            name: "Ada Lovelace".to_string(),
+            line1: "12 St James's Square".to_string(),
+            line2: None,
+            city: "London".to_string(),
+            region: None,
+            postal_code: "SW1Y 4JH".to_string(),
+            country: "GB".to_string(),
+        }));
+        order.save(db.writer()).await.unwrap();
+
+        let id = order.id.unwrap();
+        Note::create(db, id, Some("sam"), "called them on 555-0100", &[]).await.unwrap();
+
+        id
+    }
+
+    async fn scrubs(db: &Db, order_id: i64) -> Vec<Value> {
+        history::get_by_order_id(db, order_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|change| change.field == SCRUBBED)
+            .map(|change| serde_json::from_str(&change.new_value.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_cutoff() {
+        // 2025-10-16T00:00:00Z
+        let now = UNIX_EPOCH + Duration::from_secs(1_760_572_800);
+
+        assert_eq!(cutoff(now, 7).unwrap(), "2018-10-16T00:00:00.000Z");
+        assert_eq!(cutoff(now, 0).unwrap(), "2025-10-16T00:00:00.000Z");
+    }
+
+    #[tokio::test]
+    async fn test_off_without_retention_years() {
+        let db = test_db().await;
+
+        let report = enforce(&db, &AppConfig::default(), SystemTime::now(), true).await.unwrap();
+        assert_eq!(report, None);
+    }
+
+    #[tokio::test]
+    async fn test_scrubs_only_orders_past_the_cutoff() {
+        let db = test_db().await;
+        let config = config();
+
+        let complete = order(&db, OrderStatus::Complete).await;
+        let canceled = order(&db, OrderStatus::Canceled).await;
+        let open = order(&db, OrderStatus::Pending).await;
+        let later = SystemTime::now() + 8 * YEAR;
+
+        // nothing is seven years old yet
+        let report = enforce(&db, &config, SystemTime::now(), true).await.unwrap();
+        assert_eq!(report, Some(RetentionReport::default()));
+
+        let due = RetentionReport {
+            orders: 2,
+            customer_id: 2,
+            shipping_address: 2,
+            metadata_keys: 2,
+            notes: 2,
+        };
+
+        // a dry run counts without scrubbing
+        let report = enforce(&db, &config, later, false).await.unwrap();
+        assert_eq!(report, Some(due));
+        assert!(scrubs(&db, complete).await.is_empty());
+
+        let report = enforce(&db, &config, later, true).await.unwrap();
+        assert_eq!(report, Some(due));
+
+        for id in [complete, canceled] {
+            let order = Order::get_by_id(&db, id).await.unwrap().unwrap();
+            assert_eq!(order.customer_id, None);
+            assert_eq!(order.shipping_address(), None);
+            assert_eq!(serde_json::to_value(&order.metadata).unwrap(), json!({ "source": "web" }));
+            assert_eq!(order.amount, 1200);
+
+            let notes = Note::get_by_order_id(&db, id).await.unwrap();
+            assert_eq!(notes[0].body, TOMBSTONE);
+
+            assert_eq!(
+                scrubs(&db, id).await,
+                [json!(["customer_id", "shipping_address", "metadata.phone", "notes"])]
+            );
+        }
+
+        assert!(crate::audit::verify(&db).await.unwrap().first_break.is_none());
+
+        let complete = Order::get_by_id(&db, complete).await.unwrap().unwrap();
+        assert_eq!(complete.status, OrderStatus::Complete);
+
+        let open = Order::get_by_id(&db, open).await.unwrap().unwrap();
+        assert_eq!(open.customer_id.as_deref(), Some("cust-1"));
+        assert!(open.shipping_address().is_some());
+
+        // scrubbed orders have nothing left to scrub
+        let report = enforce(&db, &config, later, true).await.unwrap();
+        assert_eq!(report, Some(RetentionReport::default()));
+    }
+
+    #[tokio::test]
+    async fn test_scrubs_archived_orders() {
+        let db = test_db().await;
+
+        let id = order(&db, OrderStatus::Complete).await;
+        assert_eq!(Order::archive_before(&db, "9999-01-01").await.unwrap(), 1);
+
+        let report = enforce(&db, &config(), SystemTime::now() + 8 * YEAR, true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.orders, 1);
+
+        let archived: (Option<String>, Option<String>, String) = sqlx::query_as(
+            "select customer_id, shipping_name, metadata from orders_archive where id = ?",
+        )
+        .bind(id)
+        .fetch_one(db.reader())
+        .await
+        .unwrap();
+        assert_eq!(archived, (None, None, r#"{"source":"web"}"#.to_string()));
+
+        let bodies: Vec<String> =
+            sqlx::query_scalar("select body from order_notes_archive where order_id = ?")
+                .bind(id)
+                .fetch_all(db.reader())
+                .await
+                .unwrap();
+        assert_eq!(bodies, [TOMBSTONE]);
+        assert_eq!(scrubs(&db, id).await.len(), 1);
+    }
+}
@@ -0,0 +1,458 @@
+//! Size bounds for the tables that only ever grow: the request journal, the
+//! request log, stored idempotency responses, finished webhook deliveries and
+//! the audit log. Each is a `Table` in `TABLES` with the limits it takes from
+//! the config, rows older than `max_age` are pruned and then the oldest rows
+//! past `max_rows`.
+//!
+//! `work` runs a pass over every table every `PRUNE_INTERVAL`. Rows are
+//! deleted `BATCH_SIZE` at a time, each batch its own statement with a pause
+//! after it, so a big backlog never holds the write lock for long. What each
+//! table pruned and how long it took is counted in `/metrics`.
+//!
+//! Pending webhook deliveries are never pruned. The audit log is only pruned
+//! from its oldest row on, and the `row_hash` of the last row pruned is kept
+//! in `audit_chain_anchors` so the rest of the chain still verifies.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Result;
+
+use crate::{
+    AppState, clock,
+    config::{AppConfig, TableLimits},
+    db::Db,
+    metrics::Metrics,
+    timing::Timed,
+};
+
+/// How often `work` runs a pass.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Rows deleted per statement.
+pub const BATCH_SIZE: i64 = 500;
+
+/// The pause after each batch, for other writers to get the lock.
+const BATCH_PAUSE: Duration = Duration::from_millis(10);
+
+/// A table pruned by age and row count.
+#[derive(Debug)]
+pub struct Table {
+    pub name: &'static str,
+    /// The column rows are pruned in the order of, oldest first.
+    key: &'static str,
+    /// The column with when the row was written.
+    written_at: &'static str,
+    /// Which rows can be pruned at all.
+    prunable: &'static str,
+    /// Whether it's the audit log, which keeps its anchor.
+    chained: bool,
+    limits: fn(&AppConfig) -> TableLimits,
+}
+
+pub const JOURNAL: Table = Table {
+    name: "request_journal",
+    key: "id",
+    written_at: "created_at",
+    prunable: "true",
+    chained: false,
+    limits: |config| config.journal_limits,
+};
+
+pub const REQUEST_LOG: Table = Table {
+    name: "request_log",
+    key: "id",
+    written_at: "created_at",
+    prunable: "true",
+    chained: false,
+    limits: |config| TableLimits {
+        max_age: Some(config.request_log_retention),
+        max_rows: config.request_log_max_rows,
+    },
+};
+
+pub const IDEMPOTENCY: Table = Table {
+    name: "idempotency_responses",
+    key: "rowid",
+    written_at: "created_at",
+    prunable: "true",
+    chained: false,
+    limits: |config| TableLimits {
+        max_age: Some(config.idempotency_ttl),
+        max_rows: config.idempotency_max_rows,
+    },
+};
+
+pub const WEBHOOK_DELIVERIES: Table = Table {
+    name: "webhook_deliveries",
+    key: "id",
+    written_at: "created_at",
+    prunable: "status != 'pending'",
+    chained: false,
+    limits: |config| config.webhook_delivery_limits,
+};
+
+pub const AUDIT_LOG: Table = Table {
+    name: "order_changes",
+    key: "id",
+    written_at: "changed_at",
+    prunable: "true",
+    chained: true,
+    limits: |config| config.audit_log_limits,
+};
+
+pub const TABLES: &[Table] = &[JOURNAL, REQUEST_LOG, IDEMPOTENCY, WEBHOOK_DELIVERIES, AUDIT_LOG];
+
+/// What a table's pass pruned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pruned {
+    /// Rows older than `max_age`.
+    pub by_age: u64,
+    /// The oldest rows past `max_rows`, after those.
+    pub by_rows: u64,
+    pub took: Duration,
+}
+
+impl Pruned {
+    pub fn rows(&self) -> u64 {
+        self.by_age + self.by_rows
+    }
+}
+
+impl Table {
+    /// Prunes the rows `config` says are past the table's limits at `now`.
+    pub async fn prune(&self, db: &Db, config: &AppConfig, now: SystemTime) -> Result<Pruned> {
+        self.prune_in_batches(db, &(self.limits)(config), now, BATCH_SIZE).await
+    }
+
+    async fn prune_in_batches(
+        &self,
+        db: &Db,
+        limits: &TableLimits,
+        now: SystemTime,
+        batch_size: i64,
+    ) -> Result<Pruned> {
+        let started = Instant::now();
+        let mut pruned = Pruned::default();
+
+        if let Some(cutoff) = limits.max_age.and_then(|max_age| now.checked_sub(max_age)) {
+            let cutoff = clock::timestamp(cutoff);
+
+            loop {
+                let deleted = self.delete_oldest(db, Some(&cutoff), batch_size).await?;
+                pruned.by_age += deleted;
+
+                if deleted < batch_size as u64 {
+                    break;
+                }
+                tokio::time::sleep(BATCH_PAUSE).await;
+            }
+        }
+
+        if let Some(max_rows) = limits.max_rows {
+            let rows: i64 = sqlx::query_scalar(&format!("select count(*) from {}", self.name))
+                .fetch_one(db.reader())
+                .timed()
+                .await?;
+            let mut excess = rows - max_rows;
+
+            while excess > 0 {
+                let deleted = self.delete_oldest(db, None, excess.min(batch_size)).await?;
+                pruned.by_rows += deleted;
+                excess -= deleted as i64;
+
+                // the rest can't be pruned, like pending deliveries
+                if deleted == 0 {
+                    break;
+                }
+                tokio::time::sleep(BATCH_PAUSE).await;
+            }
+        }
+
+        pruned.took = started.elapsed();
+
+        Ok(pruned)
+    }
+
+    /// Deletes up to `limit` of the oldest prunable rows, only ones written up
+    /// to `cutoff` when there is one. Returns how many were deleted.
+    async fn delete_oldest(&self, db: &Db, cutoff: Option<&str>, limit: i64) -> Result<u64> {
+        let Table { name, key, written_at, prunable, .. } = self;
+        let oldest = format!(
+            "select {key} from {name}
+            where ({prunable}) and (?1 is null or {written_at} <= ?1)
+            order by {key} limit ?2"
+        );
+
+        if !self.chained {
+            let deleted = sqlx::query(&format!("delete from {name} where {key} in ({oldest});"))
+                .bind(cutoff)
+                .bind(limit)
+                .execute(db.writer())
+                .timed()
+                .await?
+                .rows_affected();
+
+            return Ok(deleted);
+        }
+
+        // the chain only verifies from its start, so every row up to the last
+        // one due goes, and the anchor moves on to that row
+        let mut tx = db.writer().begin().await?;
+
+        let last: Option<i64> = sqlx::query_scalar(&format!("select max({key}) from ({oldest})"))
+            .bind(cutoff)
+            .bind(limit)
+            .fetch_one(&mut *tx)
+            .timed()
+            .await?;
+
+        let Some(last) = last else {
+            return Ok(0);
+        };
+
+        let anchor: Option<(i64, String)> = sqlx::query_as(
+            "select id, row_hash from order_changes
+            where id <= ? and row_hash is not null order by id desc limit 1",
+        )
+        .bind(last)
+        .fetch_optional(&mut *tx)
+        .timed()
+        .await?;
+
+        // rows from before the chain have no hash to anchor on
+        if let Some((id, hash)) = anchor {
+            let pruned_at = clock::now();
+            sqlx::query!(
+                "INSERT INTO audit_chain_anchors (last_id, last_hash, pruned_at) VALUES (?, ?, ?);",
+                id,
+                hash,
+                pruned_at,
+            )
+            .execute(&mut *tx)
+            .timed()
+            .await?;
+
+            sqlx::query!("delete from audit_chain_anchors where last_id < ?;", id)
+                .execute(&mut *tx)
+                .timed()
+                .await?;
+        }
+
+        let deleted = sqlx::query!("delete from order_changes where id <= ?;", last)
+            .execute(&mut *tx)
+            .timed()
+            .await?
+            .rows_affected();
+
+        tx.commit().await?;
+
+        Ok(deleted)
+    }
+}
+
+/// Prunes every table, recording what each pruned in `metrics`. A table that
+/// fails doesn't stop the others.
+pub async fn prune_all(
+    db: &Db,
+    config: &AppConfig,
+    metrics: &Metrics,
+    now: SystemTime,
+) -> BTreeMap<&'static str, Result<Pruned>> {
+    let mut results = BTreeMap::new();
+
+    for table in TABLES {
+        let result = table.prune(db, config, now).await;
+
+        if let Ok(pruned) = &result {
+            metrics.record_prune(table.name, pruned.rows(), pruned.took);
+        }
+
+        results.insert(table.name, result);
+    }
+
+    results
+}
+
+/// Runs a pass every `PRUNE_INTERVAL` until the process exits.
+pub async fn work(state: AppState) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if !state.integrity.writes_allowed() {
+            continue;
+        }
+
+        let config = state.config();
+
+        for (table, result) in prune_all(&state.db, &config, &state.metrics, SystemTime::now()).await {
+            match result {
+                Ok(pruned) if pruned.rows() > 0 => {
+                    tracing::info!(
+                        "pruned {} {table} rows by age and {} past its row limit in {:?}",
+                        pruned.by_age,
+                        pruned.by_rows,
+                        pruned.took
+                    );
+                }
+                Ok(_) => {}
+                Err(err) => tracing::error!("failed to prune {table}: {err:#}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{audit, db::test_db, orders::Order};
+
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    /// Inserts a row into `table` written `age` before `now`.
+    async fn insert(db: &Db, table: &Table, now: SystemTime, age: Duration) {
+        let at = clock::timestamp(now - age);
+
+        let sql = match table.name {
+            "request_journal" => {
+                "insert into request_journal (method, path, body, created_at)
+                values ('POST', '/orders', '{}', ?)"
+            }
+            "webhook_deliveries" => {
+                "insert into webhook_deliveries (webhook_id, system_event_id, status, created_at)
+                values (1, 1, 'delivered', ?)"
+            }
+            name => unreachable!("no rows for {name}"),
+        };
+
+        sqlx::query(sql).bind(at).execute(db.writer()).await.unwrap();
+    }
+
+    async fn count(db: &Db, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("select count(*) from {table}"))
+            .fetch_one(db.reader())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prunes_by_age_then_row_count() {
+        let db = test_db().await;
+        let now = SystemTime::now();
+
+        for age in [50, 40, 30, 3, 2, 1] {
+            insert(&db, &JOURNAL, now, age * HOUR).await;
+        }
+
+        let limits = TableLimits { max_age: Some(24 * HOUR), max_rows: Some(2) };
+        let pruned = JOURNAL.prune_in_batches(&db, &limits, now, 2).await.unwrap();
+        assert_eq!((pruned.by_age, pruned.by_rows), (3, 1));
+
+        let left: Vec<String> = sqlx::query_scalar("select created_at from request_journal order by id")
+            .fetch_all(db.reader())
+            .await
+            .unwrap();
+        assert_eq!(left, [clock::timestamp(now - 2 * HOUR), clock::timestamp(now - HOUR)]);
+
+        // within its limits there's nothing more to prune
+        let pruned = JOURNAL.prune_in_batches(&db, &limits, now, 2).await.unwrap();
+        assert_eq!(pruned.rows(), 0);
+
+        // without limits nothing goes
+        let pruned = JOURNAL.prune_in_batches(&db, &TableLimits::default(), now, 2).await.unwrap();
+        assert_eq!(pruned.rows(), 0);
+        assert_eq!(count(&db, "request_journal").await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_pending_deliveries_are_kept() {
+        let db = test_db().await;
+        let now = SystemTime::now();
+
+        sqlx::query("insert into webhooks (url) values ('http://localhost:1/hook')")
+            .execute(db.writer())
+            .await
+            .unwrap();
+        sqlx::query("insert into system_events (kind, detail) values ('test', '')")
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        for age in [30, 20, 10] {
+            insert(&db, &WEBHOOK_DELIVERIES, now, age * HOUR).await;
+        }
+        sqlx::query("update webhook_deliveries set status = 'pending' where id = 1")
+            .execute(db.writer())
+            .await
+            .unwrap();
+
+        let limits = TableLimits { max_age: Some(HOUR), max_rows: Some(0) };
+        let pruned = WEBHOOK_DELIVERIES.prune_in_batches(&db, &limits, now, 10).await.unwrap();
+        assert_eq!(pruned.rows(), 2);
+
+        let left: Vec<String> = sqlx::query_scalar("select status from webhook_deliveries")
+            .fetch_all(db.reader())
+            .await
+            .unwrap();
+        assert_eq!(left, ["pending"]);
+    }
+
+    #[tokio::test]
+    async fn test_pruned_audit_log_still_verifies() {
+        let db = test_db().await;
+
+        let mut order = Order::new(500);
+        order.save(db.writer()).await.unwrap();
+        for amount in [600, 700, 800, 900] {
+            order.amount = amount;
+            order.save(db.writer()).await.unwrap();
+        }
+        let changes = count(&db, "order_changes").await;
+
+        let limits = TableLimits { max_age: None, max_rows: Some(2) };
+        let pruned = AUDIT_LOG.prune_in_batches(&db, &limits, SystemTime::now(), 1).await.unwrap();
+        assert_eq!(pruned.by_rows as i64, changes - 2);
+        assert_eq!(count(&db, "order_changes").await, 2);
+
+        let verification = audit::verify(&db).await.unwrap();
+        assert!(verification.is_intact(), "{:?}", verification.first_break);
+        assert_eq!(verification.checked, 2);
+
+        // new changes chain onto the rest
+        order.amount = 1000;
+        order.save(db.writer()).await.unwrap();
+        assert!(audit::verify(&db).await.unwrap().is_intact());
+
+        // pruned without the anchor, the first row left is a break
+        sqlx::query("delete from audit_chain_anchors").execute(db.writer()).await.unwrap();
+        assert!(!audit::verify(&db).await.unwrap().is_intact());
+    }
+
+    #[tokio::test]
+    async fn test_prune_all_counts_per_table() {
+        let db = test_db().await;
+        let now = SystemTime::now();
+        let config = AppConfig {
+            journal_limits: TableLimits { max_age: Some(HOUR), max_rows: None },
+            ..Default::default()
+        };
+        let metrics = Metrics::default();
+
+        insert(&db, &JOURNAL, now, 2 * HOUR).await;
+        insert(&db, &JOURNAL, now, Duration::ZERO).await;
+
+        let results = prune_all(&db, &config, &metrics, now).await;
+        assert_eq!(results.len(), TABLES.len());
+        assert_eq!(results["request_journal"].as_ref().unwrap().by_age, 1);
+        assert_eq!(results["order_changes"].as_ref().unwrap().rows(), 0);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("retention_pruned_rows_total{table=\"request_journal\"} 1"), "{rendered}");
+        assert!(rendered.contains("retention_prune_seconds_count{table=\"order_changes\"} 1"), "{rendered}");
+    }
+}
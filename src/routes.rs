@@ -0,0 +1,375 @@
+//! Every path the app routes, as the templates the router registers and
+//! builders that fill them in. The router, the `Location` headers, the
+//! OpenAPI document and the tests all take their paths from here, so a
+//! renamed route can't leave a stale copy behind.
+//!
+//! The router only registers a path through `RouterExt::at`, which refuses
+//! one that isn't in `all()`.
+
+use std::fmt::Display;
+
+use axum::{Router, routing::MethodRouter};
+use serde::Serialize;
+
+use crate::dto::{OrderFilter, PageParams};
+
+pub const ORDERS: &str = "/orders";
+pub const ORDER_COUNT: &str = "/orders/count";
+pub const ORDER_STATS: &str = "/orders/stats";
+pub const ORDER_SEARCH: &str = "/orders/search";
+pub const PICK_LIST: &str = "/orders/pick-list";
+pub const ORDER_EXPORT: &str = "/orders/export";
+pub const ORDER_IMPORT: &str = "/orders/import";
+pub const ORDER_GRAPH_EXPORT: &str = "/orders/export/graph.ndjson";
+pub const ORDER_GRAPH_IMPORT: &str = "/orders/import/graph";
+pub const ORDER_TRANSACTIONS: &str = "/orders/transactions";
+pub const IMPORT_JOB: &str = "/import-jobs/{id}";
+pub const ORDER: &str = "/orders/{id}";
+pub const ORDER_ITEMS: &str = "/orders/{id}/items";
+pub const ORDER_ITEM: &str = "/orders/{id}/items/{item_id}";
+pub const ORDER_EVENTS: &str = "/orders/{id}/events";
+pub const ORDER_AS_OF: &str = "/orders/{id}/as-of";
+pub const ORDER_DURATIONS: &str = "/orders/{id}/durations";
+pub const ORDER_PREVIEW_TOTALS: &str = "/orders/{id}/preview-totals";
+pub const ORDER_NOTES: &str = "/orders/{id}/notes";
+pub const ORDER_MERGE: &str = "/orders/{id}/merge";
+pub const ORDER_REOPEN: &str = "/orders/{id}/reopen";
+pub const ORDER_CONFIRM: &str = "/orders/{id}/confirm";
+pub const EVENT_STREAM: &str = "/events/stream";
+pub const PRODUCTS: &str = "/products";
+pub const PRODUCT: &str = "/products/{id}";
+pub const WEBHOOKS: &str = "/webhooks";
+pub const WEBHOOK: &str = "/webhooks/{id}";
+pub const USAGE: &str = "/admin/usage";
+pub const REQUEST_LOG: &str = "/admin/requests";
+pub const REQUEST_STATS: &str = "/admin/requests/stats";
+pub const QUOTA: &str = "/admin/quotas/{api_key}";
+pub const API_KEYS: &str = "/admin/api-keys";
+pub const API_KEY_REVOKE: &str = "/admin/api-keys/{id}/revoke";
+pub const API_KEY_ROTATE: &str = "/admin/api-keys/{id}/rotate";
+pub const SEQUENCE: &str = "/admin/sequences/{name}";
+pub const GROWTH: &str = "/admin/stats/growth";
+pub const RUNTIME: &str = "/admin/stats/runtime";
+pub const ERROR_STATS: &str = "/admin/stats/errors";
+pub const MAINTENANCE_STATS: &str = "/admin/stats/maintenance";
+pub const CACHES: &str = "/admin/caches";
+pub const CONSISTENCY_CHECK: &str = "/admin/consistency-check";
+pub const AUDIT_VERIFY: &str = "/admin/audit/verify";
+pub const RECOMPUTE: &str = "/admin/orders/{id}/recompute";
+pub const RETAG: &str = "/admin/orders/retag";
+pub const BACKUP: &str = "/admin/backup";
+pub const CONFIG_RELOAD: &str = "/admin/config/reload";
+pub const EVENT_REPLAY: &str = "/admin/events/replay";
+pub const REPLAY: &str = "/admin/replays/{id}";
+pub const DAILY_REPORT: &str = "/reports/daily";
+pub const SLA_REPORT: &str = "/reports/sla";
+pub const SCHEMA: &str = "/meta/schemas/{name}";
+pub const CONFIG: &str = "/meta/config";
+pub const ERROR_CODES: &str = "/meta/error-codes";
+pub const OPENAPI: &str = "/openapi.json";
+pub const METRICS: &str = "/metrics";
+pub const HEALTHZ: &str = "/healthz";
+pub const READYZ: &str = "/readyz";
+
+/// The html admin pages, routed with the `admin-ui` feature.
+pub const ADMIN_UI: &str = "/admin/ui";
+pub const ADMIN_UI_ORDER: &str = "/admin/ui/orders/{id}";
+pub const ADMIN_UI_ORDER_STATUS: &str = "/admin/ui/orders/{id}/status";
+
+/// The api's paths with every optional feature on, the ones in the OpenAPI
+/// document.
+pub const API: &[&str] = &[
+    ORDERS,
+    ORDER_COUNT,
+    ORDER_STATS,
+    ORDER_SEARCH,
+    PICK_LIST,
+    ORDER_EXPORT,
+    ORDER_IMPORT,
+    ORDER_GRAPH_EXPORT,
+    ORDER_GRAPH_IMPORT,
+    ORDER_TRANSACTIONS,
+    IMPORT_JOB,
+    ORDER,
+    ORDER_ITEMS,
+    ORDER_ITEM,
+    ORDER_EVENTS,
+    ORDER_AS_OF,
+    ORDER_DURATIONS,
+    ORDER_PREVIEW_TOTALS,
+    ORDER_NOTES,
+    ORDER_MERGE,
+    ORDER_REOPEN,
+    ORDER_CONFIRM,
+    EVENT_STREAM,
+    PRODUCTS,
+    PRODUCT,
+    WEBHOOKS,
+    WEBHOOK,
+    USAGE,
+    REQUEST_LOG,
+    REQUEST_STATS,
+    QUOTA,
+    API_KEYS,
+    API_KEY_REVOKE,
+    API_KEY_ROTATE,
+    SEQUENCE,
+    GROWTH,
+    RUNTIME,
+    ERROR_STATS,
+    MAINTENANCE_STATS,
+    CACHES,
+    CONSISTENCY_CHECK,
+    AUDIT_VERIFY,
+    RECOMPUTE,
+    RETAG,
+    BACKUP,
+    CONFIG_RELOAD,
+    EVENT_REPLAY,
+    REPLAY,
+    DAILY_REPORT,
+    SLA_REPORT,
+    SCHEMA,
+    CONFIG,
+    ERROR_CODES,
+    OPENAPI,
+    METRICS,
+    HEALTHZ,
+    READYZ,
+];
+
+pub const ADMIN_UI_PAGES: &[&str] = &[ADMIN_UI, ADMIN_UI_ORDER, ADMIN_UI_ORDER_STATUS];
+
+/// Every path this build can route, the admin pages only with `admin-ui`.
+pub fn all() -> impl Iterator<Item = &'static str> {
+    let pages: &[&str] = if cfg!(feature = "admin-ui") { ADMIN_UI_PAGES } else { &[] };
+
+    API.iter().chain(pages).copied()
+}
+
+/// Registers routes by the templates above.
+pub(crate) trait RouterExt<S> {
+    /// `Router::route`, panicking on a path that isn't in `all()` so that
+    /// every route has its constant.
+    fn at(self, path: &'static str, method_router: MethodRouter<S>) -> Self;
+}
+
+impl<S: Clone + Send + Sync + 'static> RouterExt<S> for Router<S> {
+    fn at(self, path: &'static str, method_router: MethodRouter<S>) -> Self {
+        assert!(all().any(|known| known == path), "{path} has no constant in routes.rs");
+
+        self.route(path, method_router)
+    }
+}
+
+/// `template` with its `{...}` segments replaced by `params` in order. Ids
+/// and names go in as they are, none of them need escaping.
+fn fill(template: &str, params: &[&dyn Display]) -> String {
+    let mut params = params.iter();
+    let path: Vec<String> = template
+        .split('/')
+        .map(|segment| match segment.starts_with('{') && segment.ends_with('}') {
+            true => params.next().expect("a param for each segment").to_string(),
+            false => segment.to_string(),
+        })
+        .collect();
+
+    debug_assert!(params.next().is_none(), "more params than {template} has segments");
+
+    path.join("/")
+}
+
+/// `path` with `query` urlencoded after it, just `path` when `query` has no
+/// parameters.
+fn with_query(path: &str, query: &impl Serialize) -> String {
+    let query = serde_urlencoded::to_string(query).expect("query parameters serialize");
+
+    match query.is_empty() {
+        true => path.to_string(),
+        false => format!("{path}?{query}"),
+    }
+}
+
+/// `GET /orders` narrowed by `filter`.
+pub fn orders_with(filter: &OrderFilter) -> String {
+    with_query(ORDERS, filter)
+}
+
+/// A page of `GET /orders` narrowed by `filter`.
+pub fn orders_page(filter: &OrderFilter, page: &PageParams) -> String {
+    let path = orders_with(filter);
+    let page = serde_urlencoded::to_string(page).expect("query parameters serialize");
+
+    match (page.is_empty(), path.contains('?')) {
+        (true, _) => path,
+        (false, true) => format!("{path}&{page}"),
+        (false, false) => format!("{path}?{page}"),
+    }
+}
+
+/// `POST /admin/orders/retag` of the orders `filter` picks.
+pub fn retag_with(filter: &OrderFilter) -> String {
+    with_query(RETAG, filter)
+}
+
+pub fn import_job(id: impl Display) -> String {
+    fill(IMPORT_JOB, &[&id])
+}
+
+pub fn order(id: impl Display) -> String {
+    fill(ORDER, &[&id])
+}
+
+pub fn order_items(id: impl Display) -> String {
+    fill(ORDER_ITEMS, &[&id])
+}
+
+pub fn order_item(id: impl Display, item_id: impl Display) -> String {
+    fill(ORDER_ITEM, &[&id, &item_id])
+}
+
+pub fn order_events(id: impl Display) -> String {
+    fill(ORDER_EVENTS, &[&id])
+}
+
+pub fn order_as_of(id: impl Display) -> String {
+    fill(ORDER_AS_OF, &[&id])
+}
+
+pub fn order_durations(id: impl Display) -> String {
+    fill(ORDER_DURATIONS, &[&id])
+}
+
+pub fn order_preview_totals(id: impl Display) -> String {
+    fill(ORDER_PREVIEW_TOTALS, &[&id])
+}
+
+pub fn order_notes(id: impl Display) -> String {
+    fill(ORDER_NOTES, &[&id])
+}
+
+pub fn order_merge(id: impl Display) -> String {
+    fill(ORDER_MERGE, &[&id])
+}
+
+pub fn order_reopen(id: impl Display) -> String {
+    fill(ORDER_REOPEN, &[&id])
+}
+
+pub fn order_confirm(id: impl Display) -> String {
+    fill(ORDER_CONFIRM, &[&id])
+}
+
+pub fn product(id: impl Display) -> String {
+    fill(PRODUCT, &[&id])
+}
+
+pub fn webhook(id: impl Display) -> String {
+    fill(WEBHOOK, &[&id])
+}
+
+pub fn quota(api_key: impl Display) -> String {
+    fill(QUOTA, &[&api_key])
+}
+
+pub fn api_key_revoke(id: impl Display) -> String {
+    fill(API_KEY_REVOKE, &[&id])
+}
+
+pub fn api_key_rotate(id: impl Display) -> String {
+    fill(API_KEY_ROTATE, &[&id])
+}
+
+pub fn sequence(name: impl Display) -> String {
+    fill(SEQUENCE, &[&name])
+}
+
+pub fn recompute(id: impl Display) -> String {
+    fill(RECOMPUTE, &[&id])
+}
+
+pub fn replay(id: impl Display) -> String {
+    fill(REPLAY, &[&id])
+}
+
+pub fn schema(name: impl Display) -> String {
+    fill(SCHEMA, &[&name])
+}
+
+pub fn admin_ui_order(id: impl Display) -> String {
+    fill(ADMIN_UI_ORDER, &[&id])
+}
+
+pub fn admin_ui_order_status(id: impl Display) -> String {
+    fill(ADMIN_UI_ORDER_STATUS, &[&id])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::{app, db::test_db, openapi, orders::OrderStatus};
+
+    use super::*;
+
+    #[test]
+    fn test_builders_fill_templates() {
+        assert_eq!(order(7), "/orders/7");
+        assert_eq!(order_item(7, 3), "/orders/7/items/3");
+        assert_eq!(quota("key-a"), "/admin/quotas/key-a");
+        assert_eq!(admin_ui_order_status(7), "/admin/ui/orders/7/status");
+
+        assert_eq!(orders_with(&OrderFilter::default()), "/orders");
+        assert_eq!(
+            orders_with(&OrderFilter {
+                status: Some(OrderStatus::Pending),
+                metadata: BTreeMap::from([("source".to_string(), "web app".to_string())]),
+                include_archived: true,
+                ..Default::default()
+            }),
+            "/orders?status=pending&metadata.source=web+app&include_archived=true"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_every_path_is_routed() {
+        let app = app(test_db().await);
+
+        let unique: HashSet<_> = all().collect();
+        assert_eq!(unique.len(), all().count(), "a path is listed twice");
+
+        for path in all() {
+            let uri = path
+                .split('/')
+                .map(|segment| match segment.starts_with('{') {
+                    true => "1",
+                    false => segment,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+
+            // no route takes TRACE, so a routed path answers 405 where an
+            // unrouted one is the router's 404
+            let response = app
+                .clone()
+                .oneshot(Request::builder().method(Method::TRACE).uri(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{path} isn't routed");
+        }
+    }
+
+    #[test]
+    fn test_openapi_documents_every_api_path() {
+        let documented: HashSet<_> = openapi::operations().map(|(_, path)| path).collect();
+
+        assert_eq!(documented, API.iter().copied().collect());
+    }
+}
@@ -0,0 +1,169 @@
+use serde_json::{Value, json};
+
+use crate::{
+    constraints::{
+        ACTOR_MAX_LEN, ADDRESS_FIELD_MAX_LEN, AMOUNT_MIN, COUNTRY_PATTERN, CURRENCY_PATTERN, CUSTOMER_ID_MAX_LEN, DESCRIPTION_MAX_LEN, METADATA_KEY_MAX_LEN,
+        METADATA_KEY_PATTERN, METADATA_MAX_KEYS, METADATA_VALUE_MAX_LEN, NOTE_MAX_LEN, QUANTITY_MIN,
+    },
+    orders::OrderStatus,
+};
+
+const DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// JSON Schemas for request bodies, looked up by the name used in `/meta/schemas/{name}`.
+pub fn get(name: &str) -> Option<Value> {
+    match name {
+        "order" => Some(order()),
+        "order-status" => Some(order_status()),
+        "order-item" => Some(order_item()),
+        "order-note" => Some(order_note()),
+        _ => None,
+    }
+}
+
+fn status() -> Value {
+    let values = OrderStatus::ALL
+        .iter()
+        .map(|status| serde_json::to_value(status).unwrap())
+        .collect::<Vec<_>>();
+
+    json!({ "type": "string", "enum": values })
+}
+
+/// `patch` allows null values, which remove a key.
+fn metadata(patch: bool) -> Value {
+    let mut types = vec!["string", "number", "boolean"];
+
+    if patch {
+        types.push("null");
+    }
+
+    json!({
+        "type": "object",
+        "maxProperties": METADATA_MAX_KEYS,
+        "propertyNames": { "pattern": METADATA_KEY_PATTERN, "maxLength": METADATA_KEY_MAX_LEN },
+        "additionalProperties": { "type": types, "maxLength": METADATA_VALUE_MAX_LEN },
+    })
+}
+
+fn order() -> Value {
+    json!({
+        "$schema": DRAFT,
+        "title": "Create order",
+        "type": "object",
+        "properties": {
+            "amount": { "type": "integer" },
+            // every order starts as pending, drafts once they're confirmed
+            "status": { "const": "pending" },
+            "draft": { "type": "boolean" },
+            "customer_id": { "type": "string", "minLength": 1, "maxLength": CUSTOMER_ID_MAX_LEN },
+            "currency": currency(),
+            "metadata": metadata(false),
+            "shipping_address": shipping_address(false),
+        },
+        "required": ["amount"],
+        // drafts are validated when they're confirmed
+        "if": { "properties": { "draft": { "const": true } }, "required": ["draft"] },
+        "else": { "properties": { "amount": { "minimum": AMOUNT_MIN } } },
+    })
+}
+
+fn order_status() -> Value {
+    json!({
+        "$schema": DRAFT,
+        "title": "Update order status",
+        "type": "object",
+        "properties": {
+            "status": status(),
+            "metadata": metadata(true),
+            "shipping_address": shipping_address(true),
+        },
+        "anyOf": [
+            { "required": ["status"] },
+            { "required": ["metadata"] },
+            { "required": ["shipping_address"] },
+        ],
+    })
+}
+
+/// `patch` allows a null address, which removes it, and leaving out fields
+/// the order's address already has. Fields are trimmed before they're
+/// checked, and the handler also checks postal code lengths for the countries
+/// in `address.rs`.
+fn shipping_address(patch: bool) -> Value {
+    let required = json!({ "type": "string", "minLength": 1, "maxLength": ADDRESS_FIELD_MAX_LEN });
+    // null removes them
+    let optional = json!({ "type": ["string", "null"], "maxLength": ADDRESS_FIELD_MAX_LEN });
+
+    let mut address = json!({
+        "type": "object",
+        "properties": {
+            "name": required,
+            "line1": required,
+            "line2": optional,
+            "city": required,
+            "region": optional,
+            "postal_code": required,
+            "country": { "type": "string", "pattern": COUNTRY_PATTERN },
+        },
+        "additionalProperties": false,
+    });
+
+    match patch {
+        true => address["type"] = json!(["object", "null"]),
+        false => address["required"] = json!(["name", "line1", "city", "postal_code", "country"]),
+    }
+
+    address
+}
+
+fn currency() -> Value {
+    json!({ "type": "string", "pattern": CURRENCY_PATTERN })
+}
+
+/// Items default to their order's currency, so one that's given can only be
+/// checked against it when the item's added.
+fn order_item() -> Value {
+    let quantity = json!({ "type": "integer", "minimum": QUANTITY_MIN });
+
+    json!({
+        "$schema": DRAFT,
+        "title": "Add order item",
+        "oneOf": [
+            {
+                "type": "object",
+                "properties": {
+                    "sku": { "type": "string" },
+                    "quantity": quantity,
+                    "currency": currency(),
+                },
+                "required": ["sku", "quantity"],
+            },
+            {
+                "type": "object",
+                "properties": {
+                    "description": { "type": "string", "maxLength": DESCRIPTION_MAX_LEN },
+                    "quantity": quantity,
+                    "unit_price": { "type": "integer" },
+                    "currency": currency(),
+                },
+                "required": ["description", "quantity", "unit_price"],
+                "not": { "required": ["sku"] },
+            },
+        ],
+    })
+}
+
+fn order_note() -> Value {
+    json!({
+        "$schema": DRAFT,
+        "title": "Add order note",
+        "type": "object",
+        "properties": {
+            // the handler also refuses a body that's only whitespace
+            "body": { "type": "string", "minLength": 1, "maxLength": NOTE_MAX_LEN },
+            "author": { "type": "string", "minLength": 1, "maxLength": ACTOR_MAX_LEN },
+        },
+        "required": ["body"],
+    })
+}
@@ -0,0 +1,485 @@
+//! The filters of `POST /orders/search`. Metadata clauses are written as
+//! `{"source": {"eq": "shopify"}, "campaign": {"exists": true}}` and compile
+//! to `json_type`/`json_extract` predicates, the keys checked and written into
+//! the json path like `GET /orders` does and the values bound.
+//!
+//! Once each field has parsed, the search as a whole is checked against
+//! `RULES`, for filters that contradict each other or ask for too much. Every
+//! rule broken is sent back together, each with its code.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::{QueryBuilder, Sqlite};
+
+use crate::{
+    clock, deadline,
+    dto::SearchOrdersRequest,
+    error::{CustomError, Result},
+    metadata,
+    orders::OrderStatus,
+};
+
+/// Each operator counts as a clause, more than this is refused so one search
+/// can't pile up json lookups.
+pub const METADATA_CLAUSES_MAX: usize = 5;
+
+pub const OPERATORS: &[&str] = &["eq", "ne", "exists"];
+
+/// The longest `created_from` to `created_to` range, so one search can't read
+/// back years of orders.
+pub const CREATED_SPAN_MAX_DAYS: u64 = 366;
+
+/// A rule a search has to keep across its fields.
+pub struct Rule {
+    /// Sent with the violation, stable for clients to match on.
+    pub code: &'static str,
+    /// What's wrong, `None` when the search keeps the rule.
+    pub check: fn(&OrderSearch) -> Option<String>,
+}
+
+/// Every rule, checked in this order. Adding one is an entry here and a case
+/// in `test_each_rule`.
+pub const RULES: &[Rule] = &[
+    Rule {
+        code: "amount_range_empty",
+        check: |search| match (search.min_amount, search.max_amount) {
+            (Some(min), Some(max)) if min > max => {
+                Some(format!("min_amount {min} is more than max_amount {max}"))
+            }
+            _ => None,
+        },
+    },
+    Rule {
+        code: "created_range_empty",
+        check: |search| match (&search.created_from, &search.created_to) {
+            (Some(from), Some(to)) if from >= to => {
+                Some(format!("created_from {from} isn't before created_to {to}"))
+            }
+            _ => None,
+        },
+    },
+    Rule {
+        code: "created_range_too_long",
+        check: |search| {
+            let span = created_span(search)?;
+            let days = span.as_secs().div_ceil(86_400);
+
+            (days > CREATED_SPAN_MAX_DAYS).then(|| {
+                format!(
+                    "created_from to created_to spans {days} days, at most {CREATED_SPAN_MAX_DAYS} are allowed"
+                )
+            })
+        },
+    },
+    Rule {
+        code: "too_many_metadata_clauses",
+        check: |search| {
+            (search.metadata.len() > METADATA_CLAUSES_MAX).then(|| {
+                format!(
+                    "metadata has {} clauses, at most {METADATA_CLAUSES_MAX} are allowed",
+                    search.metadata.len()
+                )
+            })
+        },
+    },
+    Rule {
+        code: "drafts_excluded",
+        check: |search| {
+            (search.status == Some(OrderStatus::Draft) && !search.include_drafts)
+                .then(|| "status draft needs include_drafts, drafts are left out without it".to_string())
+        },
+    },
+];
+
+/// A rule the search breaks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub message: String,
+}
+
+/// The rules `search` breaks, in `RULES` order.
+pub fn violations(search: &OrderSearch) -> Vec<Violation> {
+    RULES
+        .iter()
+        .filter_map(|rule| {
+            (rule.check)(search).map(|message| Violation {
+                rule: rule.code,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// How long the created range is, when it has both ends.
+fn created_span(search: &OrderSearch) -> Option<Duration> {
+    let from = deadline::parse_rfc3339(search.created_from.as_deref()?)?;
+    let to = deadline::parse_rfc3339(search.created_to.as_deref()?)?;
+
+    to.duration_since(from).ok()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The value is this scalar, of the same json type.
+    Eq(Value),
+    /// Anything else, orders without the key included.
+    Ne(Value),
+    /// The key is set, to any value including `null`, or it isn't.
+    Exists(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataClause {
+    pub key: String,
+    pub condition: Condition,
+}
+
+/// A checked search, ready to run with `Order::search`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderSearch {
+    pub status: Option<OrderStatus>,
+    pub min_amount: Option<i64>,
+    pub max_amount: Option<i64>,
+    /// Timestamps like the `created_at` column holds, from is inclusive and to
+    /// exclusive.
+    pub created_from: Option<String>,
+    pub created_to: Option<String>,
+    pub metadata: Vec<MetadataClause>,
+    pub include_drafts: bool,
+}
+
+impl OrderSearch {
+    /// Parses each field, then checks the search against `RULES`.
+    pub fn new(request: SearchOrdersRequest) -> Result<Self> {
+        let search = Self {
+            status: request.status,
+            min_amount: request.min_amount,
+            max_amount: request.max_amount,
+            created_from: time("created_from", request.created_from)?,
+            created_to: time("created_to", request.created_to)?,
+            metadata: parse_metadata(&request.metadata)?,
+            include_drafts: request.include_drafts,
+        };
+
+        let violations = violations(&search);
+
+        match violations.is_empty() {
+            true => Ok(search),
+            false => Err(CustomError::SearchRules(violations)),
+        }
+    }
+
+    /// Adds the search's predicates to a query ending in a `where` clause.
+    pub fn push_filters(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        if let Some(status) = &self.status {
+            query.push(" and status = ").push_bind(status.to_string());
+        }
+
+        if !self.include_drafts {
+            query.push(" and status != 'draft'");
+        }
+
+        if let Some(min) = self.min_amount {
+            query.push(" and amount >= ").push_bind(min);
+        }
+
+        if let Some(max) = self.max_amount {
+            query.push(" and amount <= ").push_bind(max);
+        }
+
+        if let Some(from) = &self.created_from {
+            query.push(" and created_at >= ").push_bind(from.clone());
+        }
+
+        if let Some(to) = &self.created_to {
+            query.push(" and created_at < ").push_bind(to.clone());
+        }
+
+        for clause in &self.metadata {
+            query.push(" and ");
+            clause.push(query);
+        }
+    }
+}
+
+impl MetadataClause {
+    fn push(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        let key = &self.key;
+
+        match &self.condition {
+            Condition::Eq(value) => push_eq(query, key, value),
+            Condition::Ne(value) => {
+                query.push("not coalesce(");
+                push_eq(query, key, value);
+                query.push(", 0)");
+            }
+            Condition::Exists(true) => {
+                query.push(format_args!("json_type(metadata, '$.{key}') is not null"));
+            }
+            Condition::Exists(false) => {
+                query.push(format_args!("json_type(metadata, '$.{key}') is null"));
+            }
+        }
+    }
+}
+
+/// Compares the json type too, so `"1"` doesn't match `1` and `true` doesn't
+/// match `1`, which `json_extract` alone gives back the same.
+fn push_eq(query: &mut QueryBuilder<'_, Sqlite>, key: &str, value: &Value) {
+    let json_type = format!("json_type(metadata, '$.{key}')");
+    let extract = format!("json_extract(metadata, '$.{key}')");
+
+    match value {
+        Value::Null => {
+            query.push(format_args!("({json_type} = 'null')"));
+        }
+        Value::Bool(value) => {
+            query.push(format_args!("({json_type} = '{value}')"));
+        }
+        Value::String(value) => {
+            query
+                .push(format_args!("({json_type} = 'text' and {extract} = "))
+                .push_bind(value.clone())
+                .push(")");
+        }
+        Value::Number(number) => {
+            query.push(format_args!(
+                "({json_type} in ('integer', 'real') and {extract} = "
+            ));
+
+            match number.as_i64() {
+                Some(number) => query.push_bind(number),
+                None => query.push_bind(number.as_f64().unwrap_or_default()),
+            };
+
+            query.push(")");
+        }
+        Value::Array(_) | Value::Object(_) => unreachable!("checked by parse_metadata"),
+    }
+}
+
+/// Reads the `metadata` of a search, each key an object of operators.
+/// Errors name the clause, like `metadata.source.gt`.
+pub fn parse_metadata(metadata: &Map<String, Value>) -> Result<Vec<MetadataClause>> {
+    let mut clauses = Vec::new();
+
+    for (key, operators) in metadata {
+        metadata::check_key(key)?;
+
+        let Value::Object(operators) = operators else {
+            return Err(CustomError::Validation(format!(
+                "metadata.{key} should be an object of operators, like {{\"eq\": \"value\"}}"
+            )));
+        };
+
+        if operators.is_empty() {
+            return Err(CustomError::Validation(format!(
+                "metadata.{key} has no operators, use {}",
+                OPERATORS.join(", ")
+            )));
+        }
+
+        for (operator, value) in operators {
+            let clause = format!("metadata.{key}.{operator}");
+
+            let condition = match (operator.as_str(), value) {
+                ("eq" | "ne", Value::Array(_) | Value::Object(_)) => {
+                    return Err(CustomError::Validation(format!(
+                        "{clause} should compare with a string, number, boolean or null"
+                    )));
+                }
+                ("eq", value) => Condition::Eq(value.clone()),
+                ("ne", value) => Condition::Ne(value.clone()),
+                ("exists", Value::Bool(exists)) => Condition::Exists(*exists),
+                ("exists", _) => {
+                    return Err(CustomError::Validation(format!(
+                        "{clause} should be true or false"
+                    )));
+                }
+                _ => {
+                    return Err(CustomError::Validation(format!(
+                        "{clause} is not an operator, use {}",
+                        OPERATORS.join(", ")
+                    )));
+                }
+            };
+
+            clauses.push(MetadataClause {
+                key: key.clone(),
+                condition,
+            });
+        }
+    }
+
+    Ok(clauses)
+}
+
+fn time(field: &str, value: Option<String>) -> Result<Option<String>> {
+    value
+        .map(|value| {
+            clock::parse(&value).ok_or_else(|| {
+                CustomError::Validation(format!(
+                    "{field} {value:?} should be an RFC 3339 time, like 2025-09-12T10:00:00Z"
+                ))
+            })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(metadata: Value) -> Result<Vec<MetadataClause>> {
+        parse_metadata(metadata.as_object().unwrap())
+    }
+
+    fn message(metadata: Value) -> String {
+        match parse(metadata) {
+            Err(CustomError::Validation(message)) => message,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parses_each_operator() {
+        let clauses = parse(json!({
+            "campaign": { "exists": true },
+            "source": { "eq": "shopify", "ne": 3 },
+        }))
+        .unwrap();
+
+        let conditions: Vec<_> = clauses
+            .iter()
+            .map(|clause| (clause.key.as_str(), clause.condition.clone()))
+            .collect();
+        assert_eq!(
+            conditions,
+            [
+                ("campaign", Condition::Exists(true)),
+                ("source", Condition::Eq(json!("shopify"))),
+                ("source", Condition::Ne(json!(3))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_clause() {
+        assert!(message(json!({ "source": { "gt": 1 } })).starts_with("metadata.source.gt is not"));
+        assert!(
+            message(json!({ "source": { "in": [1] } })).starts_with("metadata.source.in is not")
+        );
+        assert!(
+            message(json!({ "source": { "eq": [1] } })).starts_with("metadata.source.eq should")
+        );
+        assert!(
+            message(json!({ "source": { "ne": {} } })).starts_with("metadata.source.ne should")
+        );
+        assert!(
+            message(json!({ "source": { "exists": "yes" } })).starts_with("metadata.source.exists")
+        );
+        assert!(message(json!({ "source": "shopify" })).starts_with("metadata.source should"));
+        assert!(message(json!({ "source": {} })).starts_with("metadata.source has no"));
+        assert!(message(json!({ "a.b": { "eq": 1 } })).contains("\"a.b\""));
+    }
+
+    fn broken(search: &OrderSearch) -> Vec<&'static str> {
+        violations(search).into_iter().map(|violation| violation.rule).collect()
+    }
+
+    #[test]
+    fn test_clause_cap() {
+        let five = json!({ "a": { "eq": 1, "ne": 2 }, "b": { "exists": true }, "c": { "eq": 1 }, "d": { "eq": 1 } });
+        let five = OrderSearch { metadata: parse(five).unwrap(), ..Default::default() };
+        assert_eq!(five.metadata.len(), 5);
+        assert_eq!(broken(&five), [] as [&str; 0]);
+
+        let six = json!({ "a": { "eq": 1, "ne": 2 }, "b": { "exists": true }, "c": { "eq": 1 }, "d": { "eq": 1 }, "e": { "exists": false } });
+        let six = OrderSearch { metadata: parse(six).unwrap(), ..Default::default() };
+        assert_eq!(
+            violations(&six),
+            [Violation {
+                rule: "too_many_metadata_clauses",
+                message: "metadata has 6 clauses, at most 5 are allowed".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_each_rule() {
+        let created = |from: &str, to: &str| OrderSearch {
+            created_from: clock::parse(from),
+            created_to: clock::parse(to),
+            ..Default::default()
+        };
+
+        let cases = [
+            (
+                OrderSearch { min_amount: Some(500), max_amount: Some(100), ..Default::default() },
+                "amount_range_empty",
+                OrderSearch { min_amount: Some(100), max_amount: Some(100), ..Default::default() },
+            ),
+            (
+                created("2025-09-12T10:00:00Z", "2025-09-12T10:00:00Z"),
+                "created_range_empty",
+                created("2025-09-12T10:00:00Z", "2025-09-12T10:00:00.001Z"),
+            ),
+            (
+                created("2024-01-01T00:00:00Z", "2025-01-01T00:00:01Z"),
+                "created_range_too_long",
+                created("2024-01-01T00:00:00Z", "2025-01-01T00:00:00Z"),
+            ),
+            (
+                OrderSearch { status: Some(OrderStatus::Draft), ..Default::default() },
+                "drafts_excluded",
+                OrderSearch { status: Some(OrderStatus::Draft), include_drafts: true, ..Default::default() },
+            ),
+        ];
+
+        for (breaking, rule, keeping) in cases {
+            assert_eq!(broken(&breaking), [rule], "{breaking:?}");
+            assert_eq!(broken(&keeping), [] as [&str; 0], "{keeping:?}");
+        }
+
+        // one end of a range is never a violation
+        assert_eq!(broken(&OrderSearch { min_amount: Some(500), ..Default::default() }), [] as [&str; 0]);
+        assert_eq!(
+            broken(&OrderSearch { created_from: clock::parse("2020-01-01T00:00:00Z"), ..Default::default() }),
+            [] as [&str; 0]
+        );
+    }
+
+    #[test]
+    fn test_every_rule_broken_is_reported() {
+        let request = SearchOrdersRequest {
+            status: Some(OrderStatus::Draft),
+            min_amount: Some(500),
+            max_amount: Some(100),
+            created_from: Some("2025-09-12T10:00:00Z".to_string()),
+            created_to: Some("2025-09-01T10:00:00Z".to_string()),
+            ..Default::default()
+        };
+
+        match OrderSearch::new(request) {
+            Err(CustomError::SearchRules(violations)) => assert_eq!(
+                violations.iter().map(|violation| violation.rule).collect::<Vec<_>>(),
+                ["amount_range_empty", "created_range_empty", "drafts_excluded"]
+            ),
+            other => panic!("expected the search rules to fail, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_rule_codes_are_unique() {
+        let mut codes: Vec<_> = RULES.iter().map(|rule| rule.code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+
+        assert_eq!(codes.len(), RULES.len());
+    }
+}
@@ -0,0 +1,349 @@
+//! How long orders spend in each status, from the `status` changes in
+//! `order_changes`, see `orders/history.rs`. An order is in its first status
+//! from `created_at` until its first status change and in each status after
+//! until the next one. Orders created before `order_changes` existed have a
+//! `tracking_started` row in place of their earlier changes, so their times
+//! aren't known and they're left out rather than guessed at.
+//!
+//! The periods and each order's time per status are worked out in sql, the
+//! percentiles over orders in rust, sqlite has no percentile function.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{QueryBuilder, Sqlite};
+
+use crate::{
+    db::{self, Db},
+    deadline,
+    orders::{Order, OrderStatus, history},
+    timing::Timed,
+};
+
+/// A stretch of time an order spent in one status. The times are timestamps
+/// like the columns hold.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Period {
+    pub status: OrderStatus,
+    pub since: String,
+    /// `null` for the status the order is in now.
+    pub until: Option<String>,
+    /// Up to now for the current status.
+    pub millis: i64,
+}
+
+/// The time orders completed in a window spent in one status.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusTimes {
+    pub status: OrderStatus,
+    /// Orders that spent any time in the status.
+    pub orders: usize,
+    /// Milliseconds, the nearest rank percentiles of the orders' total time in
+    /// the status.
+    pub p50: i64,
+    pub p95: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SlaReport {
+    /// Orders completed in the window with their whole history.
+    pub orders: i64,
+    /// Orders completed in the window but created before status changes were
+    /// recorded, left out.
+    pub missing_history: i64,
+    /// In lifecycle order, only the statuses orders left, the time they've
+    /// spent complete since isn't counted.
+    pub statuses: Vec<StatusTimes>,
+}
+
+/// The order's periods oldest first, the last one open up to `now`. `None`
+/// when its history before tracking started isn't known.
+pub async fn periods(db: &Db, order: &Order, now: &str) -> Result<Option<Vec<Period>>> {
+    let id = order.id.context("a stored order has an id")?;
+    let created_at = order.created_at.clone().context("a stored order has a created_at")?;
+    let current = json!(order.status).to_string();
+
+    let untracked = sqlx::query_scalar!(
+        r#"select exists(select 1 from order_changes where order_id = ? and field = ?)
+        as "untracked!: bool""#,
+        id,
+        history::TRACKING_STARTED
+    )
+    .fetch_one(db.reader())
+    .timed()
+    .await?;
+
+    if untracked {
+        return Ok(None);
+    }
+
+    let rows = db::read(|| {
+        sqlx::query!(
+            r#"select status as "status!: String", since as "since!: String",
+                until as "until: String",
+                cast(round((julianday(coalesce(until, ?3)) - julianday(since)) * 86400000) as integer)
+                    as "millis!: i64"
+            from (
+                select id, old_value as status,
+                    coalesce(lag(changed_at) over (order by id), ?2) as since,
+                    changed_at as until
+                from order_changes where order_id = ?1 and field = 'status'
+                union all
+                select null, ?4, coalesce((select changed_at from order_changes
+                    where order_id = ?1 and field = 'status' order by id desc limit 1), ?2), null
+            )
+            order by id is null, id"#,
+            id,
+            created_at,
+            now,
+            current
+        )
+        .fetch_all(db.reader())
+        .timed()
+    })
+    .await?;
+
+    let periods = rows
+        .into_iter()
+        .map(|row| {
+            Ok(Period {
+                status: serde_json::from_str(&row.status)
+                    .with_context(|| format!("order #{id} has an unknown status {}", row.status))?,
+                since: row.since,
+                until: row.until,
+                millis: row.millis,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Some(periods))
+}
+
+/// The time in each status of the `periods`, in lifecycle order.
+pub fn totals(periods: &[Period]) -> BTreeMap<OrderStatus, i64> {
+    let mut totals = BTreeMap::new();
+
+    for period in periods {
+        *totals.entry(period.status).or_default() += period.millis;
+    }
+
+    totals
+}
+
+/// When an order of `orders` entered the status it's in, in sql: its last
+/// status change, or `created_at` when it hasn't had one.
+pub(crate) const STATUS_SINCE: &str = "coalesce((select changed_at from order_changes
+    where order_changes.order_id = orders.id and order_changes.field = 'status'
+    order by order_changes.id desc limit 1), orders.created_at)";
+
+/// `seconds_since` in sql, from `STATUS_SINCE` up to `now`, to sort by. Keep
+/// the two the same, responses show the one and lists sort by the other.
+pub(crate) fn push_seconds_in_status(query: &mut QueryBuilder<'_, Sqlite>, now: String) {
+    query
+        .push("max(0, unixepoch(")
+        .push_bind(now)
+        .push(") - unixepoch(")
+        .push(STATUS_SINCE)
+        .push("))");
+}
+
+/// When each of `order_ids` entered the status it's in, see `STATUS_SINCE`.
+pub async fn status_since(db: &Db, order_ids: &[i64]) -> Result<HashMap<i64, String>> {
+    let order_ids = serde_json::to_string(order_ids)?;
+    let query = format!(
+        "select id, {STATUS_SINCE} from orders where id in (select value from json_each(?))"
+    );
+
+    let rows: Vec<(i64, Option<String>)> = db::read(|| {
+        sqlx::query_as(&query)
+            .bind(&order_ids)
+            .fetch_all(db.reader())
+            .timed()
+    })
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, since)| Some((id, since?)))
+        .collect())
+}
+
+/// Whole seconds from the timestamp `since` up to `now`, counted the way
+/// sqlite's `unixepoch` does, each time rounded down to the second, and `0`
+/// when `since` is later. `None` when `since` isn't a timestamp.
+pub fn seconds_since(since: &str, now: SystemTime) -> Option<i64> {
+    let seconds = |time: SystemTime| Some(time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64);
+
+    Some((seconds(now)? - seconds(deadline::parse_rfc3339(since)?)?).max(0))
+}
+
+/// The times in each status of orders completed from `from` up to `to`, live
+/// and archived, both timestamps like the columns hold.
+pub async fn report(db: &Db, from: &str, to: &str) -> Result<SlaReport> {
+    let counts = db::read(|| {
+        sqlx::query!(
+            r#"with completed as (
+                select id from orders where completed_at >= ?1 and completed_at < ?2
+                union all
+                select id from orders_archive where completed_at >= ?1 and completed_at < ?2
+            )
+            select count(*) as "completed!: i64",
+                count(*) filter (where id in (select order_id from order_changes where field = ?3))
+                    as "missing_history!: i64"
+            from completed"#,
+            from,
+            to,
+            history::TRACKING_STARTED
+        )
+        .fetch_one(db.reader())
+        .timed()
+    })
+    .await?;
+
+    // each status change ends a period in the status it changed from
+    let rows = db::read(|| {
+        sqlx::query!(
+            r#"with completed as (
+                select id, created_at from orders where completed_at >= ?1 and completed_at < ?2
+                union all
+                select id, created_at from orders_archive where completed_at >= ?1 and completed_at < ?2
+            ),
+            periods as (
+                select c.order_id, c.old_value as status,
+                    julianday(c.changed_at) - julianday(coalesce(
+                        lag(c.changed_at) over (partition by c.order_id order by c.id),
+                        o.created_at
+                    )) as days
+                from completed o join order_changes c on c.order_id = o.id and c.field = 'status'
+                where o.id not in (select order_id from order_changes where field = ?3)
+            )
+            select status as "status!: String",
+                cast(round(sum(days) * 86400000) as integer) as "millis!: i64"
+            from periods group by order_id, status"#,
+            from,
+            to,
+            history::TRACKING_STARTED
+        )
+        .fetch_all(db.reader())
+        .timed()
+    })
+    .await?;
+
+    let mut times: BTreeMap<OrderStatus, Vec<i64>> = BTreeMap::new();
+
+    for row in rows {
+        let status: OrderStatus = serde_json::from_str(&row.status)
+            .with_context(|| format!("unknown status {} in order_changes", row.status))?;
+
+        times.entry(status).or_default().push(row.millis);
+    }
+
+    let statuses = times
+        .into_iter()
+        .map(|(status, mut millis)| {
+            millis.sort_unstable();
+
+            StatusTimes {
+                status,
+                orders: millis.len(),
+                p50: percentile(&millis, 50),
+                p95: percentile(&millis, 95),
+            }
+        })
+        .collect();
+
+    Ok(SlaReport {
+        orders: counts.completed - counts.missing_history,
+        missing_history: counts.missing_history,
+        statuses,
+    })
+}
+
+/// The nearest rank `p`th percentile of `sorted`, which isn't empty: the
+/// smallest value at least `p`% of the values are at or below.
+pub(crate) fn percentile(sorted: &[i64], p: usize) -> i64 {
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{clock, db::test_db};
+
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<i64> = (1..=20).map(|n| n * 10).collect();
+
+        assert_eq!(percentile(&values, 50), 100);
+        assert_eq!(percentile(&values, 95), 190);
+        assert_eq!(percentile(&values, 100), 200);
+
+        assert_eq!(percentile(&[7], 50), 7);
+        assert_eq!(percentile(&[7], 95), 7);
+        // 50% of 3 values is rank 2, 95% rank 3
+        assert_eq!(percentile(&[1, 2, 30], 50), 2);
+        assert_eq!(percentile(&[1, 2, 30], 95), 30);
+    }
+
+    #[tokio::test]
+    async fn test_seconds_since_counts_like_sqlite() {
+        let db = test_db().await;
+        let now = UNIX_EPOCH + Duration::from_millis(1_758_000_010_300);
+
+        for since in [
+            "2025-09-16T05:20:00.000Z",
+            "2025-09-16T05:19:59.999Z",
+            "2025-09-16T05:20:09.900Z",
+            "2025-09-16T05:20:10.300Z",
+            "2025-09-16T05:20:11.000Z",
+            "2024-02-29T23:59:59.001Z",
+        ] {
+            let expected: i64 = sqlx::query_scalar("select max(0, unixepoch(?) - unixepoch(?))")
+                .bind(clock::timestamp(now))
+                .bind(since)
+                .fetch_one(db.reader())
+                .await
+                .unwrap();
+
+            assert_eq!(seconds_since(since, now), Some(expected), "{since}");
+        }
+
+        assert_eq!(seconds_since("2025-09-16", now), None);
+    }
+
+    #[test]
+    fn test_totals_add_up_revisited_statuses() {
+        let period = |status, millis| Period {
+            status,
+            since: String::new(),
+            until: None,
+            millis,
+        };
+
+        let totals = totals(&[
+            period(OrderStatus::Pending, 1_000),
+            period(OrderStatus::Canceled, 500),
+            period(OrderStatus::Pending, 250),
+            period(OrderStatus::InProgress, 2_000),
+        ]);
+
+        assert_eq!(
+            totals.into_iter().collect::<Vec<_>>(),
+            [
+                (OrderStatus::Pending, 1_250),
+                (OrderStatus::InProgress, 2_000),
+                (OrderStatus::Canceled, 500),
+            ]
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Order totals for dashboards, `GET /orders/stats`: how many orders there
+//! are and their amounts by status, and how the amounts spread over
+//! `AMOUNT_BUCKETS`. Working them out reads every order, which gets slow on a
+//! large table when a dashboard asks every few seconds, so the unfiltered
+//! document is precomputed by `refresh_every` and kept in `AppState`.
+//!
+//! A refresh runs every `STATS_REFRESH_SECONDS`, and once a burst of order
+//! changes, as `caches.rs` hands them over, has been quiet for
+//! `STATS_DEBOUNCE_MS`. Each one has
+//! `STATS_BUDGET_MS` to finish, a refresh that runs over is interrupted and
+//! the previous document kept, so a slow table can't keep a read connection
+//! busy. Filtered stats and `?fresh=true` are worked out on the request.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use anyhow::{Result, bail};
+use tokio::sync::Notify;
+
+use crate::{
+    AppState,
+    caches::Lookups,
+    clock,
+    db::{self, Db},
+    orders::OrderStatus,
+    timing::Timed,
+};
+
+/// How often the document is refreshed when `STATS_REFRESH_SECONDS` isn't
+/// set.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// How long orders have to go unchanged after a change before a refresh.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+/// How long a background refresh may take.
+pub const DEFAULT_BUDGET: Duration = Duration::from_secs(5);
+
+/// The lower bound of each amount bucket in minor units, the last one has no
+/// upper bound. The `case` in `compute` has to match.
+pub const AMOUNT_BUCKETS: [i64; 6] = [0, 1_000, 5_000, 10_000, 50_000, 100_000];
+
+/// The totals of the placed orders, drafts aren't counted.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OrderStats {
+    pub orders: i64,
+    pub amount: i64,
+    pub orders_by_status: BTreeMap<OrderStatus, i64>,
+    pub amount_by_status: BTreeMap<OrderStatus, i64>,
+    /// Orders in each of `AMOUNT_BUCKETS`.
+    pub buckets: [i64; AMOUNT_BUCKETS.len()],
+    /// When they were worked out, the way the timestamp columns hold it.
+    pub as_of: String,
+}
+
+/// Narrows the stats to orders created from `created_from` (inclusive) up to
+/// `created_to` (exclusive), both as the timestamp columns hold them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsFilter {
+    pub created_from: Option<String>,
+    pub created_to: Option<String>,
+}
+
+impl StatsFilter {
+    pub fn is_empty(&self) -> bool {
+        self.created_from.is_none() && self.created_to.is_none()
+    }
+}
+
+/// Works the stats out with two scans of `orders`, on an interruptible
+/// connection so a caller that gives up stops them.
+pub async fn compute(db: &Db, filter: &StatsFilter) -> Result<OrderStats> {
+    let (from, to) = (filter.created_from.as_deref(), filter.created_to.as_deref());
+    let as_of = clock::now();
+
+    let (statuses, buckets) = db::read(|| {
+        db::interruptible(db, async |conn| {
+            let statuses = sqlx::query!(
+                r#"select status, count(*) as "orders!: i64", coalesce(sum(amount), 0) as "amount!: i64"
+                from orders
+                where status != 'draft' and (?1 is null or created_at >= ?1) and (?2 is null or created_at < ?2)
+                group by status"#,
+                from,
+                to
+            )
+            .fetch_all(&mut *conn)
+            .timed()
+            .await?;
+
+            let buckets = sqlx::query!(
+                r#"select case
+                    when amount < 1000 then 0
+                    when amount < 5000 then 1
+                    when amount < 10000 then 2
+                    when amount < 50000 then 3
+                    when amount < 100000 then 4
+                    else 5
+                end as "bucket!: i64", count(*) as "orders!: i64"
+                from orders
+                where status != 'draft' and (?1 is null or created_at >= ?1) and (?2 is null or created_at < ?2)
+                group by 1"#,
+                from,
+                to
+            )
+            .fetch_all(&mut *conn)
+            .timed()
+            .await?;
+
+            Ok((statuses, buckets))
+        })
+    })
+    .await?;
+
+    let mut stats = OrderStats {
+        as_of,
+        ..Default::default()
+    };
+
+    // statuses stored in another case read as the same one
+    for row in statuses {
+        let status = OrderStatus::from(row.status);
+
+        stats.orders += row.orders;
+        stats.amount += row.amount;
+        *stats.orders_by_status.entry(status).or_default() += row.orders;
+        *stats.amount_by_status.entry(status).or_default() += row.amount;
+    }
+
+    for row in buckets {
+        stats.buckets[row.bucket.clamp(0, AMOUNT_BUCKETS.len() as i64 - 1) as usize] += row.orders;
+    }
+
+    Ok(stats)
+}
+
+/// The precomputed unfiltered document.
+#[derive(Debug, Default)]
+pub struct Precomputed {
+    document: Mutex<Option<OrderStats>>,
+    /// Wakes `refresh_every` after an order changed.
+    changed: Notify,
+    pub(crate) lookups: Lookups,
+}
+
+impl Precomputed {
+    /// The last document worked out, `None` before the first.
+    pub fn get(&self) -> Option<OrderStats> {
+        self.document.lock().unwrap().clone()
+    }
+
+    /// `get` for a request, counted as a hit or a miss.
+    pub fn lookup(&self) -> Option<OrderStats> {
+        let document = self.get();
+        self.lookups.record(document.is_some());
+
+        document
+    }
+
+    /// Has `refresh_every` refresh once changes settle.
+    pub fn request_refresh(&self) {
+        self.changed.notify_one();
+    }
+
+    /// Works the document out again and keeps it. One that takes longer than
+    /// `budget` is interrupted and the previous document kept.
+    pub async fn refresh(&self, db: &Db, budget: Duration) -> Result<OrderStats> {
+        let Ok(stats) = tokio::time::timeout(budget, compute(db, &StatsFilter::default())).await else {
+            bail!("working out the order stats took longer than its {}ms budget", budget.as_millis());
+        };
+
+        Ok(self.store(stats?))
+    }
+
+    /// Keeps `stats` as the document, unless the one already kept is newer.
+    pub fn store(&self, stats: OrderStats) -> OrderStats {
+        let mut document = self.document.lock().unwrap();
+
+        match document.as_ref() {
+            Some(kept) if kept.as_of > stats.as_of => kept.clone(),
+            _ => {
+                *document = Some(stats.clone());
+                stats
+            }
+        }
+    }
+}
+
+/// Refreshes the document every `interval`, starting straight away, and after
+/// each burst of order changes, until the process exits.
+pub async fn refresh_every(state: AppState, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = state.stats.changed.notified() => {
+                settle(&state.stats.changed, state.config().stats_debounce, interval).await;
+                ticks.reset();
+            }
+        }
+
+        if let Err(err) = state.stats.refresh(&state.db, state.config().stats_budget).await {
+            tracing::warn!("failed to refresh the order stats: {err:#}");
+        }
+    }
+}
+
+/// Waits until nothing has changed for `debounce`, or for `limit` at most so
+/// a steady stream of writes still gets a refresh now and then.
+async fn settle(changed: &Notify, debounce: Duration, limit: Duration) {
+    let until = tokio::time::Instant::now() + limit;
+
+    loop {
+        let quiet = (tokio::time::Instant::now() + debounce).min(until);
+
+        match tokio::time::timeout_at(quiet, changed.notified()).await {
+            Ok(()) if tokio::time::Instant::now() < until => continue,
+            _ => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::{
+        app_and_state,
+        caches::Invalidation,
+        config::AppConfig,
+        db::test_db,
+        orders::Order,
+    };
+
+    use super::*;
+
+    async fn order(db: &Db, amount: i64, status: OrderStatus) -> Order {
+        let mut order = Order::new(amount);
+        order.status = status;
+        order.save(db.writer()).await.unwrap();
+
+        order
+    }
+
+    #[tokio::test]
+    async fn test_compute_totals_and_buckets() {
+        let db = test_db().await;
+
+        order(&db, 500, OrderStatus::Pending).await;
+        order(&db, 999, OrderStatus::Pending).await;
+        order(&db, 1_000, OrderStatus::Complete).await;
+        order(&db, 250_000, OrderStatus::Canceled).await;
+        order(&db, 7_000, OrderStatus::Draft).await;
+
+        let stats = compute(&db, &StatsFilter::default()).await.unwrap();
+
+        assert_eq!(stats.orders, 4);
+        assert_eq!(stats.amount, 252_499);
+        assert_eq!(stats.orders_by_status[&OrderStatus::Pending], 2);
+        assert_eq!(stats.amount_by_status[&OrderStatus::Pending], 1_499);
+        assert!(!stats.orders_by_status.contains_key(&OrderStatus::Draft));
+        assert_eq!(stats.buckets, [2, 1, 0, 0, 0, 1]);
+
+        let none = StatsFilter {
+            created_to: Some("2000-01-01T00:00:00.000Z".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(compute(&db, &none).await.unwrap().orders, 0);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_over_budget_keeps_the_last_document() {
+        let db: Db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap()
+            .into();
+        let precomputed = Precomputed::default();
+        let kept = precomputed.store(OrderStats {
+            orders: 1,
+            as_of: clock::now(),
+            ..Default::default()
+        });
+
+        // the only connection is busy, so the refresh can't start in time
+        let busy = db.reader().acquire().await.unwrap();
+        let err = precomputed.refresh(&db, Duration::from_millis(50)).await.unwrap_err();
+        assert!(err.to_string().contains("50ms budget"), "{err}");
+        drop(busy);
+
+        assert_eq!(precomputed.get(), Some(kept));
+    }
+
+    #[tokio::test]
+    async fn test_refreshes_once_a_burst_of_changes_settles() {
+        let db = test_db().await;
+        let config = AppConfig {
+            stats_debounce: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let (_, state) = app_and_state(db.clone(), config);
+
+        // a long interval, so only its first tick refreshes
+        tokio::spawn(refresh_every(state.clone(), Duration::from_secs(3600)));
+
+        let wait_for = async |orders: i64| {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while state.stats.get().is_none_or(|stats| stats.orders != orders) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("the stats should be refreshed");
+        };
+        wait_for(0).await;
+
+        // a burst of writes, each published
+        for _ in 0..3 {
+            let mut order = order(&db, 500, OrderStatus::Pending).await;
+            order.status = OrderStatus::Canceled;
+            order.save_with_history(&db, OrderStatus::Pending).await.unwrap();
+            state.invalidations.publish(Invalidation::moved(&order, OrderStatus::Pending));
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(state.stats.get().unwrap().orders, 0, "refreshed before the burst settled");
+
+        wait_for(3).await;
+    }
+}
@@ -0,0 +1,225 @@
+//! Order rows whose status isn't one `OrderStatus` stores, left by versions
+//! that wrote whatever status text they were given. They read as pending, so
+//! `fix-statuses` reports them and rewrites them to what the operator maps
+//! them to. Nothing is guessed, values without a mapping stay as they are.
+
+use anyhow::{Context, Result, bail};
+
+use crate::{db::Db, orders::OrderStatus, timing::Timed};
+
+/// A status value found in the database and the status it really is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusMapping {
+    pub from: String,
+    pub to: OrderStatus,
+}
+
+impl StatusMapping {
+    /// `<from>=<to>`, split at the last `=`. `to` has to be a stored status,
+    /// like `in-progress`, and `from` can't already be one.
+    pub fn parse(mapping: &str) -> Result<Self> {
+        let (from, to) = mapping
+            .rsplit_once('=')
+            .with_context(|| format!("{mapping:?} should be <from>=<to>"))?;
+
+        let Some(to) = OrderStatus::from_stored(to) else {
+            bail!(
+                "{to:?} isn't a status, it should be one of {}",
+                stored().join(", ")
+            );
+        };
+
+        if OrderStatus::from_stored(from).is_some() {
+            bail!("{from:?} is already a status");
+        }
+
+        Ok(Self {
+            from: from.to_string(),
+            to,
+        })
+    }
+}
+
+/// A status value that isn't a stored one, and how many orders have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownStatus {
+    pub value: String,
+    pub count: i64,
+}
+
+fn stored() -> Vec<String> {
+    OrderStatus::STORED
+        .map(|status| status.to_string())
+        .to_vec()
+}
+
+/// The unknown status values across live and archived orders, by value.
+pub async fn report(db: &Db) -> Result<Vec<UnknownStatus>> {
+    let stored = serde_json::to_string(&stored())?;
+
+    Ok(sqlx::query_as!(
+        UnknownStatus,
+        r#"select status as "value!: String", count(*) as "count!: i64" from (
+            select status from orders
+            union all
+            select status from orders_archive
+        )
+        where status not in (select value from json_each(?))
+        group by status order by status"#,
+        stored
+    )
+    .fetch_all(db.reader())
+    .timed()
+    .await?)
+}
+
+/// Rewrites the statuses of live and archived orders, and the order
+/// summaries, that `mappings` cover, in one transaction. Returns how many
+/// orders were rewritten.
+pub async fn apply(db: &Db, mappings: &[StatusMapping]) -> Result<u64> {
+    let mut tx = db.writer().begin().await?;
+    let mut rewritten = 0;
+
+    for mapping in mappings {
+        let to = mapping.to.to_string();
+
+        rewritten += sqlx::query!(
+            "update orders set status = ? where status = ?;",
+            to,
+            mapping.from
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?
+        .rows_affected();
+
+        rewritten += sqlx::query!(
+            "update orders_archive set status = ? where status = ?;",
+            to,
+            mapping.from
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?
+        .rows_affected();
+
+        sqlx::query!(
+            "update order_summaries set status = ? where status = ?;",
+            to,
+            mapping.from
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::test_db, orders::Order};
+
+    use super::*;
+
+    async fn seed(db: &Db, statuses: &[&str]) {
+        for status in statuses {
+            let mut order = Order::new(500);
+            order.save(db.writer()).await.unwrap();
+
+            for table in ["orders", "order_summaries"] {
+                let key = if table == "orders" { "id" } else { "order_id" };
+
+                sqlx::query(&format!("update {table} set status = ? where {key} = ?"))
+                    .bind(status)
+                    .bind(order.id)
+                    .execute(db.writer())
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    fn unknown(value: &str, count: i64) -> UnknownStatus {
+        UnknownStatus {
+            value: value.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!(
+            StatusMapping::parse("In Progress=in-progress").unwrap(),
+            StatusMapping {
+                from: "In Progress".to_string(),
+                to: OrderStatus::InProgress
+            }
+        );
+        assert_eq!(StatusMapping::parse("a=b=draft").unwrap().from, "a=b");
+
+        for invalid in ["done", "done=finished", "done=Complete", "pending=complete"] {
+            assert!(StatusMapping::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reports_and_rewrites_only_what_is_mapped() {
+        let db = test_db().await;
+        seed(
+            &db,
+            &[
+                "pending",
+                "In Progress",
+                "In Progress",
+                "done",
+                "???",
+                "complete",
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            report(&db).await.unwrap(),
+            vec![
+                unknown("???", 1),
+                unknown("In Progress", 2),
+                unknown("done", 1)
+            ]
+        );
+
+        let mappings = [
+            StatusMapping::parse("In Progress=in-progress").unwrap(),
+            StatusMapping::parse("done=complete").unwrap(),
+        ];
+        assert_eq!(apply(&db, &mappings).await.unwrap(), 3);
+
+        // the unmapped value is still there and still reported
+        assert_eq!(report(&db).await.unwrap(), vec![unknown("???", 1)]);
+
+        let statuses: Vec<String> = sqlx::query_scalar("select status from orders order by id")
+            .fetch_all(db.reader())
+            .await
+            .unwrap();
+        assert_eq!(
+            statuses,
+            [
+                "pending",
+                "in-progress",
+                "in-progress",
+                "complete",
+                "???",
+                "complete"
+            ]
+        );
+
+        let summaries: Vec<String> =
+            sqlx::query_scalar("select status from order_summaries order by order_id")
+                .fetch_all(db.reader())
+                .await
+                .unwrap();
+        assert_eq!(summaries[1], "in-progress");
+    }
+}
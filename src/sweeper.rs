@@ -0,0 +1,285 @@
+//! Periodic passes over orders that need a nudge: pending orders nobody has
+//! touched in a while, each reminded about once, stale drafts, which are
+//! deleted, and picks that outlived their lease, which are unassigned. Orders
+//! past the retention policy are scrubbed here too, see `retention.rs`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::{
+    AppState,
+    caches::Invalidation,
+    config::AppConfig,
+    db::Db,
+    events::{EventKind, OrderEvent},
+    retention,
+    timing::Timed,
+};
+
+/// How often the sweeper runs its passes.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Records a `pending_reminder` event for every order that's been pending for
+/// longer than `pending_reminder_after` but not yet `pending_cancel_after`,
+/// and marks it so later sweeps skip it. Returns the reminded order ids.
+///
+/// Orders don't record when they became pending, so this goes by when they
+/// were last updated.
+pub async fn remind_pending(db: &Db, config: &AppConfig, now: SystemTime) -> Result<Vec<i64>> {
+    let now = now.duration_since(UNIX_EPOCH)?.as_secs_f64();
+    let remind_before = now - config.pending_reminder_after.as_secs_f64();
+    let cancel_before = now - config.pending_cancel_after.as_secs_f64();
+
+    let mut tx = db.writer().begin().await?;
+
+    let due = sqlx::query!(
+        r#"select id as "id!: i64", updated_at as "updated_at!: String" from orders
+        where status = 'pending' and reminder_sent_at is null
+        and updated_at <= strftime('%Y-%m-%dT%H:%M:%fZ', ?, 'unixepoch')
+        and updated_at > strftime('%Y-%m-%dT%H:%M:%fZ', ?, 'unixepoch')
+        order by id"#,
+        remind_before,
+        cancel_before
+    )
+    .fetch_all(&mut *tx)
+    .timed()
+    .await?;
+
+    for order in &due {
+        sqlx::query!(
+            "update orders set reminder_sent_at = strftime('%Y-%m-%dT%H:%M:%fZ', ?, 'unixepoch')
+            where id = ?;",
+            now,
+            order.id
+        )
+        .execute(&mut *tx)
+        .timed()
+        .await?;
+
+        let detail = format!("pending since {}", order.updated_at);
+
+        OrderEvent::record(&mut tx, order.id, EventKind::PendingReminder, &detail, None).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(due.into_iter().map(|order| order.id).collect())
+}
+
+/// Deletes drafts last updated more than `draft_ttl` ago, with their items and
+/// history. Returns how many were deleted.
+pub async fn purge_drafts(db: &Db, config: &AppConfig, now: SystemTime) -> Result<u64> {
+    let now = now.duration_since(UNIX_EPOCH)?.as_secs_f64();
+    let expired_before = now - config.draft_ttl.as_secs_f64();
+
+    let purged = sqlx::query!(
+        "delete from orders where status = 'draft'
+        and updated_at <= strftime('%Y-%m-%dT%H:%M:%fZ', ?, 'unixepoch');",
+        expired_before
+    )
+    .execute(db.writer())
+    .timed()
+    .await?
+    .rows_affected();
+
+    Ok(purged)
+}
+
+/// Unassigns orders still open more than `pick_lease` after they were picked,
+/// so the next pick list offers them again. Returns their ids.
+pub async fn release_expired_picks(db: &Db, config: &AppConfig, now: SystemTime) -> Result<Vec<i64>> {
+    let now = now.duration_since(UNIX_EPOCH)?.as_secs_f64();
+    let expired_before = now - config.pick_lease.as_secs_f64();
+
+    let mut released = sqlx::query_scalar!(
+        r#"update orders set assigned_to = null, assigned_at = null
+        where assigned_to is not null and status in ('pending', 'in-progress')
+        and assigned_at <= strftime('%Y-%m-%dT%H:%M:%fZ', ?, 'unixepoch')
+        returning id as "id!: i64""#,
+        expired_before
+    )
+    .fetch_all(db.writer())
+    .timed()
+    .await?;
+
+    released.sort();
+
+    Ok(released)
+}
+
+/// Runs the sweeper's passes every `SWEEP_INTERVAL` until the process exits.
+pub async fn sweep(state: AppState) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if !state.integrity.writes_allowed() {
+            continue;
+        }
+
+        let config = state.config();
+
+        match remind_pending(&state.db, &config, SystemTime::now()).await {
+            Ok(reminded) if !reminded.is_empty() => {
+                tracing::info!(
+                    "sent pending reminders for {} orders: {reminded:?}",
+                    reminded.len()
+                );
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("failed to send pending reminders: {err:#}"),
+        }
+
+        match purge_drafts(&state.db, &config, SystemTime::now()).await {
+            Ok(purged) if purged > 0 => {
+                tracing::info!("purged {purged} stale drafts");
+                state.invalidations.publish(Invalidation::Orders);
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("failed to purge stale drafts: {err:#}"),
+        }
+
+        match release_expired_picks(&state.db, &config, SystemTime::now()).await {
+            Ok(released) if !released.is_empty() => {
+                tracing::info!("released {} expired picks: {released:?}", released.len());
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("failed to release expired picks: {err:#}"),
+        }
+
+        match retention::enforce(&state.db, &config, SystemTime::now(), true).await {
+            Ok(Some(report)) if report.orders > 0 => {
+                tracing::info!("scrubbed the customer data of {} orders: {report:?}", report.orders);
+            }
+            Ok(_) => {}
+            Err(err) => tracing::error!("failed to enforce the retention policy: {err:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::test_db,
+        orders::{Order, OrderStatus},
+    };
+
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    async fn order(db: &Db, status: OrderStatus) -> i64 {
+        let mut order = Order {
+            amount: 500,
+            status,
+            ..Default::default()
+        };
+        order.save(db.writer()).await.unwrap();
+
+        order.id.unwrap()
+    }
+
+    async fn reminders(db: &Db, order_id: i64) -> usize {
+        OrderEvent::get_by_order_id(db, order_id)
+            .await
+            .unwrap()
+            .iter()
+            .filter(|event| event.kind == EventKind::PendingReminder)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_reminds_once_after_threshold() {
+        let db = test_db().await;
+        let config = AppConfig::default();
+        let start = SystemTime::now();
+
+        let pending = order(&db, OrderStatus::Pending).await;
+        let complete = order(&db, OrderStatus::Complete).await;
+
+        let reminded = remind_pending(&db, &config, start + 23 * HOUR).await.unwrap();
+        assert!(reminded.is_empty());
+
+        let reminded = remind_pending(&db, &config, start + 25 * HOUR).await.unwrap();
+        assert_eq!(reminded, vec![pending]);
+
+        let reminded = remind_pending(&db, &config, start + 30 * HOUR).await.unwrap();
+        assert!(reminded.is_empty());
+
+        assert_eq!(reminders(&db, pending).await, 1);
+        assert_eq!(reminders(&db, complete).await, 0);
+
+        let order = Order::get_by_id(&db, pending).await.unwrap().unwrap();
+        assert!(order.reminder_sent_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_skips_orders_past_cancel_window() {
+        let db = test_db().await;
+        let config = AppConfig::default();
+
+        let pending = order(&db, OrderStatus::Pending).await;
+
+        let later = SystemTime::now() + config.pending_cancel_after + HOUR;
+        let reminded = remind_pending(&db, &config, later).await.unwrap();
+
+        assert!(reminded.is_empty());
+        assert_eq!(reminders(&db, pending).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_purges_stale_drafts() {
+        let db = test_db().await;
+        let config = AppConfig::default();
+        let start = SystemTime::now();
+
+        let draft = order(&db, OrderStatus::Draft).await;
+        let pending = order(&db, OrderStatus::Pending).await;
+
+        // drafts aren't pending, so they're never reminded about
+        remind_pending(&db, &config, start + 25 * HOUR).await.unwrap();
+        assert_eq!(reminders(&db, draft).await, 0);
+
+        assert_eq!(purge_drafts(&db, &config, start + 23 * HOUR).await.unwrap(), 0);
+        assert_eq!(purge_drafts(&db, &config, start + 25 * HOUR).await.unwrap(), 1);
+
+        assert!(Order::get_by_id(&db, draft).await.unwrap().is_none());
+        assert!(Order::get_by_id(&db, pending).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_releases_expired_picks() {
+        let db = test_db().await;
+        let config = AppConfig::default();
+        let start = SystemTime::now();
+
+        let picked = order(&db, OrderStatus::Pending).await;
+        let finished = order(&db, OrderStatus::InProgress).await;
+        let assigned = Order::pick_list(&db, 10, Some("worker1")).await.unwrap();
+        assert_eq!(assigned.len(), 2);
+
+        let mut order = Order::get_by_id(&db, finished).await.unwrap().unwrap();
+        order.status = OrderStatus::Complete;
+        order.save(db.writer()).await.unwrap();
+
+        let minute = Duration::from_secs(60);
+        let released = release_expired_picks(&db, &config, start + config.pick_lease - minute)
+            .await
+            .unwrap();
+        assert!(released.is_empty());
+
+        let released = release_expired_picks(&db, &config, start + config.pick_lease + minute)
+            .await
+            .unwrap();
+        assert_eq!(released, vec![picked]);
+
+        // the released order is offered again, the finished one keeps its worker
+        let picks = Order::pick_list(&db, 10, Some("worker2")).await.unwrap();
+        assert_eq!(picks.iter().map(|order| order.id).collect::<Vec<_>>(), [Some(picked)]);
+
+        let order = Order::get_by_id(&db, finished).await.unwrap().unwrap();
+        assert_eq!(order.assigned_to.as_deref(), Some("worker1"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Labels marketing puts on orders, like `promo-q1`: a set of short strings
+//! stored as a json array in `orders.tags`, set in bulk by
+//! `POST /admin/orders/retag`, see `orders/retag.rs`.
+
+use std::{collections::BTreeSet, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constraints::{TAG_MAX_LEN, TAGS_MAX_PER_ORDER, check_max_len},
+    error::{CustomError, Result},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Tags(BTreeSet<String>);
+
+impl Tags {
+    /// The tags with `add` added and then `remove` removed, `None` when that
+    /// would be more than `TAGS_MAX_PER_ORDER`.
+    pub fn retagged(&self, add: &[String], remove: &[String]) -> Option<Self> {
+        let mut tags = self.0.clone();
+        tags.extend(add.iter().cloned());
+
+        for tag in remove {
+            tags.remove(tag);
+        }
+
+        (tags.len() <= TAGS_MAX_PER_ORDER).then_some(Self(tags))
+    }
+
+    pub fn to_vec(&self) -> Vec<String> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+impl Display for Tags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", serde_json::to_string(&self.0).map_err(|_| std::fmt::Error)?)
+    }
+}
+
+impl From<String> for Tags {
+    fn from(value: String) -> Self {
+        serde_json::from_str(&value).unwrap_or_default()
+    }
+}
+
+pub fn check_tag(tag: &str) -> Result<()> {
+    let valid = tag
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if tag.is_empty() || !valid {
+        return Err(CustomError::Validation(format!(
+            "tag {tag:?} may only contain letters, digits, _ and -"
+        )));
+    }
+
+    check_max_len("tag", tag, TAG_MAX_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_retagged_adds_then_removes() {
+        let current = Tags::from(r#"["vip","promo-q1"]"#.to_string());
+
+        let retagged = current
+            .retagged(&tags(&["promo-q2", "vip"]), &tags(&["promo-q1", "absent"]))
+            .unwrap();
+
+        assert_eq!(retagged.to_vec(), tags(&["promo-q2", "vip"]));
+        assert_eq!(retagged.to_string(), r#"["promo-q2","vip"]"#);
+    }
+
+    #[test]
+    fn test_retagged_refuses_too_many() {
+        let full = Tags::default()
+            .retagged(&(0..TAGS_MAX_PER_ORDER).map(|n| format!("tag-{n}")).collect::<Vec<_>>(), &[])
+            .unwrap();
+
+        assert!(full.retagged(&tags(&["one-more"]), &[]).is_none());
+        assert!(full.retagged(&tags(&["one-more"]), &tags(&["tag-0"])).is_some());
+        assert!(full.retagged(&tags(&["tag-0"]), &[]).is_some());
+    }
+
+    #[test]
+    fn test_check_tag() {
+        assert!(check_tag("promo-q1").is_ok());
+        assert!(check_tag("").is_err());
+        assert!(check_tag("promo q1").is_err());
+        assert!(check_tag(&"x".repeat(TAG_MAX_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{AppState, faults};
+
+pub const SLOW_REQUEST_HEADER: &str = "x-slow-request";
+/// The statements the request ran, in debug builds, to spot an N+1 query from
+/// the outside.
+pub const DB_STATEMENTS_HEADER: &str = "x-db-statements";
+
+tokio::task_local! {
+    /// Nanoseconds spent waiting on the database by the current request.
+    static DB_TIME: Arc<AtomicU64>;
+    /// Database statements run by the current request.
+    static DB_STATEMENTS: Arc<Statements>;
+}
+
+/// A count of database statements that also counts toward the one it was
+/// started inside of, so a test counting around a request sees the request's
+/// statements too.
+#[derive(Debug, Default)]
+pub struct Statements {
+    count: AtomicU64,
+    outer: Option<Arc<Statements>>,
+}
+
+impl Statements {
+    fn nested() -> Arc<Self> {
+        Arc::new(Self {
+            count: AtomicU64::new(0),
+            outer: DB_STATEMENTS.try_with(Arc::clone).ok(),
+        })
+    }
+
+    fn add(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(outer) = &self.outer {
+            outer.add();
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Adds the time a database call takes to the current request's db timing,
+/// and counts it as one of its statements. Outside a request, e.g. in tests
+/// or commands, both are discarded. Every query goes through here, so it's
+/// also where the request's `X-Chaos` failures are injected, see `faults.rs`.
+pub trait Timed<T>: Future<Output = sqlx::Result<T>> + Sized {
+    fn timed(self) -> impl Future<Output = sqlx::Result<T>> {
+        async move {
+            let start = Instant::now();
+            let output = match faults::before_query().await {
+                Ok(()) => self.await,
+                Err(err) => Err(err),
+            };
+            let elapsed = start.elapsed().as_nanos() as u64;
+
+            let _ = DB_TIME.try_with(|db_time| db_time.fetch_add(elapsed, Ordering::Relaxed));
+            let _ = DB_STATEMENTS.try_with(|statements| statements.add());
+
+            output
+        }
+    }
+}
+
+impl<T, F: Future<Output = sqlx::Result<T>>> Timed<T> for F {}
+
+/// Keeps counting toward the current request's db timing and statements in
+/// `future`, for work a request spawns onto a task of its own.
+pub fn keep<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let db_time = DB_TIME.try_with(Arc::clone).ok();
+    let statements = DB_STATEMENTS.try_with(Arc::clone).ok();
+
+    async move {
+        match (db_time, statements) {
+            (Some(db_time), Some(statements)) => {
+                DB_TIME.scope(db_time, DB_STATEMENTS.scope(statements, future)).await
+            }
+            _ => future.await,
+        }
+    }
+}
+
+/// Runs `future` and fails if it ran more than `max` database statements,
+/// which pins an endpoint's statements so a query per row fails a test.
+#[cfg(test)]
+pub async fn assert_max_statements<F: Future>(max: u64, future: F) -> F::Output {
+    let statements = Statements::nested();
+    let output = DB_STATEMENTS.scope(statements.clone(), future).await;
+    let ran = statements.count();
+
+    assert!(ran <= max, "ran {ran} database statements, at most {max} were expected");
+
+    output
+}
+
+/// Reports how long the request took overall and in the database through a
+/// `Server-Timing` header, and flags requests over the configured threshold.
+pub async fn server_timing(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let db_time = Arc::new(AtomicU64::new(0));
+    let statements = Statements::nested();
+    let route = format!("{} {}", request.method(), request.uri().path());
+    let start = Instant::now();
+
+    let mut response = DB_TIME
+        .scope(db_time.clone(), DB_STATEMENTS.scope(statements.clone(), next.run(request)))
+        .await;
+
+    let total = start.elapsed();
+    let db = Duration::from_nanos(db_time.load(Ordering::Relaxed));
+    let statements = statements.count();
+
+    tracing::debug!("{route} ran {statements} database statements");
+
+    let timing = format!("app;dur={:.3}, db;dur={:.3}", millis(total), millis(db));
+    let headers = response.headers_mut();
+
+    if let Ok(value) = HeaderValue::from_str(&timing) {
+        headers.insert("server-timing", value);
+    }
+
+    if cfg!(debug_assertions) {
+        headers.insert(DB_STATEMENTS_HEADER, HeaderValue::from(statements));
+    }
+
+    if total > state.config().slow_request_threshold {
+        headers.insert(SLOW_REQUEST_HEADER, HeaderValue::from_static("true"));
+    }
+
+    response
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
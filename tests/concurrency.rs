@@ -0,0 +1,257 @@
+//! Interleavings that used to lose updates, run the same way every time: one
+//! request is held at a `chaos::Point` between its read and its write while
+//! another runs to the end, then let go. Each scenario checks what must still
+//! hold afterwards, on the real app and a sqlite file of its own.
+//!
+//! Needs the `chaos` feature: `cargo test --features chaos --test concurrency`.
+
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use sp_exercise::{
+    chaos::{ChaosHooks, Point},
+    routes,
+};
+use tower::ServiceExt;
+
+static DATABASES: AtomicUsize = AtomicUsize::new(0);
+
+struct Scenario {
+    app: Router,
+    hooks: Arc<ChaosHooks>,
+    path: PathBuf,
+}
+
+impl Scenario {
+    async fn new() -> Self {
+        let n = DATABASES.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "sp-exercise-concurrency-{}-{n}.sqlite",
+            std::process::id()
+        ));
+        let (app, hooks) = sp_exercise::app_with_chaos(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+
+        Self { app, hooks, path }
+    }
+
+    async fn send(&self, method: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+        send(self.app.clone(), method, uri, body).await
+    }
+
+    async fn create(&self, amount: i64) -> i64 {
+        let body = json!({ "amount": amount, "status": "pending" });
+        let (status, order) = self.send("POST", routes::ORDERS, body).await;
+        assert!(status.is_success(), "{order}");
+
+        order["id"].as_i64().unwrap()
+    }
+
+    async fn order(&self, id: i64) -> Value {
+        let (status, order) = self
+            .send("GET", &routes::order(id), Value::Null)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        order
+    }
+
+    /// How many of the order's events are of `kind`.
+    async fn events(&self, id: i64, kind: &str) -> usize {
+        let uri = routes::order_events(id);
+        let (status, events) = self.send("GET", &uri, Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+
+        events
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["kind"] == kind)
+            .count()
+    }
+
+    /// Sends `held` and holds it at `point`, runs `meanwhile` to the end, then
+    /// lets `held` go on. Returns what each answered.
+    async fn interleave(
+        &self,
+        point: Point,
+        held: (&str, String, Value),
+        meanwhile: (&str, String, Value),
+    ) -> (StatusCode, StatusCode) {
+        let mut hold = self.hooks.hold(point);
+
+        let (method, uri, body) = held;
+        let request = send(self.app.clone(), method, &uri, body);
+        let held = tokio::spawn(async move { request.await.0 });
+        hold.reached().await;
+
+        let (method, uri, body) = meanwhile;
+        let (meanwhile, _) = self.send(method, &uri, body).await;
+
+        hold.release();
+
+        (held.await.unwrap(), meanwhile)
+    }
+}
+
+impl Drop for Scenario {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn send(
+    app: Router,
+    method: &str,
+    uri: &str,
+    body: Value,
+) -> impl Future<Output = (StatusCode, Value)> + Send + 'static {
+    let request = Request::builder()
+        .method(method)
+        .header("Content-Type", "application/json")
+        .uri(uri)
+        .body(match body {
+            Value::Null => Body::empty(),
+            body => Body::from(body.to_string()),
+        })
+        .unwrap();
+
+    async move {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+}
+
+/// A status change that read the order before an item's quantity changed its
+/// amount used to write the old amount back.
+#[tokio::test]
+async fn test_status_change_keeps_a_concurrent_amount() {
+    let scenario = Scenario::new().await;
+    let id = scenario.create(500).await;
+
+    let item = json!({ "description": "Mug", "quantity": 2, "unit_price": 250 });
+    let (status, item) = scenario
+        .send("POST", &routes::order_items(id), item)
+        .await;
+    assert!(status.is_success(), "{item}");
+    let item_id = item["id"].as_i64().unwrap();
+
+    let (patched, adjusted) = scenario
+        .interleave(
+            Point::UpdateOrder,
+            (
+                "PATCH",
+                routes::order(id),
+                json!({ "status": "in-progress" }),
+            ),
+            (
+                "PATCH",
+                routes::order_item(id, item_id),
+                json!({ "quantity": 3 }),
+            ),
+        )
+        .await;
+    assert_eq!((patched, adjusted), (StatusCode::OK, StatusCode::OK));
+
+    let order = scenario.order(id).await;
+    assert_eq!(order["amount"], 3 * 250);
+    assert_eq!(order["status"], "inprogress");
+}
+
+/// Two cancels that both read a pending order used to record two `canceled`
+/// events.
+#[tokio::test]
+async fn test_concurrent_cancels_record_one_event() {
+    let scenario = Scenario::new().await;
+    let id = scenario.create(500).await;
+
+    let cancel = || {
+        (
+            "PATCH",
+            routes::order(id),
+            json!({ "status": "canceled" }),
+        )
+    };
+    let (first, second) = scenario
+        .interleave(Point::UpdateOrder, cancel(), cancel())
+        .await;
+    assert_eq!((first, second), (StatusCode::OK, StatusCode::OK));
+
+    assert_eq!(scenario.order(id).await["status"], "canceled");
+    assert_eq!(scenario.events(id, "canceled").await, 1);
+}
+
+/// Merging the same order into two others at once used to add its amount to
+/// both.
+#[tokio::test]
+async fn test_concurrent_merges_of_one_source() {
+    let scenario = Scenario::new().await;
+    let first = scenario.create(500).await;
+    let second = scenario.create(700).await;
+    let source = scenario.create(300).await;
+
+    let merge = |target: i64| {
+        let body = json!({ "source_id": source });
+        ("POST", routes::order_merge(target), body)
+    };
+    let (held, meanwhile) = scenario
+        .interleave(Point::MergeOrder, merge(first), merge(second))
+        .await;
+    assert_eq!((held, meanwhile), (StatusCode::CONFLICT, StatusCode::OK));
+
+    assert_eq!(scenario.order(first).await["amount"], 500);
+    assert_eq!(scenario.order(second).await["amount"], 700 + 300);
+    assert_eq!(scenario.order(source).await["status"], "canceled");
+    assert_eq!(scenario.events(source, "merged_into").await, 1);
+    assert_eq!(scenario.events(first, "merged").await, 0);
+}
+
+/// Two metadata patches of different keys used to keep only the last one
+/// written.
+#[tokio::test]
+async fn test_concurrent_metadata_patches_both_apply() {
+    let scenario = Scenario::new().await;
+    let id = scenario.create(500).await;
+
+    let patch = |metadata: Value| {
+        (
+            "PATCH",
+            routes::order(id),
+            json!({ "metadata": metadata }),
+        )
+    };
+    let (first, second) = scenario
+        .interleave(
+            Point::UpdateOrder,
+            patch(json!({ "gift": true })),
+            patch(json!({ "source": "shopify" })),
+        )
+        .await;
+    assert_eq!((first, second), (StatusCode::OK, StatusCode::OK));
+
+    let order = scenario.order(id).await;
+    assert_eq!(
+        order["metadata"],
+        json!({ "gift": true, "source": "shopify" })
+    );
+}
@@ -0,0 +1,651 @@
+//! Golden responses: a script of requests that goes through every endpoint in
+//! the same order on a new database, with the clock stopped, and compares what
+//! each answers byte for byte with its file in `tests/golden/`. The files are
+//! also the examples client teams code against, request and response side by
+//! side, so a change to a response's shape shows up here as a diff first.
+//!
+//! Needs the `deterministic` feature: `cargo test --features deterministic --test golden`.
+//! After a change that's meant to change the responses, run it again with
+//! `UPDATE_GOLDEN=1` to write the files over, and review them like code.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use axum::{
+    Router,
+    body::Body,
+    http::{HeaderMap, Request},
+};
+use http_body_util::BodyExt;
+use serde_json::{Map, Value, json};
+use sp_exercise::{clock::FixedClock, config::AppConfig};
+use tower::ServiceExt;
+
+/// 2025-10-01T12:00:00Z, every timestamp the scenario writes.
+const NOW: u64 = 1_759_320_000;
+
+/// The response headers that are part of the contract.
+const HEADERS: [&str; 4] = ["content-type", "location", "x-environment", "x-stats-as-of"];
+
+/// Operations in the OpenAPI document the scenario doesn't call, and why.
+const NOT_COVERED: [(&str, &str, &str); 1] = [(
+    "post",
+    "/admin/config/reload",
+    "reads the process environment, which differs between machines",
+)];
+
+/// One request of the scenario.
+struct Call {
+    method: &'static str,
+    uri: String,
+    headers: Vec<(&'static str, &'static str)>,
+    body: Option<CallBody>,
+}
+
+enum CallBody {
+    Json(Value),
+    Text(&'static str),
+}
+
+impl Call {
+    fn new(method: &'static str, uri: impl Into<String>) -> Self {
+        Self {
+            method,
+            uri: uri.into(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    fn json(mut self, body: Value) -> Self {
+        self.body = Some(CallBody::Json(body));
+        self
+    }
+
+    fn text(mut self, content_type: &'static str, body: &'static str) -> Self {
+        self.headers.push(("content-type", content_type));
+        self.body = Some(CallBody::Text(body));
+        self
+    }
+
+    fn header(mut self, name: &'static str, value: &'static str) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// The request as the golden file shows it.
+    fn describe(&self) -> Value {
+        let mut request = Map::new();
+        request.insert("method".to_string(), json!(self.method));
+        request.insert("uri".to_string(), json!(self.uri));
+
+        if !self.headers.is_empty() {
+            let headers = self
+                .headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), json!(value)))
+                .collect();
+            request.insert("headers".to_string(), Value::Object(headers));
+        }
+
+        if let Some(body) = &self.body {
+            let body = match body {
+                CallBody::Json(body) => body.clone(),
+                CallBody::Text(body) => json!(body),
+            };
+            request.insert("body".to_string(), body);
+        }
+
+        Value::Object(request)
+    }
+
+    fn request(&self) -> Request<Body> {
+        let mut request = Request::builder().method(self.method).uri(&self.uri);
+
+        for (name, value) in &self.headers {
+            request = request.header(*name, *value);
+        }
+
+        match &self.body {
+            Some(CallBody::Json(body)) => request
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            Some(CallBody::Text(body)) => request.body(Body::from(*body)),
+            None => request.body(Body::empty()),
+        }
+        .unwrap()
+    }
+}
+
+fn get(uri: impl Into<String>) -> Call {
+    Call::new("GET", uri)
+}
+
+fn post(uri: impl Into<String>, body: Value) -> Call {
+    Call::new("POST", uri).json(body)
+}
+
+fn patch(uri: impl Into<String>, body: Value) -> Call {
+    Call::new("PATCH", uri).json(body)
+}
+
+fn put(uri: impl Into<String>, body: Value) -> Call {
+    Call::new("PUT", uri).json(body)
+}
+
+fn delete(uri: impl Into<String>) -> Call {
+    Call::new("DELETE", uri)
+}
+
+struct Golden {
+    app: Router,
+    database: PathBuf,
+    dir: PathBuf,
+    update: bool,
+    step: usize,
+    written: BTreeSet<String>,
+    /// Method and uri of every call, for `check_coverage`.
+    called: Vec<(String, String)>,
+    failures: Vec<String>,
+}
+
+impl Golden {
+    async fn new() -> Self {
+        let database = std::env::temp_dir().join(format!("sp-exercise-golden-{}.sqlite", std::process::id()));
+        let clock = FixedClock(UNIX_EPOCH + Duration::from_secs(NOW));
+        // the admin pages are html, and only in some builds
+        let mut config = AppConfig::default();
+        config.features.admin_ui_enabled = false;
+
+        let url = format!("sqlite://{}", database.display());
+        let app = sp_exercise::app_with_clock_at(&url, config, clock).await.unwrap();
+
+        Self {
+            app,
+            database,
+            dir: Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden"),
+            update: std::env::var("UPDATE_GOLDEN").is_ok_and(|value| value == "1"),
+            step: 0,
+            written: BTreeSet::new(),
+            called: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    /// Sends `call` and compares it and its response with the file for
+    /// `name`. Returns the response's body.
+    async fn check(&mut self, name: &str, call: Call) -> Value {
+        self.step += 1;
+        self.called.push((call.method.to_lowercase(), call.uri.clone()));
+
+        let response = self.app.clone().oneshot(call.request()).await.unwrap();
+        let status = response.status().as_u16();
+        let headers = contract_headers(response.headers());
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let body = match content_type.as_str() {
+            // the stream doesn't end, only its first event is kept
+            "text/event-stream" => {
+                let mut body = response.into_body();
+                let frame = body.frame().await.unwrap().unwrap();
+
+                String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap()
+            }
+            _ => {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let parsed = match body.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_str(&body).unwrap_or_else(|_| json!(body)),
+        };
+
+        // the values are timings, the families and their types are the contract
+        let shown = match call.uri.as_str() {
+            "/metrics" => json!(body
+                .lines()
+                .filter(|line| line.starts_with("# TYPE"))
+                .collect::<Vec<_>>()),
+            // a json document a line, easier to review one by one
+            uri if uri.ends_with(".ndjson") => json!(body
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap_or_else(|_| json!(line)))
+                .collect::<Vec<Value>>()),
+            // secrets are random, their place in the response is the contract
+            uri if uri.starts_with("/admin/api-keys") => mask_secrets(parsed.clone()),
+            _ => parsed.clone(),
+        };
+
+        let golden = json!({
+            "request": call.describe(),
+            "response": {
+                "status": status,
+                "headers": headers,
+                "body": shown,
+            },
+        });
+
+        let file = format!("{:03}-{name}.json", self.step);
+        let path = self.dir.join(&file);
+        let actual = serde_json::to_string_pretty(&golden).unwrap() + "\n";
+        self.written.insert(file.clone());
+
+        if self.update {
+            std::fs::create_dir_all(&self.dir).unwrap();
+            std::fs::write(&path, &actual).unwrap();
+        } else {
+            match std::fs::read_to_string(&path) {
+                Ok(expected) if expected == actual => {}
+                Ok(expected) => self
+                    .failures
+                    .push(format!("{file} changed:\n{}", diff(&expected, &actual))),
+                Err(_) => self.failures.push(format!("{file} is missing")),
+            }
+        }
+
+        parsed
+    }
+
+    /// Every operation in the OpenAPI document is called, or listed in
+    /// `NOT_COVERED`, so a new endpoint gets a golden file too.
+    fn check_coverage(&mut self, openapi: &Value) {
+        for (path, operations) in openapi["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                let called = self
+                    .called
+                    .iter()
+                    .any(|(called, uri)| called == method && matches_path(path, uri));
+                let excused = NOT_COVERED
+                    .iter()
+                    .any(|(excused, excused_path, _)| excused == method && excused_path == path);
+
+                if !called && !excused {
+                    self.failures
+                        .push(format!("{method} {path} isn't in the scenario"));
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) {
+        let files = std::fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string());
+
+        // files of steps that are gone
+        for name in files.filter(|name| !self.written.contains(name)).collect::<Vec<_>>() {
+            match self.update {
+                true => std::fs::remove_file(self.dir.join(name)).unwrap(),
+                false => self
+                    .failures
+                    .push(format!("{name} isn't a step of the scenario anymore")),
+            }
+        }
+
+        let failures = std::mem::take(&mut self.failures);
+        assert!(
+            failures.is_empty(),
+            "{}\n\nrun with UPDATE_GOLDEN=1 if the changes are meant",
+            failures.join("\n\n")
+        );
+    }
+}
+
+impl Drop for Golden {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.database.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn contract_headers(headers: &HeaderMap) -> Value {
+    let headers = HEADERS
+        .iter()
+        .filter_map(|name| {
+            let value = headers.get(*name)?.to_str().ok()?;
+
+            Some((name.to_string(), json!(value)))
+        })
+        .collect();
+
+    Value::Object(headers)
+}
+
+/// `value` with the api keys' random `secret` and `prefix` replaced, in one
+/// key or a list of them.
+fn mask_secrets(mut value: Value) -> Value {
+    let keys = match &mut value {
+        Value::Array(keys) => keys.iter_mut().collect(),
+        key => vec![key],
+    };
+
+    for key in keys.into_iter().filter_map(Value::as_object_mut) {
+        for field in ["secret", "prefix"] {
+            if key.contains_key(field) {
+                key.insert(field.to_string(), json!(format!("<{field}>")));
+            }
+        }
+    }
+
+    value
+}
+
+/// Whether `uri` is a path the OpenAPI `template`, like `/orders/{id}`, names.
+fn matches_path(template: &str, uri: &str) -> bool {
+    let path = uri.split('?').next().unwrap_or_default();
+    let template: Vec<&str> = template.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+
+    template.len() == path.len()
+        && template
+            .iter()
+            .zip(&path)
+            .all(|(template, path)| template.starts_with('{') || template == path)
+}
+
+/// The lines of `expected` and `actual` that differ, `-` for expected and `+`
+/// for actual, with a few lines around each change.
+fn diff(expected: &str, actual: &str) -> String {
+    const CONTEXT: usize = 3;
+
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // longest common subsequence, after the common start and end
+    let start = expected
+        .iter()
+        .zip(&actual)
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+    let end = expected[start..]
+        .iter()
+        .rev()
+        .zip(actual[start..].iter().rev())
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+    let old = &expected[start..expected.len() - end];
+    let new = &actual[start..actual.len() - end];
+
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = match old[i] == new[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    for line in &expected[start.saturating_sub(CONTEXT)..start] {
+        lines.push(format!("  {line}"));
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if i < old.len() && (j == new.len() || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+
+    let after = expected.len() - end;
+    for line in &expected[after..(after + CONTEXT).min(expected.len())] {
+        lines.push(format!("  {line}"));
+    }
+
+    lines.join("\n")
+}
+
+#[tokio::test]
+async fn test_golden_responses() {
+    let mut golden = Golden::new().await;
+
+    golden.check("healthz", get("/healthz")).await;
+    golden.check("readyz", get("/readyz")).await;
+    let openapi = golden.check("openapi", get("/openapi.json")).await;
+    golden.check("error-codes", get("/meta/error-codes")).await;
+    golden.check("order-schema", get("/meta/schemas/order")).await;
+    golden.check("config", get("/meta/config")).await;
+
+    // the catalog
+    let product = json!({ "sku": "MUG-1", "name": "Mug", "unit_price": 250 });
+    golden.check("create-product", post("/products", product)).await;
+    golden.check("list-products", get("/products")).await;
+    golden.check("get-product", get("/products/1")).await;
+    golden
+        .check("update-product", patch("/products/1", json!({ "unit_price": 300 })))
+        .await;
+
+    // an order through its life
+    let order = json!({
+        "amount": 500,
+        "status": "pending",
+        "customer_id": "customer-1",
+        "metadata": { "source": "web" },
+        "shipping_address": {
+            "name": "Ada Lovelace",
+            "line1": "12 St James's Square",
+            "city": "London",
+            "postal_code": "SW1Y 4JH",
+            "country": "gb"
+        }
+    });
+    golden
+        .check("create-order", post("/orders", order).header("x-api-key", "golden-client"))
+        .await;
+    golden
+        .check("create-order-invalid", post("/orders", json!({ "amount": -5, "status": "pending" })))
+        .await;
+    golden.check("get-order", get("/orders/1")).await;
+    golden.check("get-order-missing", get("/orders/999")).await;
+    golden.check("order-exists", Call::new("HEAD", "/orders/1")).await;
+    golden
+        .check("add-item", post("/orders/1/items", json!({ "sku": "MUG-1", "quantity": 2 })))
+        .await;
+    golden.check("list-items", get("/orders/1/items")).await;
+    golden
+        .check(
+            "adjust-item",
+            patch("/orders/1/items/1", json!({ "quantity": 3, "actor": "ops" })),
+        )
+        .await;
+    golden
+        .check(
+            "preview-totals",
+            post(
+                "/orders/1/preview-totals",
+                json!({ "discount": { "percent": 10 }, "tax_rate": 8, "add": [{ "sku": "MUG-1", "quantity": 1 }] }),
+            ),
+        )
+        .await;
+    golden
+        .check(
+            "add-note",
+            post("/orders/1/notes", json!({ "body": "Gift wrap it", "author": "ops" })),
+        )
+        .await;
+    golden.check("list-notes", get("/orders/1/notes")).await;
+    golden
+        .check(
+            "update-order",
+            patch("/orders/1", json!({ "status": "in-progress", "metadata": { "gift": "yes" } })),
+        )
+        .await;
+    golden.check("pick-list", get("/orders/pick-list?limit=5")).await;
+
+    golden
+        .check("create-duplicate", post("/orders", json!({ "amount": 700, "status": "pending" })))
+        .await;
+    golden
+        .check("merge-order", post("/orders/1/merge", json!({ "source_id": 2 })))
+        .await;
+
+    golden
+        .check("create-draft", post("/orders", json!({ "amount": 900, "draft": true })))
+        .await;
+    golden.check("confirm-order", Call::new("POST", "/orders/3/confirm")).await;
+
+    golden
+        .check("complete-order", patch("/orders/1", json!({ "status": "complete" })))
+        .await;
+    golden
+        .check("cancel-order", patch("/orders/3", json!({ "status": "canceled" })))
+        .await;
+    golden
+        .check("reopen-order", post("/orders/3/reopen", json!({ "actor": "ops" })))
+        .await;
+    golden.check("order-events", get("/orders/1/events")).await;
+    golden
+        .check("order-as-of", get("/orders/1/as-of?at=2025-10-01T12:00:00Z"))
+        .await;
+    golden.check("order-durations", get("/orders/3/durations")).await;
+
+    // reading orders back
+    golden.check("list-orders", get("/orders")).await;
+    golden
+        .check("list-orders-page", get("/orders?status=pending&page=1&per_page=2"))
+        .await;
+    golden.check("count-orders", get("/orders/count")).await;
+    golden.check("order-stats", get("/orders/stats")).await;
+    golden
+        .check("search-orders", post("/orders/search", json!({ "min_amount": 600 })))
+        .await;
+    golden.check("export-orders", get("/orders/export")).await;
+    golden
+        .check("daily-report", get("/reports/daily?date=2025-10-01"))
+        .await;
+    golden
+        .check("sla-report", get("/reports/sla?from=2025-10-01T00:00:00Z&to=2025-10-02T00:00:00Z"))
+        .await;
+    golden
+        .check("event-stream", get("/events/stream?include_counts=true"))
+        .await;
+
+    // imports
+    let csv = "amount,status\n1200,pending\n-1,pending\n";
+    golden
+        .check("import-orders", Call::new("POST", "/orders/import").text("text/csv", csv))
+        .await;
+    golden.check("get-import-job", get("/import-jobs/1")).await;
+    golden.check("export-order-graphs", get("/orders/export/graph.ndjson")).await;
+    // order 1 is already here, so it's skipped and nothing changes
+    let graphs = concat!(
+        r#"{"order":{"id":1,"amount":500,"status":"pending","created_at":"2025-10-01T12:00:00.000Z","updated_at":"2025-10-01T12:00:00.000Z"}}"#,
+        "\n{}\n",
+    );
+    golden
+        .check(
+            "import-order-graphs",
+            Call::new("POST", "/orders/import/graph").text("application/x-ndjson", graphs),
+        )
+        .await;
+
+    // webhooks
+    let webhook = json!({ "url": "https://hooks.example.com/orders" });
+    golden.check("create-webhook", post("/webhooks", webhook)).await;
+    golden.check("list-webhooks", get("/webhooks")).await;
+    golden
+        .check("update-webhook", patch("/webhooks/1", json!({ "payload_version": 1 })))
+        .await;
+    golden
+        .check("replay-events", post("/admin/events/replay", json!({ "webhook_id": 1 })))
+        .await;
+    golden.check("get-replay", get("/admin/replays/1")).await;
+
+    // admin
+    golden.check("usage", get("/admin/usage")).await;
+    golden.check("request-log", get("/admin/requests?api_key=golden-client")).await;
+    golden
+        .check(
+            "request-stats",
+            get("/admin/requests/stats?from=2025-10-01T00:00:00Z&to=2025-10-02T00:00:00Z"),
+        )
+        .await;
+    golden
+        .check("set-quota", put("/admin/quotas/golden-client", json!({ "monthly_limit": 100 })))
+        .await;
+    golden
+        .check(
+            "create-api-key",
+            post("/admin/api-keys", json!({ "name": "golden-client", "scopes": ["read", "write"] })),
+        )
+        .await;
+    golden
+        .check("rotate-api-key", Call::new("POST", "/admin/api-keys/1/rotate?overlap_hours=2"))
+        .await;
+    golden
+        .check("revoke-api-key", Call::new("POST", "/admin/api-keys/1/revoke"))
+        .await;
+    golden.check("api-keys", get("/admin/api-keys")).await;
+    golden.check("get-sequence", get("/admin/sequences/audit_chain")).await;
+    golden
+        .check("reset-sequence", put("/admin/sequences/golden", json!({ "value": 10 })))
+        .await;
+    golden.check("growth-stats", get("/admin/stats/growth")).await;
+    golden.check("runtime-stats", get("/admin/stats/runtime")).await;
+    golden.check("error-stats", get("/admin/stats/errors")).await;
+    golden
+        .check("maintenance-stats", get("/admin/stats/maintenance"))
+        .await;
+    golden.check("caches", get("/admin/caches")).await;
+    golden
+        .check("consistency-check", get("/admin/consistency-check"))
+        .await;
+    golden.check("verify-audit", get("/admin/audit/verify")).await;
+    golden
+        .check("recompute-order", Call::new("POST", "/admin/orders/1/recompute"))
+        .await;
+    golden
+        .check(
+            "retag-orders",
+            post("/admin/orders/retag?status=complete", json!({ "add_tags": ["promo-q1"] })),
+        )
+        .await;
+    golden
+        .check("backup", Call::new("POST", "/admin/backup"))
+        .await;
+    golden.check("metrics", get("/metrics")).await;
+
+    // replacing the imported order, after the reads above so they don't change
+    let transaction = json!([
+        { "op": "cancel", "id": 4, "reason": "replaced" },
+        {
+            "op": "create",
+            "order": { "amount": 1200, "status": "pending" },
+            "items": [{ "sku": "MUG-1", "quantity": 4 }]
+        }
+    ]);
+    golden
+        .check("order-transaction", post("/orders/transactions", transaction))
+        .await;
+
+    // deletes last, they'd take the examples above away
+    golden.check("delete-webhook", delete("/webhooks/1")).await;
+    golden.check("delete-order", delete("/orders/3")).await;
+    golden.check("delete-product", delete("/products/1")).await;
+
+    golden.check_coverage(&openapi);
+    golden.finish();
+}
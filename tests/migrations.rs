@@ -0,0 +1,15 @@
+//! Every migration upgrading a database with rows in every table, from each
+//! version this build carries to the latest, see `migrations.rs`. A migration
+//! that only works on empty tables fails here rather than on a deploy.
+//!
+//! Needs the `migration-tests` feature:
+//! `cargo test --features migration-tests --test migrations`.
+
+use sp_exercise::migrations::{seed, test_upgrade_path};
+
+#[tokio::test]
+async fn test_every_version_upgrades_with_data() {
+    let checked = test_upgrade_path(seed).await.unwrap();
+
+    assert!(checked > 0);
+}